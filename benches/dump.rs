@@ -0,0 +1,66 @@
+use std::{fs::File, io::Write, time::{Duration, Instant}};
+
+use indexmap::IndexMap;
+use marshr::prelude::*;
+
+const NUMBER_OF_OBJECTS: usize = 200_000;
+const ITERATIONS: u32 = 5;
+
+/// Builds an array of `NUMBER_OF_OBJECTS` objects, each holding a UTF-8 string and a fixnum,
+/// symbol ids are assigned in the order the dumper will first encounter them
+fn build_root() -> Root {
+    let symbols = ["Person", "@name", "E", "@hp"].map(SymbolStr::from).to_vec();
+    let mut objects = Vec::with_capacity(NUMBER_OF_OBJECTS * 2 + 1);
+    objects.push(RubyObject::Array(Vec::with_capacity(NUMBER_OF_OBJECTS)));
+
+    for i in 0..NUMBER_OF_OBJECTS {
+        let mut string = RubyString::new(format!("Person number {}", i).into_bytes());
+        let mut string_instance_variables = ValuePairsSymbolKeys::new();
+        string_instance_variables.insert(2, RubyValue::Boolean(true));
+        string.set_instance_variables(string_instance_variables);
+
+        let object_id = objects.len();
+        let string_id = object_id + 1;
+
+        let mut instance_variables = IndexMap::new();
+        instance_variables.insert(1, RubyValue::String(string_id));
+        instance_variables.insert(3, RubyValue::FixNum(i32::try_from(i).unwrap()));
+        objects.push(RubyObject::Object(Object::new(0, instance_variables)));
+        objects.push(RubyObject::String(string));
+
+        objects[0].as_mut_array().push(RubyValue::Object(object_id));
+    }
+
+    Root::new(RubyValue::Array(0), symbols, objects)
+}
+
+/// Dumps `root` with the dumper's internal buffer or, with a `buffer_capacity` of 0, handing every tag, length and
+/// payload to the writer like before the dumper buffered its output
+fn bench_dump<T: Write>(name: &str, root: &Root, buffer_capacity: Option<usize>, mut make_writer: impl FnMut() -> T) {
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let mut writer = make_writer();
+        let start = Instant::now();
+        let dumper = Dumper::new(&mut writer);
+        let mut dumper = match buffer_capacity {
+            Some(capacity) => dumper.with_buffer_capacity(capacity),
+            None => dumper,
+        };
+        dumper.dump(root, root.get_root()).unwrap();
+        total += start.elapsed();
+    }
+    println!("{:<32} {:>10.2?} per dump", name, total / ITERATIONS);
+}
+
+fn main() {
+    let root = build_root();
+
+    bench_dump("dump into Vec<u8>", &root, None, Vec::new);
+    bench_dump("dump into Vec<u8>, unbuffered", &root, Some(0), Vec::new);
+
+    // every write to a File is a system call, the unbuffered dump is the baseline the buffer is measured against
+    let path = std::env::temp_dir().join("marshr_dump_bench.bin");
+    bench_dump("dump into File", &root, None, || File::create(&path).unwrap());
+    bench_dump("dump into File, unbuffered", &root, Some(0), || File::create(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,90 @@
+//! Summarizes a Rails cache entry (an `ActiveSupport::Cache::Entry` dumped with Marshal) without knowing its classes
+//!
+//! `cargo run --example cache_inspector -- <file>`, without a file a generated entry is inspected. Statistics are
+//! collected by a `LoadVisitor` while loading, cached exceptions are shown through `ExceptionView`.
+
+use std::ops::ControlFlow;
+
+use marshr::prelude::*;
+
+/// Counts strings and their bytes and remembers the largest collection, as the document is loaded
+#[derive(Default)]
+struct Stats {
+    strings: usize,
+    string_bytes: usize,
+    largest_collection: usize,
+}
+
+impl LoadVisitor for Stats {
+    fn on_string(&mut self, bytes: &[u8]) -> ControlFlow<String> {
+        self.strings += 1;
+        self.string_bytes += bytes.len();
+        ControlFlow::Continue(())
+    }
+
+    fn on_array(&mut self, len: usize) -> ControlFlow<String> {
+        self.largest_collection = self.largest_collection.max(len);
+        ControlFlow::Continue(())
+    }
+
+    fn on_hash(&mut self, len: usize) -> ControlFlow<String> {
+        self.largest_collection = self.largest_collection.max(len);
+        ControlFlow::Continue(())
+    }
+}
+
+/// An entry caching the error a failed request raised
+fn sample_entry() -> Vec<u8> {
+    let mut builder = RootBuilder::new();
+    let error = marshr::views::exception::add_exception(
+        builder.root_mut(),
+        "Net::ReadTimeout",
+        "Net::ReadTimeout with #<TCPSocket:(closed)>",
+        &["app/services/weather.rb:12:in `fetch'", "app/controllers/weather_controller.rb:5:in `show'"],
+    );
+    let version = builder.string("v3");
+    let entry = builder.object(
+        "ActiveSupport::Cache::Entry",
+        [("@value", error), ("@version", version), ("@created_at", RubyValue::Nil), ("@expires_in", RubyValue::FixNum(300))],
+    );
+    let root = builder.build(entry);
+    marshr::dump(&root, &Options::default()).unwrap_or_default()
+}
+
+fn main() -> Result<(), String> {
+    let input = match std::env::args().nth(1) {
+        Some(path) => std::fs::read(&path).map_err(|err| format!("could not read {}: {}", path, err))?,
+        None => sample_entry(),
+    };
+
+    let mut stats = Stats::default();
+    let mut reader = &input[..];
+    let (root, extent) = Loader::new(&mut reader).with_visitor(&mut stats).load_with_extent().map_err(|err| err.to_string())?;
+    println!("{} bytes, {} objects, {} symbols", extent.consumed, root.get_objects().len(), root.get_symbols().len());
+    if extent.trailing_data {
+        println!("warning: {} bytes follow the entry", input.len() as u64 - extent.consumed);
+    }
+    println!("{} strings holding {} bytes, largest collection has {} elements", stats.strings, stats.string_bytes, stats.largest_collection);
+    for (class_name, count) in root.classes_used() {
+        println!("  {:>4} {}", count, class_name);
+    }
+
+    let value = root.get_symbol_id("@value")
+        .zip(root.get_root().object_id())
+        .and_then(|(name, entry_id)| root.get_object(entry_id)?.try_as_object()?.get_instance_variable(name))
+        .unwrap_or(root.get_root());
+    if let Ok(exception) = ExceptionView::new(&root, value) {
+        let message = exception.message().ok().flatten().unwrap_or_default();
+        println!("cached exception {}: {}", exception.class_name(), message);
+        for line in exception.backtrace().unwrap_or_default() {
+            println!("    from {}", line);
+        }
+        return Ok(());
+    }
+
+    let mut printed = String::new();
+    let limits = PrintLimits { max_depth: 4, max_nodes: 200, indent: Some(2), ..Default::default() };
+    root.print_limited(value, &mut printed, &limits).map_err(|err| err.to_string())?;
+    println!("cached value:\n{}", printed);
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Decodes the session of a Rails cookie written with the Marshal serializer: `<base64 data>--<digest>`
+//!
+//! `cargo run --example cookie_decoder -- <cookie>`, without a cookie a sample session is decoded. Cookies come from
+//! the client, so the data is loaded with tight limits and no application classes are permitted. The digest isn't
+//! verified here, do that with the application's secret before trusting the session.
+
+use marshr::prelude::*;
+
+const SAMPLE_COOKIE: &str = "BAh7CEkiD3Nlc3Npb25faWQGOgZFVEkiCTRmMmEGOwBUSSIMdXNlcl9pZAY7AFRpL0kiCmZsYXNoBjsAVHsGSSILbm90aWNlBjsAVEkiClNhdmVkBjsAVA==--d3b4ca8d753ea2b3130fe23c9f954577f686fe72";
+
+/// Decodes standard base64 with padding, `None` for anything else
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for byte in input {
+        bits = bits << 6 | ALPHABET.iter().position(|c| c == byte)? as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            output.push((bits >> bit_count) as u8);
+        }
+    }
+    Some(output)
+}
+
+/// Cookies copied from a browser may have `=`, `/` and `+` percent-encoded
+fn percent_decode(data: &str) -> String {
+    data.replace("%3D", "=").replace("%2F", "/").replace("%2B", "+")
+}
+
+fn main() -> Result<(), String> {
+    let cookie = std::env::args().nth(1).unwrap_or_else(|| SAMPLE_COOKIE.to_string());
+    let (data, _digest) = cookie.rsplit_once("--").ok_or("cookie has no digest")?;
+    let bytes = decode_base64(&percent_decode(data)).ok_or("cookie data isn't base64")?;
+
+    let options = Options::builder()
+        .with_max_depth(16)
+        .with_max_objects(1_000)
+        .with_max_item_bytes(4 * 1024)
+        .with_permitted_classes([])
+        .build();
+    let root = marshr::load(&bytes, &options).map_err(|err| format!("{} ({})", err, err.code().as_str()))?;
+
+    let mut session: Vec<_> = root.flatten().map_err(|err| err.to_string())?.into_iter().collect();
+    session.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, value) in session {
+        println!("{} = {:?}", path, value);
+    }
+    Ok(())
+}
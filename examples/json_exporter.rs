@@ -0,0 +1,52 @@
+//! Exports an array of records (like the rows of a Marshal dumped table) as JSON Lines on standard output
+//!
+//! `cargo run --example json_exporter -- <file> [instance variable]`, without a file generated users are exported.
+//! Every element is converted and written on its own, so large arrays never exist as JSON in memory at once. With an
+//! instance variable name, only that column is printed.
+
+use std::io::Write;
+
+use marshr::prelude::*;
+
+fn sample_users() -> Vec<u8> {
+    let mut builder = RootBuilder::new();
+    let users = [("alice", 34, &["admin", "editor"][..]), ("bob", 27, &[][..])]
+        .iter()
+        .map(|&(name, age, roles)| {
+            let name = builder.string(name);
+            let roles = roles.iter().map(|role| builder.symbol(role)).collect();
+            let roles = builder.array(roles);
+            builder.object("User", [("@name", name), ("@age", RubyValue::FixNum(age)), ("@roles", roles)])
+        })
+        .collect();
+    let users = builder.array(users);
+    let root = builder.build(users);
+    marshr::dump(&root, &Options::default()).unwrap_or_default()
+}
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let input = match args.next() {
+        Some(path) => std::fs::read(&path).map_err(|err| format!("could not read {}: {}", path, err))?,
+        None => sample_users(),
+    };
+    let root = marshr::load(&input, &Options::default()).map_err(|err| err.to_string())?;
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+
+    let options = DynOptions { key_collision: KeyCollision::Disambiguate };
+    if let Some(instance_variable) = args.next() {
+        let column: Vec<Option<RubyValue>> = root.column(root.get_root(), &instance_variable).map_err(|err| err.to_string())?;
+        for value in column {
+            let mut json = String::new();
+            let value = to_dyn(&root, &value.unwrap_or(RubyValue::Nil), &options).map_err(|err| err.to_string())?;
+            marshr::export::write_json(&value, &mut json);
+            writeln!(output, "{}", json).map_err(|err| err.to_string())?;
+        }
+        return Ok(());
+    }
+
+    let lines = marshr::export::to_jsonl_with_options(&root, root.get_root(), &mut output, &options).map_err(|err| err.to_string())?;
+    eprintln!("{} records exported", lines);
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Changes the gold of the party in an RPG Maker style save file and writes the file back
+//!
+//! `cargo run --example save_editor -- <file> <gold>`, without a file a generated save is edited in memory. The save is
+//! loaded with `Preservation::Bytes`, so everything but the edited value is written back byte for byte.
+
+use marshr::prelude::*;
+
+/// A save holding the party with its gold and a large blob of map data
+fn sample_save() -> Vec<u8> {
+    let mut builder = RootBuilder::new();
+    let actors = vec![builder.string("Aluxes"), builder.string("Basil")];
+    let actors = builder.array(actors);
+    let party = builder.object("Game_Party", [("@actors", actors), ("@gold", RubyValue::FixNum(120))]);
+    let map_data = builder.string(&"\u{1}".repeat(64 * 1024));
+    let map = builder.object("Game_Map", [("@map_id", RubyValue::FixNum(3)), ("@data", map_data)]);
+    let save = builder.array(vec![party, map]);
+    let root = builder.build(save);
+    marshr::dump(&root, &Options::default()).unwrap_or_default()
+}
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next();
+    let gold: i32 = args.next().map_or(Ok(9_999), |gold| gold.parse()).map_err(|err| format!("invalid gold: {}", err))?;
+    let input = match &path {
+        Some(path) => std::fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))?,
+        None => sample_save(),
+    };
+
+    let options = Options::builder().with_preservation(Preservation::Bytes).build();
+    let mut root = marshr::load(&input, &options).map_err(|err| err.to_string())?;
+
+    let gold_name = root.get_symbol_id("@gold").ok_or("the save has no gold")?;
+    let parties = root.find_objects("Game_Party");
+    for &party_id in &parties {
+        let Some(RubyObject::Object(party)) = root.get_mut_object(party_id) else { continue };
+        if let Some(value) = party.get_mut_instance_variables().get_mut(&gold_name) {
+            println!("gold of party {}: {:?} -> {}", party_id, value, gold);
+            *value = RubyValue::FixNum(gold);
+        }
+    }
+
+    let output = marshr::dump(&root, &options).map_err(|err| err.to_string())?;
+    println!("{} parties edited, the save went from {} to {} bytes", parties.len(), input.len(), output.len());
+    if let Some(path) = path {
+        std::fs::write(&path, output).map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+    Ok(())
+}
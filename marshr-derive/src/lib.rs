@@ -0,0 +1,270 @@
+//! Derive macros for the traits of `marshr::typed`, use them through `marshr` with the `derive` feature
+//!
+//! Structs with named fields can derive `FromRubyObject` and `IntoRubyObject`, each field is one instance variable
+//! named after the field (`hp` is `@hp`). Attributes on the struct:
+//! - `#[marshr(class = "Actor")]` the Ruby class name, checked when decoding if given, the struct name when encoding
+//!   otherwise
+//! - `#[marshr(unknown = "deny" | "ignore" | "log")]` `FromRubyObject::UNKNOWN_INSTANCE_VARIABLES`
+//! - `#[marshr(none = "nil" | "omit")]` `IntoRubyObject::NONE`
+//!
+//! Attributes on fields:
+//! - `#[marshr(rename = "@hit_points")]` the instance variable name
+//! - `#[marshr(enum)]` the field is a `RubyEnum`
+//! - `#[marshr(object)]` the field is a nested object, decoded with the same `TypedDecoder`
+//! - `#[marshr(rest)]` the field is a `HashMap<String, RubyValue>` taking every instance variable not read otherwise,
+//!   it's left out when encoding
+//!
+//! Enums with unit variants only can derive `RubyEnum`, with `#[marshr(repr = "symbol" | "string")]` and
+//! `#[marshr(casing = "as_is" | "snake_case" | "camel_case")]` on the enum.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{ext::IdentExt, parse_macro_input, Attribute, Data, DeriveInput, Fields, FieldsNamed, LitStr, Type};
+
+#[proc_macro_derive(FromRubyObject, attributes(marshr))]
+pub fn derive_from_ruby_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_ruby_object(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(IntoRubyObject, attributes(marshr))]
+pub fn derive_into_ruby_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_ruby_object(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(RubyEnum, attributes(marshr))]
+pub fn derive_ruby_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    ruby_enum(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn from_ruby_object(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let container = ContainerAttributes::parse(&input.attrs)?;
+    let mut reads = Vec::new();
+    let mut rest = None;
+    let mut idents = Vec::new();
+    for field in &named_fields(input)?.named {
+        let ident = field.ident.as_ref().expect("named field");
+        let attributes = FieldAttributes::parse(&field.attrs)?;
+        idents.push(ident);
+        if attributes.rest {
+            if rest.is_some() {
+                return Err(syn::Error::new_spanned(ident, "only one field can take the remaining instance variables"));
+            }
+            // read last, after every other field took its instance variable
+            rest = Some(quote! { let #ident = fields.rest()?; });
+            continue;
+        }
+        let name = attributes.instance_variable_name(ident);
+        let method = match attributes.kind {
+            FieldKind::Enum => quote!(get_enum),
+            FieldKind::Object => quote!(object),
+            FieldKind::Value => quote!(get),
+        };
+        reads.push(quote! { let #ident = fields.#method(#name)?; });
+    }
+
+    let class = match container.class {
+        Some(class) => quote!(::core::option::Option::Some(#class)),
+        None => quote!(::core::option::Option::None),
+    };
+    let unknown = container.unknown.unwrap_or_else(|| Ident::new("Deny", Span::call_site()));
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::marshr::typed::FromRubyObject for #ident #type_generics #where_clause {
+            const CLASS_NAME: ::core::option::Option<&'static str> = #class;
+            const UNKNOWN_INSTANCE_VARIABLES: ::marshr::typed::UnknownInstanceVariables =
+                ::marshr::typed::UnknownInstanceVariables::#unknown;
+
+            fn from_ruby_object(
+                fields: &mut ::marshr::typed::Fields<'_, '_>,
+            ) -> ::core::result::Result<Self, ::marshr::convert::ConvertError> {
+                #(#reads)*
+                #rest
+                ::core::result::Result::Ok(Self { #(#idents),* })
+            }
+        }
+    })
+}
+
+fn into_ruby_object(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let container = ContainerAttributes::parse(&input.attrs)?;
+    let mut writes = Vec::new();
+    for field in &named_fields(input)?.named {
+        let ident = field.ident.as_ref().expect("named field");
+        let attributes = FieldAttributes::parse(&field.attrs)?;
+        if attributes.rest {
+            continue;
+        }
+        let name = attributes.instance_variable_name(ident);
+        let method = match attributes.kind {
+            FieldKind::Enum => quote!(set_enum),
+            FieldKind::Object => quote!(set_object),
+            FieldKind::Value if is_option(&field.ty) => quote!(set_option),
+            FieldKind::Value => quote!(set),
+        };
+        writes.push(quote! { fields.#method(#name, self.#ident)?; });
+    }
+
+    let class = container.class.unwrap_or_else(|| LitStr::new(&input.ident.unraw().to_string(), input.ident.span()));
+    let none = container.none.unwrap_or_else(|| Ident::new("Nil", Span::call_site()));
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::marshr::typed::IntoRubyObject for #ident #type_generics #where_clause {
+            const CLASS_NAME: &'static str = #class;
+            const NONE: ::marshr::typed::OptionRepr = ::marshr::typed::OptionRepr::#none;
+
+            fn into_ruby_object(
+                self,
+                fields: &mut ::marshr::typed::FieldsWriter<'_>,
+            ) -> ::core::result::Result<(), ::marshr::convert::ConvertError> {
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+fn ruby_enum(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(&input.ident, "RubyEnum can only be derived for enums"));
+    };
+    let container = ContainerAttributes::parse(&input.attrs)?;
+    let mut variants = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(&variant.ident, "RubyEnum variants can't have fields"));
+        }
+        let ident = &variant.ident;
+        let name = ident.unraw().to_string();
+        variants.push(quote! { (#name, Self::#ident) });
+    }
+
+    let repr = container.repr.unwrap_or_else(|| Ident::new("Symbol", Span::call_site()));
+    let casing = container.casing.unwrap_or_else(|| Ident::new("SnakeCase", Span::call_site()));
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::marshr::typed::RubyEnum for #ident #type_generics #where_clause {
+            const VARIANTS: &'static [(&'static str, Self)] = &[#(#variants),*];
+            const REPR: ::marshr::typed::EnumRepr = ::marshr::typed::EnumRepr::#repr;
+            const CASING: ::marshr::typed::Casing = ::marshr::typed::Casing::#casing;
+        }
+    })
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<&FieldsNamed> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields),
+            _ => Err(syn::Error::new_spanned(&input.ident, "only structs with named fields can be derived")),
+        },
+        _ => Err(syn::Error::new_spanned(&input.ident, "only structs with named fields can be derived")),
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none() && path.path.segments.last().is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Maps the string value of an attribute to the variant of the corresponding enum in `marshr::typed`
+fn variant(value: &LitStr, variants: &[(&str, &str)]) -> syn::Result<Ident> {
+    let value_string = value.value();
+    match variants.iter().find(|(name, _)| *name == value_string) {
+        Some((_, variant)) => Ok(Ident::new(variant, value.span())),
+        None => {
+            let expected = variants.iter().map(|(name, _)| format!("\"{name}\"")).collect::<Vec<_>>().join(", ");
+            Err(syn::Error::new(value.span(), format!("expected one of {expected}")))
+        }
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttributes {
+    class: Option<LitStr>,
+    unknown: Option<Ident>,
+    none: Option<Ident>,
+    repr: Option<Ident>,
+    casing: Option<Ident>,
+}
+
+impl ContainerAttributes {
+    fn parse(attributes: &[Attribute]) -> syn::Result<Self> {
+        let mut container = ContainerAttributes::default();
+        for attribute in attributes.iter().filter(|attribute| attribute.path().is_ident("marshr")) {
+            attribute.parse_nested_meta(|meta| {
+                let value: LitStr = meta.value()?.parse()?;
+                if meta.path.is_ident("class") {
+                    container.class = Some(value);
+                } else if meta.path.is_ident("unknown") {
+                    container.unknown = Some(variant(&value, &[("deny", "Deny"), ("ignore", "Ignore"), ("log", "Log")])?);
+                } else if meta.path.is_ident("none") {
+                    container.none = Some(variant(&value, &[("nil", "Nil"), ("omit", "Omit")])?);
+                } else if meta.path.is_ident("repr") {
+                    container.repr = Some(variant(&value, &[("symbol", "Symbol"), ("string", "String")])?);
+                } else if meta.path.is_ident("casing") {
+                    container.casing = Some(variant(
+                        &value,
+                        &[("as_is", "AsIs"), ("snake_case", "SnakeCase"), ("camel_case", "CamelCase")],
+                    )?);
+                } else {
+                    return Err(meta.error("unknown marshr attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(container)
+    }
+}
+
+#[derive(Default)]
+enum FieldKind {
+    #[default]
+    Value,
+    Enum,
+    Object,
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    rename: Option<LitStr>,
+    kind: FieldKind,
+    rest: bool,
+}
+
+impl FieldAttributes {
+    fn parse(attributes: &[Attribute]) -> syn::Result<Self> {
+        let mut field = FieldAttributes::default();
+        for attribute in attributes.iter().filter(|attribute| attribute.path().is_ident("marshr")) {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    field.rename = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("enum") {
+                    field.kind = FieldKind::Enum;
+                } else if meta.path.is_ident("object") {
+                    field.kind = FieldKind::Object;
+                } else if meta.path.is_ident("rest") {
+                    field.rest = true;
+                } else {
+                    return Err(meta.error("unknown marshr attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(field)
+    }
+
+    fn instance_variable_name(&self, ident: &Ident) -> LitStr {
+        match &self.rename {
+            Some(rename) => rename.clone(),
+            None => LitStr::new(&format!("@{}", ident.unraw()), ident.span()),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Reports about the contents of a document that help deciding how to store it
+
+use std::{collections::{hash_map::DefaultHasher, BTreeMap, HashMap}, hash::{Hash, Hasher}};
+
+use crate::{diagnostics::DiagnosticKind, values::*};
+
+/// String payload that is stored in more than one string object
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DuplicateString {
+    /// first string object with this payload, the others are equal copies
+    pub object_id: ObjectID,
+    /// number of string objects with this payload
+    pub count: usize,
+    /// length of the payload in bytes
+    pub len: usize,
+}
+
+impl DuplicateString {
+    /// Bytes that linking the copies to the first string would save
+    pub fn wasted_bytes(&self) -> usize {
+        (self.count - 1) * self.len
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DuplicateStringReport {
+    /// the payloads wasting the most bytes, most wasteful first
+    pub top: Vec<DuplicateString>,
+    /// bytes wasted by all duplicated payloads, including the ones that didn't make it into `top`
+    pub total_wasted_bytes: usize,
+    /// number of distinct payloads stored more than once
+    pub duplicated_payloads: usize,
+    /// set if the document has more distinct payloads than there are counters, the counts and totals are lower
+    /// bounds then, see `duplicate_strings()`
+    pub approximate: bool,
+}
+
+/// Counters kept per payload reported by `duplicate_strings()`
+const COUNTERS_PER_REPORTED_PAYLOAD: usize = 16;
+/// Fewest counters kept by `duplicate_strings()`
+const MIN_COUNTERS: usize = 1024;
+
+/// Finds string payloads stored in several string objects (instead of being linked) and reports the `top_n` payloads
+/// wasting the most bytes. Instance variables (like the encoding) aren't compared.
+///
+/// Memory use is bounded: payloads are counted in `16 * top_n` (at least 1024) counters that hold a hash and the id
+/// of the first string, payloads aren't copied. If a payload shows up while every counter is taken, it and one
+/// occurrence of every counted payload cancel out and counters dropping to zero are freed (the Misra-Gries
+/// algorithm). Every payload making up more than a `1 / (counters + 1)` share of the strings is still reported, the
+/// report is marked as approximate and its counts are lower bounds.
+pub fn duplicate_strings(root: &Root, top_n: usize) -> DuplicateStringReport {
+    let capacity = top_n.saturating_mul(COUNTERS_PER_REPORTED_PAYLOAD).max(MIN_COUNTERS);
+    // payload hash to the counters of the payloads with that hash, usually one
+    let mut payloads: HashMap<u64, Vec<DuplicateString>> = HashMap::new();
+    let mut counters = 0;
+    let mut approximate = false;
+    for (object_id, object) in root.get_objects().iter().enumerate() {
+        let RubyObject::String(string) = object else { continue };
+        let bytes = string.get_string();
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let existing = payloads.get_mut(&hash).and_then(|candidates| candidates.iter_mut().find(|candidate| {
+            matches!(root.get_object(candidate.object_id), Some(RubyObject::String(first)) if first.get_string() == bytes)
+        }));
+        if let Some(existing) = existing {
+            existing.count += 1;
+        } else if counters < capacity {
+            payloads.entry(hash).or_default().push(DuplicateString { object_id, count: 1, len: bytes.len() });
+            counters += 1;
+        } else {
+            approximate = true;
+            payloads.retain(|_, candidates| {
+                candidates.retain_mut(|candidate| {
+                    candidate.count -= 1;
+                    candidate.count > 0
+                });
+                !candidates.is_empty()
+            });
+            counters = payloads.values().map(Vec::len).sum();
+        }
+    }
+
+    let mut duplicates: Vec<DuplicateString> = payloads.into_values().flatten().filter(|payload| payload.count > 1).collect();
+    let total_wasted_bytes = duplicates.iter().map(DuplicateString::wasted_bytes).sum();
+    let duplicated_payloads = duplicates.len();
+    duplicates.sort_by(|a, b| b.wasted_bytes().cmp(&a.wasted_bytes()).then(a.object_id.cmp(&b.object_id)));
+    duplicates.truncate(top_n);
+    DuplicateStringReport { top: duplicates, total_wasted_bytes, duplicated_payloads, approximate }
+}
+
+/// Size of a heap slot of 64-bit CRuby, every heap object takes one
+const SLOT_BYTES: usize = 40;
+/// Longest string stored inside its slot
+const EMBEDDED_STRING_LEN: usize = 23;
+/// Most array elements, instance variables or struct members stored inside the slot
+const EMBEDDED_ELEMENTS: usize = 3;
+/// Most entries of a hash kept in an array table, larger hashes get a hash table
+const AR_TABLE_ENTRIES: usize = 8;
+/// An array table holds `AR_TABLE_ENTRIES` keys and values plus a word of hashes
+const AR_TABLE_BYTES: usize = AR_TABLE_ENTRIES * 16 + 8;
+/// Header of a hash table, which has 24 bytes of entries and 8 bytes of bins per entry of its power of two capacity
+const ST_TABLE_BYTES: usize = 56;
+
+/// What loading the objects of one class allocates
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ClassAllocation {
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AllocationEstimate {
+    /// heap objects `Marshal.load` would allocate
+    pub objects: usize,
+    /// bytes of their slots and of the buffers allocated for them
+    pub bytes: usize,
+    /// the same by class name, instances of subclasses of String, Array, Hash and Regexp count for the subclass
+    pub classes: BTreeMap<String, ClassAllocation>,
+}
+
+/// Estimates how many objects and how many bytes of Ruby heap `Marshal.load` of the document would allocate on 64-bit
+/// CRuby 3.x. It's a rough model: every object takes a 40 byte slot, strings longer than 23 bytes and arrays, objects
+/// and structs with more than 3 elements get a buffer, hashes an array table or beyond 8 entries a hash table. Fixnums
+/// (including bignums that fit into 62 bits), flonums, symbols, classes and modules allocate nothing. UserDefined
+/// objects count their slot and the data string passed to `_load`, whatever `_load` allocates besides isn't known.
+pub fn allocation_estimate(root: &Root) -> AllocationEstimate {
+    // the object wrapped by a user class is the instance of the subclass
+    let subclasses: HashMap<ObjectID, SymbolID> = root.get_objects().iter()
+        .filter_map(|object| match object {
+            RubyObject::UserClass(user_class) => Some((user_class.get_wrapped_object().object_id()?, user_class.get_name())),
+            _ => None,
+        })
+        .collect();
+
+    let mut estimate = AllocationEstimate::default();
+    for (object_id, object) in root.get_objects().iter().enumerate() {
+        let Some((class_name, objects, bytes)) = object_allocation(object) else { continue };
+        let class_name = match subclasses.get(&object_id).and_then(|name| root.get_symbol(*name)) {
+            Some(name) => &**name,
+            None => match class_name {
+                ClassOf::Builtin(name) => name,
+                ClassOf::Symbol(symbol_id) => root.get_symbol(symbol_id).map_or("?", |name| &**name),
+            },
+        };
+        let class = estimate.classes.entry(class_name.to_string()).or_default();
+        class.objects += objects;
+        class.bytes += bytes;
+        estimate.objects += objects;
+        estimate.bytes += bytes;
+    }
+    estimate
+}
+
+enum ClassOf {
+    Builtin(&'static str),
+    Symbol(SymbolID),
+}
+
+/// Class, number of heap objects and bytes allocated for `object`, `None` if it doesn't allocate
+fn object_allocation(object: &RubyObject) -> Option<(ClassOf, usize, usize)> {
+    let string_bytes = |len: usize| if len <= EMBEDDED_STRING_LEN { SLOT_BYTES } else { SLOT_BYTES + len + 1 };
+    let elements_bytes = |len: usize| if len <= EMBEDDED_ELEMENTS { SLOT_BYTES } else { SLOT_BYTES + len * 8 };
+    let hash_bytes = |len: usize| match len {
+        0 => SLOT_BYTES,
+        len if len <= AR_TABLE_ENTRIES => SLOT_BYTES + AR_TABLE_BYTES,
+        len => SLOT_BYTES + ST_TABLE_BYTES + len.next_power_of_two() * (24 + 8),
+    };
+
+    let allocation = match object {
+        RubyObject::Array(array) => (ClassOf::Builtin("Array"), 1, elements_bytes(array.len())),
+        RubyObject::Hash(hash) => (ClassOf::Builtin("Hash"), 1, hash_bytes(hash.len())),
+        RubyObject::HashWithDefault(hash) => (ClassOf::Builtin("Hash"), 1, hash_bytes(hash.len())),
+        RubyObject::String(string) => (ClassOf::Builtin("String"), 1, string_bytes(string.get_string().len())),
+        RubyObject::Float(float) if !is_flonum(*float) => (ClassOf::Builtin("Float"), 1, SLOT_BYTES),
+        RubyObject::BigNum(bignum) if !(-(1 << 62)..(1 << 62)).contains(bignum) => (ClassOf::Builtin("Integer"), 1, SLOT_BYTES),
+        // the regexp keeps its source as a string, the compiled pattern isn't counted
+        RubyObject::RegExp(regexp) => (ClassOf::Builtin("Regexp"), 2, SLOT_BYTES + string_bytes(regexp.get_pattern().len())),
+        RubyObject::Struct(ruby_struct) => (ClassOf::Symbol(ruby_struct.get_name()), 1, elements_bytes(ruby_struct.get_members().len())),
+        RubyObject::Object(object) => (ClassOf::Symbol(object.get_class_name()), 1, elements_bytes(object.get_instance_variables().len())),
+        RubyObject::UserDefined(user_defined) => {
+            (ClassOf::Symbol(user_defined.get_class_name()), 2, SLOT_BYTES + string_bytes(user_defined.get_original_len()))
+        }
+        RubyObject::UserMarshal(user_marshal) => (ClassOf::Symbol(user_marshal.get_class_name()), 1, SLOT_BYTES),
+        RubyObject::Data(data) => (ClassOf::Symbol(data.get_class_name()), 1, SLOT_BYTES),
+        _ => return None,
+    };
+    Some(allocation)
+}
+
+/// Whether 64-bit CRuby stores the float in the value itself instead of allocating it
+fn is_flonum(float: f64) -> bool {
+    let bits = float.to_bits();
+    let exponent_bits = (bits >> 60) & 0x7;
+    bits == 0 || (matches!(exponent_bits, 3 | 4) && bits != 0x3000_0000_0000_0000)
+}
+
+/// Ruby versions that write distinguishable Marshal data
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Emitter {
+    /// Ruby 1.8 or older: strings without encoding, floats with extra digits
+    Ruby18,
+    /// Ruby 1.9 or later: strings, regexps and symbols that aren't ASCII-8BIT carry their encoding
+    Ruby19OrLater,
+    /// no construct in the document tells them apart, or the constructs contradict each other
+    Unknown,
+}
+
+/// What `Root::guess_emitter()` saw and concluded
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EmitterGuess {
+    pub emitter: Emitter,
+    /// strings and regexps with an `E` or `encoding` instance variable
+    pub encoded_strings: usize,
+    /// strings and regexps without one, every string of Ruby 1.8 but only binary strings of later versions
+    pub plain_strings: usize,
+    /// symbols with an encoding (see `Root::get_encoded_symbol()`)
+    pub encoded_symbols: usize,
+    /// floats with the `DiagnosticKind::LegacyFloat` diagnostic
+    pub legacy_floats: usize,
+}
+
+/// See `Root::guess_emitter()`
+pub(crate) fn guess_emitter(root: &Root) -> EmitterGuess {
+    let is_encoding = |symbol_id: &SymbolID| root.get_symbol(*symbol_id).is_some_and(|name| matches!(&**name, "E" | "encoding"));
+    let mut guess = EmitterGuess { emitter: Emitter::Unknown, encoded_strings: 0, plain_strings: 0, encoded_symbols: 0, legacy_floats: 0 };
+    for object in root.get_objects() {
+        let instance_variables = match object {
+            RubyObject::String(string) => string.get_instance_variables(),
+            RubyObject::RegExp(regexp) => regexp.get_instance_variables(),
+            _ => continue,
+        };
+        if instance_variables.iter().flat_map(|instance_variables| instance_variables.keys()).any(is_encoding) {
+            guess.encoded_strings += 1;
+        } else {
+            guess.plain_strings += 1;
+        }
+    }
+    guess.encoded_symbols = (0..root.get_symbols().len())
+        .filter(|symbol_id| root.get_encoded_symbol(*symbol_id).is_some_and(|symbol| symbol.get_instance_variables().keys().any(is_encoding)))
+        .count();
+    guess.legacy_floats = root.get_diagnostics().iter().filter(|diagnostic| diagnostic.kind == DiagnosticKind::LegacyFloat).count();
+
+    let new_constructs = guess.encoded_strings + guess.encoded_symbols;
+    guess.emitter = match (new_constructs > 0, guess.legacy_floats > 0 || guess.plain_strings > 0) {
+        (true, _) if guess.legacy_floats == 0 => Emitter::Ruby19OrLater,
+        (false, true) => Emitter::Ruby18,
+        _ => Emitter::Unknown,
+    };
+    guess
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    #[test]
+    fn test_duplicate_strings() {
+        // ["abc", "abc", "abc", "de", "de", "f", @1]
+        let root = load(b"\x04\x08[\x0c\"\x08abc\"\x08abc\"\x08abc\"\x07de\"\x07de\"\x06f@\x06");
+        let report = duplicate_strings(&root, 1);
+        assert_eq!(report.top, [DuplicateString { object_id: 1, count: 3, len: 3 }]);
+        assert_eq!(report.total_wasted_bytes, 8);
+        assert_eq!(report.duplicated_payloads, 2);
+        assert!(!report.approximate);
+
+        // a payload making up half of the strings among more distinct payloads than there are counters
+        let strings: Vec<String> = (0..MIN_COUNTERS * 4).map(|i| if i % 2 == 0 { "abc".to_string() } else { i.to_string() }).collect();
+        let root = Root::of_value(strings).unwrap();
+        let report = duplicate_strings(&root, 1);
+        assert!(report.approximate);
+        assert_eq!((report.top[0].object_id, report.top[0].len), (0, 3));
+        assert!(report.top[0].count <= MIN_COUNTERS * 2 && report.top[0].count >= MIN_COUNTERS * 2 - 4);
+    }
+
+    #[test]
+    fn test_allocation_estimate() {
+        // ["abc", "x" * 30, [1, 2, 3, 4], {1=>2}, 1.5, 1e-300, Foo(@a=1), MyStr("a")]
+        let mut input = b"\x04\x08[\x0d\"\x08abc\"\x23".to_vec();
+        input.extend([b'x'; 30]);
+        input.extend(b"[\x09i\x06i\x07i\x08i\x09{\x06i\x06i\x07f\x081.5f\x0b1e-300o:\x08Foo\x06:\x07@ai\x06C:\x0aMyStr\"\x06a");
+        let estimate = allocation_estimate(&load(&input));
+
+        assert_eq!(estimate.objects, 8);
+        assert_eq!(estimate.bytes, 583);
+        let class = |objects, bytes| ClassAllocation { objects, bytes };
+        assert_eq!(estimate.classes, BTreeMap::from([
+            ("Array".to_string(), class(2, 176)),
+            ("Float".to_string(), class(1, 40)),
+            ("Foo".to_string(), class(1, 40)),
+            ("Hash".to_string(), class(1, 176)),
+            ("MyStr".to_string(), class(1, 40)),
+            ("String".to_string(), class(2, 111)),
+        ]));
+    }
+
+    #[test]
+    fn test_guess_emitter() {
+        // ["a" (UTF-8), "b", 1.5]
+        let guess = load(b"\x04\x08[\x08I\"\x06a\x06:\x06ET\"\x06bf\x081.5").guess_emitter();
+        assert_eq!(guess, EmitterGuess { emitter: Emitter::Ruby19OrLater, encoded_strings: 1, plain_strings: 1, encoded_symbols: 0, legacy_floats: 0 });
+        // [:é]
+        assert_eq!(load(b"\x04\x08[\x06I:\x07\xc3\xa9\x06:\x06ET").guess_emitter().emitter, Emitter::Ruby19OrLater);
+
+        // ["a", 1.1 with 17 digits, 1.0 with mantissa bytes]
+        let guess = load(b"\x04\x08[\x08\"\x06af\x171.1000000000000001f\x091\x00ab").guess_emitter();
+        assert_eq!(guess, EmitterGuess { emitter: Emitter::Ruby18, encoded_strings: 0, plain_strings: 1, encoded_symbols: 0, legacy_floats: 2 });
+
+        assert_eq!(load(b"\x04\x08[\x07i\x06f\x081.5").guess_emitter().emitter, Emitter::Unknown);
+        // an encoding next to a float of Ruby 1.8
+        assert_eq!(load(b"\x04\x08[\x07I\"\x06a\x06:\x06ETf\x171.1000000000000001").guess_emitter().emitter, Emitter::Unknown);
+    }
+}
@@ -0,0 +1,298 @@
+//! Archives of named Marshal documents in one file, for snapshotting a directory of Ruby data into a single artifact.
+//! Documents keep the order they were added in and can be read one at a time without reading the others.
+//!
+//! Layout: the header (`MRAR` and the format version as 4 bytes little endian), the documents back to back, the table
+//! of contents and the footer. Every table of contents entry is the length of the name (4 bytes), the UTF-8 name, the
+//! offset and length of the document (8 bytes each) and the CRC-32 of the document (4 bytes). The footer holds the
+//! offset of the table of contents (8 bytes), the number of entries (4 bytes) and `MRAR` again. All numbers are little
+//! endian.
+
+use std::{collections::HashMap, io::{self, Read, Seek, SeekFrom, Write}};
+
+#[cfg(feature = "decode")]
+use crate::{decode::load::{ErrorLocation, LoadError, Loader}, error_code::ErrorCode};
+#[cfg(feature = "encode")]
+use crate::encode::dump::{DumpError, Dumper};
+#[cfg(any(feature = "decode", feature = "encode"))]
+use crate::values::*;
+
+const MAGIC: &[u8; 4] = b"MRAR";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8;
+const FOOTER_LEN: u64 = 16;
+
+/// CRC-32 (IEEE, as used by zlib and gzip) of `bytes`
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Table of contents entry of a document
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ArchiveEntry {
+    name: String,
+    offset: u64,
+    length: u64,
+    checksum: u32,
+}
+
+impl ArchiveEntry {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Offset of the document from the start of the archive
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+
+    /// CRC-32 of the document, see `checksum()`
+    pub fn get_checksum(&self) -> u32 {
+        self.checksum
+    }
+}
+
+/// Writes documents to `writer` and the table of contents on `finish()`, an archive that isn't finished can't be read
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    position: u64,
+    entries: Vec<ArchiveEntry>,
+    names: HashMap<String, usize>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Writes the header, `writer` should be at the start of the output since offsets are counted from there
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self { writer, position: HEADER_LEN, entries: Vec::new(), names: HashMap::new() })
+    }
+
+    /// Adds `document` (a complete Marshal document) under `name`, names have to be unique
+    pub fn add_bytes(&mut self, name: &str, document: &[u8]) -> io::Result<()> {
+        if self.names.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("archive already has an entry named {}", name)));
+        }
+        if u32::try_from(name.len()).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "entry name is longer than u32::MAX bytes"));
+        }
+        self.writer.write_all(document)?;
+        let entry = ArchiveEntry { name: name.to_string(), offset: self.position, length: document.len() as u64, checksum: checksum(document) };
+        self.position += entry.length;
+        self.names.insert(entry.name.clone(), self.entries.len());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Dumps the root value of `root` and adds it under `name`
+    #[cfg(feature = "encode")]
+    pub fn add(&mut self, name: &str, root: &Root) -> Result<(), DumpError> {
+        let mut document = Vec::new();
+        Dumper::new(&mut document).dump(root, root.get_root())?;
+        self.add_bytes(name, &document).map_err(|error| DumpError::IoError(error.to_string()))
+    }
+
+    /// Entries added so far, in order
+    pub fn get_entries(&self) -> &Vec<ArchiveEntry> {
+        &self.entries
+    }
+
+    /// Writes the table of contents and the footer, returns the flushed writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let entry_count = u32::try_from(self.entries.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "archive has more than u32::MAX entries"))?;
+        let mut toc = Vec::new();
+        for entry in &self.entries {
+            toc.extend_from_slice(&(entry.name.len() as u32).to_le_bytes());
+            toc.extend_from_slice(entry.name.as_bytes());
+            toc.extend_from_slice(&entry.offset.to_le_bytes());
+            toc.extend_from_slice(&entry.length.to_le_bytes());
+            toc.extend_from_slice(&entry.checksum.to_le_bytes());
+        }
+        toc.extend_from_slice(&self.position.to_le_bytes());
+        toc.extend_from_slice(&entry_count.to_le_bytes());
+        toc.extend_from_slice(MAGIC);
+        self.writer.write_all(&toc)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the table of contents of an archive and then single documents from it
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    entries: Vec<ArchiveEntry>,
+    names: HashMap<String, usize>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Checks the header and reads the table of contents, which has to lie within the input, like all documents
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN as usize];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("input isn't an archive".to_string()));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported archive version {}", version)));
+        }
+
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len < HEADER_LEN + FOOTER_LEN {
+            return Err(invalid_data("archive is truncated".to_string()));
+        }
+        let mut footer = [0; FOOTER_LEN as usize];
+        reader.seek(SeekFrom::Start(len - FOOTER_LEN))?;
+        reader.read_exact(&mut footer)?;
+        let (toc_offset, rest) = footer.split_at(8);
+        let (entry_count, magic) = rest.split_at(4);
+        if magic != MAGIC {
+            return Err(invalid_data("archive has no table of contents".to_string()));
+        }
+        let toc_offset = u64::from_le_bytes(toc_offset.try_into().map_err(|_| invalid_data("invalid footer".to_string()))?);
+        let entry_count = u32::from_le_bytes(entry_count.try_into().map_err(|_| invalid_data("invalid footer".to_string()))?);
+        if toc_offset < HEADER_LEN || toc_offset > len - FOOTER_LEN {
+            return Err(invalid_data(format!("table of contents offset {} is out of bounds", toc_offset)));
+        }
+
+        let mut toc = vec![0; (len - FOOTER_LEN - toc_offset) as usize];
+        reader.seek(SeekFrom::Start(toc_offset))?;
+        reader.read_exact(&mut toc)?;
+        let mut toc = &toc[..];
+        let mut entries = Vec::new();
+        let mut names = HashMap::new();
+        for _ in 0..entry_count {
+            let name_len = u32::from_le_bytes(take(&mut toc)?) as usize;
+            if name_len > toc.len() {
+                return Err(invalid_data("table of contents is truncated".to_string()));
+            }
+            let (name, rest) = toc.split_at(name_len);
+            toc = rest;
+            let name = String::from_utf8(name.to_vec()).map_err(|error| invalid_data(format!("entry name isn't UTF-8: {}", error)))?;
+            let entry = ArchiveEntry { name, offset: u64::from_le_bytes(take(&mut toc)?), length: u64::from_le_bytes(take(&mut toc)?), checksum: u32::from_le_bytes(take(&mut toc)?) };
+            if entry.offset < HEADER_LEN || entry.offset.checked_add(entry.length).map_or(true, |end| end > toc_offset) {
+                return Err(invalid_data(format!("entry {} is out of bounds", entry.name)));
+            }
+            if names.insert(entry.name.clone(), entries.len()).is_some() {
+                return Err(invalid_data(format!("archive has several entries named {}", entry.name)));
+            }
+            entries.push(entry);
+        }
+        if !toc.is_empty() {
+            return Err(invalid_data(format!("table of contents has {} trailing bytes", toc.len())));
+        }
+        Ok(Self { reader, entries, names })
+    }
+
+    /// Entries in the order the documents were added
+    pub fn get_entries(&self) -> &Vec<ArchiveEntry> {
+        &self.entries
+    }
+
+    pub fn get_entry(&self, name: &str) -> Option<&ArchiveEntry> {
+        self.names.get(name).map(|&index| &self.entries[index])
+    }
+
+    /// Reads the document named `name` and verifies its checksum, `None` if there is no such entry
+    pub fn read_bytes(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.names.get(name).map(|&index| &self.entries[index]) else {
+            return Ok(None);
+        };
+        let mut document = vec![0; entry.length as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut document)?;
+        let actual = checksum(&document);
+        if actual != entry.checksum {
+            return Err(invalid_data(format!("checksum of entry {} is {:08x}, expected {:08x}", name, actual, entry.checksum)));
+        }
+        Ok(Some(document))
+    }
+
+    /// Reads and loads the document named `name`, `None` if there is no such entry
+    #[cfg(feature = "decode")]
+    pub fn read(&mut self, name: &str) -> Result<Option<Root>, LoadError> {
+        let document = self.read_bytes(name).map_err(|error| LoadError::IoError(ErrorCode::ReadFailed, format!("Failed to read entry {}: {}", name, error), ErrorLocation::UNKNOWN))?;
+        let Some(document) = document else {
+            return Ok(None);
+        };
+        let mut data = &document[..];
+        let root = Loader::new(&mut data).load()?;
+        if !data.is_empty() {
+            return Err(LoadError::ParserError(
+                ErrorCode::TrailingBytes,
+                format!("Entry {} has {} trailing bytes", name, data.len()),
+                ErrorLocation { offset: Some((document.len() - data.len()) as u64), object_offset: None },
+            ));
+        }
+        Ok(Some(root))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Splits the next `N` bytes off `toc`
+fn take<const N: usize>(toc: &mut &[u8]) -> io::Result<[u8; N]> {
+    if toc.len() < N {
+        return Err(invalid_data("table of contents is truncated".to_string()));
+    }
+    let (bytes, rest) = toc.split_at(N);
+    *toc = rest;
+    bytes.try_into().map_err(|_| invalid_data("table of contents is truncated".to_string()))
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_archive() {
+        assert_eq!(checksum(b"123456789"), 0xcbf4_3926);
+
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        let maps = Root::of_value(vec![1, 2, 3]).unwrap();
+        let title = Root::of_value("x".repeat(200)).unwrap();
+        writer.add("Data/Map001.rxdata", &maps).unwrap();
+        writer.add("Data/Title.rxdata", &title).unwrap();
+        writer.add_bytes("Data/Empty.rxdata", b"\x04\x080").unwrap();
+        assert!(writer.add_bytes("Data/Title.rxdata", b"\x04\x080").is_err());
+        let output = writer.finish().unwrap();
+        assert_eq!(&output[..8], b"MRAR\x01\x00\x00\x00");
+
+        let mut reader = ArchiveReader::new(Cursor::new(&output)).unwrap();
+        let names: Vec<_> = reader.get_entries().iter().map(ArchiveEntry::get_name).collect();
+        assert_eq!(names, ["Data/Map001.rxdata", "Data/Title.rxdata", "Data/Empty.rxdata"]);
+        assert_eq!(reader.get_entry("Data/Map001.rxdata").unwrap().get_offset(), 8);
+        assert_eq!(reader.read("Data/Title.rxdata").unwrap(), Some(title));
+        assert_eq!(reader.read("Data/Map001.rxdata").unwrap(), Some(maps));
+        assert_eq!(reader.read_bytes("Data/Empty.rxdata").unwrap().unwrap(), b"\x04\x080");
+        assert_eq!(reader.read("Data/Missing.rxdata").unwrap(), None);
+
+        // a corrupted document, a truncated archive and an archive that wasn't finished
+        let mut corrupted = output.clone();
+        corrupted[10] ^= 1;
+        assert!(ArchiveReader::new(Cursor::new(&corrupted)).unwrap().read_bytes("Data/Map001.rxdata").is_err());
+        assert!(ArchiveReader::new(Cursor::new(&output[..output.len() - 1])).is_err());
+        let mut unfinished = ArchiveWriter::new(Vec::new()).unwrap();
+        unfinished.add_bytes("a", b"\x04\x080").unwrap();
+        assert!(ArchiveReader::new(Cursor::new(unfinished.writer)).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Incremental construction of documents, a thin layer over the `Root::add_*` constructors
+//!
+//! Large documents can be built on several threads: every worker fills its own builder (a shard with its own symbol
+//! and object tables, nothing is shared) and the shards are merged into one builder at the end, see
+//! `RootBuilder::merge()`. Moving a shard between threads needs the `sync` feature, without it `Root` isn't `Send`.
+//! Merging in a fixed order keeps the output the same no matter which worker finished first.
+//!
+//! ```
+//! # #[cfg(feature = "sync")] {
+//! use marshr::prelude::*;
+//!
+//! let shards: Vec<Root> = std::thread::scope(|scope| {
+//!     let workers: Vec<_> = (0..4).map(|worker| scope.spawn(move || {
+//!         let mut shard = RootBuilder::new();
+//!         let actors = (0..100).map(|i| shard.object("Actor", [("@id", RubyValue::FixNum(worker * 100 + i))])).collect();
+//!         let actors = shard.array(actors);
+//!         shard.build(actors)
+//!     })).collect();
+//!     workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+//! });
+//!
+//! let mut builder = RootBuilder::new();
+//! let parts = shards.into_iter().map(|shard| builder.merge(shard)).collect();
+//! let parts = builder.array(parts);
+//! let root = builder.build(parts);
+//! // "Actor" and "@id" are only defined once
+//! assert_eq!(root.get_symbols().len(), 2);
+//! # }
+//! ```
+
+#[cfg(feature = "decode")]
+use crate::decode::LoadError;
+use crate::{convert::{ConvertError, IntoRubyValue}, registry::RubyClass, values::*};
+
+#[derive(Debug, PartialEq)]
+pub struct RootBuilder {
+    root: Root,
+}
+
+impl Default for RootBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RootBuilder {
+    pub fn new() -> Self {
+        Self { root: Root::nil() }
+    }
+
+    /// Continues building on an existing document, its symbols and objects are kept
+    pub fn from_root(root: Root) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    pub fn root_mut(&mut self) -> &mut Root {
+        &mut self.root
+    }
+
+    pub fn symbol(&mut self, name: &str) -> RubyValue {
+        self.root.add_symbol(name)
+    }
+
+    pub fn string(&mut self, string: &str) -> RubyValue {
+        self.root.add_string(string)
+    }
+
+    pub fn array(&mut self, elements: Vec<RubyValue>) -> RubyValue {
+        self.root.add_array(elements)
+    }
+
+    pub fn object<'a>(&mut self, class_name: &str, instance_variables: impl IntoIterator<Item = (&'a str, RubyValue)>) -> RubyValue {
+        self.root.add_object(class_name, instance_variables)
+    }
+
+    pub fn value(&mut self, value: impl IntoRubyValue) -> Result<RubyValue, ConvertError> {
+        value.into_ruby_value(&mut self.root)
+    }
+
+    /// Adds an already encoded value (without the version header) that is written verbatim when dumping, see
+    /// `Root::add_raw()`
+    #[cfg(feature = "decode")]
+    pub fn push_raw(&mut self, bytes: &[u8]) -> Result<RubyValue, LoadError> {
+        self.root.add_raw(bytes)
+    }
+
+    /// Adds a typed model, see `RubyClass::to_object()`
+    pub fn class<T: RubyClass>(&mut self, model: &T) -> RubyValue {
+        model.to_object(self)
+    }
+
+    /// Moves the objects of `shard` (a document built by another builder, usually on another thread) into this one and
+    /// returns its root value remapped to them, symbols the shard shares with this builder are only kept once
+    pub fn merge(&mut self, shard: Root) -> RubyValue {
+        self.root.append(shard)
+    }
+
+    /// Finishes the document with `root` as its top level value
+    pub fn build(mut self, root: RubyValue) -> Root {
+        self.root.set_root(root);
+        self.root
+    }
+}
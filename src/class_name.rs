@@ -0,0 +1,114 @@
+//! Class paths like `RPG::Map::Event`, split into their namespace segments
+
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
+pub struct ClassName {
+    segments: Vec<String>,
+}
+
+impl ClassName {
+    /// Splits `path` at `::`, a leading `::` (top-level constant lookup) is ignored
+    pub fn parse(path: &str) -> Self {
+        let path = path.strip_prefix("::").unwrap_or(path);
+        Self { segments: path.split("::").map(str::to_string).collect() }
+    }
+
+    pub fn get_segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// The last segment, `Event` for `RPG::Map::Event`
+    pub fn get_name(&self) -> &str {
+        self.segments.last().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Everything before the last segment, `None` for top-level classes
+    pub fn get_namespace(&self) -> Option<ClassName> {
+        match self.segments.len() {
+            0 | 1 => None,
+            len => Some(Self { segments: self.segments[..len - 1].to_vec() }),
+        }
+    }
+
+    /// Whether the class is defined (directly or nested) in `namespace`
+    pub fn is_within(&self, namespace: &ClassName) -> bool {
+        self.segments.len() > namespace.segments.len() && self.segments.starts_with(&namespace.segments)
+    }
+
+    /// Matches the class against a glob pattern, a segment that is just `*` matches one or more whole segments and
+    /// a `*` inside a segment matches any characters of that segment, so `RPG::*` matches every class under `RPG`,
+    /// `*::Entry` every `Entry` inside a namespace and `Game_*` every top-level class starting with `Game_`
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = ClassName::parse(pattern);
+        matches_segments(&pattern.segments, &self.segments)
+    }
+}
+
+impl Display for ClassName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.segments.join("::"))
+    }
+}
+
+impl From<&str> for ClassName {
+    fn from(path: &str) -> Self {
+        Self::parse(path)
+    }
+}
+
+fn matches_segments(pattern: &[String], segments: &[String]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((first, rest)) if first == "*" => {
+            (1..=segments.len()).any(|taken| matches_segments(rest, &segments[taken..]))
+        }
+        Some((first, rest)) => match segments.split_first() {
+            Some((segment, remaining)) => matches_segment(first.as_bytes(), segment.as_bytes()) && matches_segments(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+fn matches_segment(pattern: &[u8], segment: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => (0..=segment.len()).any(|taken| matches_segment(rest, &segment[taken..])),
+        Some((first, rest)) => segment.first() == Some(first) && matches_segment(rest, &segment[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_name() {
+        let class_name = ClassName::parse("RPG::Map::Event");
+        assert_eq!(class_name.get_segments(), ["RPG", "Map", "Event"]);
+        assert_eq!(class_name.get_name(), "Event");
+        assert_eq!(class_name.get_namespace(), Some(ClassName::parse("RPG::Map")));
+        assert_eq!(class_name.to_string(), "RPG::Map::Event");
+        assert!(class_name.is_within(&ClassName::parse("RPG")));
+        assert!(!class_name.is_within(&class_name));
+        assert_eq!(ClassName::parse("::Test"), ClassName::parse("Test"));
+        assert_eq!(ClassName::parse("Test").get_namespace(), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let class_name = ClassName::parse("RPG::Map::Event");
+        assert!(class_name.matches("RPG::*"));
+        assert!(class_name.matches("*::Event"));
+        assert!(class_name.matches("RPG::*::Event"));
+        assert!(class_name.matches("RPG::M*::E*t"));
+        assert!(class_name.matches("RPG::Map::Event"));
+        assert!(!class_name.matches("RPG::Map"));
+        assert!(!class_name.matches("RPG::*::Map::Event"));
+        assert!(!class_name.matches("*::Entry"));
+
+        assert!(ClassName::parse("Game_Actor").matches("Game_*"));
+        assert!(!ClassName::parse("Event").matches("*::Event"));
+        assert!(!ClassName::parse("RPG").matches("RPG::*"));
+    }
+}
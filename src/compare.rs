@@ -0,0 +1,177 @@
+//! Comparison of values that live in different `Root`s, where symbol and object ids can't be compared directly
+
+use std::collections::HashSet;
+
+use crate::values::*;
+
+/// Whether `value_a` (in `root_a`) and `value_b` (in `root_b`) have the same contents: symbols compare by name,
+/// objects by type and contents, hashes and instance variables ignore their order like Ruby's `==` does, and sharing
+/// between objects doesn't matter. References to missing objects or symbols are never equal.
+pub fn cross_eq(root_a: &Root, value_a: &RubyValue, root_b: &Root, value_b: &RubyValue) -> bool {
+    Comparison { root_a, root_b, in_progress: HashSet::new() }.values(value_a, value_b)
+}
+
+struct Comparison<'a> {
+    root_a: &'a Root,
+    root_b: &'a Root,
+    /// pairs of objects currently being compared, comparing one of them again means the graphs have a cycle, which
+    /// is equal as long as the rest of the comparison is
+    in_progress: HashSet<(ObjectID, ObjectID)>,
+}
+
+impl Comparison<'_> {
+    fn symbols(&self, symbol_a: SymbolID, symbol_b: SymbolID) -> bool {
+        match (self.root_a.get_symbol(symbol_a), self.root_b.get_symbol(symbol_b)) {
+            (Some(name_a), Some(name_b)) => name_a == name_b,
+            _ => false,
+        }
+    }
+
+    fn symbol_lists(&self, symbols_a: &[SymbolID], symbols_b: &[SymbolID]) -> bool {
+        symbols_a.len() == symbols_b.len() && symbols_a.iter().zip(symbols_b).all(|(symbol_a, symbol_b)| self.symbols(*symbol_a, *symbol_b))
+    }
+
+    fn values(&mut self, value_a: &RubyValue, value_b: &RubyValue) -> bool {
+        if value_a.kind() != value_b.kind() {
+            return false;
+        }
+        let (object_a, object_b) = match (value_a, value_b) {
+            (RubyValue::Symbol(symbol_a), RubyValue::Symbol(symbol_b)) => return self.symbols(*symbol_a, *symbol_b),
+            (RubyValue::WithIvars(wrapper_a), RubyValue::WithIvars(wrapper_b)) => {
+                let ((inner_a, ivars_a), (inner_b, ivars_b)) = (&**wrapper_a, &**wrapper_b);
+                return self.values(inner_a, inner_b)
+                    && ivars_a.len() == ivars_b.len()
+                    && ivars_a.iter().zip(ivars_b).all(|((name_a, value_a), (name_b, value_b))| {
+                        self.symbols(*name_a, *name_b) && self.values(value_a, value_b)
+                    });
+            }
+            _ => match (value_a.object_id(), value_b.object_id()) {
+                (Some(object_a), Some(object_b)) => (object_a, object_b),
+                _ => return value_a == value_b,
+            },
+        };
+        if !self.in_progress.insert((object_a, object_b)) {
+            return true;
+        }
+        let equal = match (self.root_a.get_object(object_a), self.root_b.get_object(object_b)) {
+            (Some(object_a), Some(object_b)) => self.objects(object_a, object_b),
+            _ => false,
+        };
+        self.in_progress.remove(&(object_a, object_b));
+        equal
+    }
+
+    fn objects(&mut self, object_a: &RubyObject, object_b: &RubyObject) -> bool {
+        match (object_a, object_b) {
+            (RubyObject::Float(float_a), RubyObject::Float(float_b)) => float_a == float_b || (float_a.is_nan() && float_b.is_nan()),
+            (RubyObject::BigNum(bignum_a), RubyObject::BigNum(bignum_b)) => bignum_a == bignum_b,
+            (RubyObject::Class(name_a), RubyObject::Class(name_b))
+            | (RubyObject::Module(name_a), RubyObject::Module(name_b))
+            | (RubyObject::ClassOrModule(name_a), RubyObject::ClassOrModule(name_b)) => name_a == name_b,
+            (RubyObject::String(string_a), RubyObject::String(string_b)) => {
+                string_a.get_string() == string_b.get_string()
+                    && self.optional_instance_variables(string_a.get_instance_variables(), string_b.get_instance_variables())
+            }
+            (RubyObject::RegExp(regexp_a), RubyObject::RegExp(regexp_b)) => {
+                regexp_a.get_pattern() == regexp_b.get_pattern()
+                    && regexp_a.get_options() == regexp_b.get_options()
+                    && self.optional_instance_variables(regexp_a.get_instance_variables(), regexp_b.get_instance_variables())
+            }
+            (RubyObject::Array(elements_a), RubyObject::Array(elements_b)) => {
+                elements_a.len() == elements_b.len()
+                    && elements_a.iter().zip(elements_b).all(|(element_a, element_b)| self.values(element_a, element_b))
+            }
+            (RubyObject::Hash(hash_a), RubyObject::Hash(hash_b)) => self.hashes(hash_a, hash_b),
+            (RubyObject::HashWithDefault(hash_a), RubyObject::HashWithDefault(hash_b)) => {
+                self.hashes(hash_a.hash(), hash_b.hash()) && self.values(hash_a.default(), hash_b.default())
+            }
+            (RubyObject::Struct(struct_a), RubyObject::Struct(struct_b)) => {
+                // struct members have a fixed order
+                self.symbols(struct_a.get_name(), struct_b.get_name())
+                    && struct_a.get_members().len() == struct_b.get_members().len()
+                    && struct_a.get_members().iter().zip(struct_b.get_members())
+                        .all(|((name_a, value_a), (name_b, value_b))| self.symbols(*name_a, *name_b) && self.values(value_a, value_b))
+                    && self.optional_instance_variables(struct_a.get_instance_variables(), struct_b.get_instance_variables())
+            }
+            (RubyObject::Object(ruby_object_a), RubyObject::Object(ruby_object_b)) => {
+                self.symbols(ruby_object_a.get_class_name(), ruby_object_b.get_class_name())
+                    && self.symbol_lists(ruby_object_a.get_extended_modules(), ruby_object_b.get_extended_modules())
+                    && self.instance_variables(ruby_object_a.get_instance_variables(), ruby_object_b.get_instance_variables())
+            }
+            (RubyObject::UserClass(user_class_a), RubyObject::UserClass(user_class_b)) => {
+                self.symbols(user_class_a.get_name(), user_class_b.get_name())
+                    && self.values(user_class_a.get_wrapped_object(), user_class_b.get_wrapped_object())
+                    && self.optional_instance_variables(user_class_a.get_instance_variables(), user_class_b.get_instance_variables())
+            }
+            (RubyObject::UserDefined(user_defined_a), RubyObject::UserDefined(user_defined_b)) => {
+                self.symbols(user_defined_a.get_class_name(), user_defined_b.get_class_name())
+                    && self.symbol_lists(user_defined_a.get_extended_modules(), user_defined_b.get_extended_modules())
+                    && user_defined_a.get_data() == user_defined_b.get_data()
+                    && user_defined_a.get_original_len() == user_defined_b.get_original_len()
+                    && self.optional_instance_variables(user_defined_a.get_instance_variables(), user_defined_b.get_instance_variables())
+            }
+            (RubyObject::UserMarshal(user_marshal_a), RubyObject::UserMarshal(user_marshal_b)) => {
+                self.symbols(user_marshal_a.get_class_name(), user_marshal_b.get_class_name())
+                    && self.values(user_marshal_a.get_wrapped_object(), user_marshal_b.get_wrapped_object())
+            }
+            (RubyObject::Data(data_a), RubyObject::Data(data_b)) => {
+                self.symbols(data_a.get_class_name(), data_b.get_class_name()) && self.values(data_a.get_state(), data_b.get_state())
+            }
+            (RubyObject::Raw(raw_a), RubyObject::Raw(raw_b)) => raw_a.get_bytes() == raw_b.get_bytes(),
+            _ => false,
+        }
+    }
+
+    /// Pairs usually are in the same order, other orders need a search for every key
+    fn hashes(&mut self, hash_a: &ValuePairs, hash_b: &ValuePairs) -> bool {
+        if hash_a.len() != hash_b.len() {
+            return false;
+        }
+        let in_order = hash_a.iter().zip(hash_b)
+            .all(|((key_a, value_a), (key_b, value_b))| self.values(key_a, key_b) && self.values(value_a, value_b));
+        in_order || hash_a.iter().all(|(key_a, value_a)| {
+            let key_b = hash_b.keys().find(|key_b| self.values(key_a, key_b));
+            key_b.is_some_and(|key_b| self.values(value_a, &hash_b[key_b]))
+        })
+    }
+
+    fn instance_variables(&mut self, instance_variables_a: &ValuePairsSymbolKeys, instance_variables_b: &ValuePairsSymbolKeys) -> bool {
+        instance_variables_a.len() == instance_variables_b.len() && instance_variables_a.iter().all(|(name_a, value_a)| {
+            let value_b = instance_variables_b.iter().find(|(name_b, _)| self.symbols(*name_a, **name_b)).map(|(_, value_b)| value_b);
+            value_b.is_some_and(|value_b| self.values(value_a, value_b))
+        })
+    }
+
+    fn optional_instance_variables(&mut self, instance_variables_a: &Option<ValuePairsSymbolKeys>, instance_variables_b: &Option<ValuePairsSymbolKeys>) -> bool {
+        match (instance_variables_a, instance_variables_b) {
+            (Some(instance_variables_a), Some(instance_variables_b)) => self.instance_variables(instance_variables_a, instance_variables_b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    #[test]
+    fn test_cross_eq() {
+        // [:b, Test(@a=:a, @b="x"), {1=>2, 3=>4}]
+        let root_a = load(b"\x04\x08[\x08:\x06bo:\x09Test\x07:\x07@a:\x06a:\x07@b\"\x06x{\x07i\x06i\x07i\x08i\x09");
+        // [:b, Test(@b="x", @a=:a), {3=>4, 1=>2}] with other symbol and object ids
+        let root_b = load(b"\x04\x08[\x08:\x06bo:\x09Test\x07:\x07@b\"\x06x:\x07@a:\x06a{\x07i\x08i\x09i\x06i\x07");
+        assert!(cross_eq(&root_a, root_a.get_root(), &root_b, root_b.get_root()));
+
+        // [:b, Test(@a=:a, @b="y"), {1=>2, 3=>4}]
+        let root_c = load(b"\x04\x08[\x08:\x06bo:\x09Test\x07:\x07@a:\x06a:\x07@b\"\x06y{\x07i\x06i\x07i\x08i\x09");
+        assert!(!cross_eq(&root_a, root_a.get_root(), &root_c, root_c.get_root()));
+
+        // a = []; a << a
+        let cyclic = load(b"\x04\x08[\x06@\x00");
+        assert!(cross_eq(&cyclic, cyclic.get_root(), &cyclic, cyclic.get_root()));
+        assert!(!cross_eq(&cyclic, cyclic.get_root(), &root_a, root_a.get_root()));
+    }
+}
@@ -0,0 +1,420 @@
+//! The conversion layer between Rust values and values stored in a `Root`: `FromRubyValue` reads a value, and
+//! `IntoRubyValue` adds one (appending the objects it needs). Everything else in the crate that converts values goes
+//! through these two traits, typed instance variable access (`Fields::get`, `FieldsWriter::set`), columns
+//! (`Root::column`), maps (`Root::hash_to_map`) and the builder (`RootBuilder::value`), so implementations compose.
+//!
+//! Both are implemented for
+//! - `RubyValue` itself, `()` (`nil`), `bool`, integers (fixnums and bignums, range checked), `f32` and `f64`
+//! - strings (`String`, and `&str` for writing)
+//! - `Option<T>` (`nil` is `None`), `Vec<T>` and tuples of up to four elements (arrays), `HashMap` and `BTreeMap` (hashes)
+//! - user types implementing `FromRubyObject` or `IntoRubyObject` (objects), see the `typed` module
+
+use std::{collections::{BTreeMap, HashMap}, fmt::Display, hash::Hash};
+
+use crate::{spec::{FIXNUM_MAX, FIXNUM_MIN}, typed::{self, FromRubyObject, IntoRubyObject, TypedDecoder}, values::*};
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ConvertError {
+    /// The value has a different type than the one requested
+    TypeMismatch { expected: &'static str, found: ValueKind },
+    /// The value references an object or symbol that doesn't exist in the Root
+    InvalidReference(ValueKind, usize),
+    /// The value is a string that couldn't be decoded
+    Encoding(String),
+    /// The object is an instance of a different class than the one requested
+    ClassMismatch { expected: &'static str, found: String },
+    /// The object has an instance variable the requested type doesn't know about
+    UnknownInstanceVariable { class_name: String, name: String },
+    /// The object lacks an instance variable its class always has
+    MissingInstanceVariable { class_name: &'static str, name: &'static str },
+    /// The symbol or string doesn't name a variant of the requested enum
+    UnknownVariant(String),
+    /// The enum value isn't listed in `RubyEnum::VARIANTS` of its type, holds the type name
+    MissingVariant(&'static str),
+    /// Two keys of a hash convert to the same string
+    KeyCollision(String),
+    /// The hash key can't be converted to a string
+    UnsupportedKey(ValueKind),
+    /// The object references itself (directly or indirectly), which the requested type can't represent
+    Cycle(ObjectID),
+    /// The integer doesn't fit into the requested type
+    OutOfRange { expected: &'static str, value: i128 },
+    /// The array has a different length than the requested tuple
+    LengthMismatch { expected: usize, found: usize },
+    /// The payload of a UserDefined object doesn't have the requested layout, holds the reason
+    InvalidPayload(String),
+    /// Converting a hash entry failed, `path` holds the keys leading to it (outermost first) as printed by `Root::print`
+    AtKey { path: Vec<String>, error: Box<ConvertError> },
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::TypeMismatch { expected, found } => f.write_str(&format!("Expected {}, found {}", expected, found)),
+            ConvertError::InvalidReference(kind, id) => f.write_str(&format!("{} references non-existent id {}", kind, id)),
+            ConvertError::Encoding(error) => f.write_str(&format!("Could not decode string: {}", error)),
+            ConvertError::ClassMismatch { expected, found } => f.write_str(&format!("Expected an instance of {}, found {}", expected, found)),
+            ConvertError::UnknownInstanceVariable { class_name, name } => {
+                f.write_str(&format!("Unknown instance variable {} of {}", name, class_name))
+            }
+            ConvertError::MissingInstanceVariable { class_name, name } => {
+                f.write_str(&format!("Instance of {} is missing instance variable {}", class_name, name))
+            }
+            ConvertError::UnknownVariant(name) => f.write_str(&format!("{} is not a known enum variant", name)),
+            ConvertError::MissingVariant(type_name) => f.write_str(&format!("Variant of {} is missing from its VARIANTS", type_name)),
+            ConvertError::KeyCollision(key) => f.write_str(&format!("Hash has more than one key named {}", key)),
+            ConvertError::UnsupportedKey(kind) => f.write_str(&format!("{} can't be used as a hash key", kind)),
+            ConvertError::Cycle(object_id) => f.write_str(&format!("Object {} references itself", object_id)),
+            ConvertError::OutOfRange { expected, value } => f.write_str(&format!("{} doesn't fit into {}", value, expected)),
+            ConvertError::LengthMismatch { expected, found } => {
+                f.write_str(&format!("Expected an array of length {}, found length {}", expected, found))
+            }
+            ConvertError::InvalidPayload(reason) => f.write_str(&format!("Invalid payload: {}", reason)),
+            ConvertError::AtKey { path, error } => f.write_str(&format!("At key {}: {}", path.join(" -> "), error)),
+        }
+    }
+}
+
+/// Conversion of a value stored in a `Root` into a Rust type
+pub trait FromRubyValue: Sized {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError>;
+}
+
+fn type_mismatch<T>(expected: &'static str, value: &RubyValue) -> Result<T, ConvertError> {
+    Err(ConvertError::TypeMismatch { expected, found: value.kind() })
+}
+
+fn get_object<'a>(root: &'a Root, value: &RubyValue, object_id: ObjectID) -> Result<&'a RubyObject, ConvertError> {
+    root.get_object(object_id).ok_or(ConvertError::InvalidReference(value.kind(), object_id))
+}
+
+/// The value itself, it still references the objects and symbols of the Root it came from
+impl FromRubyValue for RubyValue {
+    fn from_ruby_value(_root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromRubyValue for bool {
+    fn from_ruby_value(_root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::Boolean(boolean) => Ok(*boolean),
+            _ => type_mismatch("boolean", value),
+        }
+    }
+}
+
+impl FromRubyValue for i32 {
+    fn from_ruby_value(_root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::FixNum(fixnum) => Ok(*fixnum),
+            _ => type_mismatch("fixnum", value),
+        }
+    }
+}
+
+impl FromRubyValue for i64 {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::FixNum(fixnum) => Ok((*fixnum).into()),
+            RubyValue::BigNum(object_id) => match get_object(root, value, *object_id)? {
+                RubyObject::BigNum(bignum) => Ok(*bignum),
+                _ => type_mismatch("bignum", value),
+            },
+            _ => type_mismatch("fixnum or bignum", value),
+        }
+    }
+}
+
+/// Integers other than `i32` and `i64` go through `i64` and are range checked
+macro_rules! from_ruby_value_via_i64 {
+    ($($integer:ty),*) => {
+        $(
+            impl FromRubyValue for $integer {
+                fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+                    let integer = i64::from_ruby_value(root, value)?;
+                    <$integer>::try_from(integer).map_err(|_| ConvertError::OutOfRange { expected: stringify!($integer), value: integer.into() })
+                }
+            }
+        )*
+    };
+}
+
+from_ruby_value_via_i64!(i8, i16, u8, u16, u32, u64, usize);
+
+impl FromRubyValue for f64 {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::Float(object_id) => match get_object(root, value, *object_id)? {
+                RubyObject::Float(float) => Ok(*float),
+                _ => type_mismatch("float", value),
+            },
+            _ => type_mismatch("float", value),
+        }
+    }
+}
+
+impl FromRubyValue for String {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::String(object_id) => match get_object(root, value, *object_id)? {
+                RubyObject::String(string) => root.decode_string(string).map_err(|error| ConvertError::Encoding(error.to_string())),
+                _ => type_mismatch("string", value),
+            },
+            RubyValue::WithIvars(wrapper) if matches!(wrapper.0, RubyValue::String(_)) => {
+                root.decode_string_value(value).map_err(|error| ConvertError::Encoding(error.to_string()))
+            }
+            _ => type_mismatch("string", value),
+        }
+    }
+}
+
+/// Returns the pairs of a hash or hash with default (whose default is ignored)
+fn get_hash<'a>(root: &'a Root, value: &RubyValue) -> Result<&'a ValuePairs, ConvertError> {
+    match value {
+        RubyValue::Hash(object_id) | RubyValue::HashWithDefault(object_id) => match get_object(root, value, *object_id)? {
+            RubyObject::Hash(hash) => Ok(hash),
+            RubyObject::HashWithDefault(hash) => Ok(hash.hash()),
+            _ => type_mismatch("hash", value),
+        },
+        _ => type_mismatch("hash", value),
+    }
+}
+
+/// Converts every pair of a hash, errors are wrapped in `ConvertError::AtKey` so nested maps report the whole path
+fn convert_pairs<K: FromRubyValue, V: FromRubyValue>(root: &Root, value: &RubyValue, mut insert: impl FnMut(K, V) -> bool) -> Result<(), ConvertError> {
+    for (key, value) in get_hash(root, value)? {
+        let at_key = |error: ConvertError| {
+            let mut description = String::new();
+            if root.print(key, &mut description, 0, 1).is_err() {
+                description = format!("{:?}", key);
+            }
+            match error {
+                ConvertError::AtKey { mut path, error } => {
+                    path.insert(0, description);
+                    ConvertError::AtKey { path, error }
+                }
+                error => ConvertError::AtKey { path: vec![description], error: Box::new(error) },
+            }
+        };
+        let converted_key = K::from_ruby_value(root, key).map_err(at_key)?;
+        let converted_value = V::from_ruby_value(root, value).map_err(at_key)?;
+        if !insert(converted_key, converted_value) {
+            return Err(at_key(ConvertError::KeyCollision("the key converts to the same value as another key".to_string())));
+        }
+    }
+    Ok(())
+}
+
+/// Hashes with default convert as well, their default is dropped
+impl<K: FromRubyValue + Eq + Hash, V: FromRubyValue> FromRubyValue for HashMap<K, V> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let mut map = HashMap::new();
+        convert_pairs(root, value, |key, value| map.insert(key, value).is_none())?;
+        Ok(map)
+    }
+}
+
+/// Hashes with default convert as well, their default is dropped
+impl<K: FromRubyValue + Ord, V: FromRubyValue> FromRubyValue for BTreeMap<K, V> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let mut map = BTreeMap::new();
+        convert_pairs(root, value, |key, value| map.insert(key, value).is_none())?;
+        Ok(map)
+    }
+}
+
+/// `nil` converts to `None`
+impl<T: FromRubyValue> FromRubyValue for Option<T> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        match value {
+            RubyValue::Nil => Ok(None),
+            _ => T::from_ruby_value(root, value).map(Some),
+        }
+    }
+}
+
+impl FromRubyValue for f32 {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        f64::from_ruby_value(root, value).map(|float| float as f32)
+    }
+}
+
+pub(crate) fn get_array<'a>(root: &'a Root, value: &RubyValue) -> Result<&'a Vec<RubyValue>, ConvertError> {
+    match value {
+        RubyValue::Array(object_id) => match get_object(root, value, *object_id)? {
+            RubyObject::Array(elements) => Ok(elements),
+            _ => type_mismatch("array", value),
+        },
+        _ => type_mismatch("array", value),
+    }
+}
+
+impl<T: FromRubyValue> FromRubyValue for Vec<T> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        get_array(root, value)?.iter().map(|element| T::from_ruby_value(root, element)).collect()
+    }
+}
+
+/// Objects decode with a fresh `TypedDecoder`, use one directly to collect skipped instance variables
+impl<T: FromRubyObject> FromRubyValue for T {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        TypedDecoder::new(root).decode(value)
+    }
+}
+
+/// Tuples convert from arrays of the same length
+macro_rules! tuple_conversions {
+    ($len:literal, $($name:ident: $index:tt),+) => {
+        impl<$($name: FromRubyValue),+> FromRubyValue for ($($name,)+) {
+            fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+                let elements = get_array(root, value)?;
+                if elements.len() != $len {
+                    return Err(ConvertError::LengthMismatch { expected: $len, found: elements.len() });
+                }
+                Ok(($($name::from_ruby_value(root, &elements[$index])?,)+))
+            }
+        }
+
+        impl<$($name: IntoRubyValue),+> IntoRubyValue for ($($name,)+) {
+            fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+                let elements = vec![$(self.$index.into_ruby_value(root)?),+];
+                Ok(root.add_array(elements))
+            }
+        }
+    };
+}
+
+tuple_conversions!(1, A: 0);
+tuple_conversions!(2, A: 0, B: 1);
+tuple_conversions!(3, A: 0, B: 1, C: 2);
+tuple_conversions!(4, A: 0, B: 1, C: 2, D: 3);
+
+/// Conversion of a Rust value into a value stored in `root`, objects (strings, floats, arrays, ...) are appended to it.
+/// Objects added before a conversion fails stay in `root`, unreferenced.
+pub trait IntoRubyValue {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError>;
+}
+
+impl IntoRubyValue for RubyValue {
+    fn into_ruby_value(self, _root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(self)
+    }
+}
+
+/// `()` converts to `nil`
+impl IntoRubyValue for () {
+    fn into_ruby_value(self, _root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(RubyValue::Nil)
+    }
+}
+
+impl IntoRubyValue for bool {
+    fn into_ruby_value(self, _root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(RubyValue::Boolean(self))
+    }
+}
+
+/// Values outside of the fixnum range (`FIXNUM_MIN..=FIXNUM_MAX`, -2^30 to 2^30 - 1) become bignums, like Ruby does
+/// when dumping
+impl IntoRubyValue for i32 {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        i64::from(self).into_ruby_value(root)
+    }
+}
+
+/// Values outside of the fixnum range become bignums, see `i32`
+impl IntoRubyValue for i64 {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        match i32::try_from(self) {
+            Ok(fixnum) if (FIXNUM_MIN..=FIXNUM_MAX).contains(&fixnum) => Ok(RubyValue::FixNum(fixnum)),
+            _ => Ok(root.add_ruby_object(RubyObject::BigNum(self))),
+        }
+    }
+}
+
+impl IntoRubyValue for f64 {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(root.add_ruby_object(RubyObject::Float(self)))
+    }
+}
+
+impl IntoRubyValue for &str {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(root.add_string(self))
+    }
+}
+
+impl IntoRubyValue for String {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        Ok(root.add_string(&self))
+    }
+}
+
+/// `None` converts to `nil`
+impl<T: IntoRubyValue> IntoRubyValue for Option<T> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        match self {
+            Some(value) => value.into_ruby_value(root),
+            None => Ok(RubyValue::Nil),
+        }
+    }
+}
+
+impl<T: IntoRubyValue> IntoRubyValue for Vec<T> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        let elements = self.into_iter().map(|element| element.into_ruby_value(root)).collect::<Result<_, _>>()?;
+        Ok(root.add_array(elements))
+    }
+}
+
+/// Integers other than `i32` and `i64` go through `i64`, values outside of its range (only possible for `u64` and
+/// `usize`) fail with `ConvertError::OutOfRange` since bignums are stored as `i64`
+macro_rules! into_ruby_value_via_i64 {
+    ($($integer:ty),*) => {
+        $(
+            impl IntoRubyValue for $integer {
+                fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+                    i64::try_from(self)
+                        .map_err(|_| ConvertError::OutOfRange { expected: "bignum", value: self as i128 })?
+                        .into_ruby_value(root)
+                }
+            }
+        )*
+    };
+}
+
+into_ruby_value_via_i64!(i8, i16, u8, u16, u32, u64, usize);
+
+impl IntoRubyValue for f32 {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        f64::from(self).into_ruby_value(root)
+    }
+}
+
+fn add_hash<K: IntoRubyValue, V: IntoRubyValue>(root: &mut Root, pairs: impl IntoIterator<Item = (K, V)>) -> Result<RubyValue, ConvertError> {
+    let hash = pairs.into_iter()
+        .map(|(key, value)| Ok((key.into_ruby_value(root)?, value.into_ruby_value(root)?)))
+        .collect::<Result<_, ConvertError>>()?;
+    Ok(root.add_ruby_object(RubyObject::Hash(hash)))
+}
+
+/// Pairs are written in the map's iteration order, which is unspecified for `HashMap`
+impl<K: IntoRubyValue, V: IntoRubyValue> IntoRubyValue for HashMap<K, V> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        add_hash(root, self)
+    }
+}
+
+impl<K: IntoRubyValue, V: IntoRubyValue> IntoRubyValue for BTreeMap<K, V> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        add_hash(root, self)
+    }
+}
+
+impl<T: IntoRubyObject> IntoRubyValue for T {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        typed::encode(root, self)
+    }
+}
@@ -1 +1,23 @@
+#[cfg(feature = "tokio")]
+pub mod async_loader;
+pub mod borrowed;
+#[cfg(feature = "compress")]
+pub mod compress;
 pub mod load;
+#[cfg(feature = "memmap2")]
+pub mod mmap;
+pub mod progress;
+pub mod push;
+pub mod visitor;
+
+#[cfg(feature = "tokio")]
+pub use async_loader::AsyncLoader;
+pub use borrowed::{load_from_slice, BorrowedObject, BorrowedRoot};
+#[cfg(feature = "compress")]
+pub use compress::{AutoDecompress, Compression};
+pub use load::{Documents, DuplicateKeys, ErrorLocation, LoadContext, LoadError, LoadExtent, Loader, LoaderOptions, PayloadLimit, TagHistogram, Token, TokenReader};
+#[cfg(feature = "memmap2")]
+pub use mmap::MappedRoot;
+pub use progress::ProgressCounter;
+pub use push::PushLoader;
+pub use visitor::LoadVisitor;
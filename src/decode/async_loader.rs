@@ -0,0 +1,110 @@
+//! Loading from a `tokio::io::AsyncRead`, like the body of a request in an async web service, see `AsyncLoader`
+
+use std::collections::VecDeque;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{error_code::ErrorCode, values::Root};
+
+use super::{
+    load::{ErrorLocation, LoadError, LoaderOptions},
+    push::PushLoader,
+};
+
+/// Size of the reads from the underlying reader
+const READ_SIZE: usize = 8 * 1024;
+
+/// Reads documents from an async reader without blocking, the counterpart of `Loader::load()`. Every read is fed to a
+/// `PushLoader`, which continues the document where the previous read left it, so no input is parsed twice and only
+/// the start of a value that isn't complete yet is buffered. Parsing runs on the calling task one read at a time.
+/// `LoaderOptions::lenient` is ignored like for `PushLoader`.
+pub struct AsyncLoader<'a, R: AsyncRead + Unpin> {
+    reader: &'a mut R,
+    push_loader: PushLoader,
+    /// documents completed by the input read so far that haven't been returned yet
+    completed: VecDeque<Root>,
+    /// the reader returned end of file
+    at_end: bool,
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncLoader<'a, R> {
+    pub fn new(reader: &'a mut R) -> Self {
+        Self::with_options(reader, LoaderOptions::default())
+    }
+
+    pub fn with_options(reader: &'a mut R, options: LoaderOptions) -> Self {
+        Self { reader, push_loader: PushLoader::with_options(options), completed: VecDeque::new(), at_end: false }
+    }
+
+    /// See `PushLoader::with_max_buffered_bytes()`
+    pub fn with_max_buffered_bytes(mut self, max_buffered_bytes: usize) -> Self {
+        self.push_loader = self.push_loader.with_max_buffered_bytes(max_buffered_bytes);
+        self
+    }
+
+    /// Loads the next document, documents written one after another are loaded by successive calls
+    pub async fn load(&mut self) -> Result<Root, LoadError> {
+        loop {
+            if let Some(root) = self.completed.pop_front() {
+                return Ok(root);
+            }
+            if self.at_end {
+                self.push_loader.check_finished()?;
+                return Err(LoadError::IoError(ErrorCode::UnexpectedEnd, "Input ended before a document started".to_string(), ErrorLocation::UNKNOWN));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Returns true once all input has been loaded
+    pub async fn is_at_end(&mut self) -> Result<bool, LoadError> {
+        if self.completed.is_empty() && self.push_loader.is_idle() && !self.at_end {
+            self.fill().await?;
+        }
+        Ok(self.completed.is_empty() && self.push_loader.is_idle() && self.at_end)
+    }
+
+    /// Reads once and feeds the input to the push loader
+    async fn fill(&mut self) -> Result<(), LoadError> {
+        let mut chunk = [0; READ_SIZE];
+        match self.reader.read(&mut chunk).await {
+            Ok(0) => self.at_end = true,
+            Ok(read) => self.completed.extend(self.push_loader.feed(&chunk[..read])?),
+            Err(err) => return Err(LoadError::IoError(ErrorCode::ReadFailed, format!("Could not read data: {}", err), ErrorLocation::UNKNOWN)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::values::RubyValue;
+
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn test_async_loader() {
+        // [1, "a"] and :b, the second one delivered a byte at a time
+        let (mut client, mut server) = tokio::io::duplex(1);
+        let input = b"\x04\x08[\x07i\x06I\"\x06a\x06:\x06ET\x04\x08:\x06b";
+        block_on(async {
+            tokio::spawn(async move { tokio::io::AsyncWriteExt::write_all(&mut client, input).await.unwrap() });
+            let mut loader = AsyncLoader::new(&mut server);
+            let first = loader.load().await.unwrap();
+            assert_eq!(first.get_object(0).unwrap().as_array()[0], RubyValue::FixNum(1));
+            let second = loader.load().await.unwrap();
+            assert_eq!(&**second.get_symbol(second.get_root().as_symbol()).unwrap(), "b");
+            assert!(loader.is_at_end().await.unwrap());
+            assert_eq!(loader.load().await.unwrap_err().code(), ErrorCode::UnexpectedEnd);
+        });
+
+        let mut truncated = &input[..5];
+        assert_eq!(block_on(AsyncLoader::new(&mut truncated).load()).unwrap_err().code(), ErrorCode::UnexpectedEnd);
+        let mut malformed = &b"\x04\x08x"[..];
+        assert_eq!(block_on(AsyncLoader::new(&mut malformed).load()).unwrap_err().code(), ErrorCode::UnknownTag);
+    }
+}
@@ -0,0 +1,111 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+//! Loading from a document that is already in memory without copying the payloads of strings and UserDefined objects,
+//! they are borrowed from the input instead
+
+use crate::values::*;
+
+use super::load::{BorrowedRanges, LoadError, Loader, LoaderOptions};
+
+/// A document whose string and UserDefined payloads are slices of the input it was loaded from, see
+/// `load_from_slice()`
+#[derive(Debug)]
+pub struct BorrowedRoot<'a> {
+    input: &'a [u8],
+    /// the loaded document with empty strings and UserDefined payloads, only handed out through `BorrowedObject`
+    structure: Root,
+    borrowed: BorrowedRanges,
+}
+
+/// An object of a `BorrowedRoot`, strings and UserDefined objects come with their borrowed payload
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BorrowedObject<'r, 'a> {
+    /// the string's own contents are empty, `bytes` holds them
+    String { string: &'r RubyString, bytes: &'a [u8] },
+    /// the object's own data is empty, `data` holds it
+    UserDefined { user_defined: &'r UserDefined, data: &'a [u8] },
+    /// any other object, including UserDefined objects truncated by `LoaderOptions::user_defined_limits`
+    Other(&'r RubyObject),
+}
+
+/// Loads a document from `input` without copying the contents of its strings and UserDefined objects, meant for
+/// large documents that are already in memory. Symbols are copied into the symbol table like by `Loader::load()`.
+pub fn load_from_slice(input: &[u8], options: LoaderOptions) -> Result<BorrowedRoot<'_>, LoadError> {
+    let mut reader = input;
+    let (structure, borrowed) = Loader::with_options(&mut reader, options).load_borrowed()?;
+    Ok(BorrowedRoot { input, structure, borrowed })
+}
+
+impl<'a> BorrowedRoot<'a> {
+    pub fn get_root(&self) -> &RubyValue {
+        self.structure.get_root()
+    }
+
+    pub fn get_symbol(&self, id: SymbolID) -> Option<&SymbolStr> {
+        self.structure.get_symbol(id)
+    }
+
+    pub fn get_symbol_id(&self, symbol: &str) -> Option<SymbolID> {
+        self.structure.get_symbol_id(symbol)
+    }
+
+    pub fn get_object(&self, id: ObjectID) -> Option<BorrowedObject<'_, 'a>> {
+        borrowed_object(self.input, &self.structure, &self.borrowed, id)
+    }
+
+    /// Copies the borrowed payloads into the document, the result equals what `Loader::load()` returns for the input
+    pub fn into_owned(self) -> Root {
+        into_owned(self.input, self.structure, self.borrowed)
+    }
+}
+
+pub(super) fn borrowed_object<'r, 'a>(input: &'a [u8], structure: &'r Root, borrowed: &BorrowedRanges, id: ObjectID) -> Option<BorrowedObject<'r, 'a>> {
+    let object = structure.get_object(id)?;
+    let payload = borrowed.payloads.get(&id).and_then(|range| input.get(range.clone()));
+    Some(match (object, payload) {
+        (RubyObject::String(string), Some(bytes)) => BorrowedObject::String { string, bytes },
+        (RubyObject::UserDefined(user_defined), Some(data)) => BorrowedObject::UserDefined { user_defined, data },
+        (object, _) => BorrowedObject::Other(object),
+    })
+}
+
+pub(super) fn into_owned(input: &[u8], structure: Root, borrowed: BorrowedRanges) -> Root {
+    let mut root = structure;
+    for (id, range) in borrowed.payloads {
+        let payload = input.get(range).unwrap_or_default().to_vec();
+        match root.get_mut_object(id) {
+            Some(RubyObject::String(string)) => string.set_string(payload),
+            Some(RubyObject::UserDefined(user_defined)) => user_defined.set_data(payload),
+            _ => {}
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::load::Loader;
+
+    use super::*;
+
+    #[test]
+    fn test_load_from_slice() {
+        // ["abc", :sym, Point._load("xy"), "abc" with @a=1]
+        let input = b"\x04\x08[\x09\"\x08abc:\x08symu:\x0aPoint\x07xyI\"\x08abc\x06:\x07@ai\x06";
+        let borrowed = load_from_slice(input, LoaderOptions::default()).unwrap();
+        let input_range = input.as_ptr_range();
+
+        let Some(BorrowedObject::String { bytes, .. }) = borrowed.get_object(1) else { panic!("expected a string") };
+        assert_eq!(bytes, b"abc");
+        assert!(input_range.contains(&bytes.as_ptr()));
+        let Some(BorrowedObject::UserDefined { user_defined, data }) = borrowed.get_object(2) else { panic!("expected a user defined object") };
+        assert_eq!(data, b"xy");
+        assert!(user_defined.get_data().is_empty());
+        assert_eq!(borrowed.get_symbol(0).map(|symbol| &**symbol), Some("sym"));
+        assert_eq!(borrowed.get_symbol_id("@a"), Some(2));
+        assert!(matches!(borrowed.get_object(0), Some(BorrowedObject::Other(RubyObject::Array(_)))));
+
+        assert_eq!(borrowed.into_owned(), Loader::new(&mut &input[..]).load().unwrap());
+        assert!(load_from_slice(&input[..input.len() - 1], LoaderOptions::default()).is_err());
+    }
+}
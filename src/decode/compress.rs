@@ -0,0 +1,134 @@
+//! Transparent decompression of gzip and zlib compressed input (rubygems indexes, cached blobs), see
+//! `Loader::new_auto()`
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+
+use super::load::{Loader, LoaderOptions};
+
+/// The compression `AutoDecompress` detected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+/// Reader that decompresses its input if it starts with the magic bytes of gzip or a zlib header, plain Marshal
+/// data (starting with the version `04 08`) is passed through
+pub struct AutoDecompress<R: BufRead> {
+    inner: Inner<R>,
+}
+
+enum Inner<R: BufRead> {
+    Plain(R),
+    Gzip(BufReader<MultiGzDecoder<R>>),
+    Zlib(BufReader<ZlibDecoder<R>>),
+}
+
+impl<R: BufRead> AutoDecompress<R> {
+    /// Looks at the first bytes of `reader` without consuming them
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let inner = match detect(reader.fill_buf()?) {
+            Compression::None => Inner::Plain(reader),
+            Compression::Gzip => Inner::Gzip(BufReader::new(MultiGzDecoder::new(reader))),
+            Compression::Zlib => Inner::Zlib(BufReader::new(ZlibDecoder::new(reader))),
+        };
+        Ok(Self { inner })
+    }
+
+    pub fn compression(&self) -> Compression {
+        match self.inner {
+            Inner::Plain(_) => Compression::None,
+            Inner::Gzip(_) => Compression::Gzip,
+            Inner::Zlib(_) => Compression::Zlib,
+        }
+    }
+}
+
+/// gzip starts with `1f 8b`, zlib with a header naming deflate whose two bytes are a multiple of 31 (`78 9c` and
+/// friends), Marshal data never matches either
+fn detect(start: &[u8]) -> Compression {
+    match start {
+        [0x1f, 0x8b, ..] => Compression::Gzip,
+        [cmf, flg, ..] if cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 => Compression::Zlib,
+        _ => Compression::None,
+    }
+}
+
+impl<R: BufRead> Read for AutoDecompress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Plain(reader) => reader.read(buf),
+            Inner::Gzip(reader) => reader.read(buf),
+            Inner::Zlib(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for AutoDecompress<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match &mut self.inner {
+            Inner::Plain(reader) => reader.fill_buf(),
+            Inner::Gzip(reader) => reader.fill_buf(),
+            Inner::Zlib(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match &mut self.inner {
+            Inner::Plain(reader) => reader.consume(amt),
+            Inner::Gzip(reader) => reader.consume(amt),
+            Inner::Zlib(reader) => reader.consume(amt),
+        }
+    }
+}
+
+impl<'a, R: BufRead> Loader<'a, AutoDecompress<R>> {
+    /// Loads from input that may be gzip or zlib compressed, offsets in errors and `bytes_read()` count
+    /// decompressed bytes
+    pub fn new_auto(reader: &'a mut AutoDecompress<R>) -> Self {
+        Self::with_options_auto(reader, LoaderOptions::default())
+    }
+
+    pub fn with_options_auto(reader: &'a mut AutoDecompress<R>, options: LoaderOptions) -> Self {
+        Self::with_options(reader, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::{GzEncoder, ZlibEncoder}, Compression as Level};
+
+    use crate::values::RubyValue;
+
+    use super::*;
+
+    #[test]
+    fn test_auto_decompress() {
+        // [1, 2]
+        let input = b"\x04\x08[\x07i\x06i\x07";
+        let mut gzip = GzEncoder::new(Vec::new(), Level::default());
+        gzip.write_all(input).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Level::best());
+        zlib.write_all(input).unwrap();
+        let zlib = zlib.finish().unwrap();
+
+        for (bytes, compression) in [(&input[..], Compression::None), (&gzip[..], Compression::Gzip), (&zlib[..], Compression::Zlib)] {
+            let mut reader = AutoDecompress::new(bytes).unwrap();
+            assert_eq!(reader.compression(), compression);
+            let mut loader = Loader::new_auto(&mut reader);
+            let root = loader.load().unwrap();
+            assert_eq!(root.get_object(0).unwrap().as_array(), &vec![RubyValue::FixNum(1), RubyValue::FixNum(2)]);
+            assert_eq!(loader.bytes_read(), input.len() as u64);
+        }
+
+        assert_eq!(detect(b"\x78\x9c"), Compression::Zlib);
+        assert_eq!(detect(b"\x78\x00"), Compression::None);
+        assert_eq!(detect(b"\x04\x08"), Compression::None);
+    }
+}
@@ -1,165 +1,1417 @@
-use std::{fmt::Display, io::{BufRead, Read}};
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
 
-use crate::values::*;
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt::Display, io::{self, BufRead, Read, Seek, SeekFrom}, ops::{ControlFlow, Range}};
+
+use indexmap::IndexMap;
+
+use crate::{
+    diagnostics::{DiagnosticKind, Diagnostics},
+    error_code::ErrorCode,
+    fixnum,
+    spec::{self, *},
+    symbol_table::SymbolTable,
+    text::{DefaultTextDecoder, TextDecoder},
+    values::*,
+};
+
+use super::{progress::ProgressCounter, visitor::LoadVisitor};
+
+mod tokens;
+
+pub use tokens::{Token, TokenReader};
+
+/// Where in the input a `LoadError` happened
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ErrorLocation {
+    /// bytes read from the input when the error was detected
+    pub offset: Option<u64>,
+    /// offset of the type tag of the innermost object (array, hash, string, object, ...) that was being read
+    pub object_offset: Option<u64>,
+}
+
+impl ErrorLocation {
+    /// Location of errors that aren't tied to a position in the input, the loader fills it in when the error passes
+    /// through it
+    pub const UNKNOWN: ErrorLocation = ErrorLocation { offset: None, object_offset: None };
+}
+
+impl Display for ErrorLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        if let Some(object_offset) = self.object_offset {
+            write!(f, " (in the object at offset {})", object_offset)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum LoadError {
-    IoError(String),
-    ParserError(String),
+    IoError(ErrorCode, String, ErrorLocation),
+    ParserError(ErrorCode, String, ErrorLocation),
+    /// values were nested deeper than `LoaderOptions::max_depth`
+    DepthLimitExceeded(usize, ErrorLocation),
+    /// a length in the input exceeds `LoaderOptions::max_item_bytes` or `LoaderOptions::max_total_bytes`
+    AllocationLimitExceeded(String, ErrorLocation),
+    /// a symbol exceeds `LoaderOptions::max_symbol_len` or the document defines more than
+    /// `LoaderOptions::max_symbols` symbols
+    SymbolLimitExceeded(String, ErrorLocation),
+    /// the document defines more than `LoaderOptions::max_objects` objects
+    ObjectLimitExceeded(String, ErrorLocation),
+    /// an object's class isn't in `LoaderOptions::permitted_classes`, holds the class name
+    ClassNotPermitted(String, ErrorLocation),
+    /// a `LoadVisitor` stopped the load, holds its reason
+    Rejected(String, ErrorLocation),
+}
+
+impl LoadError {
+    /// The class of the error, stable across releases unlike the messages
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            LoadError::IoError(code, _, _) | LoadError::ParserError(code, _, _) => *code,
+            LoadError::DepthLimitExceeded(..) => ErrorCode::DepthLimitExceeded,
+            LoadError::AllocationLimitExceeded(..) => ErrorCode::AllocationLimitExceeded,
+            LoadError::SymbolLimitExceeded(..) => ErrorCode::SymbolLimitExceeded,
+            LoadError::ObjectLimitExceeded(..) => ErrorCode::ObjectLimitExceeded,
+            LoadError::ClassNotPermitted(..) => ErrorCode::ClassNotPermitted,
+            LoadError::Rejected(..) => ErrorCode::Rejected,
+        }
+    }
+
+    pub fn location(&self) -> ErrorLocation {
+        match self {
+            LoadError::IoError(_, _, location)
+            | LoadError::ParserError(_, _, location)
+            | LoadError::DepthLimitExceeded(_, location)
+            | LoadError::AllocationLimitExceeded(_, location)
+            | LoadError::SymbolLimitExceeded(_, location)
+            | LoadError::ObjectLimitExceeded(_, location)
+            | LoadError::ClassNotPermitted(_, location)
+            | LoadError::Rejected(_, location) => *location,
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut ErrorLocation {
+        match self {
+            LoadError::IoError(_, _, location)
+            | LoadError::ParserError(_, _, location)
+            | LoadError::DepthLimitExceeded(_, location)
+            | LoadError::AllocationLimitExceeded(_, location)
+            | LoadError::SymbolLimitExceeded(_, location)
+            | LoadError::ObjectLimitExceeded(_, location)
+            | LoadError::ClassNotPermitted(_, location)
+            | LoadError::Rejected(_, location) => location,
+        }
+    }
 }
 
 impl From<std::string::FromUtf8Error> for LoadError {
     fn from(_value: std::string::FromUtf8Error) -> Self {
-        Self::ParserError(format!("Could not decode bytes into a String: {}", _value))
+        Self::ParserError(ErrorCode::BadUtf8, format!("Could not decode bytes into a String: {}", _value), ErrorLocation::UNKNOWN)
     }
 }
 
 impl From<std::num::ParseFloatError> for LoadError {
     fn from(_value: std::num::ParseFloatError) -> Self {
-        Self::ParserError("Could not parse float from sequence".to_string())
+        Self::ParserError(ErrorCode::BadFloat, "Could not parse float from sequence".to_string(), ErrorLocation::UNKNOWN)
     }
 }
 
 impl Display for LoadError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LoadError::ParserError(error) => {
-                f.write_str(&format!("Parser Error: {}", error))
+            LoadError::ParserError(_, error, location) => {
+                f.write_str(&format!("Parser Error: {}{}", error, location))
+            }
+            LoadError::IoError(_, error, location) => {
+                f.write_str(&format!("IO Error: {}{}", error, location))
+            }
+            LoadError::DepthLimitExceeded(max_depth, location) => {
+                f.write_str(&format!("Depth Limit Exceeded: values are nested deeper than {} levels{}", max_depth, location))
+            }
+            LoadError::AllocationLimitExceeded(error, location) => {
+                f.write_str(&format!("Allocation Limit Exceeded: {}{}", error, location))
+            }
+            LoadError::SymbolLimitExceeded(error, location) => {
+                f.write_str(&format!("Symbol Limit Exceeded: {}{}", error, location))
+            }
+            LoadError::ObjectLimitExceeded(error, location) => {
+                f.write_str(&format!("Object Limit Exceeded: {}{}", error, location))
+            }
+            LoadError::ClassNotPermitted(class_name, location) => {
+                f.write_str(&format!("Class Not Permitted: {}{}", class_name, location))
+            }
+            LoadError::Rejected(reason, location) => {
+                f.write_str(&format!("Rejected: {}{}", reason, location))
+            }
+        }
+    }
+}
+
+/// How much of a UserDefined payload is kept, the rest is skipped without being stored
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PayloadLimit {
+    /// no data is kept
+    Skip,
+    /// at most this many bytes are kept
+    Truncate(usize),
+}
+
+/// What happens when a hash contains the same key twice, keys are compared as values: immediates and symbols by value,
+/// objects by identity (two equal strings are different keys)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DuplicateKeys {
+    /// the later value replaces the earlier one, which keeps its position, like Ruby does
+    #[default]
+    LastWins,
+    FirstWins,
+    /// a `LoadError::ParserError`, in lenient mode the first value is kept
+    Error,
+    /// like `LastWins`, the replaced pairs are kept in the Root (see `Root::get_duplicate_hash_pairs()`)
+    CollectAll,
+}
+
+/// Options of a `Loader`, either set the fields or chain the `with_*` methods starting from `LoaderOptions::default()`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LoaderOptions {
+    /// hashes with a `nil` default behave like plain hashes, they are loaded as `RubyValue::Hash`
+    pub collapse_nil_default_hashes: bool,
+    /// limits for the data of UserDefined objects by class name, objects over the limit are loaded as
+    /// truncated placeholders (see `UserDefined::is_truncated()`)
+    pub user_defined_limits: HashMap<String, PayloadLimit>,
+    /// deepest nesting of values (the root is at depth 1). Loading doesn't recurse, but dumping, comparing and printing
+    /// values do, so a crafted input with deeply nested containers would overflow the stack there. Defaults to
+    /// `spec::DEFAULT_MAX_DEPTH`.
+    pub max_depth: usize,
+    /// most bytes a single byte sequence (string, symbol, bignum, user defined data, ...) or the elements of a single
+    /// array, hash or object may take, elements count `size_of::<RubyValue>()` bytes each (pairs twice that)
+    pub max_item_bytes: usize,
+    /// most bytes all byte sequences and elements of one document may take together, counted like `max_item_bytes`
+    pub max_total_bytes: usize,
+    /// longest symbol in bytes, symbols are kept for the whole load so this bounds what a flood of distinct symbols
+    /// costs together with `max_symbols`
+    pub max_symbol_len: usize,
+    /// most symbols one document may define, links to symbols don't count
+    pub max_symbols: usize,
+    /// most objects one document may define (every value object links can refer to, including floats, bignums and
+    /// strings), links to objects don't count. Bounds the memory a flood of tiny objects takes, which the byte limits
+    /// only do loosely.
+    pub max_objects: usize,
+    /// every `I` becomes a `RubyValue::WithIvars` wrapper, otherwise the instance variables of strings, regexps,
+    /// structs, user classes and user defined objects are stored on the object and only other values (like arrays and
+    /// hashes) are wrapped
+    pub ivar_wrappers: bool,
+    /// `load()` salvages what it can from corrupted input like `Loader::load_lenient()` does, the errors are available
+    /// from `Loader::recovered_errors()`
+    pub lenient: bool,
+    pub duplicate_hash_keys: DuplicateKeys,
+    /// classes objects may have (objects, structs, user classes, user defined, user marshal and data objects), all
+    /// other classes fail with `LoadError::ClassNotPermitted`. `None` permits every class. A lenient load reads the
+    /// objects of other classes and leaves `RubyObject::Unloaded` placeholders in their place.
+    pub permitted_classes: Option<HashSet<String>>,
+    /// accepts the Marshal versions 4.0 to 4.7 besides 4.8, as written by Ruby 1.6 and 1.7 and kept by old RPG Maker
+    /// games. Ruby reads them with a warning, their old `M` tag for classes and modules is read like in 4.8 documents.
+    pub legacy_versions: bool,
+    /// symbols are interned in this table, so equal symbols of all documents loaded with it share their text
+    pub symbol_table: Option<SymbolTable>,
+    /// the order of the instance variables of objects and of struct members is recorded in the Root (see
+    /// `Root::get_member_order()`), for dumping with `SymbolOrder::Recorded`
+    pub record_member_order: bool,
+    /// the loader keeps this counter up to date with the bytes it read, for progress displays on other threads
+    pub progress: Option<ProgressCounter>,
+}
+
+impl Default for LoaderOptions {
+    fn default() -> Self {
+        Self {
+            collapse_nil_default_hashes: false,
+            user_defined_limits: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_item_bytes: usize::MAX,
+            max_total_bytes: usize::MAX,
+            max_symbol_len: usize::MAX,
+            max_symbols: usize::MAX,
+            max_objects: usize::MAX,
+            ivar_wrappers: false,
+            lenient: false,
+            duplicate_hash_keys: DuplicateKeys::LastWins,
+            permitted_classes: None,
+            legacy_versions: false,
+            symbol_table: None,
+            record_member_order: false,
+            progress: None,
+        }
+    }
+}
+
+impl LoaderOptions {
+    pub fn with_collapse_nil_default_hashes(mut self, collapse_nil_default_hashes: bool) -> Self {
+        self.collapse_nil_default_hashes = collapse_nil_default_hashes;
+        self
+    }
+
+    pub fn with_user_defined_limit(mut self, class_name: &str, limit: PayloadLimit) -> Self {
+        self.user_defined_limits.insert(class_name.to_string(), limit);
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_item_bytes(mut self, max_item_bytes: usize) -> Self {
+        self.max_item_bytes = max_item_bytes;
+        self
+    }
+
+    pub fn with_max_total_bytes(mut self, max_total_bytes: usize) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    pub fn with_max_symbol_len(mut self, max_symbol_len: usize) -> Self {
+        self.max_symbol_len = max_symbol_len;
+        self
+    }
+
+    pub fn with_max_symbols(mut self, max_symbols: usize) -> Self {
+        self.max_symbols = max_symbols;
+        self
+    }
+
+    pub fn with_max_objects(mut self, max_objects: usize) -> Self {
+        self.max_objects = max_objects;
+        self
+    }
+
+    pub fn with_ivar_wrappers(mut self, ivar_wrappers: bool) -> Self {
+        self.ivar_wrappers = ivar_wrappers;
+        self
+    }
+
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    pub fn with_duplicate_hash_keys(mut self, duplicate_hash_keys: DuplicateKeys) -> Self {
+        self.duplicate_hash_keys = duplicate_hash_keys;
+        self
+    }
+
+    /// Permits the classes in addition to the ones permitted before, see `permitted_classes`
+    pub fn with_permitted_classes<'a>(mut self, class_names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.permitted_classes.get_or_insert_with(HashSet::new).extend(class_names.into_iter().map(str::to_string));
+        self
+    }
+
+    pub fn with_legacy_versions(mut self, legacy_versions: bool) -> Self {
+        self.legacy_versions = legacy_versions;
+        self
+    }
+
+    pub fn with_symbol_table(mut self, symbol_table: &SymbolTable) -> Self {
+        self.symbol_table = Some(symbol_table.clone());
+        self
+    }
+
+    pub fn with_record_member_order(mut self, record_member_order: bool) -> Self {
+        self.record_member_order = record_member_order;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: &ProgressCounter) -> Self {
+        self.progress = Some(progress.clone());
+        self
+    }
+}
+
+/// Most elements reserved up front for a collection, larger ones grow as their elements are read, so a length in the
+/// input doesn't allocate memory before the input proves to contain the data
+const MAX_PREALLOCATED_ELEMENTS: usize = 1 << 12;
+
+/// Most symbol and object table entries a Loader keeps reserved between loads
+const MAX_RETAINED_ELEMENTS: usize = 1 << 16;
+
+/// Symbols and objects a document defines before some position, needed to load a value in the middle of the document
+/// (see `Loader::load_object_at()`) since its symbol and object links refer to them
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LoadContext {
+    /// symbols defined before the position, in stream order
+    pub symbols: Vec<SymbolStr>,
+    /// kinds of the objects defined before the position, in stream order
+    pub object_kinds: Vec<ObjectKind>,
+}
+
+impl LoadContext {
+    /// Context of the position in the document `root` was loaded from where its symbol `symbol_count` and object
+    /// `object_count` are defined next, like an index recorded during an earlier full load
+    pub fn from_root(root: &Root, symbol_count: usize, object_count: usize) -> Self {
+        Self {
+            symbols: root.get_symbols().iter().take(symbol_count).cloned().collect(),
+            object_kinds: root.get_objects().iter().take(object_count).map(RubyObject::kind).collect(),
+        }
+    }
+}
+
+/// Byte ranges of the data a borrowing load left in the input instead of copying it, see `load_from_slice()`
+#[derive(Debug, Default)]
+pub(crate) struct BorrowedRanges {
+    /// contents of strings and data of UserDefined objects, by object id
+    pub(crate) payloads: HashMap<ObjectID, Range<usize>>,
+}
+
+/// A value whose parts are still being read, `Loader::read_value()` keeps these on a stack instead of recursing
+struct Frame {
+    /// offset of the value's tag
+    offset: u64,
+    state: FrameState,
+}
+
+enum FrameState {
+    Array { id: ObjectID, remaining: usize, elements: Vec<RubyValue> },
+    Hash { id: ObjectID, pairs: PendingPairs<RubyValue>, with_default: bool },
+    HashDefault { id: ObjectID, hash: ValuePairs },
+    Struct { id: ObjectID, name: SymbolID, members: PendingPairs<SymbolID> },
+    Object { id: ObjectID, class_name: SymbolID, instance_variables: PendingPairs<SymbolID> },
+    UserClass { id: ObjectID, name: SymbolID },
+    UserMarshal { id: ObjectID, class_name: SymbolID },
+    Data { id: ObjectID, class_name: SymbolID },
+    /// `I` followed by `u`, the object is registered once its instance variables have been read
+    UserDefined { user_defined: Box<UserDefined>, range: Option<Range<usize>>, instance_variables: PendingPairs<SymbolID>, extension: Extension },
+    /// `I` wrapping any other value, the instance variables are read after the value
+    InstanceVariables { extension: Extension },
+    /// the instance variables of an `I` after its value, `read_value()` reads their number before them
+    InstanceVariablePairs { value: RubyValue, first_new_object: ObjectID, instance_variables: Option<PendingPairs<SymbolID>> },
+    Extended { extension: Extension },
+}
+
+/// Sizes of everything `Loader::start_value()` adds to, a resumable read that runs out of input goes back to them. The
+/// tag histogram isn't restored, `PushLoader` doesn't expose it.
+#[derive(Clone, Copy)]
+struct Checkpoint {
+    position: u64,
+    symbols: usize,
+    objects: usize,
+    linked_incomplete_hashes: usize,
+    diagnostics: usize,
+    allocated_bytes: usize,
+    visits: usize,
+}
+
+/// Callbacks of the `LoadVisitor` the current document reached. A resumable read that runs out of input goes back
+/// and reads the same values again, the callbacks that were made before aren't made a second time.
+#[derive(Clone, Copy, Default)]
+struct Visits {
+    /// callbacks reached by the reads that weren't rolled back
+    reached: usize,
+    /// callbacks made, more than `reached` after a rollback
+    made: usize,
+}
+
+impl Visits {
+    /// Counts a callback that is reached, returns whether it has to be made
+    fn next(&mut self) -> bool {
+        self.reached += 1;
+        if self.reached <= self.made {
+            return false;
+        }
+        self.made = self.reached;
+        true
+    }
+}
+
+/// A document whose input ran out, `Loader::load_resumable()` continues it once more input arrived
+pub(crate) struct Suspended {
+    /// the version header has been read
+    started: bool,
+    frames: Vec<Frame>,
+    symbols: Vec<SymbolStr>,
+    encoded_symbols: BTreeMap<SymbolID, EncodedSymbol>,
+    objects: Vec<RubyObject>,
+    linked_incomplete_hashes: Vec<ObjectID>,
+    depth: usize,
+    allocated_bytes: usize,
+    position: u64,
+    /// position the input has to reach for the read that ran out of input to succeed, if it is known
+    input_needed: u64,
+    diagnostics: Diagnostics,
+    member_orders: HashMap<ObjectID, Vec<SymbolID>>,
+    duplicate_hash_pairs: HashMap<ObjectID, Vec<(RubyValue, RubyValue)>>,
+    visits: Visits,
+}
+
+impl Suspended {
+    /// Offset of the first byte that hasn't been read, the input continues from there
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Bytes that have to follow `position()` before reading again can make progress, at least one
+    pub(crate) fn bytes_needed(&self) -> u64 {
+        self.input_needed.saturating_sub(self.position).max(1)
+    }
+}
+
+/// Result of `Loader::load_resumable()`
+pub(crate) enum ResumableLoad {
+    Complete(Root),
+    Suspended(Suspended),
+}
+
+/// The `e` prefixes in front of a value and the first object id the value can have, see `Loader::extend_object()`
+struct Extension {
+    modules: Vec<SymbolID>,
+    first_new_object: ObjectID,
+}
+
+/// Result of reading a tag or of giving a frame the value it waited for
+enum Step {
+    Value(RubyValue),
+    /// the value needs more values to be read
+    Frame(FrameState),
+}
+
+/// Key:value pairs being read, keys and values are read alternately
+struct PendingPairs<K> {
+    remaining: usize,
+    key: Option<RubyValue>,
+    pairs: IndexMap<K, RubyValue>,
+}
+
+impl<K> PendingPairs<K> {
+    /// Takes the next key or value, returns the pair once its value arrived. After an error that lost the position in
+    /// the stream no more pairs are read, a key read then is dropped.
+    fn accept(&mut self, value: RubyValue, salvaging: bool) -> Option<(RubyValue, RubyValue)> {
+        match self.key.take() {
+            Some(key) => {
+                self.remaining -= 1;
+                Some((key, value))
             }
-            LoadError::IoError(error) => {
-                f.write_str(&format!("IO Error: {}", error))
+            None if salvaging => {
+                self.remaining = 0;
+                None
+            }
+            None => {
+                self.key = Some(value);
+                None
             }
         }
     }
+
+    fn is_done(&self, salvaging: bool) -> bool {
+        self.key.is_none() && (self.remaining == 0 || salvaging)
+    }
+}
+
+/// How much of the input a document took up, see `Loader::load_with_extent()`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LoadExtent {
+    /// bytes of the document including its version header
+    pub consumed: u64,
+    /// input remained after the document, like another document or the rest of an overwritten file
+    pub trailing_data: bool,
+}
+
+/// Number of occurrences of every type tag (`[`, `{`, `u`, ...) read by a `Loader`, see `Loader::tag_histogram()`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TagHistogram {
+    counts: [usize; 256],
+}
+
+impl Default for TagHistogram {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl TagHistogram {
+    pub fn get(&self, tag: u8) -> usize {
+        self.counts[usize::from(tag)]
+    }
+
+    /// Tags that occurred at least once with their counts, in ascending order of the tag byte
+    pub fn iter(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        (0..=u8::MAX).map(|tag| (tag, self.get(tag))).filter(|(_, count)| *count > 0)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    fn record(&mut self, tag: u8) {
+        self.counts[usize::from(tag)] += 1;
+    }
 }
 
 pub struct Loader<'a, T: Read> {
     reader: &'a mut T,
-    symbols: Vec<String>,
+    symbols: Vec<SymbolStr>,
+    /// symbols that aren't UTF-8 or were written with their encoding
+    encoded_symbols: BTreeMap<SymbolID, EncodedSymbol>,
     objects: Vec<RubyObject>,
+    /// hashes with default that were linked to before they were complete, those links can't be changed afterwards
+    linked_incomplete_hashes: Vec<ObjectID>,
+    tags: TagHistogram,
+    /// number of values currently being read
+    depth: usize,
+    /// bytes accounted for `LoaderOptions::max_total_bytes` by the current load
+    allocated_bytes: usize,
+    /// bytes read from the reader so far, the offset errors refer to
+    position: u64,
+    /// position the input has to reach for the last read that ran out of input to succeed, see `Suspended`
+    input_needed: u64,
+    /// position last stored in `LoaderOptions::progress`
+    reported_position: u64,
+    /// set while loading for `load_from_slice()`, string and UserDefined payloads are skipped and their ranges recorded
+    borrowed: Option<BorrowedRanges>,
+    /// errors a lenient load recovered from
+    recovered: Vec<LoadError>,
+    /// oddities of the current document, moved into its Root
+    diagnostics: Diagnostics,
+    /// see `LoaderOptions::record_member_order`
+    member_orders: HashMap<ObjectID, Vec<SymbolID>>,
+    /// see `DuplicateKeys::CollectAll`
+    duplicate_hash_pairs: HashMap<ObjectID, Vec<(RubyValue, RubyValue)>>,
+    /// set by a lenient load after an error that loses the position in the stream, no more values are read
+    salvaging: bool,
+    /// see `Loader::with_visitor()`
+    visitor: Option<&'a mut dyn LoadVisitor>,
+    /// callbacks of the visitor the current document reached and made
+    visits: Visits,
+    /// see `Loader::with_text_decoder()`
+    text_decoder: &'a dyn TextDecoder,
+    options: LoaderOptions,
+}
+
+impl<T: BufRead + Seek> Loader<'_, T> {
+    /// Loads the value starting at byte `offset` of the stream without reading the part before it, `context` supplies
+    /// the symbols and objects defined before `offset`. Objects get the ids they have in a full load of the document,
+    /// objects before `offset` are `RubyObject::Unloaded` placeholders, so links to them keep their type.
+    pub fn load_object_at(&mut self, offset: u64, context: &LoadContext) -> Result<Root, LoadError> {
+        self.reset();
+        if let Err(err) = self.reader.seek(SeekFrom::Start(offset)) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to seek to offset {}: {}", offset, err), ErrorLocation::UNKNOWN));
+        }
+        self.position = offset;
+        self.symbols.extend(context.symbols.iter().cloned());
+        self.objects.extend(context.object_kinds.iter().map(|kind| RubyObject::Unloaded(*kind)));
+        self.read_root()
+    }
+}
+
+/// Iterator over the documents of a stream, see `Loader::load_iter()`
+pub struct Documents<'l, 'a, T: Read> {
+    loader: &'l mut Loader<'a, T>,
+    failed: bool,
+}
+
+impl<T: BufRead> Iterator for Documents<'_, '_, T> {
+    type Item = Result<Root, LoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let document = match self.loader.is_at_end() {
+            Ok(true) => return None,
+            Ok(false) => self.loader.load(),
+            Err(err) => Err(err),
+        };
+        self.failed = document.is_err();
+        Some(document)
+    }
 }
 
 impl<'a, T: BufRead> Loader<'a, T> {
     pub fn new(reader: &'a mut T) -> Self {
+        Self::with_options(reader, LoaderOptions::default())
+    }
+
+    pub fn with_options(reader: &'a mut T, options: LoaderOptions) -> Self {
         Self {
             reader,
             symbols: Vec::new(),
+            encoded_symbols: BTreeMap::new(),
             objects: Vec::new(),
+            linked_incomplete_hashes: Vec::new(),
+            tags: TagHistogram::default(),
+            depth: 0,
+            allocated_bytes: 0,
+            position: 0,
+            input_needed: 0,
+            reported_position: 0,
+            borrowed: None,
+            recovered: Vec::new(),
+            diagnostics: Diagnostics::new(),
+            member_orders: HashMap::new(),
+            duplicate_hash_pairs: HashMap::new(),
+            salvaging: false,
+            visitor: None,
+            visits: Visits::default(),
+            text_decoder: &DefaultTextDecoder,
+            options,
         }
     }
 
+    /// Calls `visitor` as values are decoded, see `LoadVisitor`
+    pub fn with_visitor(mut self, visitor: &'a mut dyn LoadVisitor) -> Self {
+        self.visitor = Some(visitor);
+        self
+    }
+
+    /// Decodes symbols that aren't UTF-8 with `text_decoder` instead of `DefaultTextDecoder`, strings named to be in
+    /// an encoding it doesn't support get a `DiagnosticKind::UnknownEncoding`
+    pub fn with_text_decoder(mut self, text_decoder: &'a dyn TextDecoder) -> Self {
+        self.text_decoder = text_decoder;
+        self
+    }
+
     fn reset(&mut self) {
+        // the tables keep their capacity for the next document, up to MAX_RETAINED_ELEMENTS entries
         self.symbols.clear();
+        self.symbols.shrink_to(MAX_RETAINED_ELEMENTS);
+        self.encoded_symbols.clear();
         self.objects.clear();
+        self.objects.shrink_to(MAX_RETAINED_ELEMENTS);
+        self.linked_incomplete_hashes.clear();
+        self.tags = TagHistogram::default();
+        self.depth = 0;
+        self.allocated_bytes = 0;
+        self.salvaging = false;
+        self.recovered.clear();
+        self.diagnostics.clear();
+        self.member_orders.clear();
+        self.duplicate_hash_pairs.clear();
+        self.visits = Visits::default();
+    }
+
+    /// Accounts for `bytes` about to be allocated for `item`, fails if that exceeds the limits in `LoaderOptions`
+    fn allocate(&mut self, item: &str, bytes: usize) -> Result<(), LoadError> {
+        if bytes > self.options.max_item_bytes {
+            return Err(LoadError::AllocationLimitExceeded(format!(
+                "{} of {} bytes exceeds the limit of {} bytes per item", item, bytes, self.options.max_item_bytes
+            ), ErrorLocation::UNKNOWN));
+        }
+        self.allocated_bytes = self.allocated_bytes.saturating_add(bytes);
+        if self.allocated_bytes > self.options.max_total_bytes {
+            return Err(LoadError::AllocationLimitExceeded(format!(
+                "{} of {} bytes exceeds the limit of {} bytes per document", item, bytes, self.options.max_total_bytes
+            ), ErrorLocation::UNKNOWN));
+        }
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buffer)?;
+        self.advance_position(buffer.len() as u64);
+        Ok(())
+    }
+
+    /// Skips `len` bytes without copying them anywhere
+    fn skip_bytes(&mut self, item: &str, len: usize) -> Result<(), LoadError> {
+        let mut remaining = len;
+        while remaining > 0 {
+            let available = match self.reader.fill_buf() {
+                Ok(buffer) => buffer.len().min(remaining),
+                Err(err) => return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read {}: {}, was expecting {} bytes", item, err, len), ErrorLocation::UNKNOWN)),
+            };
+            if available == 0 {
+                return Err(LoadError::IoError(ErrorCode::UnexpectedEnd, format!("Failed to read {}: unexpected end of input, was expecting {} bytes", item, len), ErrorLocation::UNKNOWN));
+            }
+            self.reader.consume(available);
+            self.advance_position(available as u64);
+            remaining -= available;
+        }
+        Ok(())
+    }
+
+    fn consume_byte(&mut self) {
+        self.reader.consume(1);
+        self.advance_position(1);
+    }
+
+    fn advance_position(&mut self, bytes: u64) {
+        self.position += bytes;
+        if self.position.abs_diff(self.reported_position) >= ProgressCounter::INTERVAL {
+            self.report_progress();
+        }
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(progress) = &self.options.progress {
+            progress.set(self.position);
+        }
+        self.reported_position = self.position;
+    }
+
+    /// Bytes read from the reader so far, counted from the start of the stream (or from the offset passed to
+    /// `load_object_at()`), see `LoaderOptions::progress` for reading it from another thread
+    pub fn bytes_read(&self) -> u64 {
+        self.position
+    }
+
+    fn allocate_elements(&mut self, item: &str, len: usize, values_per_element: usize) -> Result<(), LoadError> {
+        self.allocate(item, len.saturating_mul(values_per_element * std::mem::size_of::<RubyValue>()))
+    }
+
+    /// Reads exactly `len` bytes, the buffer grows with the data that is actually there
+    fn read_bytes(&mut self, item: &str, len: usize) -> Result<Vec<u8>, LoadError> {
+        self.allocate(item, len)?;
+        let mut buffer = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS * std::mem::size_of::<RubyValue>()));
+        self.read_bytes_into(item, len, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Appends exactly `len` bytes to `buffer` without accounting for them
+    fn read_bytes_into(&mut self, item: &str, len: usize, buffer: &mut Vec<u8>) -> Result<(), LoadError> {
+        let read = Read::take(&mut *self.reader, len as u64).read_to_end(buffer);
+        if let Ok(read) = read {
+            self.advance_position(read as u64);
+        }
+        match read {
+            Ok(read) if read == len => Ok(()),
+            Ok(read) => {
+                self.input_needed = self.position + (len - read) as u64;
+                Err(LoadError::IoError(ErrorCode::UnexpectedEnd, format!("Failed to read {}: unexpected end of input, was expecting {} bytes", item, len), ErrorLocation::UNKNOWN))
+            }
+            Err(err) => Err(LoadError::IoError(read_error_code(&err), format!("Failed to read {}: {}, was expecting {} bytes", item, err, len), ErrorLocation::UNKNOWN)),
+        }
+    }
+
+    /// Type tags read by the last load, including the tag that made it fail (e.g. an unsupported type), meant for
+    /// telemetry about what kind of data the inputs contain
+    pub fn tag_histogram(&self) -> &TagHistogram {
+        &self.tags
     }
 
     pub fn load(&mut self) -> Result<Root, LoadError> {
         self.reset();
+        self.read_version()?;
+        self.read_root()
+    }
 
+    fn read_version(&mut self) -> Result<(), LoadError> {
         let mut buffer: [u8; 2] = [0; 2];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read Marshal version: {}", err)));
+        if let Err(err) = self.read_exact(&mut buffer) {
+            return Err(self.locate(LoadError::IoError(read_error_code(&err), format!("Failed to read Marshal version: {}", err), ErrorLocation::UNKNOWN), None));
         }
 
-        if buffer[0] > MARSHAL_MAJOR_VERSION || buffer[1] > MARSHAL_MINOR_VERSION {
-            return Err(LoadError::ParserError("Unsupported Marshal version".to_string()));
+        let supported = match buffer[1] {
+            MARSHAL_MINOR_VERSION => true,
+            minor => self.options.legacy_versions && minor < MARSHAL_MINOR_VERSION,
+        };
+        if buffer[0] != MARSHAL_MAJOR_VERSION || !supported {
+            let message = format!("Unsupported Marshal version {}.{}", buffer[0], buffer[1]);
+            return Err(self.locate(LoadError::ParserError(ErrorCode::UnsupportedVersion, message, ErrorLocation::UNKNOWN), None));
         }
+        Ok(())
+    }
+
+    /// Loads a document and reports how many bytes it took up and whether input remained after it. A file holding
+    /// more than one document (concatenated writes) has trailing data, a truncated one fails to load with
+    /// `ErrorCode::UnexpectedEnd`. Checking for trailing data waits for the reader to have more input or to end.
+    pub fn load_with_extent(&mut self) -> Result<(Root, LoadExtent), LoadError> {
+        let start = self.position;
+        let root = self.load()?;
+        let consumed = self.position - start;
+        let trailing_data = !self.is_at_end()?;
+        Ok((root, LoadExtent { consumed, trailing_data }))
+    }
 
-        let root = self.read_value()?;
+    /// Loads a document leaving string and UserDefined payloads in the input, the Root has empty payloads in their place
+    pub(crate) fn load_borrowed(&mut self) -> Result<(Root, BorrowedRanges), LoadError> {
+        self.borrowed = Some(BorrowedRanges::default());
+        let root = self.load();
+        let borrowed = self.borrowed.take().unwrap_or_default();
+        Ok((root?, borrowed))
+    }
 
-        Ok(Root::new(root, self.symbols.clone(), self.objects.clone()))
+    /// Loads a possibly corrupted document, returning the structure that could be salvaged along with the errors.
+    /// A value that fails to parse becomes `RubyValue::Nil` and the containers around it keep the values read before it.
+    /// Loading continues after dangling links, non-symbol instance variable names and classes that aren't permitted
+    /// (their objects become `RubyObject::Unloaded`), any other error loses the
+    /// position in the stream, so the rest of the document is left out and objects that were still being read become
+    /// `RubyObject::Unloaded`. Only errors in the version header are returned as `Err`.
+    pub fn load_lenient(&mut self) -> Result<(Root, Vec<LoadError>), LoadError> {
+        let lenient = std::mem::replace(&mut self.options.lenient, true);
+        let root = self.load();
+        self.options.lenient = lenient;
+        Ok((root?, std::mem::take(&mut self.recovered)))
     }
 
-    fn read_value(&mut self) -> Result<RubyValue, LoadError> {
-        let mut buffer: [u8; 1] = [0; 1];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read value type: {}", err)));
-        }
-
-        let value = match buffer[0] {
-            b'0' => RubyValue::Nil,
-            b'T' => RubyValue::Boolean(true),
-            b'F' => RubyValue::Boolean(false),
-            b'i' => RubyValue::FixNum(self.read_fixnum()?),
-            b':' => RubyValue::Symbol(self.read_symbol()?),
-            b';' => RubyValue::Symbol(self.read_symbol_link()?),
-            b'[' => RubyValue::Array(self.read_array()?),
-            b'f' => RubyValue::Float(self.read_float()?),
-            b'@' => self.read_object_link()?,
-            b'{' => RubyValue::Hash(self.read_hash()?),
-            b'}' => RubyValue::HashWithDefault(self.read_hash_with_default()?),
-            b'c' => RubyValue::Class(self.read_class()?),
-            b'm' => RubyValue::Module(self.read_module()?),
-            b'M' => RubyValue::ClassOrModule(self.read_class_or_module()?),
-            b'"' => RubyValue::String(self.read_string()?),
-            b'I' => self.read_value_with_instance_variables()?,
-            b'l' => RubyValue::BigNum(self.read_bignum()?),
-            b'/' => RubyValue::RegExp(self.read_regexp()?),
-            b'S' => RubyValue::Struct(self.read_struct()?),
-            b'o' => RubyValue::Object(self.read_object()?),
-            b'C' => RubyValue::UserClass(self.read_user_class()?),
-            b'u' => RubyValue::UserDefined(self.read_user_defined()?),
-            b'U' => RubyValue::UserMarshal(self.read_user_marshal()?),
-            b'd' => return Err(LoadError::ParserError("This parser doesn't support Data objects".to_string())),
-            _ => return Err(LoadError::ParserError(format!("Unknown value type: {}", buffer[0]))),
+    /// Errors the last load recovered from with `LoaderOptions::lenient`, see `load_lenient()`. Unlike the warnings in
+    /// `Root::get_diagnostics()` about input that loaded as written, each of these replaced part of the document.
+    pub fn recovered_errors(&self) -> &[LoadError] {
+        &self.recovered
+    }
+
+    /// Loads a single value that isn't preceded by the version header, like a value cut out of a larger stream
+    pub fn load_fragment(&mut self) -> Result<Root, LoadError> {
+        self.reset();
+        self.read_root()
+    }
+
+    /// Loads a document from input that may end before the document does, like the bytes received so far. When the
+    /// input runs out the document is suspended at the start of the value that couldn't be read, passing it to the
+    /// next call continues it there with the input that follows. Meant for `PushLoader`, `LoaderOptions::lenient` has
+    /// to be off.
+    pub(crate) fn load_resumable(&mut self, suspended: Option<Suspended>) -> Result<ResumableLoad, LoadError> {
+        let (started, mut frames) = match suspended {
+            Some(suspended) => self.resume(suspended),
+            None => {
+                self.reset();
+                self.position = 0;
+                (false, Vec::new())
+            }
         };
+        self.input_needed = 0;
+        if !started {
+            let start = self.position;
+            match self.read_version() {
+                Ok(()) => (),
+                Err(err) if err.code() == ErrorCode::UnexpectedEnd => {
+                    self.position = start;
+                    return Ok(ResumableLoad::Suspended(self.suspend(false, frames)));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match self.read_value(&mut frames, true) {
+            Ok(root) => Ok(ResumableLoad::Complete(self.finish_root(root))),
+            // read_value() went back to the start of the value that ran out of input
+            Err(err) if err.code() == ErrorCode::UnexpectedEnd => Ok(ResumableLoad::Suspended(self.suspend(true, frames))),
+            Err(err) => Err(err),
+        }
+    }
 
-        Ok(value)
+    /// Moves the state of the document being read out of the loader
+    fn suspend(&mut self, started: bool, frames: Vec<Frame>) -> Suspended {
+        Suspended {
+            started,
+            frames,
+            symbols: std::mem::take(&mut self.symbols),
+            encoded_symbols: std::mem::take(&mut self.encoded_symbols),
+            objects: std::mem::take(&mut self.objects),
+            linked_incomplete_hashes: std::mem::take(&mut self.linked_incomplete_hashes),
+            depth: self.depth,
+            allocated_bytes: self.allocated_bytes,
+            position: self.position,
+            input_needed: self.input_needed,
+            diagnostics: std::mem::take(&mut self.diagnostics),
+            member_orders: std::mem::take(&mut self.member_orders),
+            duplicate_hash_pairs: std::mem::take(&mut self.duplicate_hash_pairs),
+            visits: self.visits,
+        }
     }
 
-    fn read_fixnum(&mut self) -> Result<i32, LoadError> {
-        let mut buffer: [u8; 1] = [0; 1];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read fixnum's first byte: {}", err)));
+    /// Moves the state of a suspended document back into the loader, returns whether its version header has been read
+    /// and the frames of the values being read
+    fn resume(&mut self, suspended: Suspended) -> (bool, Vec<Frame>) {
+        self.symbols = suspended.symbols;
+        self.encoded_symbols = suspended.encoded_symbols;
+        self.objects = suspended.objects;
+        self.linked_incomplete_hashes = suspended.linked_incomplete_hashes;
+        self.depth = suspended.depth;
+        self.allocated_bytes = suspended.allocated_bytes;
+        self.position = suspended.position;
+        self.reported_position = suspended.position;
+        self.diagnostics = suspended.diagnostics;
+        self.member_orders = suspended.member_orders;
+        self.duplicate_hash_pairs = suspended.duplicate_hash_pairs;
+        self.visits = suspended.visits;
+        self.salvaging = false;
+        (suspended.started, suspended.frames)
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+            symbols: self.symbols.len(),
+            objects: self.objects.len(),
+            linked_incomplete_hashes: self.linked_incomplete_hashes.len(),
+            diagnostics: self.diagnostics.len(),
+            allocated_bytes: self.allocated_bytes,
+            visits: self.visits.reached,
+        }
+    }
+
+    /// Drops everything read since `checkpoint`
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.position;
+        self.symbols.truncate(checkpoint.symbols);
+        self.encoded_symbols.split_off(&checkpoint.symbols);
+        self.objects.truncate(checkpoint.objects);
+        self.linked_incomplete_hashes.truncate(checkpoint.linked_incomplete_hashes);
+        self.diagnostics.truncate(checkpoint.diagnostics);
+        self.allocated_bytes = checkpoint.allocated_bytes;
+        self.visits.reached = checkpoint.visits;
+    }
+
+    /// Returns the loader's options, for reusing them without cloning
+    pub(crate) fn into_options(self) -> LoaderOptions {
+        self.options
+    }
+
+    fn read_root(&mut self) -> Result<Root, LoadError> {
+        let root = self.read_value(&mut Vec::new(), false)?;
+        Ok(self.finish_root(root))
+    }
+
+    /// Copies the tables into a Root around the root value
+    fn finish_root(&mut self, root: RubyValue) -> Root {
+        if self.salvaging {
+            for object in self.objects.iter_mut() {
+                if let RubyObject::Incomplete(_) = object {
+                    *object = RubyObject::Unloaded(object.kind());
+                }
+            }
+        }
+        if self.options.lenient {
+            self.replace_unpermitted_objects();
+        }
+
+        // the entries are moved into tables of exactly their size, the loader's tables keep their capacity (see `reset()`)
+        let symbols = self.symbols.drain(..).collect();
+        let objects = self.objects.drain(..).collect();
+        let mut root = Root::new(root, symbols, objects);
+        for (symbol_id, encoded_symbol) in std::mem::take(&mut self.encoded_symbols) {
+            root.set_encoded_symbol(symbol_id, encoded_symbol);
+        }
+        root.set_diagnostics(std::mem::take(&mut self.diagnostics));
+        for (object_id, member_order) in std::mem::take(&mut self.member_orders) {
+            root.set_member_order(object_id, member_order);
+        }
+        root.set_duplicate_hash_pairs(std::mem::take(&mut self.duplicate_hash_pairs));
+        self.report_progress();
+        root
+    }
+
+    /// Replaces the objects of classes that aren't in `LoaderOptions::permitted_classes` by `RubyObject::Unloaded`
+    fn replace_unpermitted_objects(&mut self) {
+        let Some(permitted_classes) = &self.options.permitted_classes else {
+            return;
+        };
+        for object in self.objects.iter_mut() {
+            let class_name = object.class_symbol().and_then(|class_name| self.symbols.get(class_name));
+            if class_name.is_some_and(|class_name| !permitted_classes.contains(&**class_name)) {
+                *object = RubyObject::Unloaded(object.kind());
+            }
         }
+    }
+
+    /// Whether the reader has no more input, for reading documents that were written one after another
+    pub fn is_at_end(&mut self) -> Result<bool, LoadError> {
+        Ok(self.peek_byte()?.is_none())
+    }
 
-        if buffer[0] == 0 {
-            return Ok(0);
+    /// Loads documents written one after another until the reader is exhausted, every document gets its own symbol
+    /// and object tables. The iterator ends after the first error since the position of the next document is unknown.
+    pub fn load_iter(&mut self) -> Documents<'_, 'a, T> {
+        Documents { loader: self, failed: false }
+    }
+
+    /// Loads a document from `reader` instead of the loader's own reader, with the loader's options, visitor and text
+    /// decoder. Meant for batch processing many inputs with one Loader: the symbol and object tables stay with the
+    /// loader and keep their capacity, `reader` only has to live for the call. `tag_histogram()` and `recovered_errors()`
+    /// describe this document afterwards.
+    pub fn load_from<R: BufRead>(&mut self, reader: &mut R) -> Result<Root, LoadError> {
+        let mut visitor = self.visitor.take();
+        let mut loader = Loader::with_options(reader, std::mem::take(&mut self.options)).with_text_decoder(self.text_decoder);
+        if let Some(visitor) = visitor.as_deref_mut() {
+            loader = loader.with_visitor(visitor);
         }
+        self.swap_tables(&mut loader);
+        let result = loader.load();
+        self.swap_tables(&mut loader);
+        self.options = loader.options;
+        self.visitor = visitor;
+        result
+    }
 
-        let mut is_positive = true;
-        let mut int_len = buffer[0];
+    /// Exchanges the tables and the per-document state read after a load with `other`, see `load_from()`
+    fn swap_tables<R: Read>(&mut self, other: &mut Loader<'_, R>) {
+        std::mem::swap(&mut self.symbols, &mut other.symbols);
+        std::mem::swap(&mut self.encoded_symbols, &mut other.encoded_symbols);
+        std::mem::swap(&mut self.objects, &mut other.objects);
+        std::mem::swap(&mut self.linked_incomplete_hashes, &mut other.linked_incomplete_hashes);
+        std::mem::swap(&mut self.tags, &mut other.tags);
+        std::mem::swap(&mut self.recovered, &mut other.recovered);
+        std::mem::swap(&mut self.diagnostics, &mut other.diagnostics);
+        std::mem::swap(&mut self.member_orders, &mut other.member_orders);
+        std::mem::swap(&mut self.duplicate_hash_pairs, &mut other.duplicate_hash_pairs);
+    }
+
+    /// In lenient mode records an error after which loading can go on at the current position, otherwise returns it
+    fn recover(&mut self, error: LoadError) -> Result<(), LoadError> {
+        let error = self.locate(error, None);
+        if !self.options.lenient {
+            return Err(error);
+        }
+        self.recovered.push(error);
+        Ok(())
+    }
 
-        if (int_len as i8) < 0 {
-            int_len = int_len.wrapping_neg();
-            is_positive = false;
+    /// In lenient mode records the first error that loses the position in the stream and replaces the value by nil,
+    /// errors caused by the placeholders afterwards aren't recorded. Otherwise returns the error.
+    fn salvage(&mut self, error: LoadError, object_offset: Option<u64>) -> Result<RubyValue, LoadError> {
+        let error = self.locate(error, object_offset);
+        if !self.options.lenient {
+            return Err(error);
+        }
+        if !self.salvaging {
+            self.recovered.push(error);
+            self.salvaging = true;
         }
+        Ok(RubyValue::Nil)
+    }
 
-        if int_len > 0 && int_len < 5 {
-            let mut buffer = [0; 4];
-            if let Err(err) = self.reader.read_exact(&mut buffer[..int_len.into()]) {
-                return Err(LoadError::IoError(format!("Failed to read fixnum's following bytes: {}", err)));
+    /// Fills in the parts of the error's location that inner values didn't set
+    fn locate(&self, mut error: LoadError, object_offset: Option<u64>) -> LoadError {
+        let location = error.location_mut();
+        location.offset = location.offset.or(Some(self.position));
+        location.object_offset = location.object_offset.or(object_offset);
+        error
+    }
+
+    /// Reads a value without recursing, values containing other values wait on a stack of frames while those are read.
+    /// Values are only limited in depth by `LoaderOptions::max_depth`. A `resumable` read that runs out of input goes
+    /// back to the start of the value it was reading and returns the error, `frames` hold the values around it.
+    fn read_value(&mut self, frames: &mut Vec<Frame>, resumable: bool) -> Result<RubyValue, LoadError> {
+        let mut delivered = None;
+        loop {
+            if let Some(frame) = frames.pop() {
+                match self.advance(frame.offset, frame.state, delivered.take()) {
+                    Ok(Step::Value(value)) => {
+                        self.depth -= 1;
+                        delivered = Some(value);
+                        continue;
+                    }
+                    Ok(Step::Frame(state)) => frames.push(Frame { offset: frame.offset, state }),
+                    Err(err) => {
+                        self.depth -= 1;
+                        delivered = Some(self.salvage(err, Some(frame.offset))?);
+                        continue;
+                    }
+                }
+            } else if let Some(value) = delivered {
+                return Ok(value);
             }
 
-            if is_positive {
-                Ok(i32::from_le_bytes(buffer))
-            } else {
-                let mut n: i32 = -1;
-                for i in 0..int_len {
-                    n &= !(0xFF_i32 << (i * 8));
-                    n |= i32::from(buffer[i as usize]) << (i * 8);
+            let checkpoint = self.checkpoint();
+            if let Some(Frame { state: FrameState::InstanceVariablePairs { instance_variables: count @ None, .. }, .. }) = frames.last_mut() {
+                match self.read_pairs_header() {
+                    Ok(instance_variables) => *count = Some(instance_variables),
+                    Err(err) if resumable && err.code() == ErrorCode::UnexpectedEnd => {
+                        self.rollback(checkpoint);
+                        return Err(err);
+                    }
+                    Err(err) => {
+                        let offset = frames.pop().map(|frame| frame.offset);
+                        self.depth -= 1;
+                        delivered = Some(self.salvage(err, offset)?);
+                    }
                 }
+                continue;
+            }
 
-                Ok(n)
+            let parent_offset = frames.last().map(|frame| frame.offset);
+            match self.start_value() {
+                Ok((_, Step::Value(value))) => delivered = Some(value),
+                Ok((offset, Step::Frame(state))) => {
+                    self.depth += 1;
+                    frames.push(Frame { offset, state });
+                }
+                Err(err) if resumable && err.code() == ErrorCode::UnexpectedEnd => {
+                    self.rollback(checkpoint);
+                    return Err(err);
+                }
+                Err(err) => delivered = Some(self.salvage(err, parent_offset)?),
             }
-        } else {
-            let value = i8::from_le_bytes([buffer[0]]);
+        }
+    }
+
+    /// Reads the tag of the next value and as much of the value as doesn't need other values, returns the tag's offset
+    fn start_value(&mut self) -> Result<(u64, Step), LoadError> {
+        if self.salvaging {
+            return Ok((self.position, Step::Value(RubyValue::Nil)));
+        }
+        if self.depth >= self.options.max_depth {
+            return Err(LoadError::DepthLimitExceeded(self.options.max_depth, ErrorLocation::UNKNOWN));
+        }
+        let offset = self.position;
+        let mut buffer: [u8; 1] = [0; 1];
+        if let Err(err) = self.read_exact(&mut buffer) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read value type: {}", err), ErrorLocation::UNKNOWN));
+        }
+        let tag = buffer[0];
+        self.tags.record(tag);
+        self.check_object_limit(tag, offset, self.objects.len())?;
 
-            if value > 0 {
-                Ok(value as i32 - 5)
-            } else {
-                Ok(value as i32 + 5)
+        match self.start_tagged_value(tag) {
+            Ok(step) => Ok((offset, step)),
+            Err(err) => Err(self.locate(err, spec::is_object_tag(tag).then_some(offset))),
+        }
+    }
+
+    /// Fails if the value with `tag` at `offset` defines an object beyond `LoaderOptions::max_objects`
+    fn check_object_limit(&self, tag: u8, offset: u64, object_count: usize) -> Result<(), LoadError> {
+        let defines_object = spec::is_object_tag(tag) && !matches!(tag, TAG_INSTANCE_VARIABLES | TAG_EXTENDED);
+        if defines_object && object_count >= self.options.max_objects {
+            return Err(LoadError::ObjectLimitExceeded(
+                format!("object exceeds the limit of {} objects per document", self.options.max_objects),
+                ErrorLocation { offset: Some(offset), object_offset: None },
+            ));
+        }
+        Ok(())
+    }
+
+    fn start_tagged_value(&mut self, tag: u8) -> Result<Step, LoadError> {
+        let value = match tag {
+            TAG_NIL => RubyValue::Nil,
+            TAG_TRUE => RubyValue::Boolean(true),
+            TAG_FALSE => RubyValue::Boolean(false),
+            TAG_FIXNUM => RubyValue::FixNum(self.read_fixnum()?),
+            TAG_SYMBOL => RubyValue::Symbol(self.read_symbol()?),
+            TAG_SYMBOL_LINK => RubyValue::Symbol(self.read_symbol_link()?),
+            TAG_FLOAT => RubyValue::Float(self.read_float()?),
+            TAG_OBJECT_LINK => self.read_object_link()?,
+            TAG_CLASS => RubyValue::Class(self.read_class()?),
+            TAG_MODULE => RubyValue::Module(self.read_module()?),
+            TAG_CLASS_OR_MODULE => RubyValue::ClassOrModule(self.read_class_or_module()?),
+            TAG_STRING => RubyValue::String(self.read_string()?),
+            TAG_BIGNUM => RubyValue::BigNum(self.read_bignum()?),
+            TAG_REGEXP => RubyValue::RegExp(self.read_regexp()?),
+            TAG_USER_DEFINED => RubyValue::UserDefined(self.read_user_defined()?),
+            TAG_ARRAY => return self.start_array(),
+            TAG_HASH => return self.start_hash(false),
+            TAG_HASH_WITH_DEFAULT => return self.start_hash(true),
+            TAG_STRUCT => return self.start_struct(),
+            TAG_OBJECT => return self.start_object(),
+            TAG_USER_CLASS => return self.start_user_class(),
+            TAG_USER_MARSHAL => return self.start_user_marshal(),
+            TAG_DATA => return self.start_data(),
+            TAG_INSTANCE_VARIABLES => return self.start_instance_variables(),
+            TAG_EXTENDED => return self.start_extended(),
+            _ => return Err(LoadError::ParserError(ErrorCode::UnknownTag, format!("Unknown value type: {}", tag), ErrorLocation::UNKNOWN)),
+        };
+        Ok(Step::Value(value))
+    }
+
+    /// Gives a frame the value it waited for (`None` right after the frame was started), the frame either finishes
+    /// its value or waits for another one
+    fn advance(&mut self, offset: u64, state: FrameState, delivered: Option<RubyValue>) -> Result<Step, LoadError> {
+        let salvaging = self.salvaging;
+        match state {
+            FrameState::Array { id, mut remaining, mut elements } => {
+                if let Some(value) = delivered {
+                    elements.push(value);
+                    remaining -= 1;
+                }
+                if remaining > 0 && !salvaging {
+                    return Ok(Step::Frame(FrameState::Array { id, remaining, elements }));
+                }
+                self.objects[id] = RubyObject::Array(elements);
+                Ok(Step::Value(RubyValue::Array(id)))
+            }
+            FrameState::Hash { id, mut pairs, with_default } => {
+                if let Some((key, value)) = delivered.and_then(|value| pairs.accept(value, salvaging)) {
+                    self.insert_hash_pair(id, &mut pairs.pairs, key, value, offset)?;
+                }
+                if !pairs.is_done(salvaging) {
+                    return Ok(Step::Frame(FrameState::Hash { id, pairs, with_default }));
+                }
+                if with_default {
+                    return Ok(Step::Frame(FrameState::HashDefault { id, hash: pairs.pairs }));
+                }
+                self.objects[id] = RubyObject::Hash(pairs.pairs);
+                Ok(Step::Value(RubyValue::Hash(id)))
+            }
+            FrameState::HashDefault { id, hash } => {
+                let Some(default) = delivered else {
+                    return Ok(Step::Frame(FrameState::HashDefault { id, hash }));
+                };
+                // self-referencing hashes stay as they are, the links inside them already point to a hash with default
+                if self.options.collapse_nil_default_hashes && default == RubyValue::Nil && !self.linked_incomplete_hashes.contains(&id) {
+                    self.objects[id] = RubyObject::Hash(hash);
+                    return Ok(Step::Value(RubyValue::Hash(id)));
+                }
+                self.objects[id] = RubyObject::HashWithDefault(HashWithDefault::new(hash, default));
+                Ok(Step::Value(RubyValue::HashWithDefault(id)))
+            }
+            FrameState::Struct { id, name, mut members } => {
+                if let Some(value) = delivered {
+                    self.accept_symbol_pair(&mut members, value)?;
+                }
+                if !members.is_done(salvaging) {
+                    return Ok(Step::Frame(FrameState::Struct { id, name, members }));
+                }
+                self.record_member_order(id, &members.pairs);
+                self.objects[id] = RubyObject::Struct(Struct::new(name, members.pairs));
+                Ok(Step::Value(RubyValue::Struct(id)))
+            }
+            FrameState::Object { id, class_name, mut instance_variables } => {
+                if let Some(value) = delivered {
+                    self.accept_symbol_pair(&mut instance_variables, value)?;
+                }
+                if !instance_variables.is_done(salvaging) {
+                    return Ok(Step::Frame(FrameState::Object { id, class_name, instance_variables }));
+                }
+                self.record_member_order(id, &instance_variables.pairs);
+                if let Some(visitor) = &mut self.visitor {
+                    if self.visits.next() {
+                        let symbols = &self.symbols;
+                        let named_instance_variables: Vec<(&str, &RubyValue)> = instance_variables.pairs.iter()
+                            .filter_map(|(name, value)| Some((&**symbols.get(*name)?, value)))
+                            .collect();
+                        let class_name = symbols.get(class_name).map_or("", |name| &**name);
+                        visited(visitor.on_object(class_name, &named_instance_variables))?;
+                    }
+                }
+                self.objects[id] = RubyObject::Object(Object::new(class_name, instance_variables.pairs));
+                Ok(Step::Value(RubyValue::Object(id)))
+            }
+            FrameState::UserClass { id, name } => {
+                let Some(wrapped_object) = delivered else {
+                    return Ok(Step::Frame(FrameState::UserClass { id, name }));
+                };
+                self.objects[id] = RubyObject::UserClass(UserClass::new(name, wrapped_object));
+                Ok(Step::Value(RubyValue::UserClass(id)))
+            }
+            FrameState::UserMarshal { id, class_name } => {
+                let Some(wrapped_object) = delivered else {
+                    return Ok(Step::Frame(FrameState::UserMarshal { id, class_name }));
+                };
+                self.objects[id] = RubyObject::UserMarshal(UserMarshal::new(class_name, wrapped_object));
+                Ok(Step::Value(RubyValue::UserMarshal(id)))
+            }
+            FrameState::Data { id, class_name } => {
+                let Some(state) = delivered else {
+                    return Ok(Step::Frame(FrameState::Data { id, class_name }));
+                };
+                self.objects[id] = RubyObject::Data(Data::new(class_name, state));
+                Ok(Step::Value(RubyValue::Data(id)))
             }
+            FrameState::UserDefined { mut user_defined, range, mut instance_variables, extension } => {
+                if let Some(value) = delivered {
+                    self.accept_symbol_pair(&mut instance_variables, value)?;
+                }
+                if !instance_variables.is_done(salvaging) {
+                    return Ok(Step::Frame(FrameState::UserDefined { user_defined, range, instance_variables, extension }));
+                }
+                let mut wrapper_ivars = None;
+                if self.options.ivar_wrappers {
+                    wrapper_ivars = Some(instance_variables.pairs);
+                } else {
+                    user_defined.set_instance_variables(instance_variables.pairs);
+                }
+                let value = RubyValue::UserDefined(self.register_user_defined(*user_defined, range));
+                self.extend_object(&value, extension)?;
+                Ok(Step::Value(match wrapper_ivars {
+                    Some(ivars) => value.with_ivars(ivars.into_iter().collect()),
+                    None => value,
+                }))
+            }
+            FrameState::InstanceVariables { extension } => {
+                let Some(value) = delivered else {
+                    return Ok(Step::Frame(FrameState::InstanceVariables { extension }));
+                };
+                if salvaging {
+                    return Ok(Step::Value(value));
+                }
+                let first_new_object = extension.first_new_object;
+                self.extend_object(&value, extension)?;
+                Ok(Step::Frame(FrameState::InstanceVariablePairs { value, first_new_object, instance_variables: None }))
+            }
+            FrameState::InstanceVariablePairs { value, first_new_object, instance_variables: None } => {
+                Ok(Step::Frame(FrameState::InstanceVariablePairs { value, first_new_object, instance_variables: None }))
+            }
+            FrameState::InstanceVariablePairs { value, first_new_object, instance_variables: Some(mut instance_variables) } => {
+                if let Some(delivered) = delivered {
+                    self.accept_symbol_pair(&mut instance_variables, delivered)?;
+                }
+                if !instance_variables.is_done(salvaging) {
+                    let instance_variables = Some(instance_variables);
+                    return Ok(Step::Frame(FrameState::InstanceVariablePairs { value, first_new_object, instance_variables }));
+                }
+                Ok(Step::Value(self.attach_instance_variables(value, first_new_object, instance_variables.pairs, offset)))
+            }
+            FrameState::Extended { extension } => {
+                let Some(value) = delivered else {
+                    return Ok(Step::Frame(FrameState::Extended { extension }));
+                };
+                self.extend_object(&value, extension)?;
+                Ok(Step::Value(value))
+            }
+        }
+    }
+
+    fn record_member_order(&mut self, id: ObjectID, pairs: &ValuePairsSymbolKeys) {
+        if self.options.record_member_order {
+            self.member_orders.insert(id, pairs.keys().copied().collect());
+        }
+    }
+
+    fn read_fixnum(&mut self) -> Result<i32, LoadError> {
+        let mut buffer = [0; fixnum::MAX_ENCODED_LEN];
+        if let Err(err) = self.read_exact(&mut buffer[..1]) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read fixnum's first byte: {}", err), ErrorLocation::UNKNOWN));
+        }
+
+        let len = fixnum::encoded_len(buffer[0]);
+        if let Err(err) = self.read_exact(&mut buffer[1..len]) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read fixnum's following bytes: {}", err), ErrorLocation::UNKNOWN));
+        }
+
+        match fixnum::decode(&buffer[..len]) {
+            Ok((value, _)) => Ok(value),
+            Err(err) => Err(LoadError::ParserError(ErrorCode::BadFixnum, format!("Could not parse fixnum: {}", err), ErrorLocation::UNKNOWN)),
         }
     }
 
     fn read_byte_sequence(&mut self) -> Result<Vec<u8>, LoadError> {
-        let sequence_len = self.read_fixnum()?.try_into().unwrap();
-        let mut buffer = vec![0; sequence_len];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read byte sequence: {}, was expecting {} bytes", err, sequence_len)));
+        let sequence_len = match usize::try_from(self.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse byte sequence length (length was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        self.read_bytes("byte sequence", sequence_len)
+    }
+
+    /// Reads a byte sequence that becomes the payload of an object, a borrowing load leaves it in the input and returns
+    /// its range along with an empty buffer. With a visitor the buffer holds a copy of the payload for the visitor,
+    /// which the caller drops after calling it (see `borrow_payload()`).
+    fn read_payload_sequence(&mut self) -> Result<(Vec<u8>, Option<Range<usize>>), LoadError> {
+        if self.borrowed.is_none() {
+            return Ok((self.read_byte_sequence()?, None));
+        }
+        let sequence_len = match usize::try_from(self.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse byte sequence length (length was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        let start = self.position as usize;
+        let range = Some(start..start + sequence_len);
+        if self.visitor.is_some() {
+            return Ok((self.read_bytes("byte sequence", sequence_len)?, range));
+        }
+        self.skip_bytes("byte sequence", sequence_len)?;
+        Ok((Vec::new(), range))
+    }
+
+    /// Records the range of a borrowed payload and empties the object's own copy of it made for the visitor
+    fn borrow_payload(&mut self, object_id: ObjectID, range: Option<Range<usize>>) {
+        let (Some(borrowed), Some(range)) = (&mut self.borrowed, range) else {
+            return;
+        };
+        borrowed.payloads.insert(object_id, range);
+        match self.objects.get_mut(object_id) {
+            Some(RubyObject::String(string)) => string.set_string(Vec::new()),
+            Some(RubyObject::UserDefined(user_defined)) => user_defined.set_data(Vec::new()),
+            _ => {}
         }
-        Ok(buffer)
     }
 
     fn read_sequence(&mut self) -> Result<String, LoadError> {
@@ -169,152 +1421,242 @@ impl<'a, T: BufRead> Loader<'a, T> {
     }
 
     fn read_symbol(&mut self) -> Result<SymbolID, LoadError> {
-        let symbol = self.read_sequence()?;
+        // the offset of the symbol's tag
+        let offset = self.position.saturating_sub(1);
+        let symbol_len = self.read_symbol_len(self.symbols.len())?;
+        if symbol_len == 0 {
+            self.diagnostics.push(DiagnosticKind::EmptySymbol, offset);
+        }
+        let symbol = match String::from_utf8(self.read_bytes("symbol", symbol_len)?) {
+            Ok(symbol) => symbol,
+            Err(err) => {
+                // like Shift-JIS symbols of Ruby 1.8 dumps, read_encoded_symbol() decodes them if the encoding follows
+                let symbol = String::from_utf8_lossy(err.as_bytes()).into_owned();
+                self.encoded_symbols.insert(self.symbols.len(), EncodedSymbol::new(err.into_bytes(), ValuePairsSymbolKeys::new()));
+                symbol
+            }
+        };
+        let symbol = self.intern_symbol(symbol);
+        if let Some(visitor) = &mut self.visitor {
+            if self.visits.next() {
+                visited(visitor.on_symbol(&symbol))?;
+            }
+        }
 
         self.symbols.push(symbol);
         Ok(self.symbols.len()-1)
     }
 
+    /// Reads the length of a symbol definition (after its tag) and checks it against the limits, `symbol_count` symbols
+    /// were defined before it
+    fn read_symbol_len(&mut self, symbol_count: usize) -> Result<usize, LoadError> {
+        // the offset of the symbol's tag
+        let offset = self.position.saturating_sub(1);
+        if symbol_count >= self.options.max_symbols {
+            return Err(LoadError::SymbolLimitExceeded(
+                format!("symbol exceeds the limit of {} symbols per document", self.options.max_symbols),
+                ErrorLocation { offset: Some(offset), object_offset: None },
+            ));
+        }
+        let symbol_len = match usize::try_from(self.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse symbol length (length was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        if symbol_len > self.options.max_symbol_len {
+            return Err(LoadError::SymbolLimitExceeded(
+                format!("symbol of {} bytes exceeds the limit of {} bytes", symbol_len, self.options.max_symbol_len),
+                ErrorLocation { offset: Some(offset), object_offset: None },
+            ));
+        }
+        Ok(symbol_len)
+    }
+
+    fn intern_symbol(&self, symbol: String) -> SymbolStr {
+        match &self.options.symbol_table {
+            Some(symbol_table) => symbol_table.intern(&symbol),
+            None => SymbolStr::from(symbol),
+        }
+    }
+
+    /// Reads a symbol definition (after its `I` and `:` tags) followed by the instance variables giving its encoding
+    fn read_encoded_symbol(&mut self) -> Result<SymbolID, LoadError> {
+        // the offset of the `I` tag in front of the symbol's tag
+        let offset = self.position.saturating_sub(2);
+        let symbol_id = self.read_symbol()?;
+        let count = match usize::try_from(self.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse symbol encoding (number of instance variables was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        let mut instance_variables = ValuePairsSymbolKeys::new();
+        for _ in 0..count {
+            let tag = self.read_tag()?;
+            let name = self.read_plain_symbol_value("symbol encoding", tag)?;
+            let value = match self.read_tag()? {
+                TAG_TRUE => RubyValue::Boolean(true),
+                TAG_FALSE => RubyValue::Boolean(false),
+                TAG_STRING => RubyValue::String(self.read_string()?),
+                TAG_OBJECT_LINK => self.read_object_link()?,
+                tag => return Err(unexpected_value("symbol encoding", "a boolean or a string", tag)),
+            };
+            instance_variables.insert(name, value);
+        }
+
+        let bytes = match self.encoded_symbols.get(&symbol_id) {
+            Some(encoded_symbol) => encoded_symbol.get_bytes().to_vec(),
+            None => self.symbols[symbol_id].as_bytes().to_vec(),
+        };
+        let symbol = self.named_encoding(&instance_variables, offset).and_then(|encoding| self.text_decoder.decode(&encoding, &bytes).ok());
+        if let Some(symbol) = symbol {
+            self.symbols[symbol_id] = self.intern_symbol(symbol);
+        }
+        self.encoded_symbols.insert(symbol_id, EncodedSymbol::new(bytes, instance_variables));
+        Ok(symbol_id)
+    }
+
+    /// Encoding named by the `E` or `encoding` instance variable of a string or symbol, an `encoding` the text decoder
+    /// doesn't know is recorded as a diagnostic of the value at `offset`
+    fn named_encoding(&mut self, instance_variables: &ValuePairsSymbolKeys, offset: u64) -> Option<String> {
+        for (name, value) in instance_variables {
+            match (self.symbols.get(*name).map(|name| &**name), value) {
+                (Some("E"), RubyValue::Boolean(true)) => return Some("UTF-8".to_string()),
+                (Some("E"), RubyValue::Boolean(false)) => return Some("US-ASCII".to_string()),
+                // a borrowing load leaves the name in the input
+                (Some("encoding"), RubyValue::String(object_id)) if self.borrowed.is_none() => {
+                    let Some(RubyObject::String(name)) = self.objects.get(*object_id) else {
+                        return None;
+                    };
+                    let name = String::from_utf8_lossy(name.get_string()).into_owned();
+                    if !self.text_decoder.supports(&name) {
+                        self.diagnostics.push(DiagnosticKind::UnknownEncoding(name), offset);
+                        return None;
+                    }
+                    return Some(name);
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
     fn read_symbol_link(&mut self) -> Result<SymbolID, LoadError> {
         let symbol_id = match usize::try_from(self.read_fixnum()?) {
             Ok(val) => val,
-            Err(_) => return Err(LoadError::ParserError("Could not parse symbol link (could not convert symbol index to usize)".to_string())),
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadSymbolLink, "Could not parse symbol link (could not convert symbol index to usize)".to_string(), ErrorLocation::UNKNOWN)),
         };
 
         if symbol_id >= self.symbols.len() {
-            Err(LoadError::ParserError("Could not parse symbol link (links to a non-existent symbol)".to_string()))
+            Err(LoadError::ParserError(ErrorCode::BadSymbolLink, "Could not parse symbol link (links to a non-existent symbol)".to_string(), ErrorLocation::UNKNOWN))
         } else {
             Ok(symbol_id)
         }
     }
 
-    fn read_array(&mut self) -> Result<ObjectID, LoadError> {
+    fn start_array(&mut self) -> Result<Step, LoadError> {
         let array_len = match usize::try_from(self.read_fixnum()?) {
             Ok(val) => val,
-            Err(_) => return Err(LoadError::ParserError("Could not parse array length (could not convert array length to usize)".to_string())),
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse array length (could not convert array length to usize)".to_string(), ErrorLocation::UNKNOWN)),
         };
 
         self.objects.push(RubyObject::Incomplete(IncompleteObject::Array));
-        let array_id = self.objects.len()-1;
+        let id = self.objects.len()-1;
 
-        let mut array = Vec::with_capacity(array_len);
-
-        for _ in 0..array_len {
-            array.push(self.read_value()?);
+        if let Some(visitor) = &mut self.visitor {
+            if self.visits.next() {
+                visited(visitor.on_array(array_len))?;
+            }
         }
-
-        self.objects[array_id] = RubyObject::Array(array);
-        Ok(array_id)
+        self.allocate_elements("array", array_len, 1)?;
+        let elements = Vec::with_capacity(array_len.min(MAX_PREALLOCATED_ELEMENTS));
+        Ok(Step::Frame(FrameState::Array { id, remaining: array_len, elements }))
     }
 
     fn read_float(&mut self) -> Result<ObjectID, LoadError> {
+        // the offset of the float's tag
+        let offset = self.position.saturating_sub(1);
         let float_sequence = self.read_byte_sequence()?;
-        let float_value: f64 = unsafe { libc::strtod(float_sequence.as_ptr() as *const i8, std::ptr::null_mut()) };
-        self.objects.push(RubyObject::Float(float_value));
+        let value = parse_float(&float_sequence);
+        if is_legacy_float(&float_sequence, value) {
+            self.diagnostics.push(DiagnosticKind::LegacyFloat, offset);
+        }
+        self.objects.push(RubyObject::Float(value));
         Ok(self.objects.len()-1)
     }
 
     fn read_object_link(&mut self) -> Result<RubyValue, LoadError> {
         let object_id = match usize::try_from(self.read_fixnum()?) {
             Ok(val) => val,
-            Err(_) => return Err(LoadError::ParserError("Could not parse object link (could not convert object index to usize)".to_string())),
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadObjectLink, "Could not parse object link (could not convert object index to usize)".to_string(), ErrorLocation::UNKNOWN)),
         };
 
         if let Some(object) = self.objects.get(object_id) {
-            let ruby_value = match object {
-                RubyObject::Incomplete(object_type) => { // recursion
-                    match object_type {
-                        IncompleteObject::Array => RubyValue::Array(object_id),
-                        IncompleteObject::Hash => RubyValue::Hash(object_id),
-                        IncompleteObject::HashWithDefault => RubyValue::HashWithDefault(object_id),
-                        IncompleteObject::Struct => RubyValue::Struct(object_id),
-                        IncompleteObject::Object => RubyValue::Object(object_id),
-                        IncompleteObject::UserClass => RubyValue::UserClass(object_id),
-                        IncompleteObject::UserDefined => RubyValue::UserDefined(object_id),
-                        IncompleteObject::UserMarshal => RubyValue::UserMarshal(object_id),
-                    }
-                },
-                RubyObject::Array(_) => RubyValue::Array(object_id),
-                RubyObject::Float(_) => RubyValue::Float(object_id),
-                RubyObject::Hash(_) => RubyValue::Hash(object_id),
-                RubyObject::HashWithDefault(_) => RubyValue::HashWithDefault(object_id),
-                RubyObject::Class(_) => RubyValue::Class(object_id),
-                RubyObject::Module(_) => RubyValue::Module(object_id),
-                RubyObject::ClassOrModule(_) => RubyValue::ClassOrModule(object_id),
-                RubyObject::String(_) => RubyValue::String(object_id),
-                RubyObject::BigNum(_) => RubyValue::BigNum(object_id),
-                RubyObject::RegExp(_) => RubyValue::RegExp(object_id),
-                RubyObject::Struct(_) => RubyValue::Struct(object_id),
-                RubyObject::Object(_) => RubyValue::Object(object_id),
-                RubyObject::UserClass(_) => RubyValue::UserClass(object_id),
-                RubyObject::UserDefined(_) => RubyValue::UserDefined(object_id),
-                RubyObject::UserMarshal(_) => RubyValue::UserMarshal(object_id),
-            };
+            // incomplete objects report the kind they will have, links to them are recursive references
+            if let RubyObject::Incomplete(IncompleteObject::HashWithDefault) = object {
+                self.linked_incomplete_hashes.push(object_id);
+            }
+            let ruby_value = object.kind().to_value(object_id);
             Ok(ruby_value)
         } else {
-            Err(LoadError::ParserError("Could not parse object link (links to a non-existent object)".to_string()))
+            self.recover(LoadError::ParserError(ErrorCode::BadObjectLink, "Could not parse object link (links to a non-existent object)".to_string(), ErrorLocation::UNKNOWN))?;
+            Ok(RubyValue::Nil)
         }
     }
 
-    fn read_value_pairs(&mut self) -> Result<ValuePairs, LoadError> {
+    /// Reads the number of key:value pairs that follow
+    fn read_pairs_header<K>(&mut self) -> Result<PendingPairs<K>, LoadError> {
         let num_of_pairs = match usize::try_from(self.read_fixnum()?) {
             Ok(val) => val,
-            Err(_) => return Err(LoadError::ParserError("Could not parse number of key:value pairs (could not convert number of pairs to usize)".to_string())),
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse number of key:value pairs (could not convert number of pairs to usize)".to_string(), ErrorLocation::UNKNOWN)),
         };
 
-        let mut pairs = ValuePairs::with_capacity(num_of_pairs);
-
-        for _ in 0..num_of_pairs {
-            let key = self.read_value()?;
-            let value = self.read_value()?;
+        self.allocate_elements("key:value pairs", num_of_pairs, 2)?;
+        Ok(PendingPairs { remaining: num_of_pairs, key: None, pairs: IndexMap::with_capacity(num_of_pairs.min(MAX_PREALLOCATED_ELEMENTS)) })
+    }
 
-            pairs.insert(key, value);
+    fn accept_symbol_pair(&mut self, pairs: &mut PendingPairs<SymbolID>, value: RubyValue) -> Result<(), LoadError> {
+        match pairs.accept(value, self.salvaging) {
+            Some((RubyValue::Symbol(symbol_id), value)) => {
+                pairs.pairs.insert(symbol_id, value);
+            }
+            Some((other, _)) => self.recover(LoadError::ParserError(ErrorCode::UnexpectedValue, format!("Could not parse key:value pairs, key was not a Symbol: {:?}", other), ErrorLocation::UNKNOWN))?,
+            None => (),
         }
-
-        Ok(pairs)
+        Ok(())
     }
 
-    fn read_value_pairs_symbol_keys(&mut self) -> Result<ValuePairsSymbolKeys, LoadError> {
-        let num_of_pairs = match usize::try_from(self.read_fixnum()?) {
-            Ok(val) => val,
-            Err(_) => return Err(LoadError::ParserError("Could not parse number of key:value pairs (could not convert number of pairs to usize)".to_string())),
+    /// Adds a pair to the hash whose tag is at `offset`
+    fn insert_hash_pair(&mut self, id: ObjectID, hash: &mut ValuePairs, key: RubyValue, value: RubyValue, offset: u64) -> Result<(), LoadError> {
+        let Some(existing) = hash.get_mut(&key) else {
+            hash.insert(key, value);
+            return Ok(());
         };
-
-        let mut pairs = ValuePairsSymbolKeys::with_capacity(num_of_pairs);
-
-        for _ in 0..num_of_pairs {
-            let symbol = match self.read_value()? {
-                RubyValue::Symbol(symbol_id) => symbol_id,
-                other => return Err(LoadError::ParserError(format!("Could not parse key:value pairs, key was not a Symbol: {:?}", other)))
-            };
-            let value = self.read_value()?;
-
-            pairs.insert(symbol, value);
+        match self.options.duplicate_hash_keys {
+            DuplicateKeys::LastWins => {
+                *existing = value;
+                self.diagnostics.push(DiagnosticKind::DuplicateHashKey, offset);
+            }
+            DuplicateKeys::FirstWins => self.diagnostics.push(DiagnosticKind::DuplicateHashKey, offset),
+            DuplicateKeys::Error => self.recover(LoadError::ParserError(ErrorCode::DuplicateHashKey, format!("Duplicate hash key {:?}", key), ErrorLocation::UNKNOWN))?,
+            DuplicateKeys::CollectAll => {
+                let replaced = std::mem::replace(existing, value);
+                self.duplicate_hash_pairs.entry(id).or_default().push((key, replaced));
+                self.diagnostics.push(DiagnosticKind::DuplicateHashKey, offset);
+            }
         }
-
-        Ok(pairs)
-    }
-
-    fn read_hash(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::Hash));
-        let hash_id = self.objects.len()-1;
-
-        let hash = self.read_value_pairs()?;
-
-        self.objects[hash_id] = RubyObject::Hash(hash);
-        Ok(hash_id)
+        Ok(())
     }
 
-    fn read_hash_with_default(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::HashWithDefault));
-        let hash_id = self.objects.len()-1;
-
-        let hash = self.read_value_pairs()?;
+    fn start_hash(&mut self, with_default: bool) -> Result<Step, LoadError> {
+        let incomplete = if with_default { IncompleteObject::HashWithDefault } else { IncompleteObject::Hash };
+        self.objects.push(RubyObject::Incomplete(incomplete));
+        let id = self.objects.len()-1;
 
-        let default = self.read_value()?;
-
-        self.objects[hash_id] = RubyObject::HashWithDefault(HashWithDefault::new(hash, default));
-        Ok(hash_id)
+        let pairs: PendingPairs<RubyValue> = self.read_pairs_header()?;
+        if let Some(visitor) = &mut self.visitor {
+            if self.visits.next() {
+                visited(visitor.on_hash(pairs.remaining))?;
+            }
+        }
+        Ok(Step::Frame(FrameState::Hash { id, pairs, with_default }))
     }
 
     fn read_class(&mut self) -> Result<ObjectID, LoadError> {
@@ -339,101 +1681,231 @@ impl<'a, T: BufRead> Loader<'a, T> {
     }
 
     fn read_string(&mut self) -> Result<ObjectID, LoadError> {
-        let string = self.read_byte_sequence()?;
+        let (string, range) = self.read_payload_sequence()?;
+        if let Some(visitor) = &mut self.visitor {
+            if self.visits.next() {
+                visited(visitor.on_string(&string))?;
+            }
+        }
 
         self.objects.push(RubyObject::String(RubyString::new(string)));
+        self.borrow_payload(self.objects.len()-1, range);
         Ok(self.objects.len()-1)
     }
 
-    fn read_value_with_instance_variables(&mut self) -> Result<RubyValue, LoadError> {
-        let value = self.read_value()?;
+    fn peek_byte(&mut self) -> Result<Option<u8>, LoadError> {
+        match self.reader.fill_buf() {
+            Ok(buffer) => Ok(buffer.first().copied()),
+            Err(err) => Err(LoadError::IoError(read_error_code(&err), format!("Failed to read value type: {}", err), ErrorLocation::UNKNOWN)),
+        }
+    }
 
-        let instance_variables = self.read_value_pairs_symbol_keys()?;
-        match value {
-            RubyValue::String(object_id) => {
-                match &mut self.objects[object_id] {
-                    RubyObject::String(string) => {
-                        string.set_instance_variables(instance_variables);
-                    }
-                    _ => panic!("Got wrong object type"),
+    /// The tag of the value that has to follow, without consuming it. The end of the input fails like it does when
+    /// reading the tag, so input that ends here is never taken for another value.
+    fn peek_tag(&mut self) -> Result<u8, LoadError> {
+        match self.peek_byte()? {
+            Some(tag) => Ok(tag),
+            None => Err(LoadError::IoError(ErrorCode::UnexpectedEnd, "Failed to read value type: unexpected end of input".to_string(), ErrorLocation::UNKNOWN)),
+        }
+    }
+
+    fn start_instance_variables(&mut self) -> Result<Step, LoadError> {
+        // extended modules come between the instance variables marker and the object
+        let extension = self.read_extension(Vec::new())?;
+
+        let tag = self.peek_tag()?;
+        if extension.modules.is_empty() && tag == TAG_SYMBOL {
+            self.consume_byte();
+            self.tags.record(TAG_SYMBOL);
+            return Ok(Step::Value(RubyValue::Symbol(self.read_encoded_symbol()?)));
+        }
+        if tag != TAG_USER_DEFINED {
+            return Ok(Step::Frame(FrameState::InstanceVariables { extension }));
+        }
+        // user defined objects get their object id after their instance variables, see read_user_defined()
+        self.consume_byte();
+        self.tags.record(TAG_USER_DEFINED);
+        let (user_defined, range) = self.read_user_defined_data()?;
+        let instance_variables = self.read_pairs_header()?;
+        Ok(Step::Frame(FrameState::UserDefined { user_defined: Box::new(user_defined), range, instance_variables, extension }))
+    }
+
+    /// Stores the instance variables of the `I` at `offset` on the value read inside it, or wraps the value if it
+    /// can't take them
+    fn attach_instance_variables(&mut self, value: RubyValue, first_new_object: ObjectID, instance_variables: ValuePairsSymbolKeys, offset: u64) -> RubyValue {
+        // only records unknown encodings, decoding is up to Root::decode_string()
+        self.named_encoding(&instance_variables, offset);
+        let is_new_object = value.object_id().is_some_and(|object_id| object_id >= first_new_object);
+        if !is_new_object || matches!(value, RubyValue::WithIvars(_)) {
+            self.diagnostics.push(DiagnosticKind::UnexpectedInstanceVariables(value.kind()), offset);
+        }
+        if !self.options.ivar_wrappers {
+            // only an object read inside the wrapper can take its instance variables, links and nested wrappers can't
+            let object = match value {
+                RubyValue::WithIvars(_) => None,
+                _ => value.object_id().filter(|object_id| *object_id >= first_new_object).and_then(|object_id| self.objects.get_mut(object_id)),
+            };
+            match object {
+                Some(RubyObject::String(string)) if string.get_instance_variables().is_none() => {
+                    string.set_instance_variables(instance_variables);
+                    return value;
                 }
-            }
-            RubyValue::RegExp(object_id) => {
-                match &mut self.objects[object_id] {
-                    RubyObject::RegExp(regexp) => {
-                        regexp.set_instance_variables(instance_variables);
-                    }
-                    _ => panic!("Got wrong object type"),
+                Some(RubyObject::RegExp(regexp)) if regexp.get_instance_variables().is_none() => {
+                    regexp.set_instance_variables(instance_variables);
+                    return value;
                 }
-            }
-            RubyValue::UserClass(object_id) => {
-                match &mut self.objects[object_id] {
-                    RubyObject::UserClass(user_class) => {
-                        user_class.set_instance_variables(instance_variables);
-                    }
-                    _ => panic!("Got wrong object type"),
+                Some(RubyObject::Struct(ruby_struct)) if ruby_struct.get_instance_variables().is_none() => {
+                    ruby_struct.set_instance_variables(instance_variables);
+                    return value;
                 }
-            }
-            RubyValue::UserDefined(object_id) => {
-                match &mut self.objects[object_id] {
-                    RubyObject::UserDefined(user_defined) => {
-                        user_defined.set_instance_variables(instance_variables);
-                    }
-                    _ => panic!("Got wrong object type"),
+                Some(RubyObject::UserClass(user_class)) if user_class.get_instance_variables().is_none() => {
+                    user_class.set_instance_variables(instance_variables);
+                    return value;
                 }
+                _ => (),
+            }
+        }
+
+        value.with_ivars(instance_variables.into_iter().collect())
+    }
+
+    /// Reads the `e` prefixes (without their tag) in front of an object, Ruby writes one for every module the object
+    /// was extended with
+    fn read_extension(&mut self, mut modules: Vec<SymbolID>) -> Result<Extension, LoadError> {
+        while self.peek_tag()? == TAG_EXTENDED {
+            self.consume_byte();
+            self.tags.record(TAG_EXTENDED);
+            modules.push(self.read_symbol_value("extended module")?);
+        }
+        Ok(Extension { modules, first_new_object: self.objects.len() })
+    }
+
+    fn start_extended(&mut self) -> Result<Step, LoadError> {
+        let module = self.read_symbol_value("extended module")?;
+        let extension = self.read_extension(vec![module])?;
+        Ok(Step::Frame(FrameState::Extended { extension }))
+    }
+
+    /// Reads the name of the class of `item`, which has to be permitted by `LoaderOptions::permitted_classes`. In
+    /// lenient mode the object is read anyway and replaced by a placeholder once the document is complete.
+    fn read_class_name(&mut self, item: &str) -> Result<SymbolID, LoadError> {
+        let class_name = self.read_symbol_value(item)?;
+        if let Err(error) = self.check_class_permitted(class_name) {
+            self.recover(error)?;
+        }
+        Ok(class_name)
+    }
+
+    /// Fails if `class_name` isn't one of `LoaderOptions::permitted_classes`
+    fn check_class_permitted(&self, class_name: SymbolID) -> Result<(), LoadError> {
+        if let (Some(permitted_classes), Some(name)) = (&self.options.permitted_classes, self.symbols.get(class_name)) {
+            if !permitted_classes.contains(&**name) {
+                return Err(LoadError::ClassNotPermitted(name.to_string(), ErrorLocation::UNKNOWN));
             }
-            object => return Err(LoadError::ParserError(format!("Object {:?} doesn't support instance variables", object)))
         }
+        Ok(())
+    }
+
+    fn read_tag(&mut self) -> Result<u8, LoadError> {
+        let mut buffer: [u8; 1] = [0; 1];
+        if let Err(err) = self.read_exact(&mut buffer) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read value type: {}", err), ErrorLocation::UNKNOWN));
+        }
+        self.tags.record(buffer[0]);
+        Ok(buffer[0])
+    }
+
+    /// Reads the symbol or symbol link naming the class or module of `item`, a symbol definition can carry its encoding
+    fn read_symbol_value(&mut self, item: &str) -> Result<SymbolID, LoadError> {
+        let tag = self.read_tag()?;
+        if tag != TAG_INSTANCE_VARIABLES {
+            return self.read_plain_symbol_value(item, tag);
+        }
+        match self.read_tag()? {
+            TAG_SYMBOL => self.read_encoded_symbol(),
+            // only a symbol's definition carries its encoding, not links to it
+            tag => Err(unexpected_value(item, "a symbol with encoding", tag)),
+        }
+    }
+
+    /// Reads the symbol or symbol link starting with `tag`
+    fn read_plain_symbol_value(&mut self, item: &str, tag: u8) -> Result<SymbolID, LoadError> {
+        match tag {
+            TAG_SYMBOL => self.read_symbol(),
+            TAG_SYMBOL_LINK => self.read_symbol_link(),
+            tag => Err(unexpected_value(item, "a symbol or a symbol link", tag)),
+        }
+    }
 
-        Ok(value)
+    /// Stores the modules on the object that was just read, objects created before `first_new_object` (reached through
+    /// a link) can't be extended by the stream
+    fn extend_object(&mut self, value: &RubyValue, extension: Extension) -> Result<(), LoadError> {
+        let Extension { modules: extended_modules, first_new_object } = extension;
+        if extended_modules.is_empty() {
+            return Ok(());
+        }
+        let object = value.object_id()
+            .filter(|object_id| *object_id >= first_new_object)
+            .and_then(|object_id| self.objects.get_mut(object_id));
+        match object {
+            Some(RubyObject::Object(object)) => object.set_extended_modules(extended_modules),
+            Some(RubyObject::UserDefined(user_defined)) => user_defined.set_extended_modules(extended_modules),
+            _ => return Err(LoadError::ParserError(ErrorCode::BadExtension, format!("Extending {:?} with modules is not supported", value), ErrorLocation::UNKNOWN)),
+        }
+        Ok(())
+    }
+
+    fn read_bignum(&mut self) -> Result<ObjectID, LoadError> {
+        let value = self.read_bignum_value()?;
+        self.objects.push(RubyObject::BigNum(value));
+        Ok(self.objects.len()-1)
     }
 
-    fn read_bignum(&mut self) -> Result<ObjectID, LoadError> {
+    fn read_bignum_value(&mut self) -> Result<RubyBignum, LoadError> {
         let mut buffer: [u8; 1] = [0; 1];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read bignum's sign byte: {}", err)));
+        if let Err(err) = self.read_exact(&mut buffer) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read bignum's sign byte: {}", err), ErrorLocation::UNKNOWN));
         }
 
         let is_positive = match buffer[0] {
-            b'+' => true,
-            b'-' => false,
-            _ => return Err(LoadError::ParserError(format!("Could not parse bignum's sign byte, got \"{}\"", buffer[0]))),
+            BIGNUM_POSITIVE => true,
+            BIGNUM_NEGATIVE => false,
+            _ => return Err(LoadError::ParserError(ErrorCode::BadBignum, format!("Could not parse bignum's sign byte, got \"{}\"", buffer[0]), ErrorLocation::UNKNOWN)),
         };
 
         let length = match usize::try_from(self.read_fixnum()?) {
             Ok(val) => val * 2,
-            Err(_) => return Err(LoadError::ParserError("Could not parse array length (could not convert array length to usize)".to_string())),
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse array length (could not convert array length to usize)".to_string(), ErrorLocation::UNKNOWN)),
         };
 
-        let mut buffer = vec![0; length];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read bignum: {}, was expecting {} bytes", err, length)));
-        }
-
-        let mut value: RubyBignum = 0;
+        let buffer = self.read_bytes("bignum", length)?;
 
+        let too_big = || LoadError::ParserError(ErrorCode::BadBignum, "Could not parse bignum, it doesn't fit into 64 bits".to_string(), ErrorLocation::UNKNOWN);
+        let mut magnitude: u64 = 0;
         for (i, byte) in buffer.iter().enumerate() {
-            let shift_bits = match u32::try_from(i * 8) {
-                Ok(val) => val,
-                Err(_) => return Err(LoadError::ParserError("Could not parse bignum, exponent was too big".to_string())),
-            };
-            value += (*byte as RubyBignum) << shift_bits;
+            // zero padding past 64 bits is fine
+            if *byte == 0 {
+                continue;
+            }
+            let shift_bits = u32::try_from(i * 8).ok().filter(|bits| *bits < u64::BITS).ok_or_else(too_big)?;
+            magnitude |= (*byte as u64) << shift_bits;
         }
 
-        if !is_positive {
-            value *= -1;
+        if is_positive {
+            RubyBignum::try_from(magnitude).map_err(|_| too_big())
+        } else if magnitude == RubyBignum::MIN.unsigned_abs() {
+            Ok(RubyBignum::MIN)
+        } else {
+            Ok(-RubyBignum::try_from(magnitude).map_err(|_| too_big())?)
         }
-
-        self.objects.push(RubyObject::BigNum(value));
-        Ok(self.objects.len()-1)
     }
 
     fn read_regexp(&mut self) -> Result<ObjectID, LoadError> {
         let pattern = self.read_sequence()?;
 
         let mut buffer: [u8; 1] = [0; 1];
-        if let Err(err) = self.reader.read_exact(&mut buffer) {
-            return Err(LoadError::IoError(format!("Failed to read regexp's options byte: {}", err)));
+        if let Err(err) = self.read_exact(&mut buffer) {
+            return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read regexp's options byte: {}", err), ErrorLocation::UNKNOWN));
         }
 
         let options = buffer[0] as i8;
@@ -442,82 +1914,186 @@ impl<'a, T: BufRead> Loader<'a, T> {
         Ok(self.objects.len()-1)
     }
 
-    fn read_struct(&mut self) -> Result<ObjectID, LoadError> {
+    fn start_struct(&mut self) -> Result<Step, LoadError> {
         self.objects.push(RubyObject::Incomplete(IncompleteObject::Struct));
-        let struct_id = self.objects.len()-1;
+        let id = self.objects.len()-1;
 
-        let name = match self.read_value()? {
-            RubyValue::Symbol(symbol_id) => symbol_id,
-            value => return Err(LoadError::ParserError(format!("Could not parse struct, expected a symbol or a symbol link, got {:?}", value)))
-        };
+        let name = self.read_class_name("struct")?;
+        let members = self.read_pairs_header()?;
+        Ok(Step::Frame(FrameState::Struct { id, name, members }))
+    }
 
-        let struct_members = self.read_value_pairs_symbol_keys()?;
+    fn start_object(&mut self) -> Result<Step, LoadError> {
+        self.objects.push(RubyObject::Incomplete(IncompleteObject::Object));
+        let id = self.objects.len()-1;
 
-        self.objects[struct_id] = RubyObject::Struct(Struct::new(name, struct_members));
-        Ok(struct_id)
+        let class_name = self.read_class_name("object")?;
+        let instance_variables = self.read_pairs_header()?;
+        Ok(Step::Frame(FrameState::Object { id, class_name, instance_variables }))
     }
 
-    fn read_object(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::Object));
-        let object_id = self.objects.len()-1;
+    fn start_user_class(&mut self) -> Result<Step, LoadError> {
+        self.objects.push(RubyObject::Incomplete(IncompleteObject::UserClass));
+        let id = self.objects.len()-1;
 
-        let class_name = match self.read_value()? {
-            RubyValue::Symbol(symbol_id) => symbol_id,
-            value => return Err(LoadError::ParserError(format!("Could not parse object, expected a symbol or a symbol link, got {:?}", value)))
-        };
+        let name = self.read_class_name("user class")?;
+        Ok(Step::Frame(FrameState::UserClass { id, name }))
+    }
+
+    /// Ruby registers a user defined object only after its data and instance variables have been read
+    /// (the object is created by `_load` from them), so objects inside the instance variables come first
+    fn read_user_defined(&mut self) -> Result<ObjectID, LoadError> {
+        let (user_defined, range) = self.read_user_defined_data()?;
+        Ok(self.register_user_defined(user_defined, range))
+    }
 
-        let instance_variables = self.read_value_pairs_symbol_keys()?;
+    fn read_user_defined_data(&mut self) -> Result<(UserDefined, Option<Range<usize>>), LoadError> {
+        let class_name = self.read_class_name("user defined")?;
 
-        self.objects[object_id] = RubyObject::Object(Object::new(class_name, instance_variables));
-        Ok(object_id)
+        let limit = match self.symbols.get(class_name) {
+            Some(name) => self.options.user_defined_limits.get(&**name).copied(),
+            None => None,
+        };
+        let (user_defined, range) = match limit {
+            Some(limit) => (self.read_limited_user_defined_data(class_name, limit)?, None),
+            None => {
+                let (data, range) = self.read_payload_sequence()?;
+                (UserDefined::new(class_name, data), range)
+            }
+        };
+        if let Some(visitor) = &mut self.visitor {
+            if self.visits.next() {
+                let class_name = self.symbols.get(class_name).map_or("", |name| &**name);
+                visited(visitor.on_user_defined(class_name, user_defined.get_data()))?;
+            }
+        }
+        Ok((user_defined, range))
     }
 
-    fn read_user_class(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::UserClass));
-        let user_class_id = self.objects.len()-1;
+    fn register_user_defined(&mut self, user_defined: UserDefined, range: Option<Range<usize>>) -> ObjectID {
+        self.objects.push(RubyObject::UserDefined(user_defined));
+        self.borrow_payload(self.objects.len()-1, range);
+        self.objects.len()-1
+    }
 
-        let name = match self.read_value()? {
-            RubyValue::Symbol(symbol_id) => symbol_id,
-            value => return Err(LoadError::ParserError(format!("Could not parse user class, expected a symbol or a symbol link, got {:?}", value)))
+    fn read_limited_user_defined_data(&mut self, class_name: SymbolID, limit: PayloadLimit) -> Result<UserDefined, LoadError> {
+        let data_len = match usize::try_from(self.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse byte sequence length (length was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        let kept_len = match limit {
+            PayloadLimit::Skip => 0,
+            PayloadLimit::Truncate(max_len) => data_len.min(max_len),
         };
 
-        let wrapped_object = self.read_value()?;
+        let data = self.read_bytes("byte sequence", kept_len)?;
+        self.skip_bytes("byte sequence", data_len - kept_len)?;
 
-        self.objects[user_class_id] = RubyObject::UserClass(UserClass::new(name, wrapped_object));
-        Ok(user_class_id)
+        if kept_len == data_len {
+            Ok(UserDefined::new(class_name, data))
+        } else {
+            Ok(UserDefined::truncated(class_name, data, data_len))
+        }
     }
 
-    fn read_user_defined(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::UserDefined));
-        let user_defined_id = self.objects.len()-1;
+    fn start_user_marshal(&mut self) -> Result<Step, LoadError> {
+        self.objects.push(RubyObject::Incomplete(IncompleteObject::UserMarshal));
+        let id = self.objects.len()-1;
 
-        let class_name = match self.read_value()? {
-            RubyValue::Symbol(symbol_id) => symbol_id,
-            value => return Err(LoadError::ParserError(format!("Could not parse user defined, expected a symbol or a symbol link, got {:?}", value)))
-        };
+        let class_name = self.read_class_name("user marshal")?;
+        Ok(Step::Frame(FrameState::UserMarshal { id, class_name }))
+    }
 
-        let data = self.read_byte_sequence()?;
+    fn start_data(&mut self) -> Result<Step, LoadError> {
+        self.objects.push(RubyObject::Incomplete(IncompleteObject::Data));
+        let id = self.objects.len()-1;
 
-        self.objects[user_defined_id] = RubyObject::UserDefined(UserDefined::new(class_name, data));
-        Ok(user_defined_id)
+        let class_name = self.read_class_name("data object")?;
+        Ok(Step::Frame(FrameState::Data { id, class_name }))
     }
+}
 
-    fn read_user_marshal(&mut self) -> Result<ObjectID, LoadError> {
-        self.objects.push(RubyObject::Incomplete(IncompleteObject::UserMarshal));
-        let user_marshal_id = self.objects.len()-1;
+/// Turns the answer of a `LoadVisitor` into the error stopping the load
+fn visited(flow: ControlFlow<String>) -> Result<(), LoadError> {
+    match flow {
+        ControlFlow::Continue(()) => Ok(()),
+        ControlFlow::Break(reason) => Err(LoadError::Rejected(reason, ErrorLocation::UNKNOWN)),
+    }
+}
 
-        let class_name = match self.read_value()? {
-            RubyValue::Symbol(symbol_id) => symbol_id,
-            value => return Err(LoadError::ParserError(format!("Could not parse user marshal, expected a symbol or a symbol link, got {:?}", value)))
-        };
+/// Error for a value of the type `tag` where `item` needs `expected`
+fn unexpected_value(item: &str, expected: &str, tag: u8) -> LoadError {
+    LoadError::ParserError(
+        ErrorCode::UnexpectedValue,
+        format!("Could not parse {}, expected {}, got {}", item, expected, spec::tag_name(tag).unwrap_or("an unknown value type")),
+        ErrorLocation::UNKNOWN,
+    )
+}
+
+/// `ErrorCode::UnexpectedEnd` if the reader ran out of data, `ErrorCode::ReadFailed` otherwise
+fn read_error_code(error: &io::Error) -> ErrorCode {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => ErrorCode::UnexpectedEnd,
+        _ => ErrorCode::ReadFailed,
+    }
+}
 
-        let wrapped_object = self.read_value()?;
+/// Parses a float the same way Ruby's Marshal does: `nan`, `inf` and `-inf` are written for the special values,
+/// everything else is read like `strtod` reads it, taking the longest prefix that is a decimal number (`2`, `-1.5`,
+/// `1e-5`, `.5`) and ignoring the rest, `0.0` if there is no such prefix
+fn parse_float(bytes: &[u8]) -> f64 {
+    match bytes {
+        b"nan" => return f64::NAN,
+        b"inf" => return f64::INFINITY,
+        b"-inf" => return f64::NEG_INFINITY,
+        _ => (),
+    }
+    let digits = |from: usize| bytes.get(from..).map_or(0, |rest| rest.iter().take_while(|byte| byte.is_ascii_digit()).count());
 
-        self.objects[user_marshal_id] = RubyObject::UserMarshal(UserMarshal::new(class_name, wrapped_object));
-        Ok(user_marshal_id)
+    let start = bytes.iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+    let mut end = start;
+    if matches!(bytes.get(end), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let integer_digits = digits(end);
+    end += integer_digits;
+    let fraction_digits = if bytes.get(end) == Some(&b'.') { digits(end + 1) } else { 0 };
+    if integer_digits == 0 && fraction_digits == 0 {
+        return 0.0;
+    }
+    if bytes.get(end) == Some(&b'.') {
+        end += 1 + fraction_digits;
     }
+    // the exponent only counts if it has digits, "1e" is 1
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign_len = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_digits = digits(end + 1 + sign_len);
+        if exponent_digits > 0 {
+            end += 1 + sign_len + exponent_digits;
+        }
+    }
+
+    let number = bytes.get(start..end).and_then(|number| std::str::from_utf8(number).ok());
+    number.and_then(|number| number.parse().ok()).unwrap_or(0.0)
 }
 
+/// Whether the text of a float has mantissa bytes after a NUL or more significant digits than the shortest text that
+/// reads back as the same value, Ruby 1.8 wrote both while Ruby 1.9 and later write the shortest text
+fn is_legacy_float(bytes: &[u8], value: f64) -> bool {
+    if bytes.contains(&0) {
+        return true;
+    }
+    if !value.is_finite() {
+        return false;
+    }
+    let significant_digits = |text: &[u8]| {
+        let mantissa = text.iter().take_while(|byte| !matches!(byte, b'e' | b'E'));
+        let digits: Vec<u8> = mantissa.filter(|byte| byte.is_ascii_digit()).copied().collect();
+        let digits = digits.iter().position(|digit| *digit != b'0').map_or(&digits[..0], |start| &digits[start..]);
+        digits.len() - digits.iter().rev().take_while(|digit| **digit == b'0').count()
+    };
+    significant_digits(bytes) > significant_digits(format!("{:e}", value).as_bytes())
+}
 
 #[cfg(test)]
 mod tests {
@@ -541,7 +2117,7 @@ mod tests {
 
         let result = loader.load();
         assert!(result.is_err());
-        if ! matches!(result.unwrap_err(), LoadError::ParserError(_)) {
+        if ! matches!(result.unwrap_err(), LoadError::ParserError(..)) {
             panic!("Got wrong error type");
         }
     }
@@ -657,6 +2233,21 @@ mod tests {
         let result = loader.load();
         assert!(result.is_ok());
         assert_eq!(result.unwrap().get_root(), &RubyValue::FixNum(1073741824));
+
+        let input = b"\x04\x08i\x04\xff\xff\xff\xff";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        assert!(matches!(loader.load(), Err(LoadError::ParserError(..))));
+
+        let input = b"\x04\x08i\x02\xff";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        assert!(matches!(loader.load(), Err(LoadError::IoError(..))));
+
+        let input = b"\x04\x08\"\xfa";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        assert!(matches!(loader.load(), Err(LoadError::ParserError(..))));
     }
 
     #[test]
@@ -670,7 +2261,7 @@ mod tests {
         match root {
             RubyValue::Symbol(symbol_id) => {
                 assert_eq!(*symbol_id, 0);
-                assert_eq!(result.get_symbol(*symbol_id).unwrap(), "hello");
+                assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "hello");
             },
             _ => panic!("Got wrong value type"),
         }
@@ -692,7 +2283,7 @@ mod tests {
                         for val in array {
                             match val {
                                 RubyValue::Symbol(symbol_id) => {
-                                    assert_eq!(result.get_symbol(*symbol_id).unwrap(), "hello")
+                                    assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "hello")
                                 }
                                 _ => panic!("Got wrong value type"),
                             }
@@ -899,7 +2490,7 @@ mod tests {
                         let key = hash.keys().next().unwrap();
                         match key {
                             RubyValue::Symbol(symbol_id) => {
-                                assert_eq!(result.get_symbol(*symbol_id).unwrap(), "a");
+                                assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "a");
                             }
                             _ => panic!("Got wrong value type"),
                         }
@@ -934,7 +2525,7 @@ mod tests {
                         let key = hash.keys().next().unwrap();
                         match key {
                             RubyValue::Symbol(symbol_id) => {
-                                assert_eq!(result.get_symbol(*symbol_id).unwrap(), "a");
+                                assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "a");
                             }
                             _ => panic!("Got wrong value type"),
                         }
@@ -960,6 +2551,29 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_collapse_nil_default_hashes() {
+        let load = |input: &[u8], collapse_nil_default_hashes: bool| {
+            let mut reader = BufReader::new(input);
+            Loader::with_options(&mut reader, LoaderOptions { collapse_nil_default_hashes, ..Default::default() }).load().unwrap()
+        };
+
+        // Hash.new(nil).merge(a: 1)
+        let input = b"\x04\x08}\x06:\x06ai\x060";
+        assert!(matches!(load(input, false).get_root(), RubyValue::HashWithDefault(_)));
+        let result = load(input, true);
+        let RubyValue::Hash(object_id) = result.get_root() else { panic!("Got wrong value type") };
+        assert_eq!(result.get_object(*object_id).unwrap().try_as_hash().unwrap().len(), 1);
+
+        // other defaults are kept
+        assert!(matches!(load(b"\x04\x08}\x00i\x06", true).get_root(), RubyValue::HashWithDefault(_)));
+
+        // h = Hash.new(nil); h[1] = h
+        let result = load(b"\x04\x08}\x06i\x06@\x000", true);
+        assert!(matches!(result.get_root(), RubyValue::HashWithDefault(_)));
+        assert!(matches!(result.get_object(0).unwrap(), RubyObject::HashWithDefault(_)));
+    }
+
     #[test]
     fn test_read_class() {
         let input = b"\x04\x08c\x09Test";
@@ -1054,7 +2668,7 @@ mod tests {
                         assert_eq!(result.decode_string(string).unwrap(), "Test");
                         assert_eq!(string.get_instance_variables().as_ref().unwrap().len(), 1);
                         let symbol_id = string.get_instance_variables().as_ref().unwrap().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "E");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "E");
                         match string.get_instance_variable(*symbol_id).unwrap() {
                             RubyValue::Boolean(boolean) => {
                                 assert!(*boolean);
@@ -1069,6 +2683,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_instance_variables_wrapper() {
+        // the instance variables of a symbol are its encoding
+        let input = b"\x04\x08I:\x06a\x06:\x06ET";
+        let mut reader = BufReader::new(&input[..]);
+        let result = Loader::new(&mut reader).load().unwrap();
+        assert_eq!(result.get_root(), &RubyValue::Symbol(0));
+        let encoding = result.get_encoded_symbol(0).unwrap().get_instance_variables();
+        assert_eq!(encoding, &ValuePairsSymbolKeys::from([(1, RubyValue::Boolean(true))]));
+
+        // with ivar_wrappers the instance variables of a string stay on the wrapper
+        let input = b"\x04\x08I\"\x06a\x06:\x06ET";
+        let mut reader = BufReader::new(&input[..]);
+        let options = LoaderOptions { ivar_wrappers: true, ..Default::default() };
+        let result = Loader::with_options(&mut reader, options).load().unwrap();
+        assert_eq!(result.get_root().without_ivars(), &RubyValue::String(0));
+        assert!(result.get_object(0).unwrap().as_string().get_instance_variables().is_none());
+        assert_eq!(result.decode_string_value(result.get_root()).unwrap(), "a");
+
+        // the user defined object is still registered after its instance variables
+        let input = b"\x04\x08[\x07Iu:\x09Test\x061\x06:\x07@a\"\x06b@\x07";
+        let mut reader = BufReader::new(&input[..]);
+        let options = LoaderOptions { ivar_wrappers: true, ..Default::default() };
+        let result = Loader::with_options(&mut reader, options).load().unwrap();
+        let array = result.get_object(result.get_root().as_array()).unwrap().as_array();
+        assert_eq!(array[0], RubyValue::UserDefined(2).with_ivars(vec![(1, RubyValue::String(1))]));
+        assert_eq!(array[1], RubyValue::UserDefined(2));
+        assert!(result.get_object(2).unwrap().as_user_defined().get_instance_variables().is_none());
+    }
+
+    #[test]
+    fn test_read_container_instance_variables() {
+        let load = |input: &[u8]| Loader::new(&mut BufReader::new(input)).load().unwrap();
+
+        // a struct keeps its instance variables apart from its members
+        let result = load(b"\x04\x08IS:\x09Test\x06:\x06ai\x06\x06:\x07@bT");
+        assert_eq!(result.get_root(), &RubyValue::Struct(0));
+        let ruby_struct = result.get_object(0).unwrap().as_struct();
+        assert_eq!(ruby_struct.get_members(), &ValuePairsSymbolKeys::from([(1, RubyValue::FixNum(1))]));
+        assert_eq!(ruby_struct.get_instance_variable(2), Some(&RubyValue::Boolean(true)));
+        assert_eq!(result.ivars_of(result.get_root()), [("@b", &RubyValue::Boolean(true))]);
+
+        // arrays and hashes hold their instance variables in a wrapper
+        let result = load(b"\x04\x08I[\x06i\x06\x06:\x07@aT");
+        assert_eq!(result.get_root(), &RubyValue::Array(0).with_ivars(vec![(0, RubyValue::Boolean(true))]));
+        let result = load(b"\x04\x08I}\x00i\x06\x06:\x07@aT");
+        assert_eq!(result.get_root(), &RubyValue::HashWithDefault(0).with_ivars(vec![(0, RubyValue::Boolean(true))]));
+        assert!(result.get_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_read_instance_variables_of_incomplete_object() {
+        // the wrapped object links to the user class that is still being read, so the instance variables go to a wrapper
+        let input = b"\x04\x08C:\x09TestI@\x00\x00";
+        let mut reader = BufReader::new(&input[..]);
+        let result = Loader::new(&mut reader).load().unwrap();
+        let wrapped = result.get_object(0).unwrap().as_user_class().get_wrapped_object();
+        assert_eq!(wrapped, &RubyValue::UserClass(0).with_ivars(Vec::new()));
+    }
+
     #[test]
     fn test_read_bignum() {
         let input = b"\x04\x08l+\x09\xb9\xa3\x38\x97\x22\x26\x36\x00";
@@ -1105,6 +2779,14 @@ mod tests {
             _ => panic!("Got wrong value type"),
         }
 
+        // -(2**63) fits, 2**63 and 2**64 don't
+        let load = |input: &[u8]| Loader::new(&mut BufReader::new(input)).load();
+        let root = load(b"\x04\x08l-\x09\x00\x00\x00\x00\x00\x00\x00\x80").unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_bignum(), &i64::MIN);
+        let error = load(b"\x04\x08l+\x09\x00\x00\x00\x00\x00\x00\x00\x80").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::BadBignum);
+        let error = load(b"\x04\x08l+\x0a\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::BadBignum);
     }
 
     #[test]
@@ -1121,7 +2803,7 @@ mod tests {
                         assert_eq!(regexp.get_pattern(), "iii");
                         assert_eq!(regexp.get_options(), 0);
                         let symbol_id = regexp.get_instance_variables().as_ref().unwrap().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "E");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "E");
                         match regexp.get_instance_variable(*symbol_id).unwrap() {
                             RubyValue::Boolean(boolean) => {
                                 assert!(!*boolean);
@@ -1147,9 +2829,9 @@ mod tests {
             RubyValue::Struct(object_id) => {
                 match result.get_object(*object_id).unwrap() {
                     RubyObject::Struct(ruby_struct) => {
-                        assert_eq!(result.get_symbol(ruby_struct.get_name()).unwrap(), "Test");
+                        assert_eq!(&**result.get_symbol(ruby_struct.get_name()).unwrap(), "Test");
                         let symbol_id = ruby_struct.get_members().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "a");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "a");
                         match ruby_struct.get_member(*symbol_id).unwrap() {
                             RubyValue::FixNum(fixnum) => {
                                 assert_eq!(*fixnum, 1);
@@ -1175,9 +2857,9 @@ mod tests {
             RubyValue::Object(object_id) => {
                 match result.get_object(*object_id).unwrap() {
                     RubyObject::Object(object) => {
-                        assert_eq!(result.get_symbol(object.get_class_name()).unwrap(), "Test");
+                        assert_eq!(&**result.get_symbol(object.get_class_name()).unwrap(), "Test");
                         let symbol_id = object.get_instance_variables().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "@a");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "@a");
                         match object.get_instance_variable(*symbol_id).unwrap() {
                             RubyValue::FixNum(fixnum) => {
                                 assert_eq!(*fixnum, 1);
@@ -1203,10 +2885,10 @@ mod tests {
             RubyValue::UserClass(object_id) => {
                 match result.get_object(*object_id).unwrap() {
                     RubyObject::UserClass(user_class) => {
-                        assert_eq!(result.get_symbol(user_class.get_name()).unwrap(), "Test");
+                        assert_eq!(&**result.get_symbol(user_class.get_name()).unwrap(), "Test");
                         assert_eq!(user_class.decode_wrapped_string(&result).unwrap(), "a");
                         let symbol_id = user_class.get_instance_variables().as_ref().unwrap().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "E");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "E");
                         match user_class.get_instance_variable(*symbol_id).unwrap() {
                             RubyValue::Boolean(boolean) => {
                                 assert!(*boolean);
@@ -1232,10 +2914,10 @@ mod tests {
             RubyValue::UserDefined(object_id) => {
                 match result.get_object(*object_id).unwrap() {
                     RubyObject::UserDefined(user_defined) => {
-                        assert_eq!(result.get_symbol(user_defined.get_class_name()).unwrap(), "Test");
+                        assert_eq!(&**result.get_symbol(user_defined.get_class_name()).unwrap(), "Test");
                         assert_eq!(user_defined.get_data(), &vec![b'1']);
                         let symbol_id = user_defined.get_instance_variables().as_ref().unwrap().keys().next().unwrap();
-                        assert_eq!(result.get_symbol(*symbol_id).unwrap(), "E");
+                        assert_eq!(&**result.get_symbol(*symbol_id).unwrap(), "E");
                         match user_defined.get_instance_variable(*symbol_id).unwrap() {
                             RubyValue::Boolean(boolean) => {
                                 assert!(!*boolean);
@@ -1248,6 +2930,45 @@ mod tests {
             }
             _ => panic!("Got wrong value type"),
         }
+
+        // the user defined object is registered after the objects in its instance variables
+        let input = b"\x04\x08[\x07Iu:\x09Test\x061\x06:\x07@a\"\x06b@\x07";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let result = loader.load().unwrap();
+
+        let array = result.get_object(result.get_root().as_array()).unwrap().as_array();
+        assert_eq!(array[0], RubyValue::UserDefined(2));
+        assert_eq!(array[1], RubyValue::UserDefined(2));
+        assert_eq!(result.get_object(1).unwrap().as_string().get_string(), b"b");
+    }
+
+    #[test]
+    fn test_user_defined_limits() {
+        // [Test._load("abcdef"), Other._load("abcdef"), 1]
+        let input = b"\x04\x08[\x08u:\x09Test\x0babcdefu:\x0aOther\x0babcdefi\x06";
+        let load = |limit: PayloadLimit| {
+            let mut reader = BufReader::new(&input[..]);
+            let options = LoaderOptions { user_defined_limits: HashMap::from([("Test".to_string(), limit)]), ..Default::default() };
+            Loader::with_options(&mut reader, options).load().unwrap()
+        };
+
+        let result = load(PayloadLimit::Truncate(2));
+        let test = result.get_object(1).unwrap().try_as_user_defined().unwrap();
+        assert_eq!(test.get_data(), b"ab");
+        assert!(test.is_truncated());
+        assert_eq!(test.get_original_len(), 6);
+        let other = result.get_object(2).unwrap().try_as_user_defined().unwrap();
+        assert_eq!(other.get_data(), b"abcdef");
+        assert!(!other.is_truncated());
+        assert_eq!(result.get_object(0).unwrap().try_as_array().unwrap()[2], RubyValue::FixNum(1));
+
+        let result = load(PayloadLimit::Skip);
+        assert_eq!(result.get_object(1).unwrap().try_as_user_defined().unwrap().get_data(), b"");
+
+        // payloads within the limit are complete
+        let result = load(PayloadLimit::Truncate(6));
+        assert!(!result.get_object(1).unwrap().try_as_user_defined().unwrap().is_truncated());
     }
 
     #[test]
@@ -1261,7 +2982,7 @@ mod tests {
             RubyValue::UserMarshal(object_id) => {
                 match result.get_object(*object_id).unwrap() {
                     RubyObject::UserMarshal(user_marshal) => {
-                        assert_eq!(result.get_symbol(user_marshal.get_class_name()).unwrap(), "Test");
+                        assert_eq!(&**result.get_symbol(user_marshal.get_class_name()).unwrap(), "Test");
                         if let RubyValue::FixNum(fixnum) = user_marshal.get_wrapped_object() {
                             assert_eq!(*fixnum, 1);
                         } else {
@@ -1275,6 +2996,628 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_data() {
+        // [Test._load_data([1]), the same object again]
+        let input = b"\x04\x08[\x07d:\x09Test[\x06i\x06@\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let result = loader.load().unwrap();
+
+        let elements = result.get_object(0).unwrap().as_array();
+        assert_eq!(elements, &[RubyValue::Data(1), RubyValue::Data(1)]);
+        let data = result.get_object(1).unwrap().as_data();
+        assert_eq!(&**result.get_symbol(data.get_class_name()).unwrap(), "Test");
+        assert_eq!(data.get_state(), &RubyValue::Array(2));
+    }
+
+    #[test]
+    fn test_read_extended() {
+        // o = Object.new; o.extend(Comparable); o.extend(Enumerable); [o, o]
+        let input = b"\x04\x08[\x07e:\x0fEnumerablee:\x0fComparableo:\x0bObject\x00@\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let result = loader.load().unwrap();
+
+        let object = result.get_object(1).unwrap().as_object();
+        assert_eq!(&**result.get_symbol(object.get_class_name()).unwrap(), "Object");
+        let extended_modules: Vec<&str> = object.get_extended_modules().iter().map(|module| &**result.get_symbol(*module).unwrap()).collect();
+        assert_eq!(extended_modules, ["Enumerable", "Comparable"]);
+
+        // user defined object with instance variables, the modules come after the instance variables marker
+        let input = b"\x04\x08Ie:\x06Mu:\x08Foo\x06a\x06:\x06ET";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let result = loader.load().unwrap();
+        let user_defined = result.get_object(0).unwrap().as_user_defined();
+        assert_eq!(user_defined.get_extended_modules(), &[0]);
+        assert!(user_defined.get_instance_variables().is_some());
+
+        // only objects and user defined objects can be extended
+        let input = b"\x04\x08e:\x06M[\x00";
+        let mut reader = BufReader::new(&input[..]);
+        assert!(Loader::new(&mut reader).load().is_err());
+    }
+
+    #[test]
+    fn test_load_iter() {
+        // [:a], :a, "b"
+        let input = b"\x04\x08[\x06:\x06a\x04\x08:\x06a\x04\x08\"\x06b";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let documents: Vec<Root> = loader.load_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(documents.len(), 3);
+        // symbol and object ids start over in every document
+        assert_eq!(documents[1].get_root(), &RubyValue::Symbol(0));
+        assert_eq!(documents[2].get_root(), &RubyValue::String(0));
+
+        // a broken document ends the iteration
+        let input = b"\x04\x08i\x06\x04\x08[\x07i\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let results: Vec<bool> = loader.load_iter().map(|document| document.is_ok()).collect();
+        assert_eq!(results, [true, false]);
+    }
+
+    #[test]
+    fn test_load_object_at() {
+        // ["abc", Test(@a=:x, @b=@1), :x]
+        let input = b"\x04\x08[\x08\"\x08abco:\x09Test\x07:\x07@a:\x06x:\x07@b@\x06;\x07";
+        let full = Loader::new(&mut &input[..]).load().unwrap();
+
+        // the object starts at offset 9, after the array and the string
+        let context = LoadContext::from_root(&full, 0, 2);
+        let mut reader = std::io::Cursor::new(&input[..]);
+        let partial = Loader::new(&mut reader).load_object_at(9, &context).unwrap();
+        assert_eq!(partial.get_root(), &RubyValue::Object(2));
+        assert_eq!(partial.get_object(2), full.get_object(2));
+        assert_eq!(partial.get_object(1), Some(&RubyObject::Unloaded(ObjectKind::String)));
+        assert_eq!(&partial.get_symbols()[..], &full.get_symbols()[..]);
+
+        // the symbol link at offset 30 needs the symbols before it
+        let context = LoadContext::from_root(&full, 4, 3);
+        let partial = Loader::new(&mut reader).load_object_at(30, &context).unwrap();
+        assert_eq!(&**partial.get_symbol(partial.get_root().as_symbol()).unwrap(), "x");
+        assert!(Loader::new(&mut reader).load_object_at(30, &LoadContext::default()).is_err());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut input = b"\x04\x08".to_vec();
+        input.extend([b'[', b'\x06'].repeat(100_000));
+        input.push(b'0');
+        let mut reader = BufReader::new(&input[..]);
+        assert!(matches!(Loader::new(&mut reader).load(), Err(LoadError::DepthLimitExceeded(256, _))));
+
+        // [[[nil]]] is 4 levels deep
+        let input = b"\x04\x08[\x06[\x06[\x060";
+        let options = |max_depth| LoaderOptions { max_depth, ..Default::default() };
+        assert!(Loader::with_options(&mut &input[..], options(4)).load().is_ok());
+        assert!(matches!(Loader::with_options(&mut &input[..], options(3)).load(), Err(LoadError::DepthLimitExceeded(3, _))));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        // loading keeps the values being read on the heap, any depth fits into the stack of the test thread
+        let levels = 200_000;
+        let mut input = b"\x04\x08".to_vec();
+        for level in 0..levels {
+            match level % 4 {
+                0 => input.extend(b"[\x06"),
+                1 => input.extend(b"{\x06i\x06"),
+                2 => input.extend(b"o:\x09Test\x06:\x07@a"),
+                _ => input.extend(b"I[\x06"),
+            }
+        }
+        input.push(b'0');
+        for level in 0..levels {
+            if level % 4 == 3 {
+                input.extend(b"\x06:\x07@bT");
+            }
+        }
+
+        let options = LoaderOptions { max_depth: usize::MAX, ..Default::default() };
+        let root = Loader::with_options(&mut &input[..], options).load().unwrap();
+        let mut value = root.get_root();
+        for _ in 0..levels {
+            value = match value {
+                RubyValue::Array(id) => &root.get_object(*id).unwrap().as_array()[0],
+                RubyValue::Hash(id) => &root.get_object(*id).unwrap().as_hash()[&RubyValue::FixNum(1)],
+                RubyValue::Object(id) => root.get_object(*id).unwrap().as_object().get_instance_variables().values().next().unwrap(),
+                RubyValue::WithIvars(wrapper) => &root.get_object(wrapper.0.as_array()).unwrap().as_array()[0],
+                other => panic!("Got wrong value type {:?}", other),
+            };
+        }
+        assert_eq!(value, &RubyValue::Nil);
+    }
+
+    #[test]
+    fn test_allocation_limits() {
+        // a string and an array announcing 2^30 bytes and elements in a few bytes are fine without limits, nothing
+        // is allocated for data that isn't there
+        for input in [&b"\x04\x08\"\x04\x00\x00\x00\x40abc"[..], b"\x04\x08[\x04\x00\x00\x00\x40i\x06"] {
+            let mut reader = BufReader::new(input);
+            assert!(matches!(Loader::new(&mut reader).load(), Err(LoadError::IoError(..))));
+        }
+
+        let options = |max_item_bytes, max_total_bytes| LoaderOptions { max_item_bytes, max_total_bytes, ..Default::default() };
+        // ["abc", "de"]
+        let input = b"\x04\x08[\x07\"\x08abc\"\x07de";
+        let array_bytes = 2 * std::mem::size_of::<RubyValue>();
+        assert!(Loader::with_options(&mut &input[..], options(array_bytes, array_bytes + 5)).load().is_ok());
+        assert!(matches!(
+            Loader::with_options(&mut &input[..], options(array_bytes - 1, usize::MAX)).load(),
+            Err(LoadError::AllocationLimitExceeded(..))
+        ));
+        assert!(matches!(
+            Loader::with_options(&mut &input[..], options(usize::MAX, array_bytes + 4)).load(),
+            Err(LoadError::AllocationLimitExceeded(..))
+        ));
+    }
+
+    #[test]
+    fn test_error_location() {
+        let load_err = |input: &[u8]| Loader::new(&mut BufReader::new(input)).load().unwrap_err();
+
+        // [1, {1 => <unknown type X>}]
+        let error = load_err(b"\x04\x08[\x07i\x06{\x06i\x06X");
+        assert!(matches!(error, LoadError::ParserError(..)));
+        assert_eq!(error.location(), ErrorLocation { offset: Some(11), object_offset: Some(6) });
+        assert_eq!(error.to_string(), "Parser Error: Unknown value type: 88 at offset 11 (in the object at offset 6)");
+
+        // truncated string
+        let error = load_err(b"\x04\x08\"\x08ab");
+        assert_eq!(error.location(), ErrorLocation { offset: Some(6), object_offset: Some(2) });
+        let error = load_err(b"\x04");
+        assert_eq!(error.location(), ErrorLocation { offset: Some(0), object_offset: None });
+    }
+
+    #[test]
+    fn test_error_code() {
+        let code = |input: &[u8]| Loader::new(&mut BufReader::new(input)).load().unwrap_err().code();
+
+        assert_eq!(code(b"\x04\x09i\x06"), ErrorCode::UnsupportedVersion);
+        assert_eq!(code(b"\x03\x08i\x06"), ErrorCode::UnsupportedVersion);
+        assert_eq!(code(b"\x04\x08X"), ErrorCode::UnknownTag);
+        assert_eq!(code(b"\x04\x08\"\x08ab"), ErrorCode::UnexpectedEnd);
+        assert_eq!(code(b"\x04\x08;\x06"), ErrorCode::BadSymbolLink);
+        assert_eq!(code(b"\x04\x08[\x06@\x09"), ErrorCode::BadObjectLink);
+        assert_eq!(code(b"\x04\x08[\xfa"), ErrorCode::BadLength);
+        assert_eq!(code(b"\x04\x08c\x06\xff"), ErrorCode::BadUtf8);
+        assert_eq!(code(b"\x04\x08l*\x06\x00\x00"), ErrorCode::BadBignum);
+        assert_eq!(ErrorCode::BadSymbolLink.to_string(), "E021");
+    }
+
+    #[test]
+    fn test_load_lenient() {
+        let load = |input: &[u8]| {
+            let mut reader = BufReader::new(input);
+            Loader::new(&mut reader).load_lenient().unwrap()
+        };
+
+        // the truncated string becomes nil, the elements before it are kept
+        let (root, errors) = load(b"\x04\x08[\x08i\x06i\x07\"\x09ab");
+        assert_eq!(root.get_object(0).unwrap().as_array(), &vec![RubyValue::FixNum(1), RubyValue::FixNum(2), RubyValue::Nil]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], LoadError::IoError(ErrorCode::UnexpectedEnd, _, ErrorLocation { offset: Some(12), object_offset: Some(8) })));
+
+        // nothing is read after an unknown type
+        let (root, errors) = load(b"\x04\x08o:\x08Foo\x07:\x07@ai\x06:\x07@bXi\x06");
+        let object = root.get_object(0).unwrap().as_object();
+        assert_eq!(object.get_instance_variables().values().collect::<Vec<_>>(), vec![&RubyValue::FixNum(1), &RubyValue::Nil]);
+        assert_eq!(errors.len(), 1);
+
+        // loading continues after dangling links and non-symbol instance variable names
+        let (root, errors) = load(b"\x04\x08[\x08@\x09o:\x08Foo\x07i\x06i\x07:\x07@ai\x08i\x09");
+        let array = root.get_object(0).unwrap().as_array();
+        assert_eq!(array[0], RubyValue::Nil);
+        assert_eq!(array[2], RubyValue::FixNum(4));
+        let object = root.get_object(1).unwrap().as_object();
+        assert_eq!(object.get_instance_variables().values().collect::<Vec<_>>(), vec![&RubyValue::FixNum(3)]);
+        assert_eq!(errors.iter().map(LoadError::code).collect::<Vec<_>>(), vec![ErrorCode::BadObjectLink, ErrorCode::UnexpectedValue]);
+        let mut reader = BufReader::new(&b"\x04\x08[\x06@\x09"[..]);
+        assert!(Loader::new(&mut reader).load().is_err());
+
+        // objects that were still being read become placeholders
+        let (root, errors) = load(b"\x04\x08[\x06C");
+        assert_eq!(root.get_object(0).unwrap().as_array(), &vec![RubyValue::Nil]);
+        assert_eq!(root.get_object(1), Some(&RubyObject::Unloaded(ObjectKind::UserClass)));
+        assert_eq!(errors.len(), 1);
+
+        let mut reader = BufReader::new(&b"\x05\x00"[..]);
+        assert!(Loader::new(&mut reader).load_lenient().is_err());
+    }
+
+    #[test]
+    fn test_loader_options() {
+        let options = LoaderOptions::default().with_max_depth(16).with_user_defined_limit("Blob", PayloadLimit::Skip).with_lenient(true);
+        let mut expected = LoaderOptions { max_depth: 16, lenient: true, ..Default::default() };
+        expected.user_defined_limits.insert("Blob".to_string(), PayloadLimit::Skip);
+        assert_eq!(options, expected);
+
+        // [Foo(@a=1), Bar(@a=2)]
+        let input = b"\x04\x08[\x07o:\x08Foo\x06:\x07@ai\x06o:\x08Bar\x06;\x06i\x07";
+        let options = LoaderOptions::default().with_permitted_classes(["Foo", "Bar"]);
+        assert!(Loader::with_options(&mut &input[..], options).load().is_ok());
+        let options = LoaderOptions::default().with_permitted_classes(["Foo"]);
+        let err = Loader::with_options(&mut &input[..], options.clone()).load().unwrap_err();
+        assert!(matches!(&err, LoadError::ClassNotPermitted(name, _) if name == "Bar"));
+        assert_eq!(err.to_string(), "Class Not Permitted: Bar at offset 23 (in the object at offset 17)");
+        let (root, errors) = Loader::with_options(&mut &input[..], options.clone()).load_lenient().unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_array().len(), 2);
+        assert!(matches!(root.get_object(1), Some(RubyObject::Object(_))));
+        assert_eq!(root.get_object(2), Some(&RubyObject::Unloaded(ObjectKind::Object)));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "Class Not Permitted: Bar at offset 23");
+        // structs, user defined, user marshal and user class objects are checked as well
+        for input in [&b"\x04\x08S:\x08Bar\x00"[..], b"\x04\x08u:\x08Bar\x00", b"\x04\x08U:\x08Bar[\x00", b"\x04\x08C:\x08Bar[\x00"] {
+            let err = Loader::with_options(&mut &input[..], options.clone()).load().unwrap_err();
+            assert_eq!(err.code(), ErrorCode::ClassNotPermitted);
+            let (root, _) = Loader::with_options(&mut &input[..], options.clone()).load_lenient().unwrap();
+            assert!(matches!(root.get_object(0), Some(RubyObject::Unloaded(_))));
+        }
+
+        // {1=>2, 1=>3}
+        let input = b"\x04\x08{\x07i\x06i\x07i\x06i\x08";
+        let first_value = |options: LoaderOptions| {
+            let root = Loader::with_options(&mut &input[..], options).load()?;
+            let hash = root.get_object(0).unwrap().as_hash();
+            assert_eq!(hash.len(), 1);
+            Ok::<_, LoadError>(hash[&RubyValue::FixNum(1)].clone())
+        };
+        assert_eq!(first_value(LoaderOptions::default()).unwrap(), RubyValue::FixNum(3));
+        assert_eq!(first_value(LoaderOptions::default().with_duplicate_hash_keys(DuplicateKeys::FirstWins)).unwrap(), RubyValue::FixNum(2));
+        assert!(first_value(LoaderOptions::default().with_duplicate_hash_keys(DuplicateKeys::Error)).is_err());
+        assert_eq!(first_value(LoaderOptions::default().with_duplicate_hash_keys(DuplicateKeys::CollectAll)).unwrap(), RubyValue::FixNum(3));
+
+        // {1=>2, 1=>3, 1=>4}
+        let input = b"\x04\x08{\x08i\x06i\x07i\x06i\x08i\x06i\x09";
+        let options = LoaderOptions::default().with_duplicate_hash_keys(DuplicateKeys::CollectAll);
+        let root = Loader::with_options(&mut &input[..], options).load().unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_hash()[&RubyValue::FixNum(1)], RubyValue::FixNum(4));
+        let replaced = [(RubyValue::FixNum(1), RubyValue::FixNum(2)), (RubyValue::FixNum(1), RubyValue::FixNum(3))];
+        assert_eq!(root.get_duplicate_hash_pairs(0), Some(&replaced[..]));
+        assert_eq!(root.get_diagnostics().len(), 2);
+        let input = b"\x04\x08{\x07i\x06i\x07i\x06i\x08";
+
+        // lenient loads keep their errors in the loader
+        let options = LoaderOptions::default().with_duplicate_hash_keys(DuplicateKeys::Error).with_lenient(true);
+        let mut reader = &input[..];
+        let mut loader = Loader::with_options(&mut reader, options);
+        let root = loader.load().unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_hash()[&RubyValue::FixNum(1)], RubyValue::FixNum(2));
+        assert_eq!(loader.recovered_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = |input: &[u8]| {
+            let root = Loader::new(&mut BufReader::new(input)).load().unwrap();
+            root.get_diagnostics().iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>()
+        };
+
+        // {1=>2, 1=>3}
+        assert_eq!(diagnostics(b"\x04\x08{\x07i\x06i\x07i\x06i\x08"), vec!["Duplicate hash key at offset 2"]);
+        // [:"", <the array with @a=1>]
+        assert_eq!(
+            diagnostics(b"\x04\x08[\x07:\x00I@\x00\x06:\x07@ai\x06"),
+            vec!["Empty symbol at offset 4", "Instance variables on Array at offset 6"],
+        );
+        // "a" in an encoding named XYZ
+        assert_eq!(diagnostics(b"\x04\x08I\"\x06a\x06:\x0dencoding\"\x08XYZ"), vec!["Unknown encoding XYZ at offset 2"]);
+        #[cfg(any(feature = "encoding", feature = "encoding_rs"))]
+        assert!(diagnostics(b"\x04\x08I\"\x06a\x06:\x0dencoding\"\x0eShift_JIS").is_empty());
+    }
+
+    #[test]
+    fn test_text_decoder() {
+        use crate::text::{TextDecodeError, TextDecoders};
+
+        // "HAL" and :HAL in a made-up encoding shifting letters by one
+        let input = b"\x04\x08[\x07I\"\x08HAL\x06:\x0dencoding\"\x0bGame-1I:\x08HAL\x06;\x00@\x07";
+        let mut decoders = TextDecoders::new();
+        decoders.register("Game-1", |_: &str, bytes: &[u8]| Ok::<_, TextDecodeError>(bytes.iter().map(|&byte| char::from(byte + 1)).collect()));
+
+        let root = Loader::new(&mut &input[..]).load().unwrap();
+        assert_eq!(root.get_diagnostics().len(), 2);
+        assert_eq!(&**root.get_symbol(1).unwrap(), "HAL");
+        let root = Loader::new(&mut &input[..]).with_text_decoder(&decoders).load().unwrap();
+        assert!(root.get_diagnostics().is_empty());
+        assert_eq!(&**root.get_symbol(1).unwrap(), "IBM");
+        let string = &root.get_object(0).unwrap().as_array()[0];
+        assert_eq!(root.decode_string_value_with(string, &decoders).unwrap(), "IBM");
+        assert_eq!(root.decode_string_value(string).unwrap_err().to_string(), "Encoding Error: Could not find encoding Game-1");
+    }
+
+    #[test]
+    fn test_progress() {
+        // a string of 200000 bytes, the fixnum of its length takes 4 bytes
+        let mut input = b"\x04\x08\"\x03\x40\x0d\x03".to_vec();
+        input.extend(std::iter::repeat(b'a').take(200_000));
+        let progress = ProgressCounter::new();
+        let options = LoaderOptions::default().with_progress(&progress);
+        let mut reader = &input[..];
+        let mut loader = Loader::with_options(&mut reader, options.clone());
+        loader.load().unwrap();
+        assert_eq!(progress.bytes_read(), input.len() as u64);
+        assert_eq!(loader.bytes_read(), input.len() as u64);
+
+        // a failed load leaves the count of the last update, which came after reading the string in one piece
+        let progress = ProgressCounter::new();
+        let options = LoaderOptions::default().with_progress(&progress);
+        let mut reader = &input[..input.len() - 1];
+        assert!(Loader::with_options(&mut reader, options).load().is_err());
+        assert_eq!(progress.bytes_read(), input.len() as u64 - 1);
+
+        // small documents only update it when they are complete
+        let progress = ProgressCounter::new();
+        let options = LoaderOptions::default().with_progress(&progress);
+        assert!(Loader::with_options(&mut &b"\x04\x08[\x07i\x06"[..], options).load().is_err());
+        assert_eq!(progress.bytes_read(), 0);
+    }
+
+        #[test]
+    fn test_legacy_versions() {
+        // [Foo, 1] in Marshal 4.6 with the old class or module tag
+        let input = b"\x04\x06[\x07M\x08Fooi\x06";
+        let err = Loader::new(&mut &input[..]).load().unwrap_err();
+        assert_eq!(err.to_string(), "Parser Error: Unsupported Marshal version 4.6 at offset 2");
+        let options = LoaderOptions::default().with_legacy_versions(true);
+        let root = Loader::with_options(&mut &input[..], options.clone()).load().unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_array(), &vec![RubyValue::ClassOrModule(1), RubyValue::FixNum(1)]);
+
+        assert!(Loader::with_options(&mut &b"\x04\x09i\x06"[..], options.clone()).load().is_err());
+        assert!(Loader::with_options(&mut &b"\x03\x08i\x06"[..], options).load().is_err());
+    }
+
+    #[test]
+    fn test_symbol_limits() {
+        // [:abc, :de, :abc]
+        let input = b"\x04\x08[\x08:\x08abc:\x07de;\x00";
+        let options = |max_symbol_len, max_symbols| LoaderOptions { max_symbol_len, max_symbols, ..Default::default() };
+        assert!(Loader::with_options(&mut &input[..], options(3, 2)).load().is_ok());
+
+        let error = Loader::with_options(&mut &input[..], options(2, usize::MAX)).load().unwrap_err();
+        assert!(matches!(error, LoadError::SymbolLimitExceeded(..)));
+        assert_eq!(error.location(), ErrorLocation { offset: Some(4), object_offset: Some(2) });
+        let error = Loader::with_options(&mut &input[..], options(usize::MAX, 1)).load().unwrap_err();
+        assert_eq!(error.to_string(), "Symbol Limit Exceeded: symbol exceeds the limit of 1 symbols per document at offset 9 (in the object at offset 2)");
+    }
+
+    #[test]
+    fn test_object_limit() {
+        // [1.5, "a", "a" with a link to it, :b]
+        let input = b"\x04\x08[\x09f\x081.5I\"\x06a\x06:\x06ET@\x07:\x06b";
+        assert!(Loader::with_options(&mut &input[..], LoaderOptions::default().with_max_objects(3)).load().is_ok());
+
+        let error = Loader::with_options(&mut &input[..], LoaderOptions::default().with_max_objects(2)).load().unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ObjectLimitExceeded);
+        assert_eq!(error.to_string(), "Object Limit Exceeded: object exceeds the limit of 2 objects per document at offset 10 (in the object at offset 9)");
+    }
+
+    #[test]
+    fn test_visitor() {
+        #[derive(Default)]
+        struct Stats {
+            strings: usize,
+            objects: Vec<(String, Vec<String>)>,
+            rejected_class: Option<&'static str>,
+        }
+        impl LoadVisitor for Stats {
+            fn on_string(&mut self, _bytes: &[u8]) -> ControlFlow<String> {
+                self.strings += 1;
+                ControlFlow::Continue(())
+            }
+
+            fn on_object(&mut self, class_name: &str, instance_variables: &[(&str, &RubyValue)]) -> ControlFlow<String> {
+                if self.rejected_class == Some(class_name) {
+                    return ControlFlow::Break(format!("class {}", class_name));
+                }
+                self.objects.push((class_name.to_string(), instance_variables.iter().map(|(name, _)| name.to_string()).collect()));
+                ControlFlow::Continue(())
+            }
+        }
+
+        // [Foo(@a="x"), "yy"]
+        let input = b"\x04\x08[\x07o:\x08Foo\x06:\x07@a\"\x06x\"\x07yy";
+        let mut stats = Stats::default();
+        assert!(Loader::new(&mut &input[..]).with_visitor(&mut stats).load().is_ok());
+        assert_eq!(stats.strings, 2);
+        assert_eq!(stats.objects, [("Foo".to_string(), vec!["@a".to_string()])]);
+
+        let mut stats = Stats { rejected_class: Some("Foo"), ..Default::default() };
+        let error = Loader::new(&mut &input[..]).with_visitor(&mut stats).load().unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Rejected);
+        assert_eq!(error.to_string(), "Rejected: class Foo at offset 18 (in the object at offset 4)");
+    }
+
+    #[test]
+    fn test_visitor_resumable() {
+        #[derive(Default)]
+        struct Events(Vec<String>);
+        impl LoadVisitor for Events {
+            fn on_symbol(&mut self, name: &str) -> ControlFlow<String> {
+                self.0.push(format!("symbol {}", name));
+                ControlFlow::Continue(())
+            }
+
+            fn on_string(&mut self, bytes: &[u8]) -> ControlFlow<String> {
+                self.0.push(format!("string {:?}", bytes));
+                ControlFlow::Continue(())
+            }
+
+            fn on_array(&mut self, len: usize) -> ControlFlow<String> {
+                self.0.push(format!("array {}", len));
+                ControlFlow::Continue(())
+            }
+
+            fn on_object(&mut self, class_name: &str, _instance_variables: &[(&str, &RubyValue)]) -> ControlFlow<String> {
+                self.0.push(format!("object {}", class_name));
+                ControlFlow::Continue(())
+            }
+
+            fn on_user_defined(&mut self, class_name: &str, _data: &[u8]) -> ControlFlow<String> {
+                self.0.push(format!("user defined {}", class_name));
+                ControlFlow::Continue(())
+            }
+        }
+
+        // [Foo(@a="x"), "yy" with encoding, U with instance variables]
+        let input = b"\x04\x08[\x08o:\x08Foo\x06:\x07@a\"\x06xI\"\x07yy\x06:\x06ETIu:\x06U\x06z\x06;\x07F";
+        let mut expected = Events::default();
+        Loader::new(&mut &input[..]).with_visitor(&mut expected).load().unwrap();
+        assert_eq!(expected.0.len(), 9);
+
+        // a document split anywhere makes every callback once, the values read again after going back aren't visited again
+        for split in 0..input.len() {
+            let mut events = Events::default();
+            let mut reader = &input[..split];
+            let suspended = match Loader::new(&mut reader).with_visitor(&mut events).load_resumable(None).unwrap() {
+                ResumableLoad::Suspended(suspended) => suspended,
+                ResumableLoad::Complete(_) => panic!("complete at {}", split),
+            };
+            let mut reader = &input[suspended.position() as usize..];
+            let resumed = Loader::new(&mut reader).with_visitor(&mut events).load_resumable(Some(suspended)).unwrap();
+            assert!(matches!(resumed, ResumableLoad::Complete(_)));
+            assert_eq!(events.0, expected.0, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_visitor_borrowed() {
+        #[derive(Default)]
+        struct Payloads(Vec<Vec<u8>>);
+        impl LoadVisitor for Payloads {
+            fn on_string(&mut self, bytes: &[u8]) -> ControlFlow<String> {
+                self.0.push(bytes.to_vec());
+                ControlFlow::Continue(())
+            }
+
+            fn on_user_defined(&mut self, _class_name: &str, data: &[u8]) -> ControlFlow<String> {
+                self.0.push(data.to_vec());
+                ControlFlow::Continue(())
+            }
+        }
+
+        // a borrowing load passes the payloads, the Root still leaves them in the input
+        // ["ab", "", U with instance variables, U]
+        let input = b"\x04\x08[\x09\"\x07ab\"\x00Iu:\x06U\x06x\x06:\x06ETu;\x00\x07yz";
+        let mut payloads = Payloads::default();
+        let (root, borrowed) = Loader::new(&mut &input[..]).with_visitor(&mut payloads).load_borrowed().unwrap();
+        assert_eq!(payloads.0, [b"ab".to_vec(), Vec::new(), b"x".to_vec(), b"yz".to_vec()]);
+        assert_eq!(root.get_object(1).unwrap().as_string().get_string(), b"");
+        assert_eq!(root.get_object(3).unwrap().as_user_defined().get_data(), b"");
+        assert_eq!(borrowed.payloads.len(), 4);
+        assert_eq!(borrowed.payloads.get(&3), Some(&(16..17)));
+        assert_eq!(borrowed.payloads.get(&4), Some(&(26..28)));
+    }
+
+    #[test]
+    fn test_load_with_extent() {
+        let input = b"\x04\x08[\x06i\x06\x04\x08T";
+        let mut reader = &input[..];
+        let mut loader = Loader::new(&mut reader);
+        let (root, extent) = loader.load_with_extent().unwrap();
+        assert_eq!(root.get_object(0).unwrap().as_array(), &vec![RubyValue::FixNum(1)]);
+        assert_eq!(extent, LoadExtent { consumed: 6, trailing_data: true });
+        let (root, extent) = loader.load_with_extent().unwrap();
+        assert_eq!(root.get_root(), &RubyValue::Boolean(true));
+        assert_eq!(extent, LoadExtent { consumed: 3, trailing_data: false });
+
+        assert_eq!(Loader::new(&mut &input[..5]).load_with_extent().unwrap_err().code(), ErrorCode::UnexpectedEnd);
+    }
+
+    #[test]
+    fn test_validate() {
+        let inputs: [&[u8]; 14] = [
+            // [1, 2**40 as a bignum, 1.5, "a" with a link to it, :b, :b]
+            b"\x04\x08[\x0bi\x06l+\x08\x00\x00\x00\x00\x00\x01f\x081.5I\"\x06a\x06:\x06ET@\x08:\x06b;\x07",
+            // #<Point @x=1, @y=[]> extended with M, {nil=>/a/} with default 0, a UserDefined with a link to the empty array
+            b"\x04\x08[\x08e:\x06Mo:\x0aPoint\x07:\x07@xi\x06:\x07@y[\x00}\x060/\x06a\x00i\x00Iu:\x06U\x06x\x06:\x07@s@\x07",
+            // a struct, a user class wrapping a hash, an encoded symbol as a class name, a user marshal and a data object
+            b"\x04\x08[\x09S:\x06S\x06:\x06ai\x06C:\x06H{\x00c\x06XoI:\x06O\x06:\x06ET\x00U:\x06M0d:\x06D0",
+            b"\x04\x08[\x06@\x06",
+            b"\x04\x08[\x07:\x06a;\x06",
+            b"\x04\x08o:\x06O\x06i\x06i\x07",
+            b"\x04\x08u\x060\x00",
+            b"\x04\x08l*\x06\x00\x00",
+            b"\x04\x08l+\x0a\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00",
+            b"\x04\x08[\x06x",
+            b"\x04\x08[\xfa",
+            b"\x04\x08\"\x08ab",
+            b"\x04\x09[\x00",
+            b"\x04\x08[\x06[\x06[\x06[\x06[\x060",
+        ];
+        for (i, input) in inputs.into_iter().enumerate() {
+            let options = LoaderOptions::default().with_max_depth(4);
+            let loaded = Loader::with_options(&mut &input[..], options.clone()).load().map(|_| ()).map_err(|err| (err.code(), err.location()));
+            let validated = Loader::with_options(&mut &input[..], options).validate().map_err(|err| (err.code(), err.location()));
+            assert_eq!(validated, loaded, "{:?}", input);
+            assert_eq!(validated.is_ok(), i < 3, "{:?}", input);
+        }
+
+        // the validation doesn't hold on to payloads, only the limits of a load apply
+        let input = b"\x04\x08[\x07\"\x08abc:\x06s";
+        let error = Loader::with_options(&mut &input[..], LoaderOptions::default().with_max_objects(1)).validate().unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ObjectLimitExceeded);
+        let mut reader = &input[..];
+        let mut loader = Loader::new(&mut reader);
+        loader.validate().unwrap();
+        assert_eq!(loader.bytes_read(), input.len() as u64);
+        assert!(loader.is_at_end().unwrap());
+
+        // class names are checked like a TokenReader checks them
+        let options = LoaderOptions::default().with_permitted_classes(["Point"]);
+        let error = Loader::with_options(&mut &b"\x04\x08o:\x09Evil\x00"[..], options).validate().unwrap_err();
+        assert_eq!(error.code(), ErrorCode::ClassNotPermitted);
+    }
+
+    #[test]
+    fn test_parse_float() {
+        assert_eq!(parse_float(b"2"), 2.0);
+        assert_eq!(parse_float(b"-1.5"), -1.5);
+        assert_eq!(parse_float(b"1e-5"), 0.00001);
+        assert_eq!(parse_float(b"1.2345678901234567e+300"), 1.2345678901234567e300);
+        assert_eq!(parse_float(b".5"), 0.5);
+        assert_eq!(parse_float(b"3."), 3.0);
+        assert!(parse_float(b"-0").is_sign_negative());
+        assert!(parse_float(b"nan").is_nan());
+        assert_eq!(parse_float(b"inf"), f64::INFINITY);
+        assert_eq!(parse_float(b"-inf"), f64::NEG_INFINITY);
+        assert_eq!(parse_float(b"1e400"), f64::INFINITY);
+        // trailing garbage (like the mantissa bytes old Rubies appended) is ignored
+        assert_eq!(parse_float(b"1.5\x00\x12\x34"), 1.5);
+        assert_eq!(parse_float(b"2e"), 2.0);
+        assert_eq!(parse_float(b"2e+x"), 2.0);
+        assert_eq!(parse_float(b""), 0.0);
+        assert_eq!(parse_float(b"-"), 0.0);
+        assert_eq!(parse_float(b"abc"), 0.0);
+    }
+
+    #[test]
+    fn test_value_kind() {
+        let input = b"\x04\x08[\x08i\x06:\x06af\x082.5";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let result = loader.load().unwrap();
+
+        assert_eq!(result.get_root().kind(), ValueKind::Array);
+        assert_eq!(result.get_root().object_id(), Some(0));
+
+        let array = result.get_object(0).unwrap().as_array();
+        assert_eq!(array[0].kind(), ValueKind::FixNum);
+        assert_eq!(array[0].object_id(), None);
+        assert_eq!(array[1].kind(), ValueKind::Symbol);
+        assert_eq!(array[1].symbol_id(), Some(0));
+        assert_eq!(array[2].kind(), ValueKind::Float);
+        assert_eq!(array[2].object_id(), Some(1));
+
+        assert_eq!(result.get_object(0).unwrap().kind(), ObjectKind::Array);
+        assert_eq!(result.get_object(1).unwrap().kind(), ObjectKind::Float);
+        assert_eq!(ValueKind::from(ObjectKind::Float), ValueKind::Float);
+        assert_eq!(ObjectKind::Float.to_value(1), array[2]);
+    }
+
     #[test]
     fn test_read_concatenated_objects() {
         let input = b"\x04\x08i\x06\x04\x08i\x07";
@@ -1303,4 +3646,77 @@ mod tests {
         assert_eq!(result.get_symbols().len(), 1);
         assert_eq!(result.get_objects().len(), 1);
     }
+
+    #[test]
+    fn test_tag_histogram() {
+        // [1, "a", :b, :b, Foo._load("")]
+        let input = b"\x04\x08[\x0ai\x06I\"\x06a\x06:\x06ET:\x06b;\x06Iu:\x08Foo\x00\x00";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        loader.load().unwrap();
+        let histogram = loader.tag_histogram();
+        assert_eq!(
+            histogram.iter().collect::<Vec<_>>(),
+            [(b'"', 1), (b':', 3), (b';', 1), (b'I', 2), (b'T', 1), (b'[', 1), (b'i', 1), (b'u', 1)]
+        );
+        assert_eq!(histogram.total(), 11);
+
+        // the tag of a value that fails to load is counted too
+        let input = b"\x04\x08[\x06d:\x08Foo";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        assert!(loader.load().is_err());
+        assert_eq!(loader.tag_histogram().get(b'd'), 1);
+    }
+
+    #[test]
+    fn test_load_from() {
+        let inputs: [&[u8]; 3] = [b"\x04\x08o:\x09Test\x00", b"\x04\x08[\x07I\"\x06a\x06:\x06ET@\x06", b"\x04\x08i\x06"];
+        let mut first = BufReader::new(inputs[0]);
+        let mut loader = Loader::new(&mut first);
+
+        let result = loader.load().unwrap();
+        assert_eq!(result.get_symbols(), &vec![SymbolStr::from("Test")]);
+        assert_eq!(result.get_objects().len(), 1);
+
+        // readers only have to live for the call
+        let mut results = Vec::new();
+        for input in &inputs[1..] {
+            let mut reader = BufReader::new(*input);
+            results.push(loader.load_from(&mut reader).unwrap());
+        }
+        assert_eq!(results[0].get_symbols(), &vec![SymbolStr::from("E")]);
+        assert_eq!(results[0].get_objects().len(), 2);
+        assert_eq!(results[1].get_root(), &RubyValue::FixNum(1));
+        assert!(results[1].get_symbols().is_empty() && results[1].get_objects().is_empty());
+
+        // the loader's own reader is untouched, readers of another type work too
+        assert!(loader.is_at_end().unwrap());
+        let mut reader = inputs[0];
+        assert_eq!(loader.load_from(&mut reader).unwrap().get_symbols(), &vec![SymbolStr::from("Test")]);
+        assert_eq!(loader.tag_histogram().get(b'o'), 1);
+    }
+
+    #[test]
+    fn test_table_capacity() {
+        let input = b"\x04\x08[\x07I\"\x06a\x06:\x06ET@\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let root = loader.load().unwrap();
+        // the Root got tables of exactly its size, the loader's tables keep their capacity
+        assert_eq!((root.get_symbols().capacity(), root.get_objects().capacity()), (1, 2));
+        assert!(loader.symbols.capacity() >= 1 && loader.objects.capacity() >= 2);
+        assert!(loader.symbols.is_empty() && loader.objects.is_empty());
+
+        // loading from another reader uses the same tables
+        let objects = loader.objects.as_ptr();
+        let mut reader = BufReader::new(&input[..]);
+        assert_eq!(loader.load_from(&mut reader).unwrap().get_objects().len(), 2);
+        assert_eq!(loader.objects.as_ptr(), objects);
+
+        // the tables of a failed load are kept, up to the limit
+        loader.objects.extend((0..MAX_RETAINED_ELEMENTS * 2).map(|_| RubyObject::Unloaded(ObjectKind::Array)));
+        loader.reset();
+        assert!(loader.objects.is_empty() && loader.objects.capacity() <= MAX_RETAINED_ELEMENTS);
+    }
 }
@@ -0,0 +1,614 @@
+//! Reading a document as a stream of tokens without building a `Root`, see `TokenReader`
+
+use std::io::BufRead;
+
+
+use crate::{error_code::ErrorCode, spec::{self, *}, values::{ObjectID, RubyBignum, SymbolID}};
+
+use super::{parse_float, read_error_code, unexpected_value, ErrorLocation, LoadError, Loader};
+
+/// A piece of a document read by a `TokenReader`, in the order of the input. Containers start with a `*Start` token
+/// carrying their length and are closed by `End` once their values have been read, key:value pairs come as the key
+/// followed by the value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum Token<'t> {
+    Nil,
+    Boolean(bool),
+    FixNum(i32),
+    Float(f64),
+    BigNum(RubyBignum),
+    /// a symbol definition or a link to one, symbols written with their encoding are decoded
+    Symbol(&'t str),
+    /// a link to an earlier object, objects are numbered like `Loader::load()` numbers them
+    ObjectLink(ObjectID),
+    String(&'t [u8]),
+    RegExp { pattern: &'t str, options: i8 },
+    Class(&'t str),
+    Module(&'t str),
+    ClassOrModule(&'t str),
+    UserDefined { class_name: &'t str, data: &'t [u8] },
+    /// `len` elements follow
+    ArrayStart(usize),
+    /// `len` key:value pairs follow
+    HashStart(usize),
+    /// `len` key:value pairs follow and then the default value
+    HashWithDefaultStart(usize),
+    /// `len` members follow as pairs of their name and value
+    StructStart { name: &'t str, len: usize },
+    /// `len` instance variables follow as pairs of their name and value
+    ObjectStart { class_name: &'t str, len: usize },
+    /// the wrapped string, regexp, array or hash follows
+    UserClassStart(&'t str),
+    /// the value the object was dumped as follows
+    UserMarshalStart(&'t str),
+    /// the state of the object follows
+    DataStart(&'t str),
+    /// the next value is extended with this module, not closed by `End`
+    Extended(&'t str),
+    /// `len` instance variables of the value before follow as pairs of their name and value
+    InstanceVariables(usize),
+    /// closes the innermost `*Start` or `InstanceVariables`
+    End,
+}
+
+/// A token whose text hasn't been borrowed from the reader yet, names are symbol ids and payloads are in the buffer
+enum Step {
+    Token(Token<'static>),
+    Symbol(SymbolID),
+    String,
+    RegExp(i8),
+    Class,
+    Module,
+    ClassOrModule,
+    UserDefined(SymbolID),
+    StructStart(SymbolID, usize),
+    ObjectStart(SymbolID, usize),
+    UserClassStart(SymbolID),
+    UserMarshalStart(SymbolID),
+    DataStart(SymbolID),
+    Extended(SymbolID),
+}
+
+/// A value whose tokens are still being read
+enum TokenFrame {
+    /// `remaining` values follow, with `symbol_keys` they are key:value pairs whose keys have to be symbols.
+    /// `registers_object` counts the object once they have been read, like an `I` wrapping a `u`. `non_symbol_key` is
+    /// set while the value of a key that isn't a symbol is read. `ends` closes them with `End`, the `e` prefix of a
+    /// value isn't closed.
+    Values { offset: u64, remaining: usize, symbol_keys: bool, registers_object: bool, non_symbol_key: bool, ends: bool },
+    /// `I` waiting for the value it wraps, a `u` that is counted after its instance variables sets `registers_object`
+    InstanceVariables { offset: u64, registers_object: bool },
+}
+
+impl TokenFrame {
+    fn offset(&self) -> u64 {
+        match self {
+            TokenFrame::Values { offset, .. } | TokenFrame::InstanceVariables { offset, .. } => *offset,
+        }
+    }
+}
+
+/// Pull parser handing out the tokens of a document one at a time without building a `Root`, see `Loader::tokens()`.
+/// Only the symbols and the containers being read are kept, so huge documents are read in constant memory. Strings
+/// and other payloads are borrowed from a buffer the next token reuses. The limits of `LoaderOptions` apply (elements
+/// of containers aren't counted, they aren't stored) and so do `permitted_classes`, the options shaping the Root and
+/// `lenient` are ignored.
+pub struct TokenReader<'l, 'a, T: BufRead> {
+    loader: &'l mut Loader<'a, T>,
+    frames: Vec<TokenFrame>,
+    /// objects defined so far, links are checked against it
+    objects: usize,
+    /// payload of the last string, float, regexp, class, module or UserDefined object
+    buffer: Vec<u8>,
+    /// the version header has been read
+    started: bool,
+    /// the document has been read or reading it failed
+    finished: bool,
+    /// the last token finished a value, `Some(true)` if it was a symbol
+    delivered: Option<bool>,
+    /// string and UserDefined payloads are skipped instead of copied, their tokens carry empty slices
+    skip_payloads: bool,
+    /// symbol and object links fail no matter their target, see `Loader::scan_raw()`
+    forbid_links: bool,
+}
+
+impl<'a, T: BufRead> Loader<'a, T> {
+    /// Reads the next document as tokens instead of loading it, see `TokenReader`
+    pub fn tokens(&mut self) -> TokenReader<'_, 'a, T> {
+        self.reset();
+        TokenReader {
+            loader: self,
+            frames: Vec::new(),
+            objects: 0,
+            buffer: Vec::new(),
+            started: false,
+            finished: false,
+            delivered: None,
+            skip_payloads: false,
+            forbid_links: false,
+        }
+    }
+
+    /// Checks that the next document is well-formed without loading it, by reading its tokens and dropping them.
+    /// Tags, lengths, symbol and object links, class names and the limits in `LoaderOptions` are checked like a
+    /// `TokenReader` checks them, strings and UserDefined payloads are skipped instead of copied and no objects are
+    /// built. Meant for vetting untrusted input cheaply before a full load. `LoaderOptions::lenient` is ignored.
+    pub fn validate(&mut self) -> Result<(), LoadError> {
+        let mut tokens = self.tokens();
+        tokens.skip_payloads = true;
+        while tokens.next_token()?.is_some() {}
+        Ok(())
+    }
+
+    /// Checks that the input is a single value without version header, symbol links and object links, returns the
+    /// number of symbols and objects it defines, see `RawFragment`
+    pub(crate) fn scan_raw(&mut self) -> Result<(usize, usize), LoadError> {
+        let mut tokens = self.tokens();
+        tokens.started = true;
+        tokens.skip_payloads = true;
+        tokens.forbid_links = true;
+        while tokens.next_token()?.is_some() {}
+        let objects = tokens.objects;
+        if !self.is_at_end()? {
+            return Err(LoadError::ParserError(ErrorCode::TrailingBytes, "Raw fragment has trailing bytes".to_string(), ErrorLocation { offset: Some(self.position), object_offset: None }));
+        }
+        Ok((self.symbols.len(), objects))
+    }
+}
+
+impl<T: BufRead> TokenReader<'_, '_, T> {
+    /// Reads the next token, `None` once the document is complete. After an error no more tokens are read.
+    pub fn next_token(&mut self) -> Result<Option<Token<'_>>, LoadError> {
+        if self.finished {
+            return Ok(None);
+        }
+        match self.step() {
+            Ok(Some(step)) => Ok(Some(self.token(step))),
+            Ok(None) => {
+                self.finished = true;
+                Ok(None)
+            }
+            Err(err) => {
+                self.finished = true;
+                Err(err)
+            }
+        }
+    }
+
+    fn token(&self, step: Step) -> Token<'_> {
+        let symbol = |symbol_id: SymbolID| &*self.loader.symbols[symbol_id];
+        // checked to be UTF-8 when it was read
+        let text = || std::str::from_utf8(&self.buffer).unwrap_or_default();
+        match step {
+            Step::Token(token) => token,
+            Step::Symbol(symbol_id) => Token::Symbol(symbol(symbol_id)),
+            Step::String => Token::String(&self.buffer),
+            Step::RegExp(options) => Token::RegExp { pattern: text(), options },
+            Step::Class => Token::Class(text()),
+            Step::Module => Token::Module(text()),
+            Step::ClassOrModule => Token::ClassOrModule(text()),
+            Step::UserDefined(class_name) => Token::UserDefined { class_name: symbol(class_name), data: &self.buffer },
+            Step::StructStart(name, len) => Token::StructStart { name: symbol(name), len },
+            Step::ObjectStart(class_name, len) => Token::ObjectStart { class_name: symbol(class_name), len },
+            Step::UserClassStart(name) => Token::UserClassStart(symbol(name)),
+            Step::UserMarshalStart(class_name) => Token::UserMarshalStart(symbol(class_name)),
+            Step::DataStart(class_name) => Token::DataStart(symbol(class_name)),
+            Step::Extended(module) => Token::Extended(symbol(module)),
+        }
+    }
+
+    /// `Loader::read_value()` stopping at every token, frames are told about the value the last token finished
+    fn step(&mut self) -> Result<Option<Step>, LoadError> {
+        if !self.started {
+            self.started = true;
+            self.loader.read_version()?;
+        }
+        loop {
+            if let Some(is_symbol) = self.delivered.take() {
+                match self.frames.pop() {
+                    None => {
+                        self.loader.report_progress();
+                        return Ok(None);
+                    }
+                    Some(TokenFrame::Values { offset, remaining, symbol_keys, registers_object, mut non_symbol_key, ends }) => {
+                        if symbol_keys && remaining % 2 == 0 {
+                            non_symbol_key = !is_symbol;
+                        } else if non_symbol_key {
+                            let err = LoadError::ParserError(ErrorCode::UnexpectedValue, "Could not parse key:value pairs, key was not a Symbol".to_string(), ErrorLocation::UNKNOWN);
+                            return Err(self.loader.locate(err, Some(offset)));
+                        }
+                        self.frames.push(TokenFrame::Values { offset, remaining: remaining - 1, symbol_keys, registers_object, non_symbol_key, ends });
+                    }
+                    Some(TokenFrame::InstanceVariables { offset, registers_object }) => {
+                        let len = self.read_len("number of instance variables").map_err(|err| self.loader.locate(err, Some(offset)))?;
+                        self.frames.push(TokenFrame::Values { offset, remaining: 2 * len, symbol_keys: true, registers_object, non_symbol_key: false, ends: true });
+                        return Ok(Some(Step::Token(Token::InstanceVariables(len))));
+                    }
+                }
+            }
+
+            if let Some(&TokenFrame::Values { remaining: 0, registers_object, ends, .. }) = self.frames.last() {
+                self.frames.pop();
+                if registers_object {
+                    self.objects += 1;
+                }
+                self.delivered = Some(false);
+                if ends {
+                    return Ok(Some(Step::Token(Token::End)));
+                }
+                continue;
+            }
+
+            if let Some(step) = self.start_step()? {
+                return Ok(Some(step));
+            }
+        }
+    }
+
+    /// Reads the tag of the next value and what follows it up to the next token, `None` for an `I` that has no token of
+    /// its own
+    fn start_step(&mut self) -> Result<Option<Step>, LoadError> {
+        let parent_offset = self.frames.last().map(TokenFrame::offset);
+        if self.frames.len() >= self.loader.options.max_depth {
+            return Err(self.loader.locate(LoadError::DepthLimitExceeded(self.loader.options.max_depth, ErrorLocation::UNKNOWN), parent_offset));
+        }
+        let offset = self.loader.position;
+        let tag = self.loader.read_tag().map_err(|err| self.loader.locate(err, parent_offset))?;
+        self.loader.check_object_limit(tag, offset, self.objects).map_err(|err| self.loader.locate(err, parent_offset))?;
+
+        let frames = self.frames.len();
+        match self.start_tagged_step(tag, offset) {
+            Ok(step) => {
+                if self.frames.len() == frames {
+                    self.delivered = Some(matches!(step, Some(Step::Symbol(_))));
+                }
+                Ok(step)
+            }
+            Err(err) => Err(self.loader.locate(err, spec::is_object_tag(tag).then_some(offset).or(parent_offset))),
+        }
+    }
+
+    fn start_tagged_step(&mut self, tag: u8, offset: u64) -> Result<Option<Step>, LoadError> {
+        if spec::is_object_tag(tag) && !matches!(tag, TAG_INSTANCE_VARIABLES | TAG_EXTENDED | TAG_USER_DEFINED) {
+            self.objects += 1;
+        }
+        let step = match tag {
+            TAG_NIL => Step::Token(Token::Nil),
+            TAG_TRUE => Step::Token(Token::Boolean(true)),
+            TAG_FALSE => Step::Token(Token::Boolean(false)),
+            TAG_FIXNUM => Step::Token(Token::FixNum(self.loader.read_fixnum()?)),
+            TAG_SYMBOL => Step::Symbol(self.loader.read_symbol()?),
+            TAG_SYMBOL_LINK => Step::Symbol(self.read_symbol_link()?),
+            TAG_OBJECT_LINK => Step::Token(Token::ObjectLink(self.read_object_link()?)),
+            TAG_FLOAT => {
+                self.read_payload()?;
+                Step::Token(Token::Float(parse_float(&self.buffer)))
+            }
+            TAG_BIGNUM => Step::Token(Token::BigNum(self.loader.read_bignum_value()?)),
+            TAG_STRING => {
+                self.read_value_payload()?;
+                Step::String
+            }
+            TAG_REGEXP => {
+                self.read_text()?;
+                let mut buffer: [u8; 1] = [0; 1];
+                if let Err(err) = self.loader.read_exact(&mut buffer) {
+                    return Err(LoadError::IoError(read_error_code(&err), format!("Failed to read regexp's options byte: {}", err), ErrorLocation::UNKNOWN));
+                }
+                Step::RegExp(buffer[0] as i8)
+            }
+            TAG_CLASS => {
+                self.read_text()?;
+                Step::Class
+            }
+            TAG_MODULE => {
+                self.read_text()?;
+                Step::Module
+            }
+            TAG_CLASS_OR_MODULE => {
+                self.read_text()?;
+                Step::ClassOrModule
+            }
+            TAG_USER_DEFINED => {
+                let class_name = self.read_class_name("user defined")?;
+                self.read_value_payload()?;
+                if !self.defer_object() {
+                    self.objects += 1;
+                }
+                Step::UserDefined(class_name)
+            }
+            TAG_ARRAY => {
+                let len = self.read_len("array length")?;
+                self.push_values(offset, len, false);
+                Step::Token(Token::ArrayStart(len))
+            }
+            TAG_HASH => {
+                let len = self.read_len("number of key:value pairs")?;
+                self.push_values(offset, 2 * len, false);
+                Step::Token(Token::HashStart(len))
+            }
+            TAG_HASH_WITH_DEFAULT => {
+                let len = self.read_len("number of key:value pairs")?;
+                self.push_values(offset, 2 * len + 1, false);
+                Step::Token(Token::HashWithDefaultStart(len))
+            }
+            TAG_STRUCT => {
+                let name = self.read_class_name("struct")?;
+                let len = self.read_len("number of key:value pairs")?;
+                self.push_values(offset, 2 * len, true);
+                Step::StructStart(name, len)
+            }
+            TAG_OBJECT => {
+                let class_name = self.read_class_name("object")?;
+                let len = self.read_len("number of key:value pairs")?;
+                self.push_values(offset, 2 * len, true);
+                Step::ObjectStart(class_name, len)
+            }
+            TAG_USER_CLASS => {
+                let name = self.read_class_name("user class")?;
+                self.push_values(offset, 1, false);
+                Step::UserClassStart(name)
+            }
+            TAG_USER_MARSHAL => {
+                let class_name = self.read_class_name("user marshal")?;
+                self.push_values(offset, 1, false);
+                Step::UserMarshalStart(class_name)
+            }
+            TAG_DATA => {
+                let class_name = self.read_class_name("data object")?;
+                self.push_values(offset, 1, false);
+                Step::DataStart(class_name)
+            }
+            TAG_EXTENDED => {
+                let module = self.read_symbol_value("extended module")?;
+                self.frames.push(TokenFrame::Values { offset, remaining: 1, symbol_keys: false, registers_object: false, non_symbol_key: false, ends: false });
+                Step::Extended(module)
+            }
+            TAG_INSTANCE_VARIABLES => {
+                if self.loader.peek_byte()? == Some(TAG_SYMBOL) {
+                    self.loader.consume_byte();
+                    self.loader.tags.record(TAG_SYMBOL);
+                    Step::Symbol(self.read_encoded_symbol()?)
+                } else {
+                    self.frames.push(TokenFrame::InstanceVariables { offset, registers_object: false });
+                    return Ok(None);
+                }
+            }
+            _ => return Err(LoadError::ParserError(ErrorCode::UnknownTag, format!("Unknown value type: {}", tag), ErrorLocation::UNKNOWN)),
+        };
+        Ok(Some(step))
+    }
+
+    fn push_values(&mut self, offset: u64, remaining: usize, symbol_keys: bool) {
+        self.frames.push(TokenFrame::Values { offset, remaining, symbol_keys, registers_object: false, non_symbol_key: false, ends: true });
+    }
+
+    /// A `u` wrapped in `I` (behind any `e` prefixes) is counted once its instance variables have been read, like in
+    /// `Loader::start_instance_variables()`, returns whether it is
+    fn defer_object(&mut self) -> bool {
+        for frame in self.frames.iter_mut().rev() {
+            match frame {
+                TokenFrame::Values { ends: false, .. } => continue,
+                TokenFrame::InstanceVariables { registers_object, .. } => {
+                    *registers_object = true;
+                    return true;
+                }
+                TokenFrame::Values { .. } => return false,
+            }
+        }
+        false
+    }
+
+    fn read_len(&mut self, item: &str) -> Result<usize, LoadError> {
+        match usize::try_from(self.loader.read_fixnum()?) {
+            Ok(len) => Ok(len),
+            Err(_) => Err(LoadError::ParserError(ErrorCode::BadLength, format!("Could not parse {} (could not convert it to usize)", item), ErrorLocation::UNKNOWN)),
+        }
+    }
+
+    /// Reads a byte sequence into the buffer in place of the previous payload
+    fn read_payload(&mut self) -> Result<(), LoadError> {
+        let len = self.read_payload_len()?;
+        self.buffer.clear();
+        self.loader.read_bytes_into("byte sequence", len, &mut self.buffer)
+    }
+
+    /// `read_payload()` for the payload of a string or UserDefined object, which `skip_payloads` skips
+    fn read_value_payload(&mut self) -> Result<(), LoadError> {
+        if !self.skip_payloads {
+            return self.read_payload();
+        }
+        let len = self.read_payload_len()?;
+        self.buffer.clear();
+        self.loader.skip_bytes("byte sequence", len)
+    }
+
+    /// Reads the length of a byte sequence and accounts for it like a load would
+    fn read_payload_len(&mut self) -> Result<usize, LoadError> {
+        let len = match usize::try_from(self.loader.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse byte sequence length (length was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        self.loader.allocate("byte sequence", len)?;
+        Ok(len)
+    }
+
+    /// `read_payload()` for byte sequences that have to be UTF-8 like `Loader::read_sequence()`
+    fn read_text(&mut self) -> Result<(), LoadError> {
+        self.read_payload()?;
+        match std::str::from_utf8(&self.buffer) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(LoadError::ParserError(ErrorCode::BadUtf8, format!("Could not decode bytes into a String: {}", err), ErrorLocation::UNKNOWN)),
+        }
+    }
+
+    fn read_object_link(&mut self) -> Result<ObjectID, LoadError> {
+        if self.forbid_links {
+            return Err(LoadError::ParserError(ErrorCode::BadObjectLink, "Object links aren't allowed in raw fragments".to_string(), ErrorLocation::UNKNOWN));
+        }
+        match usize::try_from(self.loader.read_fixnum()?) {
+            Ok(object_id) if object_id < self.objects => Ok(object_id),
+            Ok(_) => Err(LoadError::ParserError(ErrorCode::BadObjectLink, "Could not parse object link (links to a non-existent object)".to_string(), ErrorLocation::UNKNOWN)),
+            Err(_) => Err(LoadError::ParserError(ErrorCode::BadObjectLink, "Could not parse object link (could not convert object index to usize)".to_string(), ErrorLocation::UNKNOWN)),
+        }
+    }
+
+    fn read_class_name(&mut self, item: &str) -> Result<SymbolID, LoadError> {
+        let class_name = self.read_symbol_value(item)?;
+        self.loader.check_class_permitted(class_name)?;
+        Ok(class_name)
+    }
+
+    fn read_symbol_link(&mut self) -> Result<SymbolID, LoadError> {
+        if self.forbid_links {
+            return Err(LoadError::ParserError(ErrorCode::BadSymbolLink, "Symbol links aren't allowed in raw fragments".to_string(), ErrorLocation::UNKNOWN));
+        }
+        self.loader.read_symbol_link()
+    }
+
+    /// `Loader::read_plain_symbol_value()` checking `forbid_links`
+    fn read_plain_symbol_value(&mut self, item: &str, tag: u8) -> Result<SymbolID, LoadError> {
+        match tag {
+            TAG_SYMBOL_LINK => self.read_symbol_link(),
+            tag => self.loader.read_plain_symbol_value(item, tag),
+        }
+    }
+
+    /// `Loader::read_symbol_value()` for the encoded symbols of `read_encoded_symbol()`
+    fn read_symbol_value(&mut self, item: &str) -> Result<SymbolID, LoadError> {
+        let tag = self.loader.read_tag()?;
+        if tag != TAG_INSTANCE_VARIABLES {
+            return self.read_plain_symbol_value(item, tag);
+        }
+        match self.loader.read_tag()? {
+            TAG_SYMBOL => self.read_encoded_symbol(),
+            tag => Err(unexpected_value(item, "a symbol with encoding", tag)),
+        }
+    }
+
+    /// `Loader::read_encoded_symbol()` counting the strings of the encoding instead of storing them
+    fn read_encoded_symbol(&mut self) -> Result<SymbolID, LoadError> {
+        let symbol_id = self.loader.read_symbol()?;
+        let count = match usize::try_from(self.loader.read_fixnum()?) {
+            Ok(val) => val,
+            Err(_) => return Err(LoadError::ParserError(ErrorCode::BadLength, "Could not parse symbol encoding (number of instance variables was negative)".to_string(), ErrorLocation::UNKNOWN)),
+        };
+        let mut encoding = None;
+        for _ in 0..count {
+            let tag = self.loader.read_tag()?;
+            let name = self.read_plain_symbol_value("symbol encoding", tag)?;
+            match self.loader.read_tag()? {
+                TAG_TRUE | TAG_FALSE => (),
+                TAG_STRING => {
+                    self.objects += 1;
+                    self.read_payload()?;
+                    if &*self.loader.symbols[name] == "encoding" {
+                        encoding = Some(String::from_utf8_lossy(&self.buffer).into_owned());
+                    }
+                }
+                TAG_OBJECT_LINK => {
+                    self.read_object_link()?;
+                }
+                tag => return Err(unexpected_value("symbol encoding", "a boolean or a string", tag)),
+            }
+        }
+
+        // symbols that aren't UTF-8 keep their bytes, see Loader::read_symbol()
+        let symbol = encoding.zip(self.loader.encoded_symbols.get(&symbol_id))
+            .and_then(|(encoding, encoded_symbol)| self.loader.text_decoder.decode(&encoding, encoded_symbol.get_bytes()).ok());
+        if let Some(symbol) = symbol {
+            self.loader.symbols[symbol_id] = self.loader.intern_symbol(symbol);
+        }
+        Ok(symbol_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::LoaderOptions;
+
+    use super::*;
+
+    fn assert_tokens(input: &[u8], expected: &[Token]) {
+        let mut reader = input;
+        let mut loader = Loader::new(&mut reader);
+        let mut tokens = loader.tokens();
+        for token in expected {
+            assert_eq!(tokens.next_token().unwrap().as_ref(), Some(token));
+        }
+        assert_eq!(tokens.next_token().unwrap(), None);
+        assert!(reader.is_empty());
+    }
+
+    fn read_error(input: &[u8], options: LoaderOptions) -> LoadError {
+        let mut reader = input;
+        let mut loader = Loader::with_options(&mut reader, options);
+        let mut tokens = loader.tokens();
+        loop {
+            match tokens.next_token() {
+                Ok(Some(_)) => (),
+                Ok(None) => panic!("expected an error"),
+                Err(err) => return err,
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        // [1, :a, :a, "x", @1, 2.5]
+        assert_tokens(b"\x04\x08[\x0bi\x06:\x06a;\x00\"\x06x@\x06f\x082.5", &[
+            Token::ArrayStart(6), Token::FixNum(1), Token::Symbol("a"), Token::Symbol("a"), Token::String(b"x"), Token::ObjectLink(1),
+            Token::Float(2.5), Token::End,
+        ]);
+        // Test(@a="x" with encoding), the encoding follows the string
+        assert_tokens(b"\x04\x08o:\x09Test\x06:\x07@aI\"\x06x\x06:\x06ET", &[
+            Token::ObjectStart { class_name: "Test", len: 1 }, Token::Symbol("@a"), Token::String(b"x"), Token::InstanceVariables(1),
+            Token::Symbol("E"), Token::Boolean(true), Token::End, Token::End,
+        ]);
+        // {:a => []} with default nil, an encoded symbol and Foo._load("a") with instance variables, counted after them
+        assert_tokens(b"\x04\x08[\x08}\x06I:\x06a\x06:\x06ET[\x000Iu:\x08Foo\x06a\x06;\x06T@\x08", &[
+            Token::ArrayStart(3), Token::HashWithDefaultStart(1), Token::Symbol("a"), Token::ArrayStart(0), Token::End, Token::Nil,
+            Token::End, Token::UserDefined { class_name: "Foo", data: b"a" }, Token::InstanceVariables(1), Token::Symbol("E"),
+            Token::Boolean(true), Token::End, Token::ObjectLink(3), Token::End,
+        ]);
+        // Point(@x=1) extended with M
+        assert_tokens(b"\x04\x08e:\x06Mo:\x0aPoint\x06:\x07@xi\x06", &[
+            Token::Extended("M"), Token::ObjectStart { class_name: "Point", len: 1 }, Token::Symbol("@x"), Token::FixNum(1), Token::End,
+        ]);
+
+        // documents written one after another are read by successive readers
+        let mut reader = &b"\x04\x08i\x06\x04\x08:\x06b"[..];
+        let mut loader = Loader::new(&mut reader);
+        assert_eq!(loader.tokens().next_token().unwrap(), Some(Token::FixNum(1)));
+        let mut tokens = loader.tokens();
+        assert_eq!(tokens.next_token().unwrap(), Some(Token::Symbol("b")));
+        assert_eq!(tokens.next_token().unwrap(), None);
+    }
+
+    #[test]
+    fn test_token_errors() {
+        let inputs: [&[u8]; 6] = [
+            // [@2]
+            b"\x04\x08[\x06@\x07",
+            // Test(1 => 2)
+            b"\x04\x08o:\x09Test\x06i\x06i\x07",
+            // a truncated string
+            b"\x04\x08[\x06\"\x08ab",
+            // [[[[[]]]]]
+            b"\x04\x08[\x06[\x06[\x06[\x06[\x00",
+            b"\x04\x08[\x06x",
+            b"\x04\x09i\x06",
+        ];
+        for input in inputs {
+            let options = LoaderOptions::default().with_max_depth(4);
+            let expected = Loader::with_options(&mut &input[..], options.clone()).load().unwrap_err();
+            let err = read_error(input, options);
+            assert_eq!((err.code(), err.location()), (expected.code(), expected.location()));
+        }
+
+        let options = LoaderOptions::default().with_permitted_classes(["Test"]);
+        assert_eq!(read_error(b"\x04\x08o:\x09Evil\x00", options).code(), ErrorCode::ClassNotPermitted);
+        assert_eq!(read_error(b"\x04\x08[\x07i\x06i\x06", LoaderOptions::default().with_max_objects(0)).code(), ErrorCode::ObjectLimitExceeded);
+    }
+}
@@ -0,0 +1,105 @@
+//! Loading from a memory-mapped file through the zero-copy path of `load_from_slice()`, see
+//! `Loader::from_path_mmap()`
+
+use std::{fs::File, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{error_code::ErrorCode, values::*};
+
+use super::{
+    borrowed::{self, BorrowedObject},
+    load::{BorrowedRanges, ErrorLocation, LoadError, Loader, LoaderOptions},
+};
+
+/// A document loaded from a memory-mapped file, string and UserDefined payloads are slices of the mapping like those
+/// of a `BorrowedRoot`
+#[derive(Debug)]
+pub struct MappedRoot {
+    mmap: Mmap,
+    /// the loaded document with empty strings and UserDefined payloads
+    structure: Root,
+    borrowed: BorrowedRanges,
+}
+
+impl MappedRoot {
+    pub fn get_root(&self) -> &RubyValue {
+        self.structure.get_root()
+    }
+
+    pub fn get_symbol(&self, id: SymbolID) -> Option<&SymbolStr> {
+        self.structure.get_symbol(id)
+    }
+
+    pub fn get_symbol_id(&self, symbol: &str) -> Option<SymbolID> {
+        self.structure.get_symbol_id(symbol)
+    }
+
+    pub fn get_object(&self, id: ObjectID) -> Option<BorrowedObject<'_, '_>> {
+        borrowed::borrowed_object(&self.mmap, &self.structure, &self.borrowed, id)
+    }
+
+    /// Copies the payloads out of the mapping and unmaps the file
+    pub fn into_owned(self) -> Root {
+        borrowed::into_owned(&self.mmap, self.structure, self.borrowed)
+    }
+}
+
+impl Loader<'_, &[u8]> {
+    /// Maps the file at `path` into memory and loads it without reading it into a buffer first, meant for documents
+    /// of hundreds of megabytes
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other process, while the returned `MappedRoot`
+    /// exists. The slices it hands out would change under it or point past the end of the file, which is undefined
+    /// behavior.
+    pub unsafe fn from_path_mmap(path: impl AsRef<Path>) -> Result<MappedRoot, LoadError> {
+        Self::from_path_mmap_with_options(path, LoaderOptions::default())
+    }
+
+    /// `from_path_mmap()` with options
+    ///
+    /// # Safety
+    ///
+    /// Same as `from_path_mmap()`, the file must not be modified or truncated while the returned `MappedRoot` exists.
+    pub unsafe fn from_path_mmap_with_options(path: impl AsRef<Path>, options: LoaderOptions) -> Result<MappedRoot, LoadError> {
+        let path = path.as_ref();
+        let map_error = |err: std::io::Error| {
+            LoadError::IoError(ErrorCode::ReadFailed, format!("Could not map {}: {}", path.display(), err), ErrorLocation::UNKNOWN)
+        };
+        let file = File::open(path).map_err(map_error)?;
+        // SAFETY: the mapping is only read and the caller guarantees the file isn't modified while it exists
+        let mmap = unsafe { Mmap::map(&file) }.map_err(map_error)?;
+
+        let mut reader = &mmap[..];
+        let (structure, borrowed) = Loader::with_options(&mut reader, options).load_borrowed()?;
+        Ok(MappedRoot { mmap, structure, borrowed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path_mmap() {
+        // ["abc", :sym]
+        let input = b"\x04\x08[\x07\"\x08abc:\x08sym";
+        let path = std::env::temp_dir().join(format!("marshr-mmap-{}.bin", std::process::id()));
+        std::fs::write(&path, input).unwrap();
+
+        // SAFETY: the file is private to this test and only written again after the mapping is dropped
+        let mapped = unsafe { Loader::from_path_mmap(&path) }.unwrap();
+        let Some(BorrowedObject::String { bytes, .. }) = mapped.get_object(1) else { panic!("expected a string") };
+        assert_eq!(bytes, b"abc");
+        assert_eq!(mapped.get_symbol(0).map(|symbol| &**symbol), Some("sym"));
+        assert_eq!(mapped.into_owned(), Loader::new(&mut &input[..]).load().unwrap());
+
+        std::fs::write(&path, &input[..input.len() - 1]).unwrap();
+        // SAFETY: as above, the load fails so no mapping outlives the call
+        assert_eq!(unsafe { Loader::from_path_mmap(&path) }.unwrap_err().code(), ErrorCode::UnexpectedEnd);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(unsafe { Loader::from_path_mmap(&path) }.unwrap_err().code(), ErrorCode::ReadFailed);
+    }
+}
@@ -0,0 +1,38 @@
+//! Progress of long loads, readable from other threads, see `ProgressCounter`
+
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+/// Number of bytes a `Loader` read, shared with the threads showing progress (clones share the count). Pass it with
+/// `LoaderOptions::with_progress()`, the loader updates it once it read `ProgressCounter::INTERVAL` bytes since the
+/// last update (long strings are read in one piece) and when a document is complete.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressCounter {
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl ProgressCounter {
+    /// Bytes between two updates
+    pub const INTERVAL: u64 = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes read by the loader as of its last update, counted from the start of the stream like error offsets
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, bytes_read: u64) {
+        self.bytes_read.store(bytes_read, Ordering::Relaxed);
+    }
+}
+
+/// Counters are equal if they are clones of each other
+impl PartialEq for ProgressCounter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.bytes_read, &other.bytes_read)
+    }
+}
+
+impl Eq for ProgressCounter {}
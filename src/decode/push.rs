@@ -0,0 +1,212 @@
+//! Loading documents from input that arrives in chunks, like reads from a non-blocking socket, see `PushLoader`
+
+use crate::{error_code::ErrorCode, values::Root};
+
+use super::load::{ErrorLocation, LoadError, Loader, LoaderOptions, ResumableLoad, Suspended};
+
+/// Collects bytes with `feed()` and returns the documents they complete, documents written one after another are
+/// split like `Loader::load_iter()` does. A document that isn't complete yet is suspended at the start of the value
+/// that ran out of input and continued there when more bytes arrive, only the bytes of that value are kept. Reading
+/// is retried once enough bytes arrived for the read that ran out, so a long string fed in tiny chunks is read once.
+/// `LoaderOptions::lenient` is ignored, a lenient load would salvage a document before all its bytes arrived.
+pub struct PushLoader {
+    /// moved into the `Loader` of every `feed()` and back out of it
+    options: LoaderOptions,
+    /// bytes that haven't been read yet, the start of the value the suspended document waits for
+    buffer: Vec<u8>,
+    suspended: Option<Suspended>,
+    /// `buffer` has to hold this many bytes before reading is retried
+    bytes_needed: usize,
+    max_buffered_bytes: usize,
+    /// a document failed to load, the start of the next one is unknown
+    failed: bool,
+}
+
+impl Default for PushLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PushLoader {
+    pub fn new() -> Self {
+        Self::with_options(LoaderOptions::default())
+    }
+
+    pub fn with_options(options: LoaderOptions) -> Self {
+        Self {
+            options: options.with_lenient(false),
+            buffer: Vec::new(),
+            suspended: None,
+            bytes_needed: 0,
+            max_buffered_bytes: usize::MAX,
+            failed: false,
+        }
+    }
+
+    /// Most bytes that may wait for the rest of a value (like the start of a long string), `feed()` fails with
+    /// `LoadError::AllocationLimitExceeded` once more are buffered. Unlimited by default, `LoaderOptions::max_item_bytes`
+    /// bounds how long a single value can announce to be.
+    pub fn with_max_buffered_bytes(mut self, max_buffered_bytes: usize) -> Self {
+        self.max_buffered_bytes = max_buffered_bytes;
+        self
+    }
+
+    /// Appends `bytes` to the input and returns the documents completed by them in stream order. After an error the
+    /// position of the next document is unknown, every later call fails.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<Root>, LoadError> {
+        if self.failed {
+            return Err(LoadError::ParserError(
+                ErrorCode::ReadFailed,
+                "An earlier document failed to load, the start of the next one is unknown".to_string(),
+                ErrorLocation::UNKNOWN,
+            ));
+        }
+        self.buffer.extend_from_slice(bytes);
+        let mut result = if self.buffer.len() < self.bytes_needed { Ok(Vec::new()) } else { self.read_documents() };
+        if result.is_ok() && self.buffer.len() > self.max_buffered_bytes {
+            result = Err(LoadError::AllocationLimitExceeded(
+                format!("{} buffered bytes exceed the limit of {} bytes", self.buffer.len(), self.max_buffered_bytes),
+                ErrorLocation::UNKNOWN,
+            ));
+        }
+        if result.is_err() {
+            self.failed = true;
+            self.buffer = Vec::new();
+            self.suspended = None;
+        }
+        result
+    }
+
+    /// Reads the buffered bytes until they run out or a document fails, drops the bytes that have been read
+    fn read_documents(&mut self) -> Result<Vec<Root>, LoadError> {
+        let mut reader = &self.buffer[..];
+        let mut loader = Loader::with_options(&mut reader, std::mem::take(&mut self.options));
+        let mut roots = Vec::new();
+        let mut consumed = 0;
+        self.bytes_needed = 0;
+        let result = loop {
+            if self.suspended.is_none() && consumed == self.buffer.len() {
+                break Ok(());
+            }
+            // documents and suspended values continue at the offset they stopped at
+            let start = self.suspended.as_ref().map_or(0, Suspended::position);
+            match loader.load_resumable(self.suspended.take()) {
+                Ok(ResumableLoad::Complete(root)) => {
+                    consumed += (loader.bytes_read() - start) as usize;
+                    roots.push(root);
+                }
+                Ok(ResumableLoad::Suspended(suspended)) => {
+                    consumed += (suspended.position() - start) as usize;
+                    let buffered = self.buffer.len() - consumed;
+                    self.bytes_needed = buffered.saturating_add(1).max(suspended.bytes_needed() as usize);
+                    self.suspended = Some(suspended);
+                    break Ok(());
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        self.options = loader.into_options();
+        result?;
+        self.buffer.drain(..consumed);
+        Ok(roots)
+    }
+
+    /// Number of bytes received but not read yet, the start of the value the incomplete document waits for
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Whether no document is waiting for the rest of its bytes, the input could end here
+    pub fn is_idle(&self) -> bool {
+        self.suspended.is_none() && self.buffer.is_empty()
+    }
+
+    /// Ends the input, fails if it stopped in the middle of a document
+    pub fn finish(self) -> Result<(), LoadError> {
+        self.check_finished()
+    }
+
+    /// `finish()` without ending the input
+    pub(crate) fn check_finished(&self) -> Result<(), LoadError> {
+        if self.is_idle() {
+            return Ok(());
+        }
+        Err(LoadError::IoError(
+            ErrorCode::UnexpectedEnd,
+            format!("Input ended in the middle of a document, {} bytes of it weren't read", self.buffer.len()),
+            ErrorLocation::UNKNOWN,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::values::RubyValue;
+
+    use super::*;
+
+    #[test]
+    fn test_push_loader() {
+        // [1, "a"] and :b, fed one byte at a time
+        let input = b"\x04\x08[\x07i\x06I\"\x06a\x06:\x06ET\x04\x08:\x06b";
+        let mut loader = PushLoader::new();
+        let mut roots = Vec::new();
+        for (i, byte) in input.iter().enumerate() {
+            let completed = loader.feed(&[*byte]).unwrap();
+            if i == 14 || i == input.len() - 1 {
+                assert_eq!(completed.len(), 1, "at byte {}", i);
+            } else {
+                assert!(completed.is_empty(), "at byte {}", i);
+            }
+            roots.extend(completed);
+        }
+        assert_eq!(roots[0].decode_string_value(&roots[0].get_object(0).unwrap().as_array()[1]).unwrap(), "a");
+        assert_eq!(&**roots[1].get_symbol(roots[1].get_root().as_symbol()).unwrap(), "b");
+        loader.finish().unwrap();
+
+        // both documents at once and an incomplete third one
+        let mut loader = PushLoader::new();
+        let mut chunk = input.to_vec();
+        chunk.extend(b"\x04\x08[\x06");
+        assert_eq!(loader.feed(&chunk).unwrap().len(), 2);
+        // the array has been started, nothing waits to be read
+        assert_eq!(loader.buffered_len(), 0);
+        assert_eq!(loader.feed(b"0").unwrap()[0].get_object(0).unwrap().as_array(), &vec![RubyValue::Nil]);
+
+        loader.feed(b"\x04\x08[").unwrap();
+        assert_eq!(loader.finish().unwrap_err().code(), ErrorCode::UnexpectedEnd);
+
+        // malformed input fails every later call
+        let mut loader = PushLoader::new();
+        assert_eq!(loader.feed(b"\x04\x08x").unwrap_err().code(), ErrorCode::UnknownTag);
+        assert!(loader.feed(b"\x04\x080").is_err());
+    }
+
+    #[test]
+    fn test_push_loader_partial_values() {
+        // ["abcdefghij"], only the string waits for the rest of it
+        let mut loader = PushLoader::new();
+        assert!(loader.feed(b"\x04\x08[\x06\"\x0fabc").unwrap().is_empty());
+        assert_eq!(loader.buffered_len(), 5);
+        assert!(loader.feed(b"defghi").unwrap().is_empty());
+        assert_eq!(loader.buffered_len(), 11);
+        let roots = loader.feed(b"j").unwrap();
+        let string = roots[0].get_object(0).unwrap().as_array()[0].as_string();
+        assert_eq!(roots[0].get_object(string).unwrap().as_string().get_string(), b"abcdefghij");
+        assert_eq!(loader.buffered_len(), 0);
+        loader.finish().unwrap();
+
+        // errors refer to offsets in their document
+        let mut loader = PushLoader::new();
+        loader.feed(b"\x04\x080\x04\x08[\x06").unwrap();
+        let error = loader.feed(b"x").unwrap_err();
+        assert_eq!((error.code(), error.location().offset), (ErrorCode::UnknownTag, Some(5)));
+
+        // the version header has been read, the string's tag, length and 4 bytes wait
+        let mut loader = PushLoader::new().with_max_buffered_bytes(6);
+        loader.feed(b"\x04\x08\"\x0fabcd").unwrap();
+        assert!(matches!(loader.feed(b"e"), Err(LoadError::AllocationLimitExceeded(..))));
+        assert!(loader.feed(b"fghij").is_err());
+    }
+}
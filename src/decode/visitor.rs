@@ -0,0 +1,42 @@
+//! Callbacks invoked while a `Loader` decodes values, see `LoadVisitor`
+
+use std::ops::ControlFlow;
+
+use crate::values::RubyValue;
+
+/// Hooks a `Loader` calls as it decodes values, set with `Loader::with_visitor()`. Meant for collecting statistics or
+/// rejecting unwanted values during the load instead of walking the Root afterwards. Returning `ControlFlow::Break`
+/// stops the load with `LoadError::Rejected` carrying the reason, a lenient load replaces the rejected value by nil
+/// and reads no further values. Values are passed as they are in the Root being
+/// built, so ids in them may refer to objects that are still incomplete. Every callback does nothing by default.
+pub trait LoadVisitor {
+    /// A symbol was defined, links to it don't call this again
+    fn on_symbol(&mut self, _name: &str) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+
+    /// A string was read, its instance variables (like its encoding) follow
+    fn on_string(&mut self, _bytes: &[u8]) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+
+    /// An array of `len` elements starts, called before the elements are read
+    fn on_array(&mut self, _len: usize) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+
+    /// A hash of `len` key:value pairs starts, called before the pairs are read
+    fn on_hash(&mut self, _len: usize) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+
+    /// An object was read along with its instance variables
+    fn on_object(&mut self, _class_name: &str, _instance_variables: &[(&str, &RubyValue)]) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+
+    /// The data of a UserDefined object (written by `_dump`) was read, its instance variables follow
+    fn on_user_defined(&mut self, _class_name: &str, _data: &[u8]) -> ControlFlow<String> {
+        ControlFlow::Continue(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Oddities in loaded data that don't stop loading, collected by the `Loader` and kept on the `Root` (see
+//! `Root::get_diagnostics()`), so tools can surface data-quality warnings
+
+use std::fmt::Display;
+
+use crate::values::ValueKind;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// a string or symbol names an encoding (its `encoding` instance variable) the decoder doesn't know, holds the name
+    UnknownEncoding(String),
+    /// a hash has a key more than once, `LoaderOptions::duplicate_hash_keys` decides which value is kept
+    DuplicateHashKey,
+    /// a symbol with an empty name
+    EmptySymbol,
+    /// instance variables on a value Ruby can't attach them to (an immediate value, a link or another `I`), they are
+    /// kept in a `RubyValue::WithIvars` wrapper
+    UnexpectedInstanceVariables(ValueKind),
+    /// a float written like Ruby 1.8 did: with more digits than needed to read it back or with mantissa bytes after
+    /// the text
+    LegacyFloat,
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::UnknownEncoding(name) => f.write_str(&format!("Unknown encoding {}", name)),
+            DiagnosticKind::DuplicateHashKey => f.write_str("Duplicate hash key"),
+            DiagnosticKind::EmptySymbol => f.write_str("Empty symbol"),
+            DiagnosticKind::UnexpectedInstanceVariables(kind) => f.write_str(&format!("Instance variables on {}", kind)),
+            DiagnosticKind::LegacyFloat => f.write_str("Float in the format of Ruby 1.8"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// offset of the type tag of the value it concerns (the hash, the symbol or the `I` wrapper)
+    pub offset: u64,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} at offset {}", self.kind, self.offset))
+    }
+}
+
+/// Diagnostics of one document in stream order
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, kind: DiagnosticKind, offset: u64) {
+        self.diagnostics.push(Diagnostic { kind, offset });
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.diagnostics.clear();
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.diagnostics.truncate(len);
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
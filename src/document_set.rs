@@ -0,0 +1,111 @@
+//! Several documents sharing one symbol and object table, for workloads with many small documents that repeat the
+//! same symbols (class and instance variable names) and for queries across all of them
+
+#[cfg(feature = "decode")]
+use std::io::BufRead;
+
+#[cfg(feature = "decode")]
+use crate::decode::load::{LoadError, Loader, LoaderOptions};
+use crate::values::*;
+
+/// Documents whose objects live in one `Root`, every document keeps its own root value. Symbols are stored once for
+/// all documents, objects aren't shared between documents.
+#[derive(Debug, PartialEq)]
+pub struct DocumentSet {
+    /// shared tables, its own root value is `nil`
+    arena: Root,
+    roots: Vec<RubyValue>,
+    /// id of the first object of every document, documents own the objects up to the next one's first object
+    first_objects: Vec<ObjectID>,
+}
+
+impl Default for DocumentSet {
+    fn default() -> Self {
+        Self { arena: Root::nil(), roots: Vec::new(), first_objects: Vec::new() }
+    }
+}
+
+impl DocumentSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads documents (each with its version header) from `reader` until it is exhausted, like a file several
+    /// dumps were appended to
+    #[cfg(feature = "decode")]
+    pub fn load_all<R: BufRead>(reader: &mut R, options: LoaderOptions) -> Result<Self, LoadError> {
+        let mut documents = Self::new();
+        for document in Loader::with_options(reader, options).load_iter() {
+            documents.push(document?);
+        }
+        Ok(documents)
+    }
+
+    /// Adds a document and returns its index
+    pub fn push(&mut self, document: Root) -> usize {
+        self.first_objects.push(self.arena.get_objects().len());
+        let root = self.arena.append(document);
+        self.roots.push(root);
+        self.roots.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Root value of document `index`, its objects and symbols are in `get_arena()`
+    pub fn get_root(&self, index: usize) -> Option<&RubyValue> {
+        self.roots.get(index)
+    }
+
+    pub fn get_roots(&self) -> &Vec<RubyValue> {
+        &self.roots
+    }
+
+    /// The shared tables, queries like `Root::find_objects()` on it cover all documents, values of every document
+    /// can be printed and dumped with it
+    pub fn get_arena(&self) -> &Root {
+        &self.arena
+    }
+
+    /// Index of the document object `id` belongs to
+    pub fn document_of(&self, id: ObjectID) -> Option<usize> {
+        if id >= self.arena.get_objects().len() {
+            return None;
+        }
+        // documents without objects share their first object id with the next document, the last one owns it
+        self.first_objects.partition_point(|first_object| *first_object <= id).checked_sub(1)
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::encode::dump::Dumper;
+
+    use super::*;
+
+    #[test]
+    fn test_document_set() {
+        // Actor(@name="a"), 1, Actor(@name="b")
+        let input = b"\x04\x08o:\x0aActor\x06:\x0a@name\"\x06a\x04\x08i\x06\x04\x08o:\x0aActor\x06:\x0a@name\"\x06b";
+        let documents = DocumentSet::load_all(&mut &input[..], LoaderOptions::default()).unwrap();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents.get_roots(), &[RubyValue::Object(0), RubyValue::FixNum(1), RubyValue::Object(2)]);
+        assert_eq!(documents.get_arena().get_symbols(), &[SymbolStr::from("Actor"), SymbolStr::from("@name")]);
+
+        assert_eq!(documents.get_arena().find_objects("Actor"), [0, 2]);
+        assert_eq!(documents.document_of(1), Some(0));
+        assert_eq!(documents.document_of(2), Some(2));
+        assert_eq!(documents.document_of(4), None);
+
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(documents.get_arena(), documents.get_root(2).unwrap()).unwrap();
+        assert_eq!(output, b"\x04\x08o:\x0aActor\x06:\x0a@name\"\x06b");
+
+        assert!(DocumentSet::load_all(&mut &input[..input.len() - 1], LoaderOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Conversion of a value graph into a self-contained tree of `DynValue`s, which is easy to inspect or turn into
+//! JSON-like formats where hash keys are plain strings
+
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use crate::{convert::{ConvertError, FromRubyValue}, values::*};
+
+/// Hash keys of a `DynValue::Hash` are strings, this decides what happens when a symbol key and a string key
+/// (`:key` and `"key"`) end up with the same name
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum KeyCollision {
+    /// every symbol key is prefixed with `:`, so `:key` becomes `":key"` and `"key"` stays `"key"`
+    #[default]
+    PrefixSymbols,
+    /// symbol keys use their plain name, a hash that has both `:key` and `"key"` fails with `ConvertError::KeyCollision`
+    Error,
+    /// symbol keys use their plain name unless the hash also has the string key, then the symbol is prefixed with `:`
+    Disambiguate,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DynOptions {
+    pub key_collision: KeyCollision,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DynValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    /// strings without a (known) encoding
+    Bytes(Vec<u8>),
+    Symbol(String),
+    Array(Vec<DynValue>),
+    /// hashes with default lose their default
+    Hash(IndexMap<String, DynValue>),
+    /// objects and structs, instance variable names keep their `@`
+    Object { class_name: String, fields: IndexMap<String, DynValue> },
+    Class(String),
+    Module(String),
+    RegExp { pattern: String, options: i8 },
+    /// objects using `_dump` (`data` is `Bytes`) or `marshal_dump` (`data` is the dumped value)
+    Data { class_name: String, data: Box<DynValue> },
+}
+
+/// Converts `value` and everything it references, fails on cyclic graphs since a tree can't represent them
+pub fn to_dyn(root: &Root, value: &RubyValue, options: &DynOptions) -> Result<DynValue, ConvertError> {
+    Converter { root, options, in_progress: Vec::new() }.convert(value)
+}
+
+/// Turns the tree into a flat map from paths to leaf values, see `Root::flatten()`. Fields and hash keys are appended
+/// with a `.`, array elements with their index in brackets (`@party[0].@name`), the path of the value itself is
+/// empty. Empty arrays, hashes and objects are leaves, so they aren't lost.
+pub fn flatten(value: DynValue) -> HashMap<String, DynValue> {
+    fn flatten_into(flat: &mut HashMap<String, DynValue>, path: String, value: DynValue) {
+        let child = |name: &str| if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) };
+        match value {
+            DynValue::Array(elements) if !elements.is_empty() => {
+                for (i, element) in elements.into_iter().enumerate() {
+                    flatten_into(flat, format!("{}[{}]", path, i), element);
+                }
+            }
+            DynValue::Hash(fields) | DynValue::Object { fields, .. } if !fields.is_empty() => {
+                for (name, field) in fields {
+                    flatten_into(flat, child(&name), field);
+                }
+            }
+            value => {
+                flat.insert(path, value);
+            }
+        }
+    }
+
+    let mut flat = HashMap::new();
+    flatten_into(&mut flat, String::new(), value);
+    flat
+}
+
+struct Converter<'a> {
+    root: &'a Root,
+    options: &'a DynOptions,
+    /// objects currently being converted, seeing one of them again means the graph has a cycle
+    in_progress: Vec<ObjectID>,
+}
+
+impl Converter<'_> {
+    fn symbol(&self, symbol_id: SymbolID) -> Result<String, ConvertError> {
+        self.root.get_symbol(symbol_id).map(|name| name.to_string()).ok_or(ConvertError::InvalidReference(ValueKind::Symbol, symbol_id))
+    }
+
+    fn convert(&mut self, value: &RubyValue) -> Result<DynValue, ConvertError> {
+        let object_id = match value {
+            RubyValue::Nil => return Ok(DynValue::Nil),
+            RubyValue::Boolean(boolean) => return Ok(DynValue::Boolean(*boolean)),
+            RubyValue::FixNum(fixnum) => return Ok(DynValue::Integer((*fixnum).into())),
+            RubyValue::Symbol(symbol_id) => return Ok(DynValue::Symbol(self.symbol(*symbol_id)?)),
+            // instance variables of wrappers are dropped like those of strings
+            RubyValue::WithIvars(wrapper) => return self.convert(&wrapper.0),
+            _ => value.object_id().unwrap(),
+        };
+        if self.in_progress.contains(&object_id) {
+            return Err(ConvertError::Cycle(object_id));
+        }
+        let object = self.root.get_object(object_id).ok_or(ConvertError::InvalidReference(value.kind(), object_id))?;
+
+        self.in_progress.push(object_id);
+        let converted = self.convert_object(value, object);
+        self.in_progress.pop();
+        converted
+    }
+
+    fn convert_object(&mut self, value: &RubyValue, object: &RubyObject) -> Result<DynValue, ConvertError> {
+        Ok(match object {
+            RubyObject::BigNum(bignum) => DynValue::Integer(*bignum),
+            RubyObject::Float(float) => DynValue::Float(*float),
+            RubyObject::String(string) => match self.root.decode_string(string) {
+                Ok(string) => DynValue::String(string),
+                Err(_) => DynValue::Bytes(string.get_string().clone()),
+            },
+            RubyObject::Class(name) => DynValue::Class(name.clone()),
+            RubyObject::Module(name) | RubyObject::ClassOrModule(name) => DynValue::Module(name.clone()),
+            RubyObject::RegExp(regexp) => DynValue::RegExp { pattern: regexp.get_pattern().clone(), options: regexp.get_options() },
+            RubyObject::Array(elements) => {
+                DynValue::Array(elements.iter().map(|element| self.convert(element)).collect::<Result<_, _>>()?)
+            }
+            RubyObject::Hash(hash) => DynValue::Hash(self.convert_hash(hash)?),
+            RubyObject::HashWithDefault(hash) => DynValue::Hash(self.convert_hash(hash.hash())?),
+            RubyObject::Object(object) => DynValue::Object {
+                class_name: self.symbol(object.get_class_name())?,
+                fields: self.convert_fields(object.get_instance_variables())?,
+            },
+            RubyObject::Struct(ruby_struct) => DynValue::Object {
+                class_name: self.symbol(ruby_struct.get_name())?,
+                fields: self.convert_fields(ruby_struct.get_members())?,
+            },
+            RubyObject::UserClass(user_class) => self.convert(user_class.get_wrapped_object())?,
+            RubyObject::UserDefined(user_defined) => DynValue::Data {
+                class_name: self.symbol(user_defined.get_class_name())?,
+                data: Box::new(DynValue::Bytes(user_defined.get_data().clone())),
+            },
+            RubyObject::UserMarshal(user_marshal) => DynValue::Data {
+                class_name: self.symbol(user_marshal.get_class_name())?,
+                data: Box::new(self.convert(user_marshal.get_wrapped_object())?),
+            },
+            RubyObject::Data(data) => DynValue::Data {
+                class_name: self.symbol(data.get_class_name())?,
+                data: Box::new(self.convert(data.get_state())?),
+            },
+            _ => return Err(ConvertError::TypeMismatch { expected: "complete object", found: value.kind() }),
+        })
+    }
+
+    fn convert_fields(&mut self, fields: &ValuePairsSymbolKeys) -> Result<IndexMap<String, DynValue>, ConvertError> {
+        fields.iter().map(|(name, value)| Ok((self.symbol(*name)?, self.convert(value)?))).collect()
+    }
+
+    fn convert_hash(&mut self, hash: &ValuePairs) -> Result<IndexMap<String, DynValue>, ConvertError> {
+        let string_keys: Vec<String> = match self.options.key_collision {
+            KeyCollision::Disambiguate => hash.keys()
+                .filter(|key| matches!(key, RubyValue::String(_)))
+                .filter_map(|key| String::from_ruby_value(self.root, key).ok())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut converted = IndexMap::with_capacity(hash.len());
+        for (key, value) in hash {
+            let key = match key {
+                RubyValue::Symbol(symbol_id) => {
+                    let name = self.symbol(*symbol_id)?;
+                    match self.options.key_collision {
+                        KeyCollision::PrefixSymbols => format!(":{}", name),
+                        KeyCollision::Disambiguate if string_keys.contains(&name) => format!(":{}", name),
+                        _ => name,
+                    }
+                }
+                _ => self.convert_key(key)?,
+            };
+            let value = self.convert(value)?;
+            if converted.contains_key(&key) {
+                return Err(ConvertError::KeyCollision(key));
+            }
+            converted.insert(key, value);
+        }
+        Ok(converted)
+    }
+
+    /// Keys other than symbols have to be strings or scalars, which are written like Ruby's `to_s` would
+    fn convert_key(&mut self, key: &RubyValue) -> Result<String, ConvertError> {
+        match self.convert(key)? {
+            DynValue::Nil => Ok(String::new()),
+            DynValue::Boolean(boolean) => Ok(boolean.to_string()),
+            DynValue::Integer(integer) => Ok(integer.to_string()),
+            DynValue::Float(float) => Ok(float.to_string()),
+            DynValue::String(string) => Ok(string),
+            _ => Err(ConvertError::UnsupportedKey(key.kind())),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    fn keys(value: DynValue) -> Vec<String> {
+        let DynValue::Hash(hash) = value else { panic!("Not a hash") };
+        hash.keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_key_collision() {
+        // {:a=>1, "a"=>2, :b=>3}
+        let root = load(b"\x04\x08{\x08:\x06ai\x06I\"\x06a\x06:\x06ETi\x07:\x06bi\x08");
+        let convert = |key_collision| to_dyn(&root, root.get_root(), &DynOptions { key_collision });
+
+        assert_eq!(keys(convert(KeyCollision::PrefixSymbols).unwrap()), [":a", "a", ":b"]);
+        assert_eq!(keys(convert(KeyCollision::Disambiguate).unwrap()), [":a", "a", "b"]);
+        assert_eq!(convert(KeyCollision::Error), Err(ConvertError::KeyCollision("a".to_string())));
+    }
+
+    #[test]
+    fn test_to_dyn() {
+        // [1, nil, Test(@a=[1.5]), {1=>:x}]
+        let root = load(b"\x04\x08[\x09i\x060o:\x09Test\x06:\x07@a[\x06f\x081.5{\x06i\x06:\x06x");
+        let expected = DynValue::Array(vec![
+            DynValue::Integer(1),
+            DynValue::Nil,
+            DynValue::Object {
+                class_name: "Test".to_string(),
+                fields: IndexMap::from([("@a".to_string(), DynValue::Array(vec![DynValue::Float(1.5)]))]),
+            },
+            DynValue::Hash(IndexMap::from([("1".to_string(), DynValue::Symbol("x".to_string()))])),
+        ]);
+        assert_eq!(to_dyn(&root, root.get_root(), &DynOptions::default()), Ok(expected));
+
+        // a = []; a << a
+        let root = load(b"\x04\x08[\x06@\x00");
+        assert_eq!(to_dyn(&root, root.get_root(), &DynOptions::default()), Err(ConvertError::Cycle(0)));
+    }
+
+    #[test]
+    fn test_flatten() {
+        // Game(@party=[Actor(@name="Eric", @level=3)], @flags={:seen=>true, "ids"=>[]})
+        let root = load(b"\x04\x08o:\x09Game\x07:\x0b@party[\x06o:\x0aActor\x07:\x0a@nameI\"\x09Eric\x06:\x06ET:\x0b@leveli\x08:\x0b@flags{\x07:\x09seenTI\"\x08ids\x06;\x09T[\x00");
+        let flat = root.flatten().unwrap();
+        let expected = HashMap::from([
+            ("@party[0].@name".to_string(), DynValue::String("Eric".to_string())),
+            ("@party[0].@level".to_string(), DynValue::Integer(3)),
+            ("@flags.seen".to_string(), DynValue::Boolean(true)),
+            ("@flags.ids".to_string(), DynValue::Array(Vec::new())),
+        ]);
+        assert_eq!(flat, expected);
+        assert_eq!(flatten(DynValue::Integer(1)), HashMap::from([(String::new(), DynValue::Integer(1))]));
+    }
+}
@@ -1 +1,3 @@
 pub mod dump;
+
+pub use dump::{DumpError, Dumper, DumperOptions, RoundTripMismatch, SpecialInstanceVariables, StringDedup, SymbolOrder};
@@ -1,15 +1,37 @@
-use std::{fmt::Display, io::Write, num::TryFromIntError};
-use crate::values::*;
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+use std::{collections::HashMap, fmt::Display, io::Write, num::TryFromIntError};
+use crate::{error_code::ErrorCode, fixnum, spec::*, values::*};
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DumpError {
     IoError(String),
-    EncoderError(String),
+    EncoderError(ErrorCode, String),
+    /// values are nested deeper than `DumperOptions::max_depth`, `path` holds the values from the root to the first
+    /// value that was too deep
+    DepthLimitExceeded { max_depth: usize, path: Vec<RubyValue> },
+    /// the value references a symbol or object missing from the `Root` being dumped, usually because it belongs to
+    /// another `Root`. `id` is the symbol or the object value, `path` holds the values from the root to the one
+    /// referencing it (empty if the dumped value itself is foreign).
+    ForeignReference { id: RubyValue, path: Vec<RubyValue> },
+}
+
+impl DumpError {
+    /// The class of the error, stable across releases unlike the messages
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DumpError::IoError(_) => ErrorCode::WriteFailed,
+            DumpError::EncoderError(code, _) => *code,
+            DumpError::DepthLimitExceeded { .. } => ErrorCode::DumpDepthLimitExceeded,
+            DumpError::ForeignReference { .. } => ErrorCode::DanglingReference,
+        }
+    }
 }
 
 impl From<TryFromIntError> for DumpError {
     fn from(value: TryFromIntError) -> Self {
-        DumpError::EncoderError(value.to_string())
+        DumpError::EncoderError(ErrorCode::OutOfRange, value.to_string())
     }
 }
 
@@ -19,44 +41,290 @@ impl Display for DumpError {
             DumpError::IoError(error) => {
                 f.write_str(&format!("IO Error: {}", error))
             }
-            DumpError::EncoderError(error) => {
+            DumpError::EncoderError(_, error) => {
                 f.write_str(&format!("Encoder Error: {}", error))
             }
+            DumpError::DepthLimitExceeded { max_depth, path } => {
+                let path: Vec<String> = path.iter().map(|value| format!("{:?}", value)).collect();
+                f.write_str(&format!("Depth Limit Exceeded: values are nested deeper than {} levels at {}", max_depth, path.join(" > ")))
+            }
+            DumpError::ForeignReference { id, path } => {
+                let path: Vec<String> = path.iter().map(|value| format!("{:?}", value)).collect();
+                f.write_str(&format!("Foreign Reference: {:?} is not part of the dumped Root, referenced at {}", id, path.join(" > ")))
+            }
         }
     }
 }
 
+/// The order instance variables and struct members are written in. Ruby numbers symbols in the order they occur
+/// and defines each one where it occurs first, so the order of the symbol definitions follows from the order of the
+/// values and can't be chosen on its own.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SymbolOrder {
+    /// the order they are stored in
+    #[default]
+    AsStored,
+    /// the order recorded for objects and structs (see `Root::get_member_order()`), names missing from it follow in
+    /// the order they are stored in. A load with `LoaderOptions::record_member_order` records the order of the input,
+    /// so output matches blobs of a specific Ruby version byte for byte even after instance variables were removed
+    /// and re-added: Ruby 1.8 wrote them in the order of its symbol table, Ruby 1.9 and later write them in insertion
+    /// order like `AsStored`.
+    Recorded,
+}
+
+/// What happens to special instance variables (see `values::is_special_instance_variable()`) when dumping
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SpecialInstanceVariables {
+    /// they are written back untouched
+    #[default]
+    Forward,
+    /// they are left out
+    Drop,
+}
+
+/// Whether separate string objects with equal contents share one object in the output
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum StringDedup {
+    /// every string object is written, equal strings stay separate like unfrozen strings do in Ruby
+    #[default]
+    Off,
+    /// a string equal to one written before (same bytes and instance variables, so the same encoding) becomes a link
+    /// to it, like the frozen string literals Ruby deduplicates
+    FrozenLiterals,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DumperOptions {
+    pub symbol_order: SymbolOrder,
+    /// plain hashes are written as hashes with a `nil` default, the inverse of `LoaderOptions::collapse_nil_default_hashes`
+    pub hashes_with_nil_default: bool,
+    pub special_instance_variables: SpecialInstanceVariables,
+    /// deepest nesting of values, like the `limit` of Ruby's `Marshal.dump(obj, limit)` (the root is at depth 1,
+    /// instance variable values count as nested), `None` is unlimited
+    pub max_depth: Option<usize>,
+    pub string_dedup: StringDedup,
+}
+
+/// Checks that all symbols and objects reachable from `value` exist in `root` before anything is written, a value
+/// built against another `Root` would otherwise fail halfway through the output or write the wrong objects
+fn check_references(root: &Root, value: &RubyValue) -> Result<(), DumpError> {
+    // values holding references with the index of the value they were reached from, to report the path
+    let mut reached: Vec<(&RubyValue, Option<usize>)> = Vec::new();
+    let mut visited = vec![false; root.get_objects().len()];
+    let mut pending: Vec<(&RubyValue, Option<usize>)> = vec![(value, None)];
+
+    let foreign = |reached: &[(&RubyValue, Option<usize>)], mut parent: Option<usize>, id: RubyValue| {
+        let mut path = Vec::new();
+        while let Some(index) = parent {
+            path.push(reached[index].0.clone());
+            parent = reached[index].1;
+        }
+        path.reverse();
+        DumpError::ForeignReference { id, path }
+    };
+
+    while let Some((value, parent)) = pending.pop() {
+        match value {
+            RubyValue::Symbol(symbol_id) => {
+                if root.get_symbol(*symbol_id).is_none() {
+                    return Err(foreign(&reached, parent, value.clone()));
+                }
+            }
+            RubyValue::WithIvars(wrapper) => {
+                let (inner, ivars) = &**wrapper;
+                reached.push((value, parent));
+                let index = Some(reached.len() - 1);
+                if let Some((name, _)) = ivars.iter().find(|(name, _)| root.get_symbol(*name).is_none()) {
+                    return Err(foreign(&reached, index, RubyValue::Symbol(*name)));
+                }
+                pending.push((inner, index));
+                pending.extend(ivars.iter().map(|(_, ivar)| (ivar, index)));
+            }
+            _ => {
+                let Some(object_id) = value.object_id() else { continue };
+                let Some(object) = root.get_object(object_id) else {
+                    return Err(foreign(&reached, parent, value.clone()));
+                };
+                if std::mem::replace(&mut visited[object_id], true) {
+                    continue;
+                }
+                reached.push((value, parent));
+                let index = Some(reached.len() - 1);
+                if let Some(name) = object.referenced_symbols().into_iter().find(|name| root.get_symbol(*name).is_none()) {
+                    return Err(foreign(&reached, index, RubyValue::Symbol(name)));
+                }
+                pending.extend(object.referenced_values().into_iter().map(|referenced| (referenced, index)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bytes and instance variables of a string, strings with equal keys are equal for `StringDedup::FrozenLiterals`
+type StringKey = (Vec<u8>, Vec<(SymbolID, RubyValue)>);
+
+/// Default size of the internal output buffer, data is handed to the writer in chunks of (at least) this size
+const BUFFER_CAPACITY: usize = 8 * 1024;
+
 pub struct Dumper<'a, T: Write> {
     writer: &'a mut T,
-    /// length is equal to the number of symbols, `symbols[i]` holds `true` if the symbol with id `i` has already been written
-    symbols: Vec<bool>, 
-    /// length is equal to the number of objects + 1 (0th object is the root), `objects[i]` holds `true` if the object with id `i` has already been written
-    objects: Vec<bool>,
+    /// output is collected here and written out in large chunks instead of issuing a `write_all` for every tag byte
+    buffer: Vec<u8>,
+    /// `buffer` is written out once it holds this many bytes, see `with_buffer_capacity()`
+    buffer_capacity: usize,
+    /// length is equal to the number of symbols, `symbols[i]` holds the position in the output's symbol table
+    /// if the symbol with id `i` has already been written, symbol links refer to that position
+    symbols: Vec<Option<usize>>,
+    /// number of symbols written so far
+    symbols_written: usize,
+    /// length is equal to the number of objects, `objects[i]` holds the position in the output's object table
+    /// if the object with id `i` has already been written, object links refer to that position
+    objects: Vec<Option<usize>>,
+    /// number of objects written so far
+    objects_written: usize,
+    /// values currently being written, from the root to the innermost one
+    path: Vec<RubyValue>,
+    /// contents and instance variables of the strings written so far with their position in the output's object
+    /// table, for `StringDedup::FrozenLiterals`
+    written_strings: HashMap<StringKey, usize>,
+    /// bytes handed to the writer so far
+    written: usize,
+    /// set by `verify_roundtrip()` to find the value at an offset of the output
+    locator: Option<Locator>,
+    options: DumperOptions,
+}
+
+/// Tracks the path to the value being written until the byte at `offset` is written
+struct Locator {
+    offset: usize,
+    segments: Vec<PathSegment>,
+    found: Option<Vec<PathSegment>>,
+}
+
+/// Step from a value to one nested in it, see `Locator`
+#[derive(Clone)]
+enum PathSegment {
+    Index(usize),
+    /// pair of a hash, both the key and the value
+    Key(RubyValue),
+    /// instance variable or struct member
+    Name(SymbolID),
+    Field(&'static str),
+}
+
+/// Formats a value path like `@party[0].@name`, empty for the root value
+fn format_path(root: &Root, segments: &[PathSegment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        let name = match segment {
+            PathSegment::Index(index) => {
+                path.push_str(&format!("[{}]", index));
+                continue;
+            }
+            PathSegment::Key(key) => {
+                let mut printed = String::new();
+                if root.print(key, &mut printed, 0, 1).is_err() {
+                    printed = "?".to_string();
+                }
+                path.push_str(&format!("[{}]", printed));
+                continue;
+            }
+            PathSegment::Name(symbol_id) => root.get_symbol(*symbol_id).map_or("?", |name| &**name),
+            PathSegment::Field(name) => name,
+        };
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(name);
+    }
+    path
 }
 
 impl<'a, T: Write> Dumper<'a, T> {
     pub fn new(writer: &'a mut T) -> Self {
+        Self::with_options(writer, DumperOptions::default())
+    }
+
+    pub fn with_options(writer: &'a mut T, options: DumperOptions) -> Self {
         Self {
             writer,
+            buffer: Vec::with_capacity(BUFFER_CAPACITY),
+            buffer_capacity: BUFFER_CAPACITY,
             symbols: Vec::new(),
+            symbols_written: 0,
             objects: Vec::new(),
+            objects_written: 0,
+            path: Vec::new(),
+            written_strings: HashMap::new(),
+            written: 0,
+            locator: None,
+            options,
         }
     }
 
+    /// Hands the output to the writer in chunks of `capacity` bytes instead of 8 KiB, 0 hands every tag, length and
+    /// payload to it as soon as it is written, for writers that buffer themselves
+    pub fn with_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer = Vec::with_capacity(capacity);
+        self.buffer_capacity = capacity;
+        self
+    }
+
     fn reset(&mut self, number_of_symbols: usize, number_of_objects: usize) {
         // TODO: use reserve()
-        self.symbols = vec![false; number_of_symbols];
-        self.objects = vec![false; number_of_objects+1];
+        self.buffer.clear();
+        self.symbols = vec![None; number_of_symbols];
+        self.symbols_written = 0;
+        self.objects = vec![None; number_of_objects];
+        self.objects_written = 0;
+        self.path.clear();
+        self.written_strings.clear();
+        self.written = 0;
+    }
+
+    /// Returns the position of the object in the output's object table if it has already been written
+    fn written_object(&self, object_id: ObjectID) -> Result<Option<usize>, DumpError> {
+        match self.objects.get(object_id) {
+            Some(object_index) => Ok(*object_index),
+            None => Err(DumpError::EncoderError(ErrorCode::DanglingReference, format!("Reference to non-existent object {}", object_id))),
+        }
+    }
+
+    /// Assigns the next position in the output's object table to the object
+    fn register_object(&mut self, object_id: ObjectID) {
+        self.objects[object_id] = Some(self.objects_written);
+        self.objects_written += 1;
     }
 
     fn write(&mut self, data: &[u8]) -> Result<(), DumpError> {
-        if let Err(err) = self.writer.write_all(data) {
+        if let Some(locator) = &mut self.locator {
+            let start = self.written + self.buffer.len();
+            if locator.found.is_none() && (start..start + data.len()).contains(&locator.offset) {
+                locator.found = Some(locator.segments.clone());
+            }
+        }
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= self.buffer_capacity {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), DumpError> {
+        self.write(&[byte])
+    }
+
+    fn write_buffer(&mut self) -> Result<(), DumpError> {
+        if let Err(err) = self.writer.write_all(&self.buffer) {
             return Err(DumpError::IoError(format!("Could not write data: {}", err)));
         }
+        self.written += self.buffer.len();
+        self.buffer.clear();
         Ok(())
     }
 
     fn flush(&mut self) -> Result<(), DumpError> {
+        self.write_buffer()?;
         if let Err(err) = self.writer.flush() {
             return Err(DumpError::IoError(format!("Could not flush data: {}", err)));
         }
@@ -64,9 +332,21 @@ impl<'a, T: Write> Dumper<'a, T> {
     }
 
     pub fn dump(&mut self, root: &Root, object: &RubyValue) -> Result<(), DumpError> {
+        check_references(root, object)?;
         self.reset(root.get_symbols().len(), root.get_objects().len());
 
-        self.write(&[MARSHAL_MAJOR_VERSION, MARSHAL_MINOR_VERSION])?;
+        self.write(&MARSHAL_VERSION)?;
+
+        self.dump_value(root, object)?;
+
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Writes `object` without the version header, the counterpart of `Loader::load_fragment()`
+    pub fn dump_fragment(&mut self, root: &Root, object: &RubyValue) -> Result<(), DumpError> {
+        check_references(root, object)?;
+        self.reset(root.get_symbols().len(), root.get_objects().len());
 
         self.dump_value(root, object)?;
 
@@ -75,10 +355,37 @@ impl<'a, T: Write> Dumper<'a, T> {
     }
 
     fn dump_value(&mut self, root: &Root, object: &RubyValue) -> Result<(), DumpError> {
+        if self.options.max_depth.is_some_and(|max_depth| self.path.len() >= max_depth) {
+            let mut path = self.path.clone();
+            path.push(object.clone());
+            return Err(DumpError::DepthLimitExceeded { max_depth: self.path.len(), path });
+        }
+        self.path.push(object.clone());
+        let result = self.dump_nested_value(root, object);
+        self.path.pop();
+        result
+    }
+
+    /// Runs `write` with `segment` added to the path of the locator, if there is one
+    fn in_segment(
+        &mut self, segment: impl FnOnce() -> PathSegment, write: impl FnOnce(&mut Self) -> Result<(), DumpError>
+    ) -> Result<(), DumpError> {
+        let Some(locator) = &mut self.locator else {
+            return write(self);
+        };
+        locator.segments.push(segment());
+        let result = write(self);
+        if let Some(locator) = &mut self.locator {
+            locator.segments.pop();
+        }
+        result
+    }
+
+    fn dump_nested_value(&mut self, root: &Root, object: &RubyValue) -> Result<(), DumpError> {
         match object {
-            RubyValue::Nil => self.write(&[b'0']),
-            RubyValue::Boolean(boolean) => if *boolean { self.write(&[b'T']) } else { self.write(&[b'F']) },
-            RubyValue::FixNum(fixnum) => { self.write(&[b'i'])?; self.write_fixnum(*fixnum) },
+            RubyValue::Nil => self.write_byte(TAG_NIL),
+            RubyValue::Boolean(boolean) => if *boolean { self.write_byte(TAG_TRUE) } else { self.write_byte(TAG_FALSE) },
+            RubyValue::FixNum(fixnum) => { self.write_byte(TAG_FIXNUM)?; self.write_fixnum(*fixnum) },
             RubyValue::Symbol(symbol_id) => self.write_symbol(root, *symbol_id),
             RubyValue::Array(object_id) => self.write_array(root, *object_id),
             RubyValue::Float(object_id) => self.write_float(root, *object_id),
@@ -94,111 +401,93 @@ impl<'a, T: Write> Dumper<'a, T> {
             RubyValue::Object(object_id) => self.write_object(root, *object_id),
             RubyValue::UserClass(object_id) => self.write_user_class(root, *object_id),
             RubyValue::UserMarshal(object_id) => self.write_user_marshal(root, *object_id),
-            RubyValue::UserDefined(object_id) => self.write_user_defined(root, *object_id),
+            RubyValue::UserDefined(object_id) => self.write_user_defined(root, *object_id, &[]),
+            RubyValue::Data(object_id) => self.write_data(root, *object_id),
+            RubyValue::WithIvars(wrapper) => self.write_with_ivars(root, &wrapper.0, &wrapper.1),
+            RubyValue::Raw(object_id) => self.write_raw(root, *object_id),
         }
 
     }
 
-    fn write_fixnum(&mut self, mut number: i32) -> Result<(), DumpError> {
-        let mut output = [0; std::mem::size_of::<i32>() + 1];
-        let mut bytes_written = 0;
-
-        match number {
-            0 => {
-                output[0] = 0x00;
-                bytes_written += 1;
-            },
-            1 ..= 122 => {
-                output[0] = (number as i8 + 5).to_le_bytes()[0];
-                bytes_written += 1;
-            },
-            -123 ..= -1 => {
-                output[0] = (number as i8 - 5).to_le_bytes()[0];
-                bytes_written += 1;
-            },
-            _ => {
-                bytes_written += 1; // for fixnum size
-                for i in 1..(std::mem::size_of::<i32>() + 1) {
-                    output[i] = u8::try_from(number & 0xFF).unwrap();
-                    bytes_written += 1;
-
-                    number >>= 8;
-                    if number == 0 {
-                        output[0] = u8::try_from(i).unwrap();
-                        break;
-                    }
-                    if number == -1 {
-                        output[0] = (-i8::try_from(i).unwrap()) as u8;
-                        break;
-                    }
-                }
-            }
-        }
-
-        self.write(&output[..bytes_written])
+    fn write_fixnum(&mut self, number: i32) -> Result<(), DumpError> {
+        self.write(fixnum::encode(number).as_bytes())
     }
 
     fn write_byte_sequence(&mut self, sequence: &[u8]) -> Result<(), DumpError> {
         if let Ok(sequence_len) = i32::try_from(sequence.len()) {
             self.write_fixnum(sequence_len)?;
         } else {
-            return Err(DumpError::EncoderError("Could not write byte sequence length, the length doesn't fit into an i32".to_string()));
+            return Err(DumpError::EncoderError(ErrorCode::OutOfRange, "Could not write byte sequence length, the length doesn't fit into an i32".to_string()));
         }
 
         self.write(sequence)
     }
 
     fn write_symbol(&mut self, root: &Root, symbol_id: SymbolID) -> Result<(), DumpError> {
-        if self.symbols[symbol_id] {
+        let Some(&written_symbol) = self.symbols.get(symbol_id) else {
+            return Err(DumpError::EncoderError(ErrorCode::DanglingReference, format!("Reference to non-existent symbol {}", symbol_id)));
+        };
+        if let Some(symbol_index) = written_symbol {
             // symbol has been written before, writing a symbol link
-            self.write(&[b';'])?;
-            self.write_fixnum(symbol_id.try_into().unwrap())?;
+            self.write_byte(TAG_SYMBOL_LINK)?;
+            self.write_fixnum(symbol_index.try_into()?)?;
         } else {
             // symbol hasn't been written before, writing a symbol
-            self.symbols[symbol_id] = true;
-            self.write(&[b':'])?;
-            self.write_byte_sequence(root.get_symbol(symbol_id).unwrap().as_bytes())?;
+            let bytes = root.get_symbol_bytes(symbol_id)
+                .ok_or_else(|| DumpError::EncoderError(ErrorCode::DanglingReference, format!("Reference to non-existent symbol {}", symbol_id)))?;
+            let encoding = root.get_encoded_symbol(symbol_id).map(EncodedSymbol::get_instance_variables).filter(|encoding| !encoding.is_empty());
+            if encoding.is_some() {
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
+            }
+            self.symbols[symbol_id] = Some(self.symbols_written);
+            self.symbols_written += 1;
+            self.write_byte(TAG_SYMBOL)?;
+            self.write_byte_sequence(bytes)?;
+            // the encoding follows the symbol's definition, after it is registered
+            if let Some(encoding) = encoding {
+                self.write_fixnum(encoding.len().try_into()?)?;
+                for (name, value) in encoding {
+                    self.write_symbol(root, *name)?;
+                    self.dump_value(root, value)?;
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn write_object_link(&mut self, object_id: ObjectID) -> Result<(), DumpError> {
-        self.write(&[b'@'])?;
-        self.write_fixnum(object_id.try_into().unwrap())
+    fn write_object_link(&mut self, object_index: usize) -> Result<(), DumpError> {
+        self.write_byte(TAG_OBJECT_LINK)?;
+        self.write_fixnum(object_index.try_into()?)
     }
 
     fn write_array(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // array has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // array hasn't been written before, writing an array
-            self.write(&[b'['])?;
-            self.objects[object_id] = true;
-            let array = root.get_object(object_id).unwrap().as_array();
+            self.write_byte(TAG_ARRAY)?;
+            self.register_object(object_id);
+            let array = get_typed_object(root, object_id, RubyObject::try_as_array)?;
             self.write_fixnum(array.len().try_into()?)?;
-            for value in array {
-                self.dump_value(root, value)?;
+            for (index, value) in array.iter().enumerate() {
+                self.in_segment(|| PathSegment::Index(index), |dumper| dumper.dump_value(root, value))?;
             }
         }
         Ok(())
     }
 
     fn write_float(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // float has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // float hasn't been written before, writing an float
-            self.write(&[b'f'])?;
-            self.objects[object_id] = true;
-            let float = root.get_object(object_id).unwrap().as_float();
-            if float.is_nan() {
-                self.write_byte_sequence(b"nan")?; // float.to_string() returns NaN
-            } else {
-                self.write_byte_sequence(float.to_string().as_bytes())?;
-            }
+            self.write_byte(TAG_FLOAT)?;
+            self.register_object(object_id);
+            let float = get_typed_object(root, object_id, RubyObject::try_as_float)?;
+            self.write_byte_sequence(format_float(*float).as_bytes())?;
         }
         Ok(())
     }
@@ -206,8 +495,10 @@ impl<'a, T: Write> Dumper<'a, T> {
     fn write_value_pairs(&mut self, root: &Root, value_pairs: &ValuePairs) -> Result<(), DumpError> {
         self.write_fixnum(value_pairs.len().try_into()?)?;
         for (key, value) in value_pairs {
-            self.dump_value(root, key)?;
-            self.dump_value(root, value)?;
+            self.in_segment(|| PathSegment::Key(key.clone()), |dumper| {
+                dumper.dump_value(root, key)?;
+                dumper.dump_value(root, value)
+            })?;
         }
         Ok(())
     }
@@ -215,250 +506,511 @@ impl<'a, T: Write> Dumper<'a, T> {
     fn write_value_pairs_with_symbol_keys(&mut self, root: &Root, value_pairs: &ValuePairsSymbolKeys) -> Result<(), DumpError> {
         self.write_fixnum(value_pairs.len().try_into()?)?;
         for (key, value) in value_pairs {
-            self.write_symbol(root, *key)?;
-            self.dump_value(root, value)?;
+            self.write_named_value(root, *key, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_instance_variables(&mut self, root: &Root, instance_variables: &ValuePairsSymbolKeys) -> Result<(), DumpError> {
+        self.write_instance_variable_pairs(root, instance_variables.iter().collect())
+    }
+
+    fn write_instance_variable_pairs(&mut self, root: &Root, mut ordered: Vec<(&SymbolID, &RubyValue)>) -> Result<(), DumpError> {
+        if self.options.special_instance_variables == SpecialInstanceVariables::Drop {
+            ordered.retain(|(symbol_id, _)| !root.get_symbol(**symbol_id).is_some_and(|name| is_special_instance_variable(name)));
+        }
+
+        self.write_fixnum(ordered.len().try_into()?)?;
+        for (key, value) in ordered {
+            self.write_named_value(root, *key, value)?;
         }
         Ok(())
     }
 
+    /// Writes an instance variable or struct member
+    fn write_named_value(&mut self, root: &Root, name: SymbolID, value: &RubyValue) -> Result<(), DumpError> {
+        self.in_segment(|| PathSegment::Name(name), |dumper| {
+            dumper.write_symbol(root, name)?;
+            dumper.dump_value(root, value)
+        })
+    }
+
     fn write_hash(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // hash has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // hash hasn't been written before, writing an hash
-            self.write(&[b'{'])?;
-            self.objects[object_id] = true;
-            let hash = root.get_object(object_id).unwrap().as_hash();
+            self.write_byte(if self.options.hashes_with_nil_default { TAG_HASH_WITH_DEFAULT } else { TAG_HASH })?;
+            self.register_object(object_id);
+            let hash = get_typed_object(root, object_id, RubyObject::try_as_hash)?;
             self.write_value_pairs(root, hash)?;
+            if self.options.hashes_with_nil_default {
+                self.write_byte(TAG_NIL)?;
+            }
         }
         Ok(())
     }
 
     fn write_hash_with_default(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // hash has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // hash hasn't been written before, writing an hash
-            self.write(&[b'}'])?;
-            self.objects[object_id] = true;
-            let hash = root.get_object(object_id).unwrap().as_hash_with_default();
+            self.write_byte(TAG_HASH_WITH_DEFAULT)?;
+            self.register_object(object_id);
+            let hash = get_typed_object(root, object_id, RubyObject::try_as_hash_with_default)?;
             self.write_value_pairs(root, hash.hash())?;
-            self.dump_value(root, hash.default())?;
+            self.in_segment(|| PathSegment::Field("default"), |dumper| dumper.dump_value(root, hash.default()))?;
         }
         Ok(())
     }
 
     fn write_class(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // class has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // class hasn't been written before, writing an class
-            self.write(&[b'c'])?;
-            self.objects[object_id] = true;
-            let class = root.get_object(object_id).unwrap().as_class();
+            self.write_byte(TAG_CLASS)?;
+            self.register_object(object_id);
+            let class = get_typed_object(root, object_id, RubyObject::try_as_class)?;
             self.write_byte_sequence(class.as_bytes())?;
         }
         Ok(())
     }
 
     fn write_module(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // module has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // module hasn't been written before, writing an module
-            self.write(&[b'm'])?;
-            self.objects[object_id] = true;
-            let module = root.get_object(object_id).unwrap().as_module();
+            self.write_byte(TAG_MODULE)?;
+            self.register_object(object_id);
+            let module = get_typed_object(root, object_id, RubyObject::try_as_module)?;
             self.write_byte_sequence(module.as_bytes())?;
         }
         Ok(())
     }
 
     fn write_class_or_module(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // class_or_module has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // class_or_module hasn't been written before, writing an class_or_module
-            self.write(&[b'M'])?;
-            self.objects[object_id] = true;
-            let class_or_module = root.get_object(object_id).unwrap().as_class_or_module();
+            self.write_byte(TAG_CLASS_OR_MODULE)?;
+            self.register_object(object_id);
+            let class_or_module = get_typed_object(root, object_id, RubyObject::try_as_class_or_module)?;
             self.write_byte_sequence(class_or_module.as_bytes())?;
         }
         Ok(())
     }
 
     fn write_string(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // string has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // string hasn't been written before, writing an string
-            self.objects[object_id] = true;
-            let string = root.get_object(object_id).unwrap().as_string();
+            let string = get_typed_object(root, object_id, RubyObject::try_as_string)?;
+            if self.options.string_dedup == StringDedup::FrozenLiterals {
+                let instance_variables = string.get_instance_variables().iter().flatten().map(|(name, value)| (*name, value.clone())).collect();
+                let key = (string.get_string().clone(), instance_variables);
+                if let Some(&object_index) = self.written_strings.get(&key) {
+                    // an equal string has been written before, this one becomes a link to it
+                    self.objects[object_id] = Some(object_index);
+                    return self.write_object_link(object_index);
+                }
+                self.written_strings.insert(key, self.objects_written);
+            }
+            self.register_object(object_id);
             let has_instance_variables = string.get_instance_variables().is_some();
             if has_instance_variables {
-                self.write(&[b'I'])?;
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
             }
-            self.write(&[b'"'])?;
+            self.write_byte(TAG_STRING)?;
             self.write_byte_sequence(string.get_string())?;
-            if has_instance_variables {
-                self.write_value_pairs_with_symbol_keys(root, string.get_instance_variables().as_ref().unwrap())?;
+            if let Some(instance_variables) = string.get_instance_variables() {
+                self.write_instance_variables(root, instance_variables)?;
             }
         }
         Ok(())
     }
 
     fn write_bignum(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // bignum has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // bignum hasn't been written before, writing an bignum
-            self.objects[object_id] = true;
-            self.write(b"l")?;
-            let bignum = root.get_object(object_id).unwrap().as_bignum();
-            if bignum.is_positive() {
-                self.write(b"+")?;
+            self.register_object(object_id);
+            self.write_byte(TAG_BIGNUM)?;
+            let bignum = get_typed_object(root, object_id, RubyObject::try_as_bignum)?;
+            if bignum.is_negative() {
+                self.write_byte(BIGNUM_NEGATIVE)?;
             } else {
-                self.write(b"-")?; // will write 0 as -0, although 0 shouldn't be encoded as bignum
+                self.write_byte(BIGNUM_POSITIVE)?;
             }
-            let bignum = bignum.abs();
+            // unsigned_abs doesn't overflow on RubyBignum::MIN
+            let bignum = bignum.unsigned_abs();
             let bignum_bytes = bignum.to_le_bytes();
-            let mut first_non_zero_byte = 0;
-            while bignum_bytes[first_non_zero_byte] == 0 {
-                first_non_zero_byte += 1;
+            // bytes are little endian, the length is counted in 16 bit words and excludes high zero words
+            let mut used_bytes = bignum_bytes.len();
+            while used_bytes > 0 && bignum_bytes[used_bytes - 1] == 0 {
+                used_bytes -= 1;
             }
-            if first_non_zero_byte % 2 == 1 {
-                first_non_zero_byte -= 1;
-            }
-            self.write_fixnum(((std::mem::size_of::<RubyBignum>() - first_non_zero_byte) / 2).try_into()?)?;
-            self.write(&bignum_bytes[first_non_zero_byte..])?;
+            let used_words = used_bytes.div_ceil(2);
+            self.write_fixnum(used_words.try_into()?)?;
+            self.write(&bignum_bytes[..used_words * 2])?;
         }
         Ok(())
     }
 
     fn write_regexp(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // regexp has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // regexp hasn't been written before, writing an regexp
-            self.objects[object_id] = true;
-            let regexp = root.get_object(object_id).unwrap().as_regexp();
+            self.register_object(object_id);
+            let regexp = get_typed_object(root, object_id, RubyObject::try_as_regexp)?;
             let has_instance_variables = regexp.get_instance_variables().is_some();
             if has_instance_variables {
-                self.write(&[b'I'])?;
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
             }
-            self.write(&[b'/'])?;
+            self.write_byte(TAG_REGEXP)?;
             self.write_byte_sequence(regexp.get_pattern().as_bytes())?;
             self.write(&[regexp.get_options() as u8])?;
-            if has_instance_variables {
-                self.write_value_pairs_with_symbol_keys(root, regexp.get_instance_variables().as_ref().unwrap())?;
+            if let Some(instance_variables) = regexp.get_instance_variables() {
+                self.write_instance_variables(root, instance_variables)?;
             }
         }
         Ok(())
     }
 
     fn write_struct(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // struct has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // struct hasn't been written before, writing an struct
-            self.objects[object_id] = true;
-            let ruby_struct = root.get_object(object_id).unwrap().as_struct();
-            self.write(&[b'S'])?;
+            self.register_object(object_id);
+            let ruby_struct = get_typed_object(root, object_id, RubyObject::try_as_struct)?;
+            if ruby_struct.get_instance_variables().is_some() {
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
+            }
+            self.write_byte(TAG_STRUCT)?;
             self.write_symbol(root, ruby_struct.get_name())?;
-            self.write_value_pairs_with_symbol_keys(root, ruby_struct.get_members())?;
+            if self.options.symbol_order == SymbolOrder::Recorded {
+                let members = recorded_order(root, object_id, ruby_struct.get_members());
+                self.write_fixnum(members.len().try_into()?)?;
+                for (key, value) in members {
+                    self.write_named_value(root, *key, value)?;
+                }
+            } else {
+                self.write_value_pairs_with_symbol_keys(root, ruby_struct.get_members())?;
+            }
+            if let Some(instance_variables) = ruby_struct.get_instance_variables() {
+                self.write_instance_variables(root, instance_variables)?;
+            }
         }
         Ok(())
     }
 
     fn write_object(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // object has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // object hasn't been written before, writing an object
-            self.objects[object_id] = true;
-            let object = root.get_object(object_id).unwrap().as_object();
-            self.write(&[b'o'])?;
+            self.register_object(object_id);
+            let object = get_typed_object(root, object_id, RubyObject::try_as_object)?;
+            self.write_extended_modules(root, object.get_extended_modules())?;
+            self.write_byte(TAG_OBJECT)?;
             self.write_symbol(root, object.get_class_name())?;
-            self.write_value_pairs_with_symbol_keys(root, object.get_instance_variables())?;
+            if self.options.symbol_order == SymbolOrder::Recorded {
+                self.write_instance_variable_pairs(root, recorded_order(root, object_id, object.get_instance_variables()))?;
+            } else {
+                self.write_instance_variables(root, object.get_instance_variables())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_extended_modules(&mut self, root: &Root, extended_modules: &[SymbolID]) -> Result<(), DumpError> {
+        for module in extended_modules {
+            self.write_byte(TAG_EXTENDED)?;
+            self.write_symbol(root, *module)?;
         }
         Ok(())
     }
 
     fn write_user_class(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // user_class has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // user_class hasn't been written before, writing an user_class
-            self.objects[object_id] = true;
-            let user_class = root.get_object(object_id).unwrap().as_user_class();
+            self.register_object(object_id);
+            let user_class = get_typed_object(root, object_id, RubyObject::try_as_user_class)?;
             let has_instance_variables = user_class.get_instance_variables().is_some();
             if has_instance_variables {
-                self.write(&[b'I'])?;
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
             }
-            self.write(&[b'C'])?;
+            self.write_byte(TAG_USER_CLASS)?;
             self.write_symbol(root, user_class.get_name())?;
-            self.dump_value(root, user_class.get_wrapped_object())?;
-            if has_instance_variables {
-                self.write_value_pairs_with_symbol_keys(root, user_class.get_instance_variables().as_ref().unwrap())?;
+            self.in_segment(|| PathSegment::Field("wrapped_object"), |dumper| dumper.dump_value(root, user_class.get_wrapped_object()))?;
+            if let Some(instance_variables) = user_class.get_instance_variables() {
+                self.write_instance_variables(root, instance_variables)?;
             }
         }
         Ok(())
     }
 
-    fn write_user_defined(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+    fn write_with_ivars(&mut self, root: &Root, inner: &RubyValue, ivars: &[(SymbolID, RubyValue)]) -> Result<(), DumpError> {
+        // a user_defined object is registered after its instance variables, write_user_defined() has to write them
+        if let RubyValue::UserDefined(object_id) = inner {
+            if self.written_object(*object_id)?.is_none() {
+                return self.write_user_defined(root, *object_id, ivars);
+            }
+        }
+        self.write_byte(TAG_INSTANCE_VARIABLES)?;
+        self.dump_value(root, inner)?;
+        self.write_instance_variable_pairs(root, ivars.iter().map(|(key, value)| (key, value)).collect())
+    }
+
+    /// `wrapper_ivars` are the instance variables of a `WithIvars` wrapper around the object, written after its own
+    fn write_user_defined(&mut self, root: &Root, object_id: ObjectID, wrapper_ivars: &[(SymbolID, RubyValue)]) -> Result<(), DumpError> {
+        if let Some(object_index) = self.written_object(object_id)? {
             // user_defined has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // user_defined hasn't been written before, writing an user_defined
-            self.objects[object_id] = true;
-            let user_defined = root.get_object(object_id).unwrap().as_user_defined();
-            let has_instance_variables = user_defined.get_instance_variables().is_some();
+            let user_defined = get_typed_object(root, object_id, RubyObject::try_as_user_defined)?;
+            if user_defined.is_truncated() {
+                return Err(DumpError::EncoderError(ErrorCode::TruncatedPayload, format!("UserDefined {} only holds a part of its data", object_id)));
+            }
+            let has_instance_variables = user_defined.get_instance_variables().is_some() || !wrapper_ivars.is_empty();
             if has_instance_variables {
-                self.write(&[b'I'])?;
+                self.write_byte(TAG_INSTANCE_VARIABLES)?;
             }
-            self.write(&[b'u'])?;
+            self.write_extended_modules(root, user_defined.get_extended_modules())?;
+            self.write_byte(TAG_USER_DEFINED)?;
             self.write_symbol(root, user_defined.get_class_name())?;
             self.write_byte_sequence(user_defined.get_data())?;
             if has_instance_variables {
-                self.write_value_pairs_with_symbol_keys(root, user_defined.get_instance_variables().as_ref().unwrap())?;
+                let own_ivars = user_defined.get_instance_variables().iter().flat_map(|instance_variables| instance_variables.iter());
+                let wrapper_ivars = wrapper_ivars.iter().map(|(key, value)| (key, value));
+                self.write_instance_variable_pairs(root, own_ivars.chain(wrapper_ivars).collect())?;
             }
+            // Ruby registers user_defined objects after their data and instance variables
+            self.register_object(object_id);
         }
         Ok(())
     }
 
     fn write_user_marshal(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
-        if self.objects[object_id] {
+        if let Some(object_index) = self.written_object(object_id)? {
             // user_marshal has been written before, writing an object link
-            self.write_object_link(object_id)?;
+            self.write_object_link(object_index)?;
         } else {
             // user_marshal hasn't been written before, writing an user_marshal
-            self.objects[object_id] = true;
-            let user_marshal = root.get_object(object_id).unwrap().as_user_marshal();
-            self.write(&[b'U'])?;
+            self.register_object(object_id);
+            let user_marshal = get_typed_object(root, object_id, RubyObject::try_as_user_marshal)?;
+            self.write_byte(TAG_USER_MARSHAL)?;
             self.write_symbol(root, user_marshal.get_class_name())?;
-            self.dump_value(root, user_marshal.get_wrapped_object())?;
+            self.in_segment(|| PathSegment::Field("wrapped_object"), |dumper| dumper.dump_value(root, user_marshal.get_wrapped_object()))?;
         }
         Ok(())
     }
+
+    fn write_data(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
+        if let Some(object_index) = self.written_object(object_id)? {
+            // data has been written before, writing an object link
+            self.write_object_link(object_index)?;
+        } else {
+            // data hasn't been written before, writing a data object
+            self.register_object(object_id);
+            let data = get_typed_object(root, object_id, RubyObject::try_as_data)?;
+            self.write_byte(TAG_DATA)?;
+            self.write_symbol(root, data.get_class_name())?;
+            self.in_segment(|| PathSegment::Field("state"), |dumper| dumper.dump_value(root, data.get_state()))?;
+        }
+        Ok(())
+    }
+
+    /// Raw fragments are written again wherever they are referenced, they have no position to link to
+    fn write_raw(&mut self, root: &Root, object_id: ObjectID) -> Result<(), DumpError> {
+        self.written_object(object_id)?;
+        let raw = get_typed_object(root, object_id, RubyObject::try_as_raw)?;
+        self.write(raw.get_bytes())?;
+        // links written later count the symbols and objects the fragment defined
+        self.symbols_written += raw.get_symbol_count();
+        self.objects_written += raw.get_object_count();
+        Ok(())
+    }
+}
+
+impl Dumper<'_, Vec<u8>> {
+    /// Dumps `root` and compares the output with `original` (usually the bytes `root` was loaded from),
+    /// returns `None` if they are identical and a description of the first difference otherwise. The description
+    /// names the value the difference is in, which takes a second dump.
+    pub fn verify_roundtrip(root: &Root, original: &[u8]) -> Result<Option<RoundTripMismatch>, DumpError> {
+        let mut dumped = Vec::with_capacity(original.len());
+        Dumper::new(&mut dumped).dump(root, root.get_root())?;
+
+        let offset = match original.iter().zip(&dumped).position(|(a, b)| a != b) {
+            Some(offset) => offset,
+            None if original.len() == dumped.len() => return Ok(None),
+            None => original.len().min(dumped.len()),
+        };
+
+        let mut sink = std::io::sink();
+        let mut locating = Dumper::new(&mut sink);
+        locating.locator = Some(Locator { offset, segments: Vec::new(), found: None });
+        locating.dump(root, root.get_root())?;
+        let path = locating.locator.and_then(|locator| locator.found).map(|segments| format_path(root, &segments));
+
+        Ok(Some(RoundTripMismatch {
+            offset,
+            path,
+            original_len: original.len(),
+            dumped_len: dumped.len(),
+            original_context: describe_context(original, offset),
+            dumped_context: describe_context(&dumped, offset),
+        }))
+    }
+}
+
+/// First difference found by `Dumper::verify_roundtrip()`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RoundTripMismatch {
+    /// offset of the first differing byte, or the length of the shorter side if one is a prefix of the other
+    pub offset: usize,
+    /// path of the value whose encoding holds the differing byte of the dump, like `@party[0].@name` (`[i]` are array
+    /// elements and hash pairs, instance variables and struct members are separated by dots), empty for the root
+    /// value, `None` if the dump ends before the offset
+    pub path: Option<String>,
+    pub original_len: usize,
+    pub dumped_len: usize,
+    /// bytes around the offset, escaped like a Ruby string literal with the byte at the offset in brackets
+    pub original_context: String,
+    pub dumped_context: String,
+}
+
+impl Display for RoundTripMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = match self.path.as_deref() {
+            Some("") => "in the root value".to_string(),
+            Some(path) => format!("in {}", path),
+            None => "after the end of the dump".to_string(),
+        };
+        f.write_str(&format!(
+            "Output differs at offset {} {} (original has {} bytes, dump has {} bytes)\n  original: {}\n  dumped:   {}",
+            self.offset, location, self.original_len, self.dumped_len, self.original_context, self.dumped_context
+        ))
+    }
+}
+
+/// Number of bytes shown on each side of the offset by `describe_context()`
+const CONTEXT_BYTES: usize = 8;
+
+fn describe_context(bytes: &[u8], offset: usize) -> String {
+    let escape = |bytes: &[u8]| -> String {
+        bytes.iter().map(|byte| match byte {
+            b'\\' => "\\\\".to_string(),
+            b'"' => "\\\"".to_string(),
+            0x20..=0x7e => (*byte as char).to_string(),
+            _ => format!("\\x{:02x}", byte),
+        }).collect()
+    };
+
+    let start = offset.saturating_sub(CONTEXT_BYTES);
+    let end = bytes.len().min(offset + CONTEXT_BYTES + 1);
+    let prefix = if start > 0 { "..." } else { "" };
+    let suffix = if end < bytes.len() { "..." } else { "" };
+    match bytes.get(offset) {
+        Some(byte) => format!(
+            "{}{}[{}]{}{}",
+            prefix, escape(&bytes[start..offset]), escape(&[*byte]), escape(&bytes[offset + 1..end]), suffix
+        ),
+        None => format!("{}{}[end]", prefix, escape(&bytes[start..])),
+    }
+}
+
+/// Formats a float the same way Ruby's Marshal does: shortest round-trip digits, written in
+/// exponent form when the decimal point lies outside of the digits (`100.0` is `1e2`, `0.00001` is `1e-5`)
+fn format_float(float: f64) -> String {
+    if float.is_nan() {
+        return "nan".to_string(); // float.to_string() returns NaN
+    }
+    if float.is_infinite() {
+        return if float.is_sign_positive() { "inf" } else { "-inf" }.to_string();
+    }
+    if float == 0.0 {
+        return if float.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    // `{:e}` yields the shortest round-trip digits, e.g. "-1.25e-3"
+    let scientific = format!("{:e}", float.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let digits_len = digits.len() as i32;
+    let decimal_point = exponent.parse::<i32>().unwrap_or(0) + 1;
+
+    let mut output = String::new();
+    if float.is_sign_negative() {
+        output.push('-');
+    }
+    if decimal_point < -3 || decimal_point > digits_len {
+        output.push_str(&digits[..1]);
+        if digits_len > 1 {
+            output.push('.');
+            output.push_str(&digits[1..]);
+        }
+        output.push_str(&format!("e{}", decimal_point - 1));
+    } else if decimal_point > 0 {
+        let (integer_part, fraction_part) = digits.split_at(decimal_point as usize);
+        output.push_str(integer_part);
+        if !fraction_part.is_empty() {
+            output.push('.');
+            output.push_str(fraction_part);
+        }
+    } else {
+        output.push_str("0.");
+        output.push_str(&"0".repeat(decimal_point.unsigned_abs() as usize));
+        output.push_str(&digits);
+    }
+    output
+}
+
+/// Pairs of object `object_id` in the order recorded for it, pairs missing from the order follow in stored order
+fn recorded_order<'r>(root: &Root, object_id: ObjectID, pairs: &'r ValuePairsSymbolKeys) -> Vec<(&'r SymbolID, &'r RubyValue)> {
+    let mut ordered: Vec<(&SymbolID, &RubyValue)> = pairs.iter().collect();
+    if let Some(member_order) = root.get_member_order(object_id) {
+        // stable, so unknown names (usize::MAX) keep their relative order
+        ordered.sort_by_key(|(symbol_id, _)| member_order.iter().position(|id| id == *symbol_id).unwrap_or(usize::MAX));
+    }
+    ordered
 }
 
-#[cfg(test)]
+/// Looks up object `object_id` and checks its type with `accessor` (one of the `RubyObject::try_as_*` methods)
+fn get_typed_object<'r, O>(root: &'r Root, object_id: ObjectID, accessor: fn(&'r RubyObject) -> Option<&'r O>) -> Result<&'r O, DumpError> {
+    match root.get_object(object_id) {
+        Some(object) => accessor(object)
+            .ok_or_else(|| DumpError::EncoderError(ErrorCode::UnexpectedType, format!("Object {} has unexpected type {}", object_id, object.kind()))),
+        None => Err(DumpError::EncoderError(ErrorCode::DanglingReference, format!("Reference to non-existent object {}", object_id))),
+    }
+}
+
+#[cfg(all(test, feature = "decode"))]
 mod tests {
-    use std::io::BufReader;
+    use std::io::{self, BufReader};
 
-    use crate::decode::load::Loader;
+    use crate::decode::load::{Loader, LoaderOptions};
 
     use super::*;
 
@@ -544,6 +1096,14 @@ mod tests {
         assert_output_is!(b"\x04\x08f\x08nan");
         assert_output_is!(b"\x04\x08f\x092.55");
         assert_output_is!(b"\x04\x08[\x07f\x092.55@\x06");
+        assert_output_is!(b"\x04\x08f\x061");
+        assert_output_is!(b"\x04\x08f\x07-0");
+        assert_output_is!(b"\x04\x08f\x083e2");
+        assert_output_is!(b"\x04\x08f\x091e-5");
+        assert_output_is!(b"\x04\x08f\x0a0.001");
+        assert_output_is!(b"\x04\x08f\x0b1.5e20");
+        assert_output_is!(b"\x04\x08f\x0d-1.25e-7");
+        assert_output_is!(b"\x04\x08f\x161697500000.123456");
     }
 
     #[test]
@@ -576,6 +1136,12 @@ mod tests {
     fn test_write_bignum() {
         assert_output_is!(b"\x04\x08l+\x09\xb9\xa3\x38\x97\x22\x26\x36\x00");
         assert_output_is!(b"\x04\x08l-\x09\xb9\xa3\x38\x97\x22\x26\x36\x00");
+        assert_output_is!(b"\x04\x08l+\x08\xf2\x2f\xce\x73\x3a\x0b");
+        assert_output_is!(b"\x04\x08l+\x07\x00\x00\x00\x40");
+        // i64::MIN, whose magnitude doesn't fit into i64
+        assert_output_is!(b"\x04\x08l-\x09\x00\x00\x00\x00\x00\x00\x00\x80");
+        // zero keeps the positive sign
+        assert_output_is!(b"\x04\x08l+\x00");
     }
 
     #[test]
@@ -596,11 +1162,61 @@ mod tests {
     #[test]
     fn test_write_user_class() {
         assert_output_is!(b"\x04\x08IC:\x09Test\"\x06a\x06:\x06ET");
+        // subclasses of Hash with a default value and instance variables
+        assert_output_is!(b"\x04\x08C:\x0dSettings}\x06i\x06i\x07i\x00");
+        assert_output_is!(b"\x04\x08IC:\x0dSettings}\x00i\x00\x06:\x0a@modeT");
+        assert_output_is!(b"\x04\x08[\x07C:\x0dSettings}\x00[\x06@\x06@\x06");
     }
 
     #[test]
     fn test_write_user_defined() {
         assert_output_is!(b"\x04\x08Iu:\x09Test\x061\x06:\x06EF");
+        assert_output_is!(b"\x04\x08[\x07Iu:\x09Test\x061\x06:\x07@a\"\x06b@\x07");
+    }
+
+    #[test]
+    fn test_write_with_ivars() {
+        assert_output_is!(b"\x04\x08I:\x06a\x06:\x06ET");
+        assert_output_is!(b"\x04\x08I[\x00\x06:\x07@ai\x06");
+        assert_output_is!(b"\x04\x08I{\x06i\x06i\x07\x06:\x07@ai\x06");
+        assert_output_is!(b"\x04\x08[\x07IS:\x09Test\x06:\x06ai\x06\x06:\x07@b@\x06@\x06");
+
+        let inputs: [&[u8]; 4] = [
+            b"\x04\x08I\"\x06a\x06:\x06ET",
+            b"\x04\x08IC:\x09Test\"\x06a\x06:\x06ET",
+            b"\x04\x08[\x07Iu:\x09Test\x061\x06:\x07@a\"\x06b@\x07",
+            b"\x04\x08[\x07Ie:\x06Mu:\x08Foo\x06a\x06:\x06ETe;\x00o;\x07\x00",
+        ];
+        for input in inputs {
+            let mut reader = BufReader::new(input);
+            let options = LoaderOptions { ivar_wrappers: true, ..Default::default() };
+            let root = Loader::with_options(&mut reader, options).load().unwrap();
+            assert!(matches!(root.get_root(), RubyValue::WithIvars(_) | RubyValue::Array(_)));
+
+            let mut output = Vec::<u8>::new();
+            Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+            assert_eq!(input, output);
+        }
+    }
+
+    #[test]
+    fn test_write_encoded_symbols() {
+        // :"é", Foo(@あ=1) with a Shift-JIS instance variable name, and a Shift-JIS symbol of Ruby 1.8 without encoding
+        assert_output_is!(b"\x04\x08I:\x07\xc3\xa9\x06:\x06ET");
+        assert_output_is!(b"\x04\x08o:\x08Foo\x06I:\x08@\x82\xa0\x06:\x0dencoding\"\x0eShift_JISi\x06");
+        assert_output_is!(b"\x04\x08[\x07:\x07\x82\xa0;\x00");
+
+        let load = |input: &[u8]| Loader::new(&mut BufReader::new(input)).load().unwrap();
+        let root = load(b"\x04\x08I:\x07\xc3\xa9\x06:\x06ET");
+        assert_eq!(root.get_root(), &RubyValue::Symbol(0));
+        assert_eq!(&**root.get_symbol(0).unwrap(), "é");
+        let root = load(b"\x04\x08o:\x08Foo\x06I:\x08@\x82\xa0\x06:\x0dencoding\"\x0eShift_JISi\x06");
+        #[cfg(any(feature = "encoding", feature = "encoding_rs"))]
+        assert_eq!(root.ivars_of(root.get_root()), vec![("@あ", &RubyValue::FixNum(1))]);
+        assert_eq!(root.get_symbol_bytes(1), Some(&b"@\x82\xa0"[..]));
+        let root = load(b"\x04\x08:\x07\x82\xa0");
+        assert_eq!(&**root.get_symbol(0).unwrap(), "\u{fffd}\u{fffd}");
+        assert!(root.get_encoded_symbol(0).unwrap().get_instance_variables().is_empty());
     }
 
     #[test]
@@ -608,10 +1224,273 @@ mod tests {
         assert_output_is!(b"\x04\x08U:\x09Testi\x06");
     }
 
+    #[test]
+    fn test_write_extended() {
+        assert_output_is!(b"\x04\x08[\x07e:\x0fEnumerablee:\x0fComparableo:\x0bObject\x00@\x06");
+        assert_output_is!(b"\x04\x08[\x07Ie:\x06Mu:\x08Foo\x06a\x06:\x06ETe;\x00o;\x07\x00");
+    }
+
+    #[test]
+    fn test_write_data() {
+        assert_output_is!(b"\x04\x08[\x07d:\x09Test[\x06i\x06@\x06");
+    }
+
+    #[test]
+    fn test_write_larger_than_buffer() {
+        let mut input = b"\x04\x08[\x02\x10\x27".to_vec(); // array of 10000 elements
+        for _ in 0..10000 {
+            input.extend_from_slice(b"i\x7f");
+        }
+
+        let mut reader = BufReader::new(&input[..]);
+        let mut loader = Loader::new(&mut reader);
+        let root = loader.load().unwrap();
+
+        let mut output = Vec::<u8>::new();
+        let mut dumper = Dumper::new(&mut output);
+        dumper.dump(&root, root.get_root()).unwrap();
+
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        /// Counts the calls to `write()`
+        #[derive(Default)]
+        struct CountingWriter {
+            output: Vec<u8>,
+            writes: usize,
+        }
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.writes += 1;
+                self.output.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // [1, "a", :b]
+        let input = b"\x04\x08[\x08i\x06\"\x06a:\x06b";
+        let root = Loader::new(&mut &input[..]).load().unwrap();
+        let dump = |capacity: Option<usize>| {
+            let mut writer = CountingWriter::default();
+            let dumper = Dumper::new(&mut writer);
+            let mut dumper = match capacity {
+                Some(capacity) => dumper.with_buffer_capacity(capacity),
+                None => dumper,
+            };
+            dumper.dump(&root, root.get_root()).unwrap();
+            assert_eq!(writer.output, input);
+            writer.writes
+        };
+        assert_eq!(dump(None), 1);
+        assert_eq!(dump(Some(4)), 3);
+        // a write for the version and every tag, length and payload
+        assert_eq!(dump(Some(0)), 11);
+    }
+
+    #[test]
+    fn test_serialized_size_estimate() {
+        let inputs: [&[u8]; 5] = [
+            b"\x04\x080",
+            b"\x04\x08i\x02\xe8\x80",
+            b"\x04\x08[\x07f\x092.55@\x06",
+            b"\x04\x08o:\x09Test\x06:\x07@ai\x06",
+            b"\x04\x08IC:\x09Test\"\x06a\x06:\x06ET",
+        ];
+        for input in inputs {
+            let mut reader = BufReader::new(input);
+            let root = Loader::new(&mut reader).load().unwrap();
+            assert_eq!(root.serialized_size_estimate(root.get_root()).unwrap(), input.len());
+        }
+    }
+
     #[test]
     fn test_write_concat() {
         assert_output_is_concat!(b"\x04\x08i\x06\x04\x08i\x07");
         assert_output_is_concat!(b"\x04\x08o:\x09Test\x00\x04\x08o:\x09Test\x00");
     }
-}
 
+    #[test]
+    fn test_symbol_order() {
+        let dump = |root: &Root, symbol_order: SymbolOrder| {
+            let mut output = Vec::<u8>::new();
+            Dumper::with_options(&mut output, DumperOptions { symbol_order, ..Default::default() }).dump(root, root.get_root()).unwrap();
+            output
+        };
+
+        let input = b"\x04\x08[\x07o:\x09Test\x07:\x07@bi\x06:\x07@ai\x07S;\x00\x07;\x06i\x06;\x07i\x07";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+        assert_eq!(dump(&root, SymbolOrder::AsStored), input);
+
+        let mut root = Root::nil();
+        root.add_symbol("@a");
+        let object = root.add_object("Test", [("@b", RubyValue::FixNum(1)), ("@a", RubyValue::FixNum(2))]);
+        root.set_root(object);
+        // symbols are defined where they occur first, not in the order of the symbol table
+        assert_eq!(dump(&root, SymbolOrder::AsStored), b"\x04\x08o:\x09Test\x07:\x07@bi\x06:\x07@ai\x07");
+    }
+
+    #[test]
+    fn test_recorded_member_order() {
+        let dump = |root: &Root, symbol_order: SymbolOrder| {
+            let mut output = Vec::<u8>::new();
+            Dumper::with_options(&mut output, DumperOptions { symbol_order, ..Default::default() }).dump(root, root.get_root()).unwrap();
+            output
+        };
+
+        // @b is removed and added again, moving it behind @a
+        let input = b"\x04\x08o:\x09Test\x07:\x07@bi\x06:\x07@ai\x07";
+        let mut reader = BufReader::new(&input[..]);
+        let mut root = Loader::with_options(&mut reader, LoaderOptions::default().with_record_member_order(true)).load().unwrap();
+        assert_eq!(root.get_member_order(0), Some(&[1, 2][..]));
+        let instance_variables = root.get_mut_object(0).unwrap().as_mut_object().get_mut_instance_variables();
+        let value = instance_variables.shift_remove(&1).unwrap();
+        instance_variables.insert(1, value);
+        assert_eq!(dump(&root, SymbolOrder::AsStored), b"\x04\x08o:\x09Test\x07:\x07@ai\x07:\x07@bi\x06");
+        assert_eq!(dump(&root, SymbolOrder::Recorded), input);
+
+        // names missing from the order follow in stored order
+        let input = b"\x04\x08S:\x09Test\x08:\x06ai\x06:\x06bi\x07:\x06ci\x08";
+        let mut reader = BufReader::new(&input[..]);
+        let mut root = Loader::new(&mut reader).load().unwrap();
+        assert_eq!(root.get_member_order(0), None);
+        assert_eq!(dump(&root, SymbolOrder::Recorded), input);
+        root.set_member_order(0, vec![3]);
+        assert_eq!(dump(&root, SymbolOrder::Recorded), b"\x04\x08S:\x09Test\x08:\x06ci\x08:\x06ai\x06:\x06bi\x07");
+    }
+
+    #[test]
+    fn test_hashes_with_nil_default() {
+        let input = b"\x04\x08[\x07{\x06i\x06i\x07}\x00i\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+
+        let mut output = Vec::<u8>::new();
+        let options = DumperOptions { hashes_with_nil_default: true, ..Default::default() };
+        Dumper::with_options(&mut output, options).dump(&root, root.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08[\x07}\x06i\x06i\x070}\x00i\x06");
+    }
+
+    #[test]
+    fn test_string_dedup() {
+        // ["a", "a", "b", "a" in binary, ["a"], "b"], separate objects all of them
+        let input = b"\x04\x08[\x0bI\"\x06a\x06:\x06ETI\"\x06a\x06;\x00TI\"\x06b\x06;\x00T\"\x06a[\x06I\"\x06a\x06;\x00TI\"\x06b\x06;\x00T";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+
+        let dump = |string_dedup| {
+            let mut output = Vec::<u8>::new();
+            Dumper::with_options(&mut output, DumperOptions { string_dedup, ..Default::default() }).dump(&root, root.get_root()).unwrap();
+            output
+        };
+        assert_eq!(dump(StringDedup::Off), input);
+        // the binary "a" differs in its encoding, the array is object 4 in the output
+        assert_eq!(dump(StringDedup::FrozenLiterals), b"\x04\x08[\x0bI\"\x06a\x06:\x06ET@\x06I\"\x06b\x06;\x00T\"\x06a[\x06@\x06@\x07");
+    }
+
+    #[test]
+    fn test_special_instance_variables() {
+        let input = b"\x04\x08o:\x09Test\x07:\x07@ai\x06:\x0b__id__i\x07";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+
+        let dump = |special_instance_variables| {
+            let mut output = Vec::<u8>::new();
+            let options = DumperOptions { special_instance_variables, ..Default::default() };
+            Dumper::with_options(&mut output, options).dump(&root, root.get_root()).unwrap();
+            output
+        };
+        assert_eq!(dump(SpecialInstanceVariables::Forward), input);
+        assert_eq!(dump(SpecialInstanceVariables::Drop), b"\x04\x08o:\x09Test\x06:\x07@ai\x06");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // [1, ["a"]], the encoding of "a" is one level deeper than the string
+        let input = b"\x04\x08[\x07i\x06[\x06I\"\x06a\x06:\x06ET";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+
+        let dump = |max_depth| {
+            let mut output = Vec::<u8>::new();
+            Dumper::with_options(&mut output, DumperOptions { max_depth: Some(max_depth), ..Default::default() }).dump(&root, root.get_root())
+        };
+        assert!(dump(4).is_ok());
+        match dump(3) {
+            Err(DumpError::DepthLimitExceeded { max_depth, path }) => {
+                assert_eq!(max_depth, 3);
+                assert_eq!(path, [RubyValue::Array(0), RubyValue::Array(1), RubyValue::String(2), RubyValue::Boolean(true)]);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_foreign_reference() {
+        // [1, ["a"]] with a reference to an object of a larger Root added to the inner array
+        let input = b"\x04\x08[\x07i\x06[\x06I\"\x06a\x06:\x06ET";
+        let mut reader = BufReader::new(&input[..]);
+        let mut root = Loader::new(&mut reader).load().unwrap();
+        root.get_mut_object(1).unwrap().as_mut_array().push(RubyValue::Hash(7));
+
+        let mut output = Vec::<u8>::new();
+        match Dumper::new(&mut output).dump(&root, root.get_root()) {
+            Err(error @ DumpError::ForeignReference { .. }) => {
+                assert_eq!(error.code(), ErrorCode::DanglingReference);
+                let DumpError::ForeignReference { id, path } = error else { unreachable!() };
+                assert_eq!(id, RubyValue::Hash(7));
+                assert_eq!(path, [RubyValue::Array(0), RubyValue::Array(1)]);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(output.is_empty());
+        assert!(matches!(Dumper::new(&mut output).dump_fragment(&root, &RubyValue::String(3)),
+            Err(DumpError::ForeignReference { id: RubyValue::String(3), path }) if path.is_empty()));
+
+        // instance variable names are symbols too
+        let input = b"\x04\x08o:\x09Test\x06:\x07@ai\x06";
+        let mut reader = BufReader::new(&input[..]);
+        let mut root = Loader::new(&mut reader).load().unwrap();
+        root.get_mut_object(0).unwrap().as_mut_object().get_mut_instance_variables().insert(4, RubyValue::Nil);
+        assert!(matches!(Dumper::new(&mut output).dump(&root, root.get_root()),
+            Err(DumpError::ForeignReference { id: RubyValue::Symbol(4), path }) if path == [RubyValue::Object(0)]));
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let input = b"\x04\x08[\x07i\x06f\x081.5";
+        let mut reader = BufReader::new(&input[..]);
+        let root = Loader::new(&mut reader).load().unwrap();
+        assert_eq!(Dumper::verify_roundtrip(&root, input).unwrap(), None);
+
+        // non-canonical fixnum (1 written as \x01\x01)
+        let mismatch = Dumper::verify_roundtrip(&root, b"\x04\x08[\x07i\x01\x01f\x081.5").unwrap().unwrap();
+        assert_eq!(mismatch.offset, 5);
+        assert_eq!(mismatch.path.as_deref(), Some("[0]"));
+        assert_eq!(mismatch.original_context, "\\x04\\x08[\\x07i[\\x01]\\x01f\\x081.5");
+        assert_eq!(mismatch.dumped_context, "\\x04\\x08[\\x07i[\\x06]f\\x081.5");
+
+        // trailing data
+        let mismatch = Dumper::verify_roundtrip(&root, b"\x04\x08[\x07i\x06f\x081.5\x00").unwrap().unwrap();
+        assert_eq!((mismatch.offset, mismatch.original_len, mismatch.dumped_len), (11, 12, 11));
+        assert_eq!(mismatch.dumped_context, "...\\x07i\\x06f\\x081.5[end]");
+        assert_eq!(mismatch.path, None);
+
+        // Game(@party=[Actor(@name="Ralph")]) with the name written without its encoding
+        let input = b"\x04\x08o:\x09Game\x06:\x0b@party[\x06o:\x0aActor\x06:\x0a@nameI\"\x0aRalph\x06:\x06ET";
+        let root = Loader::new(&mut &input[..]).load().unwrap();
+        let mismatch = Dumper::verify_roundtrip(&root, b"\x04\x08o:\x09Game\x06:\x0b@party[\x06o:\x0aActor\x06:\x0a@name\"\x0aRalph").unwrap().unwrap();
+        assert_eq!(mismatch.path.as_deref(), Some("@party[0].@name"));
+        assert!(mismatch.to_string().starts_with("Output differs at offset 36 in @party[0].@name "));
+
+        // {"a"=>1} with a different value
+        let root = Loader::new(&mut &b"\x04\x08{\x06I\"\x06a\x06:\x06ETi\x06"[..]).load().unwrap();
+        let mismatch = Dumper::verify_roundtrip(&root, b"\x04\x08{\x06I\"\x06a\x06:\x06ETi\x07").unwrap().unwrap();
+        assert_eq!(mismatch.path.as_deref(), Some("[\"a\"]"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Machine-readable classes of `LoadError` and `DumpError`, see `LoadError::code()` and `DumpError::code()`. Codes
+//! stay the same across releases, so programs can branch on them instead of on the error messages.
+
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// E001: the reader failed
+    ReadFailed,
+    /// E002: the input ended in the middle of a value
+    UnexpectedEnd,
+    /// E003: the version header isn't 4.8 or an older minor version
+    UnsupportedVersion,
+    /// E010: a byte that isn't a type tag where a value starts
+    UnknownTag,
+    /// E011: a fixnum with an invalid length byte
+    BadFixnum,
+    /// E012: a negative or too large length, count or index
+    BadLength,
+    /// E013: a class or module name that isn't valid UTF-8
+    BadUtf8,
+    /// E014: a bignum with an invalid sign byte or too many digits
+    BadBignum,
+    /// E015: a float that can't be parsed
+    BadFloat,
+    /// E020: a value of the wrong type, like a class name or an instance variable name that isn't a symbol
+    UnexpectedValue,
+    /// E021: a symbol link to a symbol that wasn't defined before
+    BadSymbolLink,
+    /// E022: an object link to an object that wasn't defined before
+    BadObjectLink,
+    /// E023: `e` in front of a value that can't be extended with modules
+    BadExtension,
+    /// E024: a hash key that occurs twice with `DuplicateKeys::Error`
+    DuplicateHashKey,
+    /// E025: data that should be a nested Marshal stream isn't one
+    NotMarshalData,
+    /// E026: bytes left after the value
+    TrailingBytes,
+    /// E030: see `LoadError::DepthLimitExceeded`
+    DepthLimitExceeded,
+    /// E031: see `LoadError::AllocationLimitExceeded`
+    AllocationLimitExceeded,
+    /// E032: see `LoadError::SymbolLimitExceeded`
+    SymbolLimitExceeded,
+    /// E033: see `LoadError::ClassNotPermitted`
+    ClassNotPermitted,
+    /// E034: see `LoadError::ObjectLimitExceeded`
+    ObjectLimitExceeded,
+    /// E035: see `LoadError::Rejected`
+    Rejected,
+    /// E101: the writer failed
+    WriteFailed,
+    /// E102: a reference to a symbol or object that isn't in the Root
+    DanglingReference,
+    /// E103: an object of a type that can't be written where it is referenced
+    UnexpectedType,
+    /// E104: a length or number that doesn't fit into the format
+    OutOfRange,
+    /// E105: a UserDefined object that only holds a part of its data
+    TruncatedPayload,
+    /// E110: see `DumpError::DepthLimitExceeded`
+    DumpDepthLimitExceeded,
+}
+
+impl ErrorCode {
+    /// The code as written in the variant's documentation, like `E010`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::ReadFailed => "E001",
+            ErrorCode::UnexpectedEnd => "E002",
+            ErrorCode::UnsupportedVersion => "E003",
+            ErrorCode::UnknownTag => "E010",
+            ErrorCode::BadFixnum => "E011",
+            ErrorCode::BadLength => "E012",
+            ErrorCode::BadUtf8 => "E013",
+            ErrorCode::BadBignum => "E014",
+            ErrorCode::BadFloat => "E015",
+            ErrorCode::UnexpectedValue => "E020",
+            ErrorCode::BadSymbolLink => "E021",
+            ErrorCode::BadObjectLink => "E022",
+            ErrorCode::BadExtension => "E023",
+            ErrorCode::DuplicateHashKey => "E024",
+            ErrorCode::NotMarshalData => "E025",
+            ErrorCode::TrailingBytes => "E026",
+            ErrorCode::DepthLimitExceeded => "E030",
+            ErrorCode::AllocationLimitExceeded => "E031",
+            ErrorCode::SymbolLimitExceeded => "E032",
+            ErrorCode::ClassNotPermitted => "E033",
+            ErrorCode::ObjectLimitExceeded => "E034",
+            ErrorCode::Rejected => "E035",
+            ErrorCode::WriteFailed => "E101",
+            ErrorCode::DanglingReference => "E102",
+            ErrorCode::UnexpectedType => "E103",
+            ErrorCode::OutOfRange => "E104",
+            ErrorCode::TruncatedPayload => "E105",
+            ErrorCode::DumpDepthLimitExceeded => "E110",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
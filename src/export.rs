@@ -0,0 +1,154 @@
+//! Export of values as JSON without pulling in serde, values go through `DynValue` (see `dynamic::to_dyn()`).
+//! `to_jsonl()` writes the elements of a large array one line at a time, only a single element is converted at once.
+
+use std::{fmt::Display, io::Write};
+
+use crate::{convert::{self, ConvertError}, dynamic::{to_dyn, DynOptions, DynValue}, values::*};
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ExportError {
+    IoError(String),
+    /// the exported value isn't an array
+    ConvertError(ConvertError),
+    /// converting the element at `index` failed, the lines of the elements before it have been written
+    Element { index: usize, error: ConvertError },
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::IoError(error) => f.write_str(&format!("IO Error: {}", error)),
+            ExportError::ConvertError(error) => f.write_str(&format!("Convert Error: {}", error)),
+            ExportError::Element { index, error } => f.write_str(&format!("Convert Error at element {}: {}", index, error)),
+        }
+    }
+}
+
+/// Writes every element of the array `value` as one line of JSON (JSON Lines), returns the number of lines written.
+/// Arrays of a subclass and arrays with instance variables are exported like plain arrays.
+pub fn to_jsonl<W: Write>(root: &Root, value: &RubyValue, writer: &mut W) -> Result<usize, ExportError> {
+    to_jsonl_with_options(root, value, writer, &DynOptions::default())
+}
+
+pub fn to_jsonl_with_options<W: Write>(root: &Root, value: &RubyValue, writer: &mut W, options: &DynOptions) -> Result<usize, ExportError> {
+    let mut value = value.without_ivars();
+    if let Some(user_class) = value.object_id().and_then(|object_id| root.get_object(object_id)).and_then(RubyObject::try_as_user_class) {
+        value = user_class.get_wrapped_object().without_ivars();
+    }
+    let elements = convert::get_array(root, value).map_err(ExportError::ConvertError)?;
+
+    let mut line = String::new();
+    for (index, element) in elements.iter().enumerate() {
+        let element = to_dyn(root, element, options).map_err(|error| ExportError::Element { index, error })?;
+        line.clear();
+        write_json(&element, &mut line);
+        line.push('\n');
+        writer.write_all(line.as_bytes()).map_err(|err| ExportError::IoError(format!("Could not write line {}: {}", index, err)))?;
+    }
+    writer.flush().map_err(|err| ExportError::IoError(format!("Could not flush data: {}", err)))?;
+    Ok(elements.len())
+}
+
+/// Appends `value` as compact JSON to `json`. Symbols, classes and modules become strings, strings without a known
+/// encoding are decoded as lossy UTF-8 and floats that JSON can't represent (NaN and infinities) become `null`.
+/// Objects, regexps and data objects are written like the `json/add` extensions of Ruby do, as JSON objects with the
+/// class name under `json_class`.
+pub fn write_json(value: &DynValue, json: &mut String) {
+    match value {
+        DynValue::Nil => json.push_str("null"),
+        DynValue::Boolean(boolean) => json.push_str(if *boolean { "true" } else { "false" }),
+        DynValue::Integer(integer) => json.push_str(&integer.to_string()),
+        DynValue::Float(float) if float.is_finite() => json.push_str(&format!("{:?}", float)),
+        DynValue::Float(_) => json.push_str("null"),
+        DynValue::String(string) | DynValue::Symbol(string) | DynValue::Class(string) | DynValue::Module(string) => {
+            json.push_str(&json_string(string))
+        }
+        DynValue::Bytes(bytes) => json.push_str(&json_string(&String::from_utf8_lossy(bytes))),
+        DynValue::Array(elements) => {
+            json.push('[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_json(element, json);
+            }
+            json.push(']');
+        }
+        DynValue::Hash(hash) => write_json_object(None, hash.iter().map(|(key, value)| (key.as_str(), value)), json),
+        DynValue::Object { class_name, fields } => {
+            write_json_object(Some(class_name), fields.iter().map(|(name, value)| (name.as_str(), value)), json)
+        }
+        DynValue::RegExp { pattern, options } => {
+            json.push_str(&format!("{{\"json_class\":\"Regexp\",\"s\":{},\"o\":{}}}", json_string(pattern), options))
+        }
+        DynValue::Data { class_name, data } => write_json_object(Some(class_name), [("data", data.as_ref())].into_iter(), json),
+    }
+}
+
+fn write_json_object<'v>(class_name: Option<&str>, members: impl Iterator<Item = (&'v str, &'v DynValue)>, json: &mut String) {
+    json.push('{');
+    let mut first = true;
+    if let Some(class_name) = class_name {
+        json.push_str("\"json_class\":");
+        json.push_str(&json_string(class_name));
+        first = false;
+    }
+    for (name, value) in members {
+        if !first {
+            json.push(',');
+        }
+        first = false;
+        json.push_str(&json_string(name));
+        json.push(':');
+        write_json(value, json);
+    }
+    json.push('}');
+}
+
+/// `string` as a quoted JSON string
+pub(crate) fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    #[test]
+    fn test_to_jsonl() {
+        // [{:a=>1, "b"=>[nil, 1.5]}, #<Point @x=1>, "x\n\"y\"", :sym, /ab/i]
+        let root = load(b"\x04\x08[\x0a{\x07:\x06ai\x06I\"\x06b\x06:\x06ET[\x070f\x081.5o:\x0aPoint\x06:\x07@xi\x06I\"\x0ax\n\"y\"\x06;\x06T:\x08symI/\x07ab\x01\x06;\x06F");
+        let mut output = Vec::new();
+        assert_eq!(to_jsonl(&root, root.get_root(), &mut output).unwrap(), 5);
+        assert_eq!(String::from_utf8(output).unwrap(), concat!(
+            "{\":a\":1,\"b\":[null,1.5]}\n",
+            "{\"json_class\":\"Point\",\"@x\":1}\n",
+            "\"x\\n\\\"y\\\"\"\n",
+            "\"sym\"\n",
+            "{\"json_class\":\"Regexp\",\"s\":\"ab\",\"o\":1}\n",
+        ));
+
+        // the lines before a failing element are written
+        let root = load(b"\x04\x08[\x07i\x06[\x06@\x06");
+        let mut output = Vec::new();
+        assert!(matches!(to_jsonl(&root, root.get_root(), &mut output), Err(ExportError::Element { index: 1, error: ConvertError::Cycle(1) })));
+        assert_eq!(output, b"1\n");
+
+        assert!(matches!(to_jsonl(&root, &RubyValue::Nil, &mut output), Err(ExportError::ConvertError(ConvertError::TypeMismatch { .. }))));
+    }
+}
@@ -0,0 +1,201 @@
+//! Codec for Marshal's variable length integers ("fixnums"), used for `i` values as well as every length and link index.
+//!
+//! The first byte decides the layout:
+//! - `0x00` is `0`
+//! - `0x01..=0x04` is the number of little endian bytes that follow, the value is positive (zero extended)
+//! - `0xfc..=0xff` (`-4..=-1`) is the negated number of bytes that follow, the value is negative (one extended)
+//! - `0x05..=0x7f` is a small positive value, `byte - 5` (`0..=122`, only `1..=122` are written)
+//! - `0x80..=0xfb` (`-128..=-5`) is a small negative value, `byte + 5` (`-123..=-1`)
+//!
+//! Decoding accepts non-canonical encodings (e.g. `1` written as `\x01\x01`) like Ruby does, while encoding always
+//! produces the shortest form.
+
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+use std::fmt::Display;
+
+use crate::spec::{SMALL_FIXNUM_MAX, SMALL_FIXNUM_MIN};
+
+/// Longest possible encoding of an `i32`: length byte and 4 value bytes
+pub const MAX_ENCODED_LEN: usize = 5;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FixnumError {
+    /// The input ended before all bytes announced by the first byte were available
+    UnexpectedEnd { expected: usize, available: usize },
+    /// The encoded value doesn't fit into an `i32` (a positive 4 byte value with the highest bit set)
+    Overflow,
+}
+
+impl Display for FixnumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixnumError::UnexpectedEnd { expected, available } => {
+                f.write_str(&format!("fixnum needs {} bytes, only {} available", expected, available))
+            }
+            FixnumError::Overflow => f.write_str("fixnum doesn't fit into an i32"),
+        }
+    }
+}
+
+/// Returns the total number of bytes (including the first one) of a fixnum starting with `first_byte`
+pub const fn encoded_len(first_byte: u8) -> usize {
+    match first_byte as i8 {
+        1..=4 => first_byte as usize + 1,
+        -4..=-1 => (first_byte as i8).unsigned_abs() as usize + 1,
+        _ => 1,
+    }
+}
+
+/// Decodes a fixnum from the start of `bytes`, returns the value and the number of bytes consumed
+pub fn decode(bytes: &[u8]) -> Result<(i32, usize), FixnumError> {
+    let Some(&first_byte) = bytes.first() else {
+        return Err(FixnumError::UnexpectedEnd { expected: 1, available: 0 });
+    };
+
+    let len = encoded_len(first_byte);
+    if bytes.len() < len {
+        return Err(FixnumError::UnexpectedEnd { expected: len, available: bytes.len() });
+    }
+
+    let value = match first_byte as i8 {
+        0 => 0,
+        1..=4 => {
+            let mut value: u32 = 0;
+            for (i, byte) in bytes[1..len].iter().enumerate() {
+                value |= u32::from(*byte) << (i * 8);
+            }
+            i32::try_from(value).map_err(|_| FixnumError::Overflow)?
+        }
+        -4..=-1 => {
+            let mut value: i32 = -1;
+            for (i, byte) in bytes[1..len].iter().enumerate() {
+                value &= !(0xFF_i32 << (i * 8));
+                value |= i32::from(*byte) << (i * 8);
+            }
+            value
+        }
+        small if small > 0 => i32::from(small) - 5,
+        small => i32::from(small) + 5,
+    };
+
+    Ok((value, len))
+}
+
+/// Encoded form of a fixnum, see `encode()`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EncodedFixnum {
+    bytes: [u8; MAX_ENCODED_LEN],
+    len: usize,
+}
+
+impl EncodedFixnum {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Encodes `number` using the shortest possible form
+pub fn encode(mut number: i32) -> EncodedFixnum {
+    let mut bytes = [0; MAX_ENCODED_LEN];
+    let mut len = 1;
+
+    match number {
+        0 => bytes[0] = 0x00,
+        1..=SMALL_FIXNUM_MAX => bytes[0] = (number as i8 + 5) as u8,
+        SMALL_FIXNUM_MIN..=-1 => bytes[0] = (number as i8 - 5) as u8,
+        _ => {
+            for i in 1..MAX_ENCODED_LEN {
+                bytes[i] = (number & 0xFF) as u8;
+                len += 1;
+
+                number >>= 8;
+                if number == 0 {
+                    bytes[0] = i as u8;
+                    break;
+                }
+                if number == -1 {
+                    bytes[0] = (-(i as i8)) as u8;
+                    break;
+                }
+            }
+        }
+    }
+
+    EncodedFixnum { bytes, len }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_values() {
+        for first_byte in 5..=0x7f_u8 {
+            assert_eq!(decode(&[first_byte]), Ok((i32::from(first_byte) - 5, 1)));
+        }
+        for first_byte in 0x80..=0xfb_u8 {
+            assert_eq!(decode(&[first_byte]), Ok((i32::from(first_byte as i8) + 5, 1)));
+        }
+        assert_eq!(decode(&[0]), Ok((0, 1)));
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let cases: [(i32, &[u8]); 18] = [
+            (0, b"\x00"),
+            (1, b"\x06"),
+            (122, b"\x7f"),
+            (123, b"\x01\x7b"),
+            (255, b"\x01\xff"),
+            (256, b"\x02\x00\x01"),
+            (-1, b"\xfa"),
+            (-123, b"\x80"),
+            (-124, b"\xff\x84"),
+            (-256, b"\xff\x00"),
+            (-257, b"\xfe\xff\xfe"),
+            (65535, b"\x02\xff\xff"),
+            (65536, b"\x03\x00\x00\x01"),
+            (16777215, b"\x03\xff\xff\xff"),
+            (16777216, b"\x04\x00\x00\x00\x01"),
+            (-16777216, b"\xfd\x00\x00\x00"),
+            (i32::MAX, b"\x04\xff\xff\xff\x7f"),
+            (i32::MIN, b"\xfc\x00\x00\x00\x80"),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(encode(value).as_bytes(), bytes, "encoding {}", value);
+            assert_eq!(decode(bytes), Ok((value, bytes.len())), "decoding {}", value);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for value in (-70000..70000).chain([i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX]) {
+            let encoded = encode(value);
+            assert_eq!(decode(encoded.as_bytes()), Ok((value, encoded.as_bytes().len())));
+        }
+        for shift in 0..31 {
+            for value in [1_i32 << shift, (1_i32 << shift) - 1, -(1_i32 << shift), -(1_i32 << shift) - 1] {
+                let encoded = encode(value);
+                assert_eq!(decode(encoded.as_bytes()), Ok((value, encoded.as_bytes().len())));
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_canonical() {
+        assert_eq!(decode(b"\x01\x01"), Ok((1, 2)));
+        assert_eq!(decode(b"\x04\x05\x00\x00\x00"), Ok((5, 5)));
+        assert_eq!(decode(b"\xff\xff"), Ok((-1, 2)));
+        assert_eq!(decode(b"\xfc\xff\xff\xff\xff"), Ok((-1, 5)));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(decode(b""), Err(FixnumError::UnexpectedEnd { expected: 1, available: 0 }));
+        assert_eq!(decode(b"\x02\x01"), Err(FixnumError::UnexpectedEnd { expected: 3, available: 2 }));
+        assert_eq!(decode(b"\xfd\x01"), Err(FixnumError::UnexpectedEnd { expected: 4, available: 2 }));
+        assert_eq!(decode(b"\x04\xff\xff\xff\xff"), Err(FixnumError::Overflow));
+        assert_eq!(decode(b"\x04\x00\x00\x00\x80"), Err(FixnumError::Overflow));
+    }
+}
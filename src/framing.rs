@@ -0,0 +1,210 @@
+//! Length-prefixed framing for storing several Marshal documents in one file or sending them over a stream, every
+//! record is its length followed by a complete document (including the version header)
+
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "decode")]
+use crate::{decode::load::{ErrorLocation, LoadError, Loader}, error_code::ErrorCode};
+#[cfg(feature = "encode")]
+use crate::encode::dump::{DumpError, Dumper};
+#[cfg(any(feature = "decode", feature = "encode"))]
+use crate::values::*;
+
+/// Encoding of the length in front of every record
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum LengthPrefix {
+    /// 4 bytes, little endian
+    #[default]
+    U32Le,
+    /// 4 bytes, big endian (network byte order)
+    U32Be,
+    /// unsigned LEB128, 7 bits per byte with the high bit marking that more bytes follow
+    Varint,
+}
+
+/// Longest varint that can hold a `u64`
+const MAX_VARINT_LEN: usize = 10;
+
+impl LengthPrefix {
+    fn write(self, writer: &mut impl Write, len: usize) -> io::Result<()> {
+        match self {
+            LengthPrefix::U32Le | LengthPrefix::U32Be => {
+                let len = u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record is longer than u32::MAX bytes"))?;
+                match self {
+                    LengthPrefix::U32Le => writer.write_all(&len.to_le_bytes()),
+                    _ => writer.write_all(&len.to_be_bytes()),
+                }
+            }
+            LengthPrefix::Varint => {
+                let mut len = len as u64;
+                let mut bytes = Vec::with_capacity(MAX_VARINT_LEN);
+                loop {
+                    let byte = (len & 0x7f) as u8;
+                    len >>= 7;
+                    if len == 0 {
+                        bytes.push(byte);
+                        break;
+                    }
+                    bytes.push(byte | 0x80);
+                }
+                writer.write_all(&bytes)
+            }
+        }
+    }
+
+    /// Reads a length, `None` if the reader is at its end before the first byte
+    fn read(self, reader: &mut impl Read) -> io::Result<Option<usize>> {
+        let mut first = [0; 1];
+        if reader.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let len = match self {
+            LengthPrefix::U32Le | LengthPrefix::U32Be => {
+                let mut bytes = [first[0], 0, 0, 0];
+                reader.read_exact(&mut bytes[1..])?;
+                match self {
+                    LengthPrefix::U32Le => u64::from(u32::from_le_bytes(bytes)),
+                    _ => u64::from(u32::from_be_bytes(bytes)),
+                }
+            }
+            LengthPrefix::Varint => {
+                let mut len = 0_u64;
+                let mut byte = first[0];
+                for shift in (0..).step_by(7).take(MAX_VARINT_LEN) {
+                    len |= u64::from(byte & 0x7f) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                    if shift == 7 * (MAX_VARINT_LEN - 1) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "varint length prefix is too long"));
+                    }
+                    let mut next = [0; 1];
+                    reader.read_exact(&mut next)?;
+                    byte = next[0];
+                }
+                len
+            }
+        };
+        usize::try_from(len).map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "record length doesn't fit into usize"))
+    }
+}
+
+/// Writes records to `writer`, each one prefixed with its length
+pub struct FramedWriter<W: Write> {
+    writer: W,
+    prefix: LengthPrefix,
+}
+
+impl<W: Write> FramedWriter<W> {
+    pub fn new(writer: W, prefix: LengthPrefix) -> Self {
+        Self { writer, prefix }
+    }
+
+    /// Writes `record` (usually a complete Marshal document) with its length prefix
+    pub fn write_frame(&mut self, record: &[u8]) -> io::Result<()> {
+        self.prefix.write(&mut self.writer, record.len())?;
+        self.writer.write_all(record)
+    }
+
+    /// Dumps `value` and writes it as one record, the record is buffered since its length has to be written first
+    #[cfg(feature = "encode")]
+    pub fn write_record(&mut self, root: &Root, value: &RubyValue) -> Result<(), DumpError> {
+        let mut record = Vec::new();
+        Dumper::new(&mut record).dump(root, value)?;
+        self.write_frame(&record).map_err(|error| DumpError::IoError(error.to_string()))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads length-prefixed records from `reader`
+pub struct FramedReader<R: Read> {
+    reader: R,
+    prefix: LengthPrefix,
+    max_len: usize,
+}
+
+impl<R: Read> FramedReader<R> {
+    pub fn new(reader: R, prefix: LengthPrefix) -> Self {
+        Self { reader, prefix, max_len: usize::MAX }
+    }
+
+    /// Records longer than `max_len` bytes are rejected before anything is allocated for them, use it when the input
+    /// isn't trusted
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Reads the next record, `None` at the end of the input, an input ending inside a record is an error
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let Some(len) = self.prefix.read(&mut self.reader)? else {
+            return Ok(None);
+        };
+        if len > self.max_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("record of {} bytes exceeds the limit of {} bytes", len, self.max_len)));
+        }
+        let mut record = vec![0; len];
+        self.reader.read_exact(&mut record)?;
+        Ok(Some(record))
+    }
+
+    /// Reads and loads the next record, `None` at the end of the input
+    #[cfg(feature = "decode")]
+    pub fn read_record(&mut self) -> Result<Option<Root>, LoadError> {
+        let record = self.read_frame().map_err(|error| LoadError::IoError(ErrorCode::ReadFailed, format!("Failed to read record: {}", error), ErrorLocation::UNKNOWN))?;
+        let Some(record) = record else {
+            return Ok(None);
+        };
+        let mut data = &record[..];
+        let root = Loader::new(&mut data).load()?;
+        if !data.is_empty() {
+            return Err(LoadError::ParserError(
+                ErrorCode::TrailingBytes,
+                format!("Record has {} trailing bytes", data.len()),
+                ErrorLocation { offset: Some((record.len() - data.len()) as u64), object_offset: None },
+            ));
+        }
+        Ok(Some(root))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        for prefix in [LengthPrefix::U32Le, LengthPrefix::U32Be, LengthPrefix::Varint] {
+            let mut writer = FramedWriter::new(Vec::new(), prefix);
+            let first = Root::of_value(vec![1, 2]).unwrap();
+            let second = Root::of_value("x".repeat(200)).unwrap();
+            writer.write_record(&first, first.get_root()).unwrap();
+            writer.write_record(&second, second.get_root()).unwrap();
+            let output = writer.into_inner();
+
+            let mut reader = FramedReader::new(&output[..], prefix);
+            assert_eq!(reader.read_record().unwrap(), Some(first));
+            assert_eq!(reader.read_record().unwrap(), Some(second));
+            assert_eq!(reader.read_record().unwrap(), None);
+        }
+
+        let mut writer = FramedWriter::new(Vec::new(), LengthPrefix::Varint);
+        writer.write_frame(&[0; 300]).unwrap();
+        assert_eq!(&writer.into_inner()[..2], [0xac, 0x02]);
+
+        // truncated record and limit
+        assert!(FramedReader::new(&b"\x05\x00\x00\x00\x04\x08"[..], LengthPrefix::U32Le).read_frame().is_err());
+        assert!(FramedReader::new(&b"\x05\x00\x00\x00\x04\x08i\x06\x00"[..], LengthPrefix::U32Le).with_max_len(4).read_frame().is_err());
+    }
+}
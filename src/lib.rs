@@ -1,3 +1,66 @@
+//! Reading and writing Ruby's Marshal format.
+//!
+//! Loading untrusted input doesn't panic, malformed input is reported as a `LoadError` and nesting is limited by
+//! `LoaderOptions::max_depth`. Dumping and printing return errors for inconsistent value graphs (e.g. dangling
+//! references) instead of panicking, but they recurse once per level of nesting, which is only bounded by
+//! `DumperOptions::max_depth` (unlimited by default) and the `max_depth` of the print functions. The modules
+//! implementing loading, dumping and printing deny `unwrap()`, `expect()` and `panic!` outside of tests, slice
+//! indexing isn't linted. The `as_*` accessors of `RubyValue` and `RubyObject` panic on purpose if the value has
+//! another type.
+//!
+//! The `decode` and `encode` features (both enabled by default) provide `Loader` and `Dumper`, consumers that only
+//! read or only write can disable the other one. The values model, conversions and views are always available. The
+//! optional `compress` feature adds `Loader::new_auto()`, which reads gzip and zlib compressed input, and `memmap2` adds
+//! `Loader::from_path_mmap()` for loading large files without reading them into memory first. `tokio` adds
+//! `AsyncLoader`, which reads from a `tokio::io::AsyncRead`, and `footprint` adds `Root::memory_footprint()` for
+//! keeping caches of documents within a memory budget. Strings in encodings other than UTF-8 and US-ASCII are decoded
+//! with the `encoding` crate (the `encoding` feature, enabled by default) or `encoding_rs` (the `encoding_rs`
+//! feature), see `text::TextDecoder`. The `derive` feature adds derive macros for `FromRubyObject`, `IntoRubyObject`
+//! and `RubyEnum`, see `typed`.
+
 pub mod values;
+pub mod analysis;
+pub mod archive;
+pub mod builder;
+pub mod class_name;
+pub mod compare;
+pub mod convert;
+#[cfg(feature = "decode")]
 pub mod decode;
+pub mod diagnostics;
+pub mod document_set;
+pub mod dynamic;
+pub mod error_code;
+pub mod export;
+#[cfg(feature = "encode")]
 pub mod encode;
+pub mod fixnum;
+pub mod framing;
+pub mod migrate;
+pub mod options;
+pub mod prelude;
+pub mod registry;
+pub mod spec;
+pub mod symbol_table;
+#[cfg(all(test, feature = "decode"))]
+mod test_util;
+pub mod text;
+pub mod typed;
+pub mod vectors;
+pub mod views;
+
+pub use class_name::ClassName;
+pub use compare::cross_eq;
+pub use error_code::ErrorCode;
+#[cfg(feature = "decode")]
+pub use decode::{LoadError, Loader, LoaderOptions};
+#[cfg(feature = "encode")]
+pub use encode::{DumpError, Dumper, DumperOptions, SymbolOrder};
+pub use options::{Options, OptionsBuilder, Preservation};
+#[cfg(feature = "decode")]
+pub use options::load;
+#[cfg(feature = "encode")]
+pub use options::dump;
+pub use values::{ObjectID, ObjectKind, Root, RubyError, RubyObject, RubyValue, SymbolID, SymbolStr, ValueKind};
+#[cfg(feature = "derive")]
+pub use marshr_derive::{FromRubyObject, IntoRubyObject, RubyEnum};
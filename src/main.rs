@@ -0,0 +1,27 @@
+//! Command line tools around the library
+//!
+//! - `marshr gen-vectors <directory>` writes the test vectors of `marshr::vectors` to `<directory>`
+
+use std::{path::Path, process::ExitCode};
+
+const USAGE: &str = "Usage: marshr gen-vectors <directory>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["gen-vectors", directory] => match marshr::vectors::write_test_vectors(Path::new(directory)) {
+            Ok(count) => {
+                println!("Wrote {} test vectors to {}", count, directory);
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("Writing test vectors to {} failed: {}", directory, error);
+                ExitCode::FAILURE
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Migrations of objects written by older versions of the Ruby code, registered per class and run over a loaded
+//! document so the rest of the application only has to understand the current layout
+//!
+//! ```
+//! use marshr::{migrate::{Migration, Migrations}, prelude::*};
+//!
+//! # #[cfg(feature = "decode")] {
+//! // Actor(@hp=10), written before @hp was renamed and @mp was added
+//! let mut input: &[u8] = b"\x04\x08o:\x0aActor\x06:\x08@hpi\x0f";
+//! let mut root = Loader::new(&mut input).load().unwrap();
+//!
+//! let mut migrations = Migrations::new();
+//! migrations.register(
+//!     Migration::for_class("Actor").when_version_below("@version", 3).rename("@hp", "@health").add("@mp", 0).set("@version", 3),
+//! );
+//! assert_eq!(migrations.run(&mut root), Ok(1));
+//! # }
+//! ```
+
+use crate::{convert::{ConvertError, FromRubyValue, IntoRubyValue}, values::*};
+
+type ValueFn = Box<dyn Fn(&mut Root) -> Result<RubyValue, ConvertError>>;
+type CustomFn = Box<dyn Fn(&mut Root, ObjectID) -> Result<(), ConvertError>>;
+
+enum Step {
+    Rename(String, String),
+    Remove(String),
+    /// the instance variable is only added if it is missing
+    Add(String, ValueFn),
+    Set(String, ValueFn),
+    Custom(CustomFn),
+}
+
+/// Changes applied to the instance variables of every object of one class, the steps run in the order they were added
+pub struct Migration {
+    class_name: String,
+    /// instance variable holding the version and the version the object has to be below
+    version_below: Option<(String, i64)>,
+    steps: Vec<Step>,
+}
+
+impl Migration {
+    pub fn for_class(class_name: &str) -> Self {
+        Self { class_name: class_name.to_string(), version_below: None, steps: Vec::new() }
+    }
+
+    /// Only migrates objects whose instance variable `name` is an integer below `version`, a missing instance
+    /// variable (or `nil`) counts as version 0 since the oldest saves usually predate the version field
+    pub fn when_version_below(mut self, name: &str, version: i64) -> Self {
+        self.version_below = Some((name.to_string(), version));
+        self
+    }
+
+    /// Renames the instance variable `from` to `to` keeping its position, objects without `from` are left alone
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.steps.push(Step::Rename(from.to_string(), to.to_string()));
+        self
+    }
+
+    pub fn remove(mut self, name: &str) -> Self {
+        self.steps.push(Step::Remove(name.to_string()));
+        self
+    }
+
+    /// Adds the instance variable `name` unless the object already has it
+    pub fn add<T: IntoRubyValue + Clone + 'static>(mut self, name: &str, value: T) -> Self {
+        self.steps.push(Step::Add(name.to_string(), Box::new(move |root| value.clone().into_ruby_value(root))));
+        self
+    }
+
+    /// Sets the instance variable `name`, replacing the current value
+    pub fn set<T: IntoRubyValue + Clone + 'static>(mut self, name: &str, value: T) -> Self {
+        self.steps.push(Step::Set(name.to_string(), Box::new(move |root| value.clone().into_ruby_value(root))));
+        self
+    }
+
+    /// Runs `migrate` with the id of the object, for changes the other steps can't express
+    pub fn custom(mut self, migrate: impl Fn(&mut Root, ObjectID) -> Result<(), ConvertError> + 'static) -> Self {
+        self.steps.push(Step::Custom(Box::new(migrate)));
+        self
+    }
+
+    fn applies_to(&self, root: &Root, object: &Object) -> Result<bool, ConvertError> {
+        let Some((name, version)) = &self.version_below else {
+            return Ok(true);
+        };
+        let value = root.get_symbol_id(name).and_then(|symbol_id| object.get_instance_variable(symbol_id)).unwrap_or(&RubyValue::Nil);
+        let current = Option::<i64>::from_ruby_value(root, value)?.unwrap_or(0);
+        Ok(current < *version)
+    }
+
+    fn apply(&self, root: &mut Root, object_id: ObjectID) -> Result<(), ConvertError> {
+        for step in &self.steps {
+            match step {
+                Step::Rename(from, to) => {
+                    let Some(from) = root.get_symbol_id(from) else { continue };
+                    let to = root.add_symbol(to).as_symbol();
+                    let instance_variables = instance_variables(root, object_id)?;
+                    if let Some((index, _, value)) = instance_variables.shift_remove_full(&from) {
+                        instance_variables.shift_insert(index, to, value);
+                    }
+                }
+                Step::Remove(name) => {
+                    let Some(name) = root.get_symbol_id(name) else { continue };
+                    instance_variables(root, object_id)?.shift_remove(&name);
+                }
+                Step::Add(name, value) => {
+                    let name = root.add_symbol(name).as_symbol();
+                    if !instance_variables(root, object_id)?.contains_key(&name) {
+                        let value = value(root)?;
+                        instance_variables(root, object_id)?.insert(name, value);
+                    }
+                }
+                Step::Set(name, value) => {
+                    let name = root.add_symbol(name).as_symbol();
+                    let value = value(root)?;
+                    instance_variables(root, object_id)?.insert(name, value);
+                }
+                Step::Custom(migrate) => migrate(root, object_id)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn instance_variables(root: &mut Root, object_id: ObjectID) -> Result<&mut ValuePairsSymbolKeys, ConvertError> {
+    match root.get_mut_object(object_id) {
+        Some(RubyObject::Object(object)) => Ok(object.get_mut_instance_variables()),
+        _ => Err(ConvertError::InvalidReference(ValueKind::Object, object_id)),
+    }
+}
+
+#[derive(Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a migration, migrations of the same class run in the order they were registered so each one can bring
+    /// objects up to the version the next one expects
+    pub fn register(&mut self, migration: Migration) -> &mut Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// Migrates every object (`o`) in `root` and returns the number of migrations that were applied
+    pub fn run(&self, root: &mut Root) -> Result<usize, ConvertError> {
+        let mut applied = 0;
+        for object_id in 0..root.get_objects().len() {
+            for migration in &self.migrations {
+                let Some(RubyObject::Object(object)) = root.get_object(object_id) else { break };
+                if root.get_symbol(object.get_class_name()).map(|name| &**name) != Some(migration.class_name.as_str()) || !migration.applies_to(root, object)? {
+                    continue;
+                }
+                migration.apply(root, object_id)?;
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    fn instance_variable_names(root: &Root, object_id: ObjectID) -> Vec<String> {
+        root.ivars_of(&RubyValue::Object(object_id)).into_iter().map(|(name, _)| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_migrations() {
+        // [Actor(@hp=10, @name=nil), Actor(@health=5, @mp=1, @version=3), Enemy(@hp=1)]
+        let mut root = load(b"\x04\x08[\x08o:\x0aActor\x07:\x08@hpi\x0f:\x0a@name0o;\x00\x08:\x0c@healthi\x0a:\x08@mpi\x06:\x0d@versioni\x08o:\x0aEnemy\x06;\x06i\x06");
+
+        let mut migrations = Migrations::new();
+        migrations
+            .register(Migration::for_class("Actor").when_version_below("@version", 2).rename("@hp", "@health").set("@version", 2))
+            .register(Migration::for_class("Actor").when_version_below("@version", 3).add("@mp", 0).remove("@name").set("@version", 3));
+        assert_eq!(migrations.run(&mut root), Ok(2));
+
+        assert_eq!(instance_variable_names(&root, 1), ["@health", "@version", "@mp"]);
+        let actor = root.get_object(1).unwrap().as_object();
+        assert_eq!(actor.get_instance_variable(root.get_symbol_id("@health").unwrap()), Some(&RubyValue::FixNum(10)));
+        assert_eq!(actor.get_instance_variable(root.get_symbol_id("@version").unwrap()), Some(&RubyValue::FixNum(3)));
+        // up to date and other classes are left alone
+        assert_eq!(instance_variable_names(&root, 2), ["@health", "@mp", "@version"]);
+        assert_eq!(instance_variable_names(&root, 3), ["@hp"]);
+
+        assert_eq!(migrations.run(&mut root), Ok(0));
+    }
+}
@@ -0,0 +1,236 @@
+//! One set of options for both directions, built with `Options::builder()` and taken by the `load()` and `dump()`
+//! helpers. Knobs that exist on both sides (like the depth limit) are set once, the per-direction options stay
+//! reachable through `OptionsBuilder::with_loader_options()` and `OptionsBuilder::with_dumper_options()`.
+
+#[cfg(feature = "decode")]
+use crate::{
+    decode::{DuplicateKeys, LoadError, Loader, LoaderOptions, ProgressCounter},
+    symbol_table::SymbolTable,
+};
+#[cfg(feature = "encode")]
+use crate::encode::{DumpError, Dumper, DumperOptions, SpecialInstanceVariables, StringDedup, SymbolOrder};
+#[cfg(any(feature = "decode", feature = "encode"))]
+use crate::values::Root;
+
+/// How closely a dump follows the document the Root was loaded from
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Preservation {
+    /// the values are kept, Ruby loads the same objects from the dump, but the bytes may differ
+    #[default]
+    Values,
+    /// the member order and hashes with a `nil` default are recorded when loading and written back, so unchanged
+    /// documents dump to the same bytes, see `SymbolOrder::Recorded`
+    Bytes,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Options {
+    #[cfg(feature = "decode")]
+    loader: LoaderOptions,
+    #[cfg(feature = "encode")]
+    dumper: DumperOptions,
+    preservation: Preservation,
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn get_loader_options(&self) -> &LoaderOptions {
+        &self.loader
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn get_dumper_options(&self) -> &DumperOptions {
+        &self.dumper
+    }
+
+    pub fn get_preservation(&self) -> Preservation {
+        self.preservation
+    }
+}
+
+/// Builder of `Options`, the preservation mode is applied last by `build()` so it wins over conflicting settings
+#[derive(Debug, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// deepest nesting of values for loading and dumping, see `LoaderOptions::max_depth` and `DumperOptions::max_depth`
+    #[cfg(any(feature = "decode", feature = "encode"))]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        #[cfg(feature = "decode")]
+        {
+            self.options.loader.max_depth = max_depth;
+        }
+        #[cfg(feature = "encode")]
+        {
+            self.options.dumper.max_depth = Some(max_depth);
+        }
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_max_item_bytes(mut self, max_item_bytes: usize) -> Self {
+        self.options.loader.max_item_bytes = max_item_bytes;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_max_total_bytes(mut self, max_total_bytes: usize) -> Self {
+        self.options.loader.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_max_objects(mut self, max_objects: usize) -> Self {
+        self.options.loader.max_objects = max_objects;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_max_symbols(mut self, max_symbols: usize, max_symbol_len: usize) -> Self {
+        self.options.loader.max_symbols = max_symbols;
+        self.options.loader.max_symbol_len = max_symbol_len;
+        self
+    }
+
+    /// corrupted input is salvaged instead of failing the load, see `LoaderOptions::lenient`
+    #[cfg(feature = "decode")]
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.options.loader.lenient = lenient;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_duplicate_hash_keys(mut self, duplicate_hash_keys: DuplicateKeys) -> Self {
+        self.options.loader.duplicate_hash_keys = duplicate_hash_keys;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_permitted_classes<'a>(mut self, class_names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.options.loader = self.options.loader.with_permitted_classes(class_names);
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_legacy_versions(mut self, legacy_versions: bool) -> Self {
+        self.options.loader.legacy_versions = legacy_versions;
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_symbol_table(mut self, symbol_table: &SymbolTable) -> Self {
+        self.options.loader.symbol_table = Some(symbol_table.clone());
+        self
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn with_progress(mut self, progress: &ProgressCounter) -> Self {
+        self.options.loader.progress = Some(progress.clone());
+        self
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn with_symbol_order(mut self, symbol_order: SymbolOrder) -> Self {
+        self.options.dumper.symbol_order = symbol_order;
+        self
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn with_special_instance_variables(mut self, special_instance_variables: SpecialInstanceVariables) -> Self {
+        self.options.dumper.special_instance_variables = special_instance_variables;
+        self
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn with_string_dedup(mut self, string_dedup: StringDedup) -> Self {
+        self.options.dumper.string_dedup = string_dedup;
+        self
+    }
+
+    pub fn with_preservation(mut self, preservation: Preservation) -> Self {
+        self.options.preservation = preservation;
+        self
+    }
+
+    /// Changes the loader options directly, for knobs without a counterpart here
+    #[cfg(feature = "decode")]
+    pub fn with_loader_options(mut self, change: impl FnOnce(LoaderOptions) -> LoaderOptions) -> Self {
+        self.options.loader = change(self.options.loader);
+        self
+    }
+
+    /// Changes the dumper options directly, for knobs without a counterpart here
+    #[cfg(feature = "encode")]
+    pub fn with_dumper_options(mut self, change: impl FnOnce(DumperOptions) -> DumperOptions) -> Self {
+        self.options.dumper = change(self.options.dumper);
+        self
+    }
+
+    pub fn build(self) -> Options {
+        let options = self.options;
+        if options.preservation != Preservation::Bytes {
+            return options;
+        }
+        Options {
+            #[cfg(feature = "decode")]
+            loader: options.loader.with_record_member_order(true).with_collapse_nil_default_hashes(false),
+            #[cfg(feature = "encode")]
+            dumper: DumperOptions {
+                symbol_order: SymbolOrder::Recorded,
+                hashes_with_nil_default: false,
+                special_instance_variables: SpecialInstanceVariables::Forward,
+                ..options.dumper
+            },
+            ..options
+        }
+    }
+}
+
+/// Loads the document at the start of `input`
+#[cfg(feature = "decode")]
+pub fn load(input: &[u8], options: &Options) -> Result<Root, LoadError> {
+    let mut reader = input;
+    Loader::with_options(&mut reader, options.loader.clone()).load()
+}
+
+/// Dumps the root value of `root` including the version header
+#[cfg(feature = "encode")]
+pub fn dump(root: &Root, options: &Options) -> Result<Vec<u8>, DumpError> {
+    let mut output = Vec::new();
+    Dumper::with_options(&mut output, options.dumper.clone()).dump(root, root.get_root())?;
+    Ok(output)
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::values::RubyValue;
+
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let options = Options::builder().with_max_depth(2).with_lenient(true).build();
+        assert_eq!(options.get_loader_options().max_depth, 2);
+        assert_eq!(options.get_dumper_options().max_depth, Some(2));
+        assert!(options.get_loader_options().lenient);
+        assert!(matches!(load(b"\x04\x08[\x06[\x06[\x00", &Options::builder().with_max_depth(2).build()), Err(LoadError::DepthLimitExceeded(..))));
+
+        // instance variables reordered after loading are written in the order of the input
+        let input = b"\x04\x08o:\x09Test\x07:\x07@ai\x06:\x07@bi\x07";
+        let options = Options::builder().with_preservation(Preservation::Bytes).with_symbol_order(SymbolOrder::AsStored).build();
+        assert_eq!(options.get_dumper_options().symbol_order, SymbolOrder::Recorded);
+        let mut root = load(input, &options).unwrap();
+        let instance_variables = root.get_mut_object(0).unwrap().as_mut_object().get_mut_instance_variables();
+        let value = instance_variables.shift_remove(&1).unwrap();
+        instance_variables.insert(1, value);
+        assert_eq!(dump(&root, &options).unwrap(), input);
+        assert_ne!(dump(&root, &Options::default()).unwrap(), input);
+        assert_eq!(root.get_root(), &RubyValue::Object(0));
+    }
+}
@@ -0,0 +1,36 @@
+//! Commonly used types, intended to be glob imported: `use marshr::prelude::*;`
+
+pub use crate::archive::{ArchiveEntry, ArchiveReader, ArchiveWriter};
+pub use crate::builder::RootBuilder;
+pub use crate::class_name::ClassName;
+pub use crate::compare::cross_eq;
+pub use crate::convert::{ConvertError, FromRubyValue, IntoRubyValue};
+#[cfg(feature = "decode")]
+pub use crate::decode::{load_from_slice, BorrowedObject, BorrowedRoot, DuplicateKeys, LoadContext, LoadError, LoadExtent, LoadVisitor, Loader, LoaderOptions, PayloadLimit, ProgressCounter, PushLoader, TagHistogram, Token, TokenReader};
+#[cfg(feature = "compress")]
+pub use crate::decode::AutoDecompress;
+#[cfg(feature = "tokio")]
+pub use crate::decode::AsyncLoader;
+pub use crate::framing::{FramedReader, FramedWriter, LengthPrefix};
+pub use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+pub use crate::document_set::DocumentSet;
+pub use crate::dynamic::{flatten, to_dyn, DynOptions, DynValue, KeyCollision};
+pub use crate::error_code::ErrorCode;
+pub use crate::export::{to_jsonl, ExportError};
+#[cfg(feature = "encode")]
+pub use crate::encode::{DumpError, Dumper, DumperOptions, RoundTripMismatch, SpecialInstanceVariables, StringDedup, SymbolOrder};
+pub use crate::options::{Options, OptionsBuilder, Preservation};
+pub use crate::registry::{ClassRegistry, DecodedObjects, RubyClass};
+pub use crate::symbol_table::SymbolTable;
+pub use crate::text::{DefaultTextDecoder, TextDecodeError, TextDecoder, TextDecoders};
+#[cfg(feature = "derive")]
+pub use marshr_derive::{FromRubyObject, IntoRubyObject, RubyEnum};
+pub use crate::typed::{
+    Casing, EnumRepr, Fields, FieldsWriter, FromRubyObject, IntoRubyObject, OptionRepr, RubyEnum, SkippedInstanceVariable, TypedDecoder,
+    UnknownInstanceVariables,
+};
+pub use crate::views::{ExceptionView, RangeView};
+pub use crate::values::{
+    Data, EncodedSymbol, Grid, HashWithDefault, Object, ObjectID, ObjectKind, Placeholder, PrintLimits, RawFragment, RegExp, Root, RubyBignum, RubyError, RubyObject, RubyString,
+    RubyValue, Struct, SymbolID, SymbolStr, UserClass, UserDefined, UserMarshal, ValueKind, ValuePairs, ValuePairsSymbolKeys,
+};
@@ -0,0 +1,242 @@
+//! Typed Rust models of application specific Ruby classes, registered by class name so every matching object of a
+//! loaded document can be decoded in one go. Classes with a custom `_dump` get a decoder of their payload instead,
+//! the counterpart of their `_load`, and classes with `marshal_dump` a converter of the value they wrap, the counterpart
+//! of their `marshal_load`.
+
+use std::{any::Any, collections::HashMap};
+
+use crate::{builder::RootBuilder, convert::ConvertError, values::*};
+
+/// Rust model of a Ruby class that is marshaled as a plain object (`o`)
+pub trait RubyClass: Sized {
+    fn class_name() -> &'static str;
+
+    fn from_object(root: &Root, object: &Object) -> Result<Self, ConvertError>;
+
+    fn to_object(&self, builder: &mut RootBuilder) -> RubyValue;
+}
+
+type Decoder = fn(&Root, &Object) -> Result<Box<dyn Any>, ConvertError>;
+
+type UserDefinedDecoder = Box<dyn Fn(&Root, &UserDefined) -> Result<Box<dyn Any>, ConvertError>>;
+
+type UserMarshalDecoder = Box<dyn Fn(&Root, &RubyValue) -> Result<Box<dyn Any>, ConvertError>>;
+
+#[derive(Default)]
+pub struct ClassRegistry {
+    decoders: HashMap<&'static str, Decoder>,
+    user_defined_decoders: HashMap<&'static str, UserDefinedDecoder>,
+    user_marshal_decoders: HashMap<&'static str, UserMarshalDecoder>,
+}
+
+impl ClassRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `T` for objects of class `T::class_name()`, replacing an earlier registration for the same class
+    pub fn register<T: RubyClass + 'static>(&mut self) -> &mut Self {
+        self.decoders.insert(T::class_name(), |root, object| T::from_object(root, object).map(|model| Box::new(model) as Box<dyn Any>));
+        self
+    }
+
+    /// Registers `decoder` for the payloads of UserDefined objects (`u`) of class `class_name`, like the class's
+    /// `_load` would. It gets the whole UserDefined, the payload is `UserDefined::get_data()` and instance variables
+    /// (like the encoding of a payload string) resolve through the Root. Replaces an earlier registration for the class.
+    pub fn register_user_defined<T: 'static>(
+        &mut self,
+        class_name: &'static str,
+        decoder: impl Fn(&Root, &UserDefined) -> Result<T, ConvertError> + 'static,
+    ) -> &mut Self {
+        let decoder = move |root: &Root, user_defined: &UserDefined| decoder(root, user_defined).map(|model| Box::new(model) as Box<dyn Any>);
+        self.user_defined_decoders.insert(class_name, Box::new(decoder));
+        self
+    }
+
+    /// Registers `decoder` for UserMarshal objects (`U`) of class `class_name`, like the class's `marshal_load` would.
+    /// It gets the value the object wraps, the one `marshal_dump` returned (like the `[numerator, denominator]` array
+    /// of a `Rational`). Replaces an earlier registration for the class.
+    pub fn register_user_marshal<T: 'static>(
+        &mut self,
+        class_name: &'static str,
+        decoder: impl Fn(&Root, &RubyValue) -> Result<T, ConvertError> + 'static,
+    ) -> &mut Self {
+        let decoder = move |root: &Root, wrapped_object: &RubyValue| decoder(root, wrapped_object).map(|model| Box::new(model) as Box<dyn Any>);
+        self.user_marshal_decoders.insert(class_name, Box::new(decoder));
+        self
+    }
+
+    /// Whether a model, a UserDefined decoder or a UserMarshal decoder is registered for the class
+    pub fn is_registered(&self, class_name: &str) -> bool {
+        self.decoders.contains_key(class_name)
+            || self.user_defined_decoders.contains_key(class_name)
+            || self.user_marshal_decoders.contains_key(class_name)
+    }
+
+    /// Decodes `value` if it is an object, UserDefined or UserMarshal object of a registered class, `None` otherwise
+    pub fn decode(&self, root: &Root, value: &RubyValue) -> Option<Result<Box<dyn Any>, ConvertError>> {
+        self.decode_object(root, root.get_object(value.object_id()?)?)
+    }
+
+    /// Decodes every object, UserDefined and UserMarshal object of a registered class in `root`
+    pub fn decode_all(&self, root: &Root) -> Result<DecodedObjects, ConvertError> {
+        let mut objects = HashMap::new();
+        for (object_id, object) in root.get_objects().iter().enumerate() {
+            if let Some(model) = self.decode_object(root, object) {
+                objects.insert(object_id, model?);
+            }
+        }
+        Ok(DecodedObjects { objects })
+    }
+
+    fn decode_object(&self, root: &Root, object: &RubyObject) -> Option<Result<Box<dyn Any>, ConvertError>> {
+        match object {
+            RubyObject::Object(object) => {
+                let decoder = self.decoders.get(&**root.get_symbol(object.get_class_name())?)?;
+                Some(decoder(root, object))
+            }
+            RubyObject::UserDefined(user_defined) => {
+                let decoder = self.user_defined_decoders.get(&**root.get_symbol(user_defined.get_class_name())?)?;
+                Some(decoder(root, user_defined))
+            }
+            RubyObject::UserMarshal(user_marshal) => {
+                let decoder = self.user_marshal_decoders.get(&**root.get_symbol(user_marshal.get_class_name())?)?;
+                Some(decoder(root, user_marshal.get_wrapped_object()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Result of `ClassRegistry::decode_all()`, models are looked up by the id of the object they were decoded from
+#[derive(Default)]
+pub struct DecodedObjects {
+    objects: HashMap<ObjectID, Box<dyn Any>>,
+}
+
+impl DecodedObjects {
+    pub fn get<T: 'static>(&self, object_id: ObjectID) -> Option<&T> {
+        self.objects.get(&object_id)?.downcast_ref()
+    }
+
+    pub fn get_value<T: 'static>(&self, value: &RubyValue) -> Option<&T> {
+        self.get(value.object_id()?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::{convert::FromRubyValue, decode::load::Loader, encode::dump::Dumper};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl RubyClass for Point {
+        fn class_name() -> &'static str {
+            "Point"
+        }
+
+        fn from_object(root: &Root, object: &Object) -> Result<Self, ConvertError> {
+            let coordinate = |name| {
+                let value = root.get_symbol_id(name).and_then(|symbol_id| object.get_instance_variable(symbol_id));
+                i32::from_ruby_value(root, value.unwrap_or(&RubyValue::Nil))
+            };
+            Ok(Point { x: coordinate("@x")?, y: coordinate("@y")? })
+        }
+
+        fn to_object(&self, builder: &mut RootBuilder) -> RubyValue {
+            builder.object("Point", [("@x", RubyValue::FixNum(self.x)), ("@y", RubyValue::FixNum(self.y))])
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut builder = RootBuilder::new();
+        let points = [Point { x: 1, y: 2 }, Point { x: 3, y: 4 }];
+        let mut elements: Vec<RubyValue> = points.iter().map(|point| builder.class(point)).collect();
+        elements.push(builder.object("Other", []));
+        let array = builder.array(elements);
+        let root = builder.build(array);
+
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08[\x08o:\x0aPoint\x07:\x07@xi\x06:\x07@yi\x07o;\x00\x07;\x06i\x08;\x07i\x09o:\x0aOther\x00");
+
+        let mut reader = BufReader::new(&output[..]);
+        let loaded = Loader::new(&mut reader).load().unwrap();
+        let mut registry = ClassRegistry::new();
+        registry.register::<Point>();
+        assert!(registry.is_registered("Point"));
+
+        let decoded = registry.decode_all(&loaded).unwrap();
+        assert_eq!(decoded.len(), 2);
+        let elements = loaded.get_object(0).unwrap().as_array();
+        assert_eq!(decoded.get_value::<Point>(&elements[0]), Some(&points[0]));
+        assert_eq!(decoded.get_value::<Point>(&elements[1]), Some(&points[1]));
+        assert_eq!(decoded.get_value::<Point>(&elements[2]), None);
+        assert!(registry.decode(&loaded, &elements[2]).is_none());
+        assert!(registry.decode(&loaded, &elements[0]).unwrap().unwrap().is::<Point>());
+    }
+
+    #[test]
+    fn test_user_defined_decoders() {
+        // [Table of 2x1 i16, Money "12.50", Other ""]
+        let input = b"\x04\x08[\x08u:\x0aTable\x1d\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x02\x00\x00\x00\x05\x00\xfa\xffu:\x0aMoney\x0a12.50u:\x0aOther\x00";
+        let loaded = Loader::new(&mut &input[..]).load().unwrap();
+        let mut registry = ClassRegistry::new();
+        registry
+            .register_user_defined("Table", |_, table| table.as_i16_grid(2))
+            .register_user_defined("Money", |_, money| {
+                let amount = std::str::from_utf8(money.get_data()).map_err(|err| ConvertError::Encoding(err.to_string()))?;
+                amount.parse::<f64>().map_err(|err| ConvertError::InvalidPayload(err.to_string()))
+            });
+        assert!(registry.is_registered("Money"));
+        assert!(!registry.is_registered("Other"));
+
+        let decoded = registry.decode_all(&loaded).unwrap();
+        assert_eq!(decoded.len(), 2);
+        let elements = loaded.get_object(0).unwrap().as_array();
+        assert_eq!(decoded.get_value::<Grid<i16>>(&elements[0]).unwrap().get_values(), [5, -6]);
+        assert_eq!(decoded.get_value::<f64>(&elements[1]), Some(&12.5));
+        assert!(registry.decode(&loaded, &elements[2]).is_none());
+        // the generic UserDefined stays in the Root
+        assert_eq!(loaded.get_object(elements[1].object_id().unwrap()).unwrap().as_user_defined().get_data(), b"12.50");
+
+        registry.register_user_defined("Other", |_, other| other.as_u8_grid(1));
+        assert!(matches!(registry.decode_all(&loaded), Err(ConvertError::InvalidPayload(_))));
+    }
+
+    #[test]
+    fn test_user_marshal_decoders() {
+        // [Rational(1, 3), Complex(2, 3)]
+        let input = b"\x04\x08[\x07U:\x0dRational[\x07i\x06i\x08U:\x0cComplex[\x07i\x07i\x08";
+        let loaded = Loader::new(&mut &input[..]).load().unwrap();
+        let mut registry = ClassRegistry::new();
+        registry.register_user_marshal("Rational", <(i64, i64)>::from_ruby_value);
+        assert!(registry.is_registered("Rational"));
+
+        let decoded = registry.decode_all(&loaded).unwrap();
+        assert_eq!(decoded.len(), 1);
+        let elements = loaded.get_object(0).unwrap().as_array();
+        assert_eq!(decoded.get_value::<(i64, i64)>(&elements[0]), Some(&(1, 3)));
+        assert!(registry.decode(&loaded, &elements[1]).is_none());
+
+        registry.register_user_marshal("Complex", <(i64, i64, i64)>::from_ruby_value);
+        assert_eq!(registry.decode(&loaded, &elements[1]).unwrap().unwrap_err(), ConvertError::LengthMismatch { expected: 3, found: 2 });
+    }
+}
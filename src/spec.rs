@@ -0,0 +1,117 @@
+//! Constants of the Marshal 4.8 format: version, type tags and integer boundaries, along with the defaults of the
+//! loader's limits. Everything is a `const` so other crates speaking the format can use them in `const` contexts and
+//! patterns.
+
+pub const MARSHAL_MAJOR_VERSION: u8 = 4;
+pub const MARSHAL_MINOR_VERSION: u8 = 8;
+/// The two bytes every document starts with
+pub const MARSHAL_VERSION: [u8; 2] = [MARSHAL_MAJOR_VERSION, MARSHAL_MINOR_VERSION];
+
+pub const TAG_NIL: u8 = b'0';
+pub const TAG_TRUE: u8 = b'T';
+pub const TAG_FALSE: u8 = b'F';
+pub const TAG_FIXNUM: u8 = b'i';
+pub const TAG_SYMBOL: u8 = b':';
+pub const TAG_SYMBOL_LINK: u8 = b';';
+pub const TAG_OBJECT_LINK: u8 = b'@';
+/// instance variables follow the wrapped value
+pub const TAG_INSTANCE_VARIABLES: u8 = b'I';
+/// prefix naming a module the following object was extended with
+pub const TAG_EXTENDED: u8 = b'e';
+pub const TAG_ARRAY: u8 = b'[';
+pub const TAG_BIGNUM: u8 = b'l';
+pub const TAG_CLASS: u8 = b'c';
+pub const TAG_MODULE: u8 = b'm';
+/// old format for classes and modules, only read
+pub const TAG_CLASS_OR_MODULE: u8 = b'M';
+pub const TAG_DATA: u8 = b'd';
+pub const TAG_FLOAT: u8 = b'f';
+pub const TAG_HASH: u8 = b'{';
+pub const TAG_HASH_WITH_DEFAULT: u8 = b'}';
+pub const TAG_OBJECT: u8 = b'o';
+pub const TAG_REGEXP: u8 = b'/';
+pub const TAG_STRING: u8 = b'"';
+pub const TAG_STRUCT: u8 = b'S';
+/// instance of a subclass of String, Array, Hash or Regexp
+pub const TAG_USER_CLASS: u8 = b'C';
+/// object with `_dump`
+pub const TAG_USER_DEFINED: u8 = b'u';
+/// object with `marshal_dump`
+pub const TAG_USER_MARSHAL: u8 = b'U';
+
+/// Every type tag of the format
+pub const TAGS: [u8; 25] = [
+    TAG_NIL, TAG_TRUE, TAG_FALSE, TAG_FIXNUM, TAG_SYMBOL, TAG_SYMBOL_LINK, TAG_OBJECT_LINK, TAG_INSTANCE_VARIABLES,
+    TAG_EXTENDED, TAG_ARRAY, TAG_BIGNUM, TAG_CLASS, TAG_MODULE, TAG_CLASS_OR_MODULE, TAG_DATA, TAG_FLOAT, TAG_HASH,
+    TAG_HASH_WITH_DEFAULT, TAG_OBJECT, TAG_REGEXP, TAG_STRING, TAG_STRUCT, TAG_USER_CLASS, TAG_USER_DEFINED,
+    TAG_USER_MARSHAL,
+];
+
+/// Name of the type a tag introduces, `None` for bytes that aren't tags
+pub const fn tag_name(tag: u8) -> Option<&'static str> {
+    Some(match tag {
+        TAG_NIL => "nil",
+        TAG_TRUE => "true",
+        TAG_FALSE => "false",
+        TAG_FIXNUM => "fixnum",
+        TAG_SYMBOL => "symbol",
+        TAG_SYMBOL_LINK => "symbol link",
+        TAG_OBJECT_LINK => "object link",
+        TAG_INSTANCE_VARIABLES => "instance variables",
+        TAG_EXTENDED => "extended",
+        TAG_ARRAY => "array",
+        TAG_BIGNUM => "bignum",
+        TAG_CLASS => "class",
+        TAG_MODULE => "module",
+        TAG_CLASS_OR_MODULE => "class or module",
+        TAG_DATA => "data",
+        TAG_FLOAT => "float",
+        TAG_HASH => "hash",
+        TAG_HASH_WITH_DEFAULT => "hash with default",
+        TAG_OBJECT => "object",
+        TAG_REGEXP => "regexp",
+        TAG_STRING => "string",
+        TAG_STRUCT => "struct",
+        TAG_USER_CLASS => "user class",
+        TAG_USER_DEFINED => "user defined",
+        TAG_USER_MARSHAL => "user marshal",
+        _ => return None,
+    })
+}
+
+/// Whether the value a tag introduces is an object that object links can refer to (or wraps one), immediate values,
+/// symbols and links aren't
+pub const fn is_object_tag(tag: u8) -> bool {
+    !matches!(tag, TAG_NIL | TAG_TRUE | TAG_FALSE | TAG_FIXNUM | TAG_SYMBOL | TAG_SYMBOL_LINK | TAG_OBJECT_LINK) && tag_name(tag).is_some()
+}
+
+/// Sign bytes of bignums
+pub const BIGNUM_POSITIVE: u8 = b'+';
+pub const BIGNUM_NEGATIVE: u8 = b'-';
+
+/// Values in `SMALL_FIXNUM_MIN..=SMALL_FIXNUM_MAX` are encoded in the fixnum's first byte, see `fixnum`
+pub const SMALL_FIXNUM_MIN: i32 = -123;
+pub const SMALL_FIXNUM_MAX: i32 = 122;
+/// Ruby writes Integers in `FIXNUM_MIN..=FIXNUM_MAX` with `i`, all others as bignums
+pub const FIXNUM_MIN: i32 = -(1 << 30);
+pub const FIXNUM_MAX: i32 = (1 << 30) - 1;
+
+/// Default of `LoaderOptions::max_depth`, values nested this deep can still be dumped, compared and printed (which
+/// recurse) on the 2 MiB stack of a spawned thread even in debug builds
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        for (i, tag) in TAGS.iter().enumerate() {
+            assert!(tag_name(*tag).is_some());
+            assert!(!TAGS[i + 1..].contains(tag));
+        }
+        assert_eq!(tag_name(b'x'), None);
+        assert!(is_object_tag(TAG_STRING) && is_object_tag(TAG_INSTANCE_VARIABLES));
+        assert!(!is_object_tag(TAG_OBJECT_LINK) && !is_object_tag(b'x'));
+    }
+}
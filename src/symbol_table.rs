@@ -0,0 +1,91 @@
+//! Symbol texts shared between documents, so indices over many loaded documents hold every class and instance
+//! variable name once
+
+use std::collections::HashSet;
+#[cfg(not(feature = "sync"))]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(feature = "sync")]
+use std::sync::{Arc, Mutex};
+
+use crate::values::SymbolStr;
+
+/// Interner handing out one `SymbolStr` per text, clones share the interned texts. Pass it to every load with
+/// `LoaderOptions::with_symbol_table()` and equal symbols of all documents point to the same allocation.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    #[cfg(not(feature = "sync"))]
+    symbols: Rc<RefCell<HashSet<SymbolStr>>>,
+    #[cfg(feature = "sync")]
+    symbols: Arc<Mutex<HashSet<SymbolStr>>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the interned text equal to `name`, adding it if it's new
+    pub fn intern(&self, name: &str) -> SymbolStr {
+        self.with_symbols(|symbols| match symbols.get(name) {
+            Some(symbol) => symbol.clone(),
+            None => {
+                let symbol = SymbolStr::from(name);
+                symbols.insert(symbol.clone());
+                symbol
+            }
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.with_symbols(|symbols| symbols.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(not(feature = "sync"))]
+    fn with_symbols<T>(&self, f: impl FnOnce(&mut HashSet<SymbolStr>) -> T) -> T {
+        f(&mut self.symbols.borrow_mut())
+    }
+
+    #[cfg(feature = "sync")]
+    fn with_symbols<T>(&self, f: impl FnOnce(&mut HashSet<SymbolStr>) -> T) -> T {
+        // the set stays consistent if another thread panicked while holding the lock
+        f(&mut self.symbols.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+/// Tables are equal if they are clones of each other
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &Self) -> bool {
+        #[cfg(not(feature = "sync"))]
+        return Rc::ptr_eq(&self.symbols, &other.symbols);
+        #[cfg(feature = "sync")]
+        return Arc::ptr_eq(&self.symbols, &other.symbols);
+    }
+}
+
+impl Eq for SymbolTable {}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use crate::decode::load::{Loader, LoaderOptions};
+
+    use super::*;
+
+    #[test]
+    fn test_shared_symbols() {
+        let symbol_table = SymbolTable::new();
+        let options = LoaderOptions::default().with_symbol_table(&symbol_table);
+        // [:a, :b] and :a
+        let first = Loader::with_options(&mut &b"\x04\x08[\x07:\x06a:\x06b"[..], options.clone()).load().unwrap();
+        let second = Loader::with_options(&mut &b"\x04\x08:\x06a"[..], options).load().unwrap();
+        assert_eq!(symbol_table.len(), 2);
+        assert!(std::ptr::eq(first.get_symbol(0).unwrap().as_ptr(), second.get_symbol(0).unwrap().as_ptr()));
+
+        let unshared = Loader::new(&mut &b"\x04\x08:\x06a"[..]).load().unwrap();
+        assert_eq!(unshared.get_symbol(0), first.get_symbol(0));
+        assert!(!std::ptr::eq(first.get_symbol(0).unwrap().as_ptr(), unshared.get_symbol(0).unwrap().as_ptr()));
+    }
+}
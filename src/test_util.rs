@@ -0,0 +1,11 @@
+//! Helpers shared by the tests of several modules
+
+use std::io::BufReader;
+
+use crate::{decode::load::Loader, values::Root};
+
+/// Loads a complete document, panicking on errors
+pub(crate) fn load(input: &[u8]) -> Root {
+    let mut reader = BufReader::new(input);
+    Loader::new(&mut reader).load().unwrap()
+}
@@ -0,0 +1,158 @@
+//! Decoding the bytes of strings and symbols into text, see `TextDecoder`. `DefaultTextDecoder` handles UTF-8 and
+//! US-ASCII itself and other encodings with `encoding_rs` or the `encoding` crate, whichever feature is enabled.
+//! `TextDecoders` puts decoders for single encodings (like EUC-TW or the codepage of an old game) in front of it.
+
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+use std::{collections::HashMap, fmt::Display};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum TextDecodeError {
+    /// the decoder doesn't know the encoding, holds its name
+    UnknownEncoding(String),
+    /// the bytes aren't valid in the encoding
+    InvalidBytes { encoding: String, reason: String },
+}
+
+impl Display for TextDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextDecodeError::UnknownEncoding(encoding) => f.write_str(&format!("Could not find encoding {}", encoding)),
+            TextDecodeError::InvalidBytes { encoding, reason } => f.write_str(&format!("Could not decode string as {}: {}", encoding, reason)),
+        }
+    }
+}
+
+/// Turns bytes into text given the name of their encoding, as Ruby names it (like `UTF-8`, `Shift_JIS` or `EUC-JP`).
+/// Used by `Root::decode_string_with()` and, for symbols and to tell which encodings are unknown, by
+/// `Loader::with_text_decoder()`.
+pub trait TextDecoder {
+    fn decode(&self, encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError>;
+
+    /// Whether the decoder knows `encoding`, by default it tries to decode no bytes
+    fn supports(&self, encoding: &str) -> bool {
+        !matches!(self.decode(encoding, &[]), Err(TextDecodeError::UnknownEncoding(_)))
+    }
+}
+
+impl<F: Fn(&str, &[u8]) -> Result<String, TextDecodeError>> TextDecoder for F {
+    fn decode(&self, encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError> {
+        self(encoding, bytes)
+    }
+}
+
+/// Decodes UTF-8 and US-ASCII, other encodings need the `encoding_rs` or `encoding` feature, which look them up by
+/// their WHATWG label. Invalid bytes are errors, nothing is replaced.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct DefaultTextDecoder;
+
+impl TextDecoder for DefaultTextDecoder {
+    fn decode(&self, encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError> {
+        let invalid = |reason: String| TextDecodeError::InvalidBytes { encoding: encoding.to_string(), reason };
+        if encoding.eq_ignore_ascii_case("UTF-8") {
+            return String::from_utf8(bytes.to_vec()).map_err(|error| invalid(error.to_string()));
+        }
+        if encoding.eq_ignore_ascii_case("US-ASCII") || encoding.eq_ignore_ascii_case("ASCII") {
+            return match bytes.iter().position(|byte| !byte.is_ascii()) {
+                Some(position) => Err(invalid(format!("byte {:#04x} at {} is not ASCII", bytes[position], position))),
+                None => Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+            };
+        }
+        decode_labeled(encoding, bytes)
+    }
+}
+
+#[cfg(feature = "encoding_rs")]
+fn decode_labeled(encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError> {
+    let Some(found) = encoding_rs::Encoding::for_label(encoding.as_bytes()) else {
+        return Err(TextDecodeError::UnknownEncoding(encoding.to_string()));
+    };
+    match found.decode_without_bom_handling_and_without_replacement(bytes) {
+        Some(text) => Ok(text.into_owned()),
+        None => Err(TextDecodeError::InvalidBytes { encoding: encoding.to_string(), reason: "malformed sequence".to_string() }),
+    }
+}
+
+#[cfg(all(feature = "encoding", not(feature = "encoding_rs")))]
+fn decode_labeled(encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError> {
+    let Some(found) = encoding::label::encoding_from_whatwg_label(encoding) else {
+        return Err(TextDecodeError::UnknownEncoding(encoding.to_string()));
+    };
+    found.decode(bytes, encoding::DecoderTrap::Strict)
+        .map_err(|error| TextDecodeError::InvalidBytes { encoding: encoding.to_string(), reason: error.into_owned() })
+}
+
+#[cfg(not(any(feature = "encoding", feature = "encoding_rs")))]
+fn decode_labeled(encoding: &str, _bytes: &[u8]) -> Result<String, TextDecodeError> {
+    Err(TextDecodeError::UnknownEncoding(encoding.to_string()))
+}
+
+/// Decoders registered for single encodings, the fallback (`DefaultTextDecoder` unless given) decodes all others.
+/// Encoding names are compared ignoring ASCII case.
+pub struct TextDecoders {
+    decoders: HashMap<String, Box<dyn TextDecoder>>,
+    fallback: Box<dyn TextDecoder>,
+}
+
+impl Default for TextDecoders {
+    fn default() -> Self {
+        Self::with_fallback(DefaultTextDecoder)
+    }
+}
+
+impl TextDecoders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_fallback(fallback: impl TextDecoder + 'static) -> Self {
+        Self { decoders: HashMap::new(), fallback: Box::new(fallback) }
+    }
+
+    /// Decodes strings in `encoding` with `decoder`, replacing an earlier registration for the encoding
+    pub fn register(&mut self, encoding: &str, decoder: impl TextDecoder + 'static) -> &mut Self {
+        self.decoders.insert(encoding.to_ascii_lowercase(), Box::new(decoder));
+        self
+    }
+}
+
+impl TextDecoder for TextDecoders {
+    fn decode(&self, encoding: &str, bytes: &[u8]) -> Result<String, TextDecodeError> {
+        match self.decoders.get(&encoding.to_ascii_lowercase()) {
+            Some(decoder) => decoder.decode(encoding, bytes),
+            None => self.fallback.decode(encoding, bytes),
+        }
+    }
+
+    fn supports(&self, encoding: &str) -> bool {
+        self.decoders.contains_key(&encoding.to_ascii_lowercase()) || self.fallback.supports(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_text_decoder() {
+        assert_eq!(DefaultTextDecoder.decode("UTF-8", "héllo".as_bytes()).unwrap(), "héllo");
+        assert_eq!(DefaultTextDecoder.decode("us-ascii", b"abc").unwrap(), "abc");
+        assert_eq!(DefaultTextDecoder.decode("US-ASCII", b"a\xe9").unwrap_err().to_string(), "Could not decode string as US-ASCII: byte 0xe9 at 1 is not ASCII");
+        assert!(matches!(DefaultTextDecoder.decode("UTF-8", b"\xff"), Err(TextDecodeError::InvalidBytes { .. })));
+        assert!(!DefaultTextDecoder.supports("EUC-TW"));
+        #[cfg(any(feature = "encoding", feature = "encoding_rs"))]
+        assert_eq!(DefaultTextDecoder.decode("Shift_JIS", b"\x82\xa0").unwrap(), "あ");
+    }
+
+    #[test]
+    fn test_text_decoders() {
+        let mut decoders = TextDecoders::new();
+        // a made-up codepage shifting letters by one
+        decoders.register("Game-1", |_: &str, bytes: &[u8]| Ok(bytes.iter().map(|&byte| char::from(byte + 1)).collect()));
+        assert!(decoders.supports("GAME-1"));
+        assert_eq!(decoders.decode("game-1", b"HAL").unwrap(), "IBM");
+        assert_eq!(decoders.decode("UTF-8", b"abc").unwrap(), "abc");
+        assert_eq!(decoders.decode("Game-2", b"abc").unwrap_err(), TextDecodeError::UnknownEncoding("Game-2".to_string()));
+    }
+}
@@ -0,0 +1,630 @@
+//! Conversion between Ruby objects and Rust structs, one instance variable per field
+//!
+//! ```
+//! use marshr::prelude::*;
+//!
+//! struct Actor {
+//!     name: String,
+//!     hp: i32,
+//! }
+//!
+//! impl FromRubyObject for Actor {
+//!     const CLASS_NAME: Option<&'static str> = Some("Actor");
+//!     const UNKNOWN_INSTANCE_VARIABLES: UnknownInstanceVariables = UnknownInstanceVariables::Log;
+//!
+//!     fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+//!         Ok(Actor { name: fields.get("@name")?, hp: fields.get("@hp")? })
+//!     }
+//! }
+//!
+//! # #[cfg(feature = "decode")] {
+//! // Actor(@name="Ralph", @hp=10, @mp=3)
+//! let mut input: &[u8] = b"\x04\x08o:\x0aActor\x08:\x0a@nameI\"\x0aRalph\x06:\x06ET:\x08@hpi\x0f:\x08@mpi\x08";
+//! let root = Loader::new(&mut input).load().unwrap();
+//!
+//! let mut decoder = TypedDecoder::new(&root);
+//! let actor: Actor = decoder.decode(root.get_root()).unwrap();
+//! assert_eq!((actor.name.as_str(), actor.hp), ("Ralph", 10));
+//! assert_eq!(decoder.skipped()[0].name, "@mp");
+//! # }
+//! ```
+//!
+//! With the `derive` feature the implementations above can be derived instead, `#[derive(FromRubyObject)]` together
+//! with `#[marshr(class = "Actor", unknown = "log")]` on `Actor` reads the same instance variables. `IntoRubyObject`
+//! and `RubyEnum` can be derived as well, the `marshr_derive` crate lists the attributes.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{convert::{ConvertError, FromRubyValue, IntoRubyValue}, values::*};
+
+/// What happens with instance variables of an object that the type doesn't read, Ruby classes tend to gain
+/// instance variables over time so skipping them is often preferable to failing
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum UnknownInstanceVariables {
+    /// fail with `ConvertError::UnknownInstanceVariable`
+    #[default]
+    Deny,
+    /// skip them silently
+    Ignore,
+    /// skip them and record them in `TypedDecoder::skipped()`
+    Log,
+}
+
+/// Conversion of a Ruby object (`o`) into a Rust type
+pub trait FromRubyObject: Sized {
+    /// If set, the class name of the object has to match
+    const CLASS_NAME: Option<&'static str> = None;
+    const UNKNOWN_INSTANCE_VARIABLES: UnknownInstanceVariables = UnknownInstanceVariables::Deny;
+
+    fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError>;
+}
+
+/// Instance variable that was skipped while decoding
+#[derive(Debug, PartialEq, Clone)]
+pub struct SkippedInstanceVariable {
+    pub class_name: String,
+    pub name: String,
+    pub value: RubyValue,
+}
+
+thread_local! {
+    /// Objects being decoded on this thread with the address of their root. `FromRubyValue` decodes nested objects
+    /// with fresh decoders, so the decoders can't keep track of them themselves.
+    static DECODING: RefCell<Vec<(usize, ObjectID)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks an object as being decoded until it is dropped
+struct Decoding((usize, ObjectID));
+
+impl Decoding {
+    /// Fails with `ConvertError::Cycle` if the object is already being decoded, i.e. it references itself
+    fn enter(root: &Root, object_id: ObjectID) -> Result<Self, ConvertError> {
+        let key = (root as *const Root as usize, object_id);
+        DECODING.with(|decoding| {
+            let mut decoding = decoding.borrow_mut();
+            if decoding.contains(&key) {
+                return Err(ConvertError::Cycle(object_id));
+            }
+            decoding.push(key);
+            Ok(Decoding(key))
+        })
+    }
+}
+
+impl Drop for Decoding {
+    fn drop(&mut self) {
+        DECODING.with(|decoding| {
+            let mut decoding = decoding.borrow_mut();
+            if let Some(index) = decoding.iter().rposition(|key| *key == self.0) {
+                decoding.remove(index);
+            }
+        });
+    }
+}
+
+pub struct TypedDecoder<'a> {
+    root: &'a Root,
+    skipped: Vec<SkippedInstanceVariable>,
+}
+
+impl<'a> TypedDecoder<'a> {
+    pub fn new(root: &'a Root) -> Self {
+        Self { root, skipped: Vec::new() }
+    }
+
+    /// Decodes the object `value` references, an object that references itself fails with `ConvertError::Cycle`
+    pub fn decode<T: FromRubyObject>(&mut self, value: &RubyValue) -> Result<T, ConvertError> {
+        let RubyValue::Object(object_id) = value else {
+            return Err(ConvertError::TypeMismatch { expected: "object", found: value.kind() });
+        };
+        let root = self.root;
+        let Some(RubyObject::Object(object)) = root.get_object(*object_id) else {
+            return Err(ConvertError::InvalidReference(value.kind(), *object_id));
+        };
+        let class_name = root.get_symbol(object.get_class_name())
+            .ok_or(ConvertError::InvalidReference(ValueKind::Symbol, object.get_class_name()))?;
+        if let Some(expected) = T::CLASS_NAME {
+            if expected != &**class_name {
+                return Err(ConvertError::ClassMismatch { expected, found: class_name.to_string() });
+            }
+        }
+
+        let _decoding = Decoding::enter(root, *object_id)?;
+        let instance_variables = object.get_instance_variables();
+        let mut fields = Fields { decoder: self, class_name, instance_variables, read: vec![false; instance_variables.len()] };
+        let decoded = T::from_ruby_object(&mut fields)?;
+        fields.finish(T::UNKNOWN_INSTANCE_VARIABLES)?;
+        Ok(decoded)
+    }
+
+    /// Instance variables skipped by types using `UnknownInstanceVariables::Log`, in the order they were encountered
+    pub fn skipped(&self) -> &[SkippedInstanceVariable] {
+        &self.skipped
+    }
+
+    pub fn into_skipped(self) -> Vec<SkippedInstanceVariable> {
+        self.skipped
+    }
+}
+
+/// Instance variables of the object being decoded, keeps track of the ones that have been read
+pub struct Fields<'d, 'a> {
+    decoder: &'d mut TypedDecoder<'a>,
+    class_name: &'a str,
+    instance_variables: &'a ValuePairsSymbolKeys,
+    read: Vec<bool>,
+}
+
+impl<'a> Fields<'_, 'a> {
+    pub fn class_name(&self) -> &'a str {
+        self.class_name
+    }
+
+    pub fn root(&self) -> &'a Root {
+        self.decoder.root
+    }
+
+    /// Returns the value of the instance variable `name` (e.g. `"@hp"`) and marks it as read
+    pub fn value(&mut self, name: &str) -> Option<&'a RubyValue> {
+        let symbol_id = self.decoder.root.get_symbol_id(name)?;
+        let (index, _, value) = self.instance_variables.get_full(&symbol_id)?;
+        self.read[index] = true;
+        Some(value)
+    }
+
+    /// Converts the instance variable `name`, a missing instance variable reads as nil like in Ruby
+    pub fn get<T: FromRubyValue>(&mut self, name: &str) -> Result<T, ConvertError> {
+        let value = self.value(name).unwrap_or(&RubyValue::Nil);
+        T::from_ruby_value(self.decoder.root, value)
+    }
+
+    /// Decodes the object stored in the instance variable `name` using the same decoder
+    pub fn object<T: FromRubyObject>(&mut self, name: &str) -> Result<T, ConvertError> {
+        let value = self.value(name).unwrap_or(&RubyValue::Nil);
+        self.decoder.decode(value)
+    }
+
+    /// Takes all instance variables that haven't been read yet, meant for a catch-all field (like `#[serde(flatten)]`)
+    pub fn rest(&mut self) -> Result<HashMap<String, RubyValue>, ConvertError> {
+        let mut rest = HashMap::new();
+        for (index, (symbol_id, value)) in self.instance_variables.iter().enumerate() {
+            if !self.read[index] {
+                rest.insert(self.symbol_name(*symbol_id)?.to_string(), value.clone());
+                self.read[index] = true;
+            }
+        }
+        Ok(rest)
+    }
+
+    /// Converts the instance variable `name` holding a symbol or string into an enum variant
+    pub fn get_enum<E: RubyEnum>(&mut self, name: &str) -> Result<E, ConvertError> {
+        let root = self.decoder.root;
+        let value = self.value(name).unwrap_or(&RubyValue::Nil);
+        let variant_name = match value {
+            RubyValue::Symbol(symbol_id) => root.get_symbol(*symbol_id).ok_or(ConvertError::InvalidReference(value.kind(), *symbol_id))?.to_string(),
+            RubyValue::String(_) => String::from_ruby_value(root, value)?,
+            _ => return Err(ConvertError::TypeMismatch { expected: "symbol or string", found: value.kind() }),
+        };
+        E::VARIANTS.iter()
+            .find(|(rust_name, _)| E::CASING.apply(rust_name) == variant_name)
+            .map(|(_, variant)| *variant)
+            .ok_or(ConvertError::UnknownVariant(variant_name))
+    }
+
+    fn symbol_name(&self, symbol_id: SymbolID) -> Result<&'a str, ConvertError> {
+        self.decoder.root.get_symbol(symbol_id).map(|name| &**name).ok_or(ConvertError::InvalidReference(ValueKind::Symbol, symbol_id))
+    }
+
+    fn finish(self, unknown_instance_variables: UnknownInstanceVariables) -> Result<(), ConvertError> {
+        let unread = self.instance_variables.iter().enumerate().filter(|(index, _)| !self.read[*index]);
+        for (_, (symbol_id, value)) in unread {
+            let name = self.symbol_name(*symbol_id)?;
+            // interpreter hints like `__id__` aren't data of the object
+            if is_special_instance_variable(name) {
+                continue;
+            }
+            match unknown_instance_variables {
+                UnknownInstanceVariables::Deny => {
+                    return Err(ConvertError::UnknownInstanceVariable { class_name: self.class_name.to_string(), name: name.to_string() });
+                }
+                UnknownInstanceVariables::Ignore => (),
+                UnknownInstanceVariables::Log => self.decoder.skipped.push(SkippedInstanceVariable {
+                    class_name: self.class_name.to_string(),
+                    name: name.to_string(),
+                    value: value.clone(),
+                }),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Casing applied to the Rust variant names of a `RubyEnum`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Casing {
+    /// `HighPriority` stays `HighPriority`
+    AsIs,
+    /// `HighPriority` becomes `high_priority`, the usual style of Ruby symbols
+    #[default]
+    SnakeCase,
+    /// `high_priority` becomes `HighPriority`
+    CamelCase,
+}
+
+impl Casing {
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Casing::AsIs => name.to_string(),
+            Casing::SnakeCase => {
+                let mut snake_case = String::with_capacity(name.len() + 4);
+                for (i, character) in name.chars().enumerate() {
+                    if character.is_uppercase() && i != 0 && !snake_case.ends_with('_') {
+                        snake_case.push('_');
+                    }
+                    snake_case.extend(character.to_lowercase());
+                }
+                snake_case
+            }
+            Casing::CamelCase => {
+                let mut camel_case = String::with_capacity(name.len());
+                let mut capitalize = true;
+                for character in name.chars() {
+                    if character == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        camel_case.extend(character.to_uppercase());
+                        capitalize = false;
+                    } else {
+                        camel_case.push(character);
+                    }
+                }
+                camel_case
+            }
+        }
+    }
+}
+
+/// How enum variants are stored in Ruby
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EnumRepr {
+    #[default]
+    Symbol,
+    String,
+}
+
+/// Rust enum stored as a Ruby symbol or string, e.g. `Priority::HighPriority` as `:high_priority`
+pub trait RubyEnum: Sized + Copy + PartialEq + 'static {
+    /// Every variant with its Rust name, casing is applied to the names
+    const VARIANTS: &'static [(&'static str, Self)];
+    const REPR: EnumRepr = EnumRepr::Symbol;
+    const CASING: Casing = Casing::SnakeCase;
+
+    /// Name of the variant as stored in Ruby, `None` if the variant is missing from `VARIANTS`
+    fn ruby_name(&self) -> Option<String> {
+        let (rust_name, _) = Self::VARIANTS.iter().find(|(_, variant)| variant == self)?;
+        Some(Self::CASING.apply(rust_name))
+    }
+}
+
+/// How `None` is stored by `FieldsWriter::set_option`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OptionRepr {
+    /// the instance variable is set to nil
+    #[default]
+    Nil,
+    /// the instance variable is left out
+    Omit,
+}
+
+/// Conversion of a Rust type into a Ruby object (`o`)
+pub trait IntoRubyObject {
+    const CLASS_NAME: &'static str;
+    const NONE: OptionRepr = OptionRepr::Nil;
+
+    fn into_ruby_object(self, fields: &mut FieldsWriter<'_>) -> Result<(), ConvertError>;
+}
+
+/// Appends `value` as an object to `root`, nested objects, strings and symbols are added as needed
+pub fn encode<T: IntoRubyObject>(root: &mut Root, value: T) -> Result<RubyValue, ConvertError> {
+    let mut fields = FieldsWriter { root, none: T::NONE, instance_variables: Vec::new() };
+    value.into_ruby_object(&mut fields)?;
+    let FieldsWriter { root, instance_variables, .. } = fields;
+    Ok(root.add_object(T::CLASS_NAME, instance_variables.iter().map(|(name, value)| (*name, value.clone()))))
+}
+
+/// Instance variables of the object being encoded, written in the order they are set
+pub struct FieldsWriter<'r> {
+    root: &'r mut Root,
+    none: OptionRepr,
+    instance_variables: Vec<(&'static str, RubyValue)>,
+}
+
+impl FieldsWriter<'_> {
+    pub fn root(&mut self) -> &mut Root {
+        self.root
+    }
+
+    /// Sets the instance variable `name` (e.g. `"@hp"`)
+    pub fn set(&mut self, name: &'static str, value: impl IntoRubyValue) -> Result<(), ConvertError> {
+        let value = value.into_ruby_value(self.root)?;
+        self.instance_variables.push((name, value));
+        Ok(())
+    }
+
+    /// Sets the instance variable `name`, `None` is stored according to `IntoRubyObject::NONE`
+    pub fn set_option(&mut self, name: &'static str, value: Option<impl IntoRubyValue>) -> Result<(), ConvertError> {
+        match (value, self.none) {
+            (Some(value), _) => self.set(name, value),
+            (None, OptionRepr::Nil) => self.set(name, RubyValue::Nil),
+            (None, OptionRepr::Omit) => Ok(()),
+        }
+    }
+
+    /// Sets the instance variable `name` to the symbol or string representing `value`
+    pub fn set_enum<E: RubyEnum>(&mut self, name: &'static str, value: E) -> Result<(), ConvertError> {
+        let ruby_name = value.ruby_name().ok_or(ConvertError::MissingVariant(std::any::type_name::<E>()))?;
+        let value = match E::REPR {
+            EnumRepr::Symbol => self.root.add_symbol(&ruby_name),
+            EnumRepr::String => self.root.add_string(&ruby_name),
+        };
+        self.set(name, value)
+    }
+
+    /// Sets the instance variable `name` to a nested object
+    pub fn set_object<T: IntoRubyObject>(&mut self, name: &'static str, value: T) -> Result<(), ConvertError> {
+        self.set(name, value)
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    macro_rules! actor {
+        ($name:ident, $policy:ident) => {
+            #[derive(Debug, PartialEq)]
+            struct $name {
+                hp: i32,
+                mp: Option<i32>,
+            }
+
+            impl FromRubyObject for $name {
+                const CLASS_NAME: Option<&'static str> = Some("Actor");
+                const UNKNOWN_INSTANCE_VARIABLES: UnknownInstanceVariables = UnknownInstanceVariables::$policy;
+
+                fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+                    Ok(Self { hp: fields.get("@hp")?, mp: fields.get("@mp")? })
+                }
+            }
+        };
+    }
+
+    actor!(DenyActor, Deny);
+    actor!(IgnoreActor, Ignore);
+    actor!(LogActor, Log);
+
+    #[derive(Debug, PartialEq)]
+    struct FlattenActor {
+        hp: i32,
+        rest: HashMap<String, RubyValue>,
+    }
+
+    impl FromRubyObject for FlattenActor {
+        fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+            Ok(Self { hp: fields.get("@hp")?, rest: fields.rest()? })
+        }
+    }
+
+    struct Party {
+        leader: LogActor,
+    }
+
+    impl FromRubyObject for Party {
+        const UNKNOWN_INSTANCE_VARIABLES: UnknownInstanceVariables = UnknownInstanceVariables::Log;
+
+        fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+            Ok(Self { leader: fields.object("@leader")? })
+        }
+    }
+
+    #[test]
+    fn test_unknown_instance_variables() {
+        // Actor(@hp=10, @sp=3, @level=7)
+        let root = load(b"\x04\x08o:\x0aActor\x08:\x08@hpi\x0f:\x08@spi\x08:\x0b@leveli\x0c");
+
+        let mut decoder = TypedDecoder::new(&root);
+        assert_eq!(
+            decoder.decode::<DenyActor>(root.get_root()),
+            Err(ConvertError::UnknownInstanceVariable { class_name: "Actor".to_string(), name: "@sp".to_string() })
+        );
+        assert_eq!(decoder.decode::<IgnoreActor>(root.get_root()), Ok(IgnoreActor { hp: 10, mp: None }));
+        assert!(decoder.skipped().is_empty());
+
+        assert_eq!(decoder.decode::<LogActor>(root.get_root()), Ok(LogActor { hp: 10, mp: None }));
+        let skipped: Vec<(&str, &RubyValue)> = decoder.skipped().iter().map(|skipped| (skipped.name.as_str(), &skipped.value)).collect();
+        assert_eq!(skipped, [("@sp", &RubyValue::FixNum(3)), ("@level", &RubyValue::FixNum(7))]);
+
+        let flatten = TypedDecoder::new(&root).decode::<FlattenActor>(root.get_root()).unwrap();
+        assert_eq!(flatten.hp, 10);
+        assert_eq!(flatten.rest, HashMap::from([("@sp".to_string(), RubyValue::FixNum(3)), ("@level".to_string(), RubyValue::FixNum(7))]));
+
+        // user types convert through FromRubyValue as well
+        assert_eq!(Option::<IgnoreActor>::from_ruby_value(&root, root.get_root()), Ok(Some(IgnoreActor { hp: 10, mp: None })));
+
+        // special instance variables aren't unknown data
+        let root = load(b"\x04\x08o:\x0aActor\x07:\x08@hpi\x0f:\x0b__id__i\x06");
+        assert_eq!(TypedDecoder::new(&root).decode::<DenyActor>(root.get_root()), Ok(DenyActor { hp: 10, mp: None }));
+    }
+
+    #[test]
+    fn test_nested_objects() {
+        // Party(@leader=Actor(@hp=10, @mp=5, @sp=3), @size=1)
+        let root = load(b"\x04\x08o:\x0aParty\x07:\x0c@leadero:\x0aActor\x08:\x08@hpi\x0f:\x08@mpi\x0a:\x08@spi\x08:\x0a@sizei\x06");
+        let mut decoder = TypedDecoder::new(&root);
+        let party = decoder.decode::<Party>(root.get_root()).unwrap();
+        assert_eq!(party.leader, LogActor { hp: 10, mp: Some(5) });
+
+        let skipped: Vec<(&str, &str)> = decoder.skipped().iter().map(|skipped| (skipped.class_name.as_str(), skipped.name.as_str())).collect();
+        assert_eq!(skipped, [("Actor", "@sp"), ("Party", "@size")]);
+
+        assert!(matches!(decoder.decode::<LogActor>(root.get_root()), Err(ConvertError::ClassMismatch { expected: "Actor", .. })));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Node {
+        next: Vec<Node>,
+    }
+
+    impl FromRubyObject for Node {
+        fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+            Ok(Self { next: fields.get("@next")? })
+        }
+    }
+
+    #[test]
+    fn test_cycle() {
+        // Node(@next=[itself])
+        let root = load(b"\x04\x08o:\x09Node\x06:\x0a@next[\x06@\x00");
+        assert_eq!(TypedDecoder::new(&root).decode::<Node>(root.get_root()), Err(ConvertError::Cycle(0)));
+        // a node referenced twice isn't a cycle
+        let root = load(b"\x04\x08[\x07o:\x09Node\x06:\x0a@next[\x00@\x06");
+        assert_eq!(Vec::<Node>::from_ruby_value(&root, root.get_root()), Ok(vec![Node { next: Vec::new() }, Node { next: Vec::new() }]));
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum Priority {
+        Low,
+        HighPriority,
+    }
+
+    impl RubyEnum for Priority {
+        const VARIANTS: &'static [(&'static str, Self)] = &[("Low", Priority::Low), ("HighPriority", Priority::HighPriority)];
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum Kind {
+        Bug,
+        FeatureRequest,
+    }
+
+    impl RubyEnum for Kind {
+        const VARIANTS: &'static [(&'static str, Self)] = &[("Bug", Kind::Bug), ("FeatureRequest", Kind::FeatureRequest)];
+        const REPR: EnumRepr = EnumRepr::String;
+        const CASING: Casing = Casing::AsIs;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Task {
+        title: String,
+        priority: Priority,
+        due: Option<i32>,
+    }
+
+    impl IntoRubyObject for Task {
+        const CLASS_NAME: &'static str = "Task";
+        const NONE: OptionRepr = OptionRepr::Omit;
+
+        fn into_ruby_object(self, fields: &mut FieldsWriter<'_>) -> Result<(), ConvertError> {
+            fields.set("@title", self.title)?;
+            fields.set_enum("@priority", self.priority)?;
+            fields.set_option("@due", self.due)
+        }
+    }
+
+    impl FromRubyObject for Task {
+        const CLASS_NAME: Option<&'static str> = Some("Task");
+
+        fn from_ruby_object(fields: &mut Fields<'_, '_>) -> Result<Self, ConvertError> {
+            Ok(Self { title: fields.get("@title")?, priority: fields.get_enum("@priority")?, due: fields.get("@due")? })
+        }
+    }
+
+    struct Issue {
+        kind: Kind,
+        due: Option<i32>,
+    }
+
+    impl IntoRubyObject for Issue {
+        const CLASS_NAME: &'static str = "Issue";
+
+        fn into_ruby_object(self, fields: &mut FieldsWriter<'_>) -> Result<(), ConvertError> {
+            fields.set_enum("@kind", self.kind)?;
+            fields.set_option("@due", self.due)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum Size {
+        Small,
+        Large,
+    }
+
+    impl RubyEnum for Size {
+        const VARIANTS: &'static [(&'static str, Self)] = &[("Small", Size::Small)];
+    }
+
+    struct Shirt {
+        size: Size,
+    }
+
+    impl IntoRubyObject for Shirt {
+        const CLASS_NAME: &'static str = "Shirt";
+
+        fn into_ruby_object(self, fields: &mut FieldsWriter<'_>) -> Result<(), ConvertError> {
+            fields.set_enum("@size", self.size)
+        }
+    }
+
+    fn dump(root: &Root) -> Vec<u8> {
+        let mut output = Vec::new();
+        crate::encode::dump::Dumper::new(&mut output).dump(root, root.get_root()).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_casing() {
+        assert_eq!(Casing::SnakeCase.apply("HighPriority"), "high_priority");
+        assert_eq!(Casing::SnakeCase.apply("Low"), "low");
+        assert_eq!(Casing::CamelCase.apply("high_priority"), "HighPriority");
+        assert_eq!(Casing::CamelCase.apply("HighPriority"), "HighPriority");
+        assert_eq!(Casing::AsIs.apply("HighPriority"), "HighPriority");
+    }
+
+    #[test]
+    fn test_encode() {
+        let task = Task { title: "a".to_string(), priority: Priority::HighPriority, due: None };
+        let mut root = Root::nil();
+        let value = encode(&mut root, task).unwrap();
+        root.set_root(value);
+        assert_eq!(dump(&root), b"\x04\x08o:\x09Task\x07:\x0b@titleI\"\x06a\x06:\x06ET:\x0e@priority:\x12high_priority");
+        assert_eq!(
+            TypedDecoder::new(&root).decode::<Task>(root.get_root()),
+            Ok(Task { title: "a".to_string(), priority: Priority::HighPriority, due: None })
+        );
+
+        let mut root = Root::nil();
+        let value = encode(&mut root, Issue { kind: Kind::FeatureRequest, due: None }).unwrap();
+        root.set_root(value);
+        assert_eq!(dump(&root), b"\x04\x08o:\x0aIssue\x07:\x0a@kindI\"\x13FeatureRequest\x06:\x06ET:\x09@due0");
+    }
+
+    #[test]
+    fn test_decode_enum() {
+        // Task(@title="a", @priority="low", @due=3) and Task(@title="b", @priority=:medium)
+        let root = load(b"\x04\x08[\x07o:\x09Task\x08:\x0b@titleI\"\x06a\x06:\x06ET:\x0e@priorityI\"\x08low\x06;\x07T:\x09@duei\x08o;\x00\x07;\x06I\"\x06b\x06;\x07T;\x08:\x0bmedium");
+        let elements = root.get_object(0).unwrap().as_array();
+        let mut decoder = TypedDecoder::new(&root);
+        assert_eq!(decoder.decode::<Task>(&elements[0]), Ok(Task { title: "a".to_string(), priority: Priority::Low, due: Some(3) }));
+        assert_eq!(decoder.decode::<Task>(&elements[1]), Err(ConvertError::UnknownVariant("medium".to_string())));
+    }
+
+    #[test]
+    fn test_missing_variant() {
+        assert_eq!(Size::Small.ruby_name(), Some("small".to_string()));
+        assert_eq!(Size::Large.ruby_name(), None);
+        let mut root = Root::nil();
+        assert_eq!(encode(&mut root, Shirt { size: Size::Large }), Err(ConvertError::MissingVariant(std::any::type_name::<Size>())));
+    }
+}
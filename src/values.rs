@@ -1,25 +1,98 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
 use indexmap::IndexMap;
 use paste::paste;
-use std::{fmt::{Display, Write}, ops::{Index, IndexMut}};
-use encoding::{label::encoding_from_whatwg_label, DecoderTrap, Encoding};
-
-pub const MARSHAL_MAJOR_VERSION: u8 = 4;
-pub const MARSHAL_MINOR_VERSION: u8 = 8;
+use std::{cell::Cell, collections::{hash_map::DefaultHasher, BTreeMap, HashMap}, fmt::{Display, Write}, hash::{Hash, Hasher}, ops::{Index, IndexMut}};
+
+use crate::{
+    class_name::ClassName,
+    convert::{ConvertError, FromRubyValue, IntoRubyValue},
+    diagnostics::Diagnostics,
+    dynamic::{DynOptions, DynValue, KeyCollision},
+    text::{DefaultTextDecoder, TextDecoder},
+};
+#[cfg(feature = "decode")]
+use crate::{decode::load::{ErrorLocation, LoadError, Loader}, error_code::ErrorCode};
+#[cfg(feature = "encode")]
+use crate::encode::dump::{DumpError, Dumper};
+
+#[cfg(feature = "footprint")]
+mod footprint;
+mod fragment;
+mod grid;
+#[cfg(feature = "encode")]
+mod normalize;
+
+pub use grid::Grid;
+pub use crate::spec::{MARSHAL_MAJOR_VERSION, MARSHAL_MINOR_VERSION, MARSHAL_VERSION};
 
 pub type ObjectID = usize;
 pub type SymbolID = usize;
 
+/// Text of a symbol, cloning it only bumps a reference count. `Arc<str>` with the `sync` feature, which makes `Root`
+/// `Send` and `Sync`.
+#[cfg(not(feature = "sync"))]
+pub type SymbolStr = std::rc::Rc<str>;
+#[cfg(feature = "sync")]
+pub type SymbolStr = std::sync::Arc<str>;
+
 #[derive(Debug)]
 pub enum RubyError {
     EncodingError(String)
 }
 
+impl Display for RubyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RubyError::EncodingError(error) => f.write_str(&format!("Encoding Error: {}", error)),
+        }
+    }
+}
+
+/// Instance variables without `@` whose meaning is known: string encodings, Range endpoints and exception data
+pub const KNOWN_INTERNAL_INSTANCE_VARIABLES: &[&str] = &["E", "encoding", "excl", "begin", "end", "mesg", "bt", "cause"];
+
+/// Whether `name` is an instance variable the interpreter added for its own use (it has no `@`) that isn't one of
+/// `KNOWN_INTERNAL_INSTANCE_VARIABLES`, like `__id__` or hints written by newer Ruby versions
+pub fn is_special_instance_variable(name: &str) -> bool {
+    !name.starts_with('@') && !KNOWN_INTERNAL_INSTANCE_VARIABLES.contains(&name)
+}
+
 pub type RubyBignum = i64;
 
 pub type ValuePairs = IndexMap<RubyValue, RubyValue>;
 pub type ValuePairsSymbolKeys = IndexMap<SymbolID, RubyValue>;
 
+/// Type of a `RubyValue` without its payload, new variants may be added when support for more Marshal types lands
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum ValueKind {
+    Nil,
+    Boolean,
+    FixNum,
+    Symbol,
+    Array,
+    BigNum,
+    Class,
+    Module,
+    ClassOrModule,
+    Data,
+    Float,
+    Hash,
+    HashWithDefault,
+    Object,
+    RegExp,
+    String,
+    Struct,
+    UserClass,
+    UserDefined,
+    UserMarshal,
+    WithIvars,
+    Raw,
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[non_exhaustive]
 pub enum RubyValue {
     Nil,
     Boolean(bool),
@@ -30,7 +103,7 @@ pub enum RubyValue {
     Class(ObjectID),
     Module(ObjectID),
     ClassOrModule(ObjectID),
-    // Data(ObjectID),
+    Data(ObjectID),
     Float(ObjectID),
     Hash(ObjectID),
     HashWithDefault(ObjectID),
@@ -41,8 +114,129 @@ pub enum RubyValue {
     UserClass(ObjectID),
     UserDefined(ObjectID),
     UserMarshal(ObjectID),
+    /// the `I` wrapper adding instance variables to a value that can't hold them itself (arrays, hashes, symbols,
+    /// links, ...), with `LoaderOptions::ivar_wrappers` to every value. The wrapped value and its instance variables
+    /// are boxed together to keep `RubyValue` small, see `RubyValue::with_ivars()` and `RubyValue::get_wrapper_ivars()`
+    WithIvars(Box<(RubyValue, Vec<(SymbolID, RubyValue)>)>),
+    /// encoded Marshal data written as it is, see `RawFragment`
+    Raw(ObjectID),
 }
 
+impl RubyValue {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            RubyValue::Nil => ValueKind::Nil,
+            RubyValue::Boolean(_) => ValueKind::Boolean,
+            RubyValue::FixNum(_) => ValueKind::FixNum,
+            RubyValue::Symbol(_) => ValueKind::Symbol,
+            RubyValue::Array(_) => ValueKind::Array,
+            RubyValue::BigNum(_) => ValueKind::BigNum,
+            RubyValue::Class(_) => ValueKind::Class,
+            RubyValue::Module(_) => ValueKind::Module,
+            RubyValue::ClassOrModule(_) => ValueKind::ClassOrModule,
+            RubyValue::Data(_) => ValueKind::Data,
+            RubyValue::Float(_) => ValueKind::Float,
+            RubyValue::Hash(_) => ValueKind::Hash,
+            RubyValue::HashWithDefault(_) => ValueKind::HashWithDefault,
+            RubyValue::Object(_) => ValueKind::Object,
+            RubyValue::RegExp(_) => ValueKind::RegExp,
+            RubyValue::String(_) => ValueKind::String,
+            RubyValue::Struct(_) => ValueKind::Struct,
+            RubyValue::UserClass(_) => ValueKind::UserClass,
+            RubyValue::UserDefined(_) => ValueKind::UserDefined,
+            RubyValue::UserMarshal(_) => ValueKind::UserMarshal,
+            RubyValue::WithIvars(_) => ValueKind::WithIvars,
+            RubyValue::Raw(_) => ValueKind::Raw,
+        }
+    }
+
+    /// Returns the id of the referenced object, `None` for values stored inline (nil, booleans, fixnums and symbols),
+    /// wrappers return the id of the wrapped value
+    pub fn object_id(&self) -> Option<ObjectID> {
+        match self {
+            RubyValue::WithIvars(wrapper) => wrapper.0.object_id(),
+            RubyValue::Nil | RubyValue::Boolean(_) | RubyValue::FixNum(_) | RubyValue::Symbol(_) => None,
+            RubyValue::Array(object_id)
+            | RubyValue::BigNum(object_id)
+            | RubyValue::Class(object_id)
+            | RubyValue::Module(object_id)
+            | RubyValue::ClassOrModule(object_id)
+            | RubyValue::Data(object_id)
+            | RubyValue::Float(object_id)
+            | RubyValue::Hash(object_id)
+            | RubyValue::HashWithDefault(object_id)
+            | RubyValue::Object(object_id)
+            | RubyValue::RegExp(object_id)
+            | RubyValue::String(object_id)
+            | RubyValue::Struct(object_id)
+            | RubyValue::UserClass(object_id)
+            | RubyValue::UserDefined(object_id)
+            | RubyValue::UserMarshal(object_id)
+            | RubyValue::Raw(object_id) => Some(*object_id),
+        }
+    }
+
+    /// Returns a value of the same type referencing `object_id`, values stored inline are returned unchanged
+    pub fn with_object_id(&self, object_id: ObjectID) -> RubyValue {
+        match self {
+            RubyValue::Nil | RubyValue::Boolean(_) | RubyValue::FixNum(_) | RubyValue::Symbol(_) => self.clone(),
+            RubyValue::Array(_) => RubyValue::Array(object_id),
+            RubyValue::BigNum(_) => RubyValue::BigNum(object_id),
+            RubyValue::Class(_) => RubyValue::Class(object_id),
+            RubyValue::Module(_) => RubyValue::Module(object_id),
+            RubyValue::ClassOrModule(_) => RubyValue::ClassOrModule(object_id),
+            RubyValue::Data(_) => RubyValue::Data(object_id),
+            RubyValue::Float(_) => RubyValue::Float(object_id),
+            RubyValue::Hash(_) => RubyValue::Hash(object_id),
+            RubyValue::HashWithDefault(_) => RubyValue::HashWithDefault(object_id),
+            RubyValue::Object(_) => RubyValue::Object(object_id),
+            RubyValue::RegExp(_) => RubyValue::RegExp(object_id),
+            RubyValue::String(_) => RubyValue::String(object_id),
+            RubyValue::Struct(_) => RubyValue::Struct(object_id),
+            RubyValue::UserClass(_) => RubyValue::UserClass(object_id),
+            RubyValue::UserDefined(_) => RubyValue::UserDefined(object_id),
+            RubyValue::UserMarshal(_) => RubyValue::UserMarshal(object_id),
+            RubyValue::Raw(_) => RubyValue::Raw(object_id),
+            RubyValue::WithIvars(wrapper) => wrapper.0.with_object_id(object_id).with_ivars(wrapper.1.clone()),
+        }
+    }
+
+    /// Wraps the value with instance variables, see `RubyValue::WithIvars`
+    pub fn with_ivars(self, ivars: Vec<(SymbolID, RubyValue)>) -> RubyValue {
+        RubyValue::WithIvars(Box::new((self, ivars)))
+    }
+
+    /// The value inside any `WithIvars` wrappers
+    pub fn without_ivars(&self) -> &RubyValue {
+        match self {
+            RubyValue::WithIvars(wrapper) => wrapper.0.without_ivars(),
+            _ => self,
+        }
+    }
+
+    /// Instance variables of a `WithIvars` wrapper, empty for all other values
+    pub fn get_wrapper_ivars(&self) -> &[(SymbolID, RubyValue)] {
+        match self {
+            RubyValue::WithIvars(wrapper) => &wrapper.1,
+            _ => &[],
+        }
+    }
+
+    /// Returns the symbol id if the value is a symbol
+    pub fn symbol_id(&self) -> Option<SymbolID> {
+        match self {
+            RubyValue::Symbol(symbol_id) => Some(*symbol_id),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, RubyValue::Nil)
+    }
+}
+
+/// The `as_*` accessors panic if the value has another type, they are meant for values whose type is already known
+#[allow(clippy::panic)]
 impl RubyValue {
     pub fn as_boolean(&self) -> bool {
         match self {
@@ -169,9 +363,83 @@ impl RubyValue {
             _ => panic!("Not a user marshal"),
         }
     }
+
+    pub fn as_data(&self) -> ObjectID {
+        match self {
+            RubyValue::Data(val) => *val,
+            _ => panic!("Not a data"),
+        }
+    }
+}
+
+impl Display for ValueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Type of a `RubyObject` without its payload, new variants may be added when support for more Marshal types lands
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum ObjectKind {
+    Array,
+    Hash,
+    HashWithDefault,
+    Float,
+    Class,
+    Module,
+    ClassOrModule,
+    String,
+    BigNum,
+    RegExp,
+    Struct,
+    Object,
+    UserClass,
+    UserDefined,
+    UserMarshal,
+    Data,
+    Raw,
+}
+
+impl ObjectKind {
+    /// Creates a `RubyValue` of the matching type referencing the object with the given id
+    pub fn to_value(self, object_id: ObjectID) -> RubyValue {
+        match self {
+            ObjectKind::Array => RubyValue::Array(object_id),
+            ObjectKind::Hash => RubyValue::Hash(object_id),
+            ObjectKind::HashWithDefault => RubyValue::HashWithDefault(object_id),
+            ObjectKind::Float => RubyValue::Float(object_id),
+            ObjectKind::Class => RubyValue::Class(object_id),
+            ObjectKind::Module => RubyValue::Module(object_id),
+            ObjectKind::ClassOrModule => RubyValue::ClassOrModule(object_id),
+            ObjectKind::String => RubyValue::String(object_id),
+            ObjectKind::BigNum => RubyValue::BigNum(object_id),
+            ObjectKind::RegExp => RubyValue::RegExp(object_id),
+            ObjectKind::Struct => RubyValue::Struct(object_id),
+            ObjectKind::Object => RubyValue::Object(object_id),
+            ObjectKind::UserClass => RubyValue::UserClass(object_id),
+            ObjectKind::UserDefined => RubyValue::UserDefined(object_id),
+            ObjectKind::UserMarshal => RubyValue::UserMarshal(object_id),
+            ObjectKind::Data => RubyValue::Data(object_id),
+            ObjectKind::Raw => RubyValue::Raw(object_id),
+        }
+    }
+}
+
+impl From<ObjectKind> for ValueKind {
+    fn from(value: ObjectKind) -> Self {
+        value.to_value(0).kind()
+    }
+}
+
+impl Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
 }
 
 #[derive(PartialEq, Clone, Debug)]
+#[non_exhaustive]
 pub enum IncompleteObject {
     Array,
     Hash,
@@ -181,11 +449,16 @@ pub enum IncompleteObject {
     UserClass,
     UserDefined,
     UserMarshal,
+    Data,
 }
 
 #[derive(PartialEq, Clone, Debug)]
+#[non_exhaustive]
 pub enum RubyObject {
     Incomplete(IncompleteObject),
+    /// placeholder for an object of the document that wasn't loaded (see `Loader::load_object_at()`) or was taken out
+    /// with `Root::take_object()`
+    Unloaded(ObjectKind),
     Array(Vec<RubyValue>),
     Hash(ValuePairs),
     HashWithDefault(HashWithDefault),
@@ -201,6 +474,8 @@ pub enum RubyObject {
     UserClass(UserClass),
     UserDefined(UserDefined),
     UserMarshal(UserMarshal),
+    Data(Data),
+    Raw(RawFragment),
 }
 
 macro_rules! implement_helpers {
@@ -209,14 +484,21 @@ macro_rules! implement_helpers {
             pub fn [<as_$func_name>](&self) -> &$ret_type {
                 match self {
                     RubyObject::$variant(object) => object,
-                    _ => panic!("Not a {}", stringify!($variant)),
+                    _ => panic!("Not a {}, got {}", stringify!($variant), self.kind()),
                 }
             }
 
             pub fn [<as_mut_$func_name>](&mut self) -> &mut $ret_type {
                 match self {
                     RubyObject::$variant(object) => object,
-                    _ => panic!("Not a {}", stringify!($variant)),
+                    _ => panic!("Not a {}, got {}", stringify!($variant), self.kind()),
+                }
+            }
+
+            pub fn [<try_as_$func_name>](&self) -> Option<&$ret_type> {
+                match self {
+                    RubyObject::$variant(object) => Some(object),
+                    _ => None,
                 }
             }
         }
@@ -224,6 +506,161 @@ macro_rules! implement_helpers {
 }
 
 impl RubyObject {
+    /// Returns the type of the object, for incomplete objects (only seen while loading) this is the type the object will have once complete
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            RubyObject::Incomplete(incomplete_object) => match incomplete_object {
+                IncompleteObject::Array => ObjectKind::Array,
+                IncompleteObject::Hash => ObjectKind::Hash,
+                IncompleteObject::HashWithDefault => ObjectKind::HashWithDefault,
+                IncompleteObject::Struct => ObjectKind::Struct,
+                IncompleteObject::Object => ObjectKind::Object,
+                IncompleteObject::UserClass => ObjectKind::UserClass,
+                IncompleteObject::UserDefined => ObjectKind::UserDefined,
+                IncompleteObject::UserMarshal => ObjectKind::UserMarshal,
+                IncompleteObject::Data => ObjectKind::Data,
+            },
+            RubyObject::Unloaded(kind) => *kind,
+            RubyObject::Array(_) => ObjectKind::Array,
+            RubyObject::Hash(_) => ObjectKind::Hash,
+            RubyObject::HashWithDefault(_) => ObjectKind::HashWithDefault,
+            RubyObject::Float(_) => ObjectKind::Float,
+            RubyObject::Class(_) => ObjectKind::Class,
+            RubyObject::Module(_) => ObjectKind::Module,
+            RubyObject::ClassOrModule(_) => ObjectKind::ClassOrModule,
+            RubyObject::String(_) => ObjectKind::String,
+            RubyObject::BigNum(_) => ObjectKind::BigNum,
+            RubyObject::RegExp(_) => ObjectKind::RegExp,
+            RubyObject::Struct(_) => ObjectKind::Struct,
+            RubyObject::Object(_) => ObjectKind::Object,
+            RubyObject::UserClass(_) => ObjectKind::UserClass,
+            RubyObject::UserDefined(_) => ObjectKind::UserDefined,
+            RubyObject::UserMarshal(_) => ObjectKind::UserMarshal,
+            RubyObject::Data(_) => ObjectKind::Data,
+            RubyObject::Raw(_) => ObjectKind::Raw,
+        }
+    }
+
+    /// Returns the instance variables of the object, `None` for object types that can't have any or don't have any
+    pub fn instance_variables(&self) -> Option<&ValuePairsSymbolKeys> {
+        match self {
+            RubyObject::Object(object) => Some(&object.instance_variables),
+            RubyObject::String(string) => string.instance_variables.as_ref(),
+            RubyObject::RegExp(regexp) => regexp.instance_variables.as_ref(),
+            RubyObject::Struct(ruby_struct) => ruby_struct.instance_variables.as_ref(),
+            RubyObject::UserClass(user_class) => user_class.instance_variables.as_ref(),
+            RubyObject::UserDefined(user_defined) => user_defined.instance_variables.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the symbol naming the object's class, for object types that carry one
+    pub fn class_symbol(&self) -> Option<SymbolID> {
+        match self {
+            RubyObject::Struct(ruby_struct) => Some(ruby_struct.name),
+            RubyObject::Object(object) => Some(object.class_name),
+            RubyObject::UserClass(user_class) => Some(user_class.name),
+            RubyObject::UserDefined(user_defined) => Some(user_defined.class_name),
+            RubyObject::UserMarshal(user_marshal) => Some(user_marshal.class_name),
+            RubyObject::Data(data) => Some(data.class_name),
+            _ => None,
+        }
+    }
+
+    /// Returns all values directly referenced by the object (elements, keys, values, default values, wrapped objects and instance variable values),
+    /// `WithIvars` wrappers are replaced by the values inside them
+    pub fn referenced_values(&self) -> Vec<&RubyValue> {
+        fn instance_variable_values(instance_variables: &Option<ValuePairsSymbolKeys>) -> Vec<&RubyValue> {
+            instance_variables.iter().flat_map(|instance_variables| instance_variables.values()).collect()
+        }
+
+        fn push_unwrapped<'v>(values: &mut Vec<&'v RubyValue>, value: &'v RubyValue) {
+            match value {
+                RubyValue::WithIvars(wrapper) => {
+                    let (inner, ivars) = &**wrapper;
+                    push_unwrapped(values, inner);
+                    for (_, ivar) in ivars {
+                        push_unwrapped(values, ivar);
+                    }
+                }
+                _ => values.push(value),
+            }
+        }
+
+        let values = match self {
+            RubyObject::Incomplete(_) | RubyObject::Unloaded(_) | RubyObject::Float(_) | RubyObject::Class(_) | RubyObject::Module(_)
+            | RubyObject::ClassOrModule(_) | RubyObject::BigNum(_) | RubyObject::Raw(_) => Vec::new(),
+            RubyObject::Array(array) => array.iter().collect(),
+            RubyObject::Hash(hash) => hash.iter().flat_map(|(key, value)| [key, value]).collect(),
+            RubyObject::HashWithDefault(hash) => hash.hash.iter().flat_map(|(key, value)| [key, value]).chain([&hash.default]).collect(),
+            RubyObject::String(string) => instance_variable_values(&string.instance_variables),
+            RubyObject::RegExp(regexp) => instance_variable_values(&regexp.instance_variables),
+            RubyObject::Struct(ruby_struct) => {
+                let mut values: Vec<&RubyValue> = ruby_struct.members.values().collect();
+                values.extend(instance_variable_values(&ruby_struct.instance_variables));
+                values
+            },
+            RubyObject::Object(object) => object.instance_variables.values().collect(),
+            RubyObject::UserClass(user_class) => {
+                let mut values = vec![&user_class.wrapped_object];
+                values.extend(instance_variable_values(&user_class.instance_variables));
+                values
+            },
+            RubyObject::UserDefined(user_defined) => instance_variable_values(&user_defined.instance_variables),
+            RubyObject::UserMarshal(user_marshal) => vec![&user_marshal.wrapped_object],
+            RubyObject::Data(data) => vec![&data.state],
+        };
+        if !values.iter().any(|value| matches!(value, RubyValue::WithIvars(_))) {
+            return values;
+        }
+        let mut unwrapped = Vec::with_capacity(values.len());
+        for value in values {
+            push_unwrapped(&mut unwrapped, value);
+        }
+        unwrapped
+    }
+
+    /// Returns all symbols the object uses as names (its class, extended modules, struct members and instance variables,
+    /// including those of `WithIvars` wrappers around the values it references), symbol values are not included
+    pub fn referenced_symbols(&self) -> Vec<SymbolID> {
+        fn push_wrapper_names(symbols: &mut Vec<SymbolID>, value: &RubyValue) {
+            if let RubyValue::WithIvars(wrapper) = value {
+                let (inner, ivars) = &**wrapper;
+                for (name, ivar) in ivars {
+                    symbols.push(*name);
+                    push_wrapper_names(symbols, ivar);
+                }
+                push_wrapper_names(symbols, inner);
+            }
+        }
+
+        let mut symbols: Vec<SymbolID> = self.class_symbol().into_iter().collect();
+        match self {
+            RubyObject::Object(object) => symbols.extend(&object.extended_modules),
+            RubyObject::UserDefined(user_defined) => symbols.extend(&user_defined.extended_modules),
+            RubyObject::Struct(ruby_struct) => symbols.extend(ruby_struct.members.keys()),
+            _ => {}
+        }
+        if let Some(instance_variables) = self.instance_variables() {
+            symbols.extend(instance_variables.keys());
+        }
+        let values: Vec<&RubyValue> = match self {
+            RubyObject::Array(array) => array.iter().collect(),
+            RubyObject::Hash(hash) => hash.iter().flat_map(|(key, value)| [key, value]).collect(),
+            RubyObject::HashWithDefault(hash) => hash.hash.iter().flat_map(|(key, value)| [key, value]).chain([&hash.default]).collect(),
+            RubyObject::Struct(ruby_struct) => ruby_struct.members.values().collect(),
+            RubyObject::UserClass(user_class) => vec![&user_class.wrapped_object],
+            RubyObject::UserMarshal(user_marshal) => vec![&user_marshal.wrapped_object],
+            RubyObject::Data(data) => vec![&data.state],
+            _ => Vec::new(),
+        };
+        let instance_variable_values = self.instance_variables().into_iter().flat_map(|instance_variables| instance_variables.values());
+        for value in values.into_iter().chain(instance_variable_values) {
+            push_wrapper_names(&mut symbols, value);
+        }
+        symbols
+    }
+
     implement_helpers!(array, Array, Vec<RubyValue>);
     implement_helpers!(hash, Hash, ValuePairs);
     implement_helpers!(hash_with_default, HashWithDefault, HashWithDefault);
@@ -239,6 +676,8 @@ impl RubyObject {
     implement_helpers!(user_class, UserClass, UserClass);
     implement_helpers!(user_defined, UserDefined, UserDefined);
     implement_helpers!(user_marshal, UserMarshal, UserMarshal);
+    implement_helpers!(data, Data, Data);
+    implement_helpers!(raw, Raw, RawFragment);
 }
 
 impl Display for RubyValue {
@@ -247,28 +686,74 @@ impl Display for RubyValue {
             RubyValue::Nil => f.write_str("nil"),
             RubyValue::Boolean(boolean) => f.write_str(&format!("{}", boolean)),
             RubyValue::FixNum(num) => f.write_str(&format!("{}", num)),
-            _ => panic!("Display not implemented for this RubyValue type, use Display on corresponding RubyObject"),
+            // values referencing the tables can only be described by Root::print
+            _ => f.write_str(&format!("{:?}", self)),
         }
     }
 }
 
+/// Bytes of a symbol that aren't plain ASCII: Ruby wraps the symbol's definition in `I` and writes its encoding like a
+/// string's (`E` or `encoding`), dumps of Ruby 1.8 have no encoding at all. The symbol's text is the bytes decoded
+/// with that encoding, or the bytes with invalid sequences replaced if they can't be decoded. Tables rebuilt by
+/// `append()` and `normalize()` only keep the text.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodedSymbol {
+    bytes: Vec<u8>,
+    instance_variables: ValuePairsSymbolKeys,
+}
+
+impl EncodedSymbol {
+    pub fn new(bytes: Vec<u8>, instance_variables: ValuePairsSymbolKeys) -> Self {
+        Self { bytes, instance_variables }
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The encoding instance variables, empty if the symbol was written without them
+    pub fn get_instance_variables(&self) -> &ValuePairsSymbolKeys {
+        &self.instance_variables
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Root {
-    symbols: Vec<String>,
+    symbols: Vec<SymbolStr>,
+    /// bytes and encoding of the symbols that aren't plain UTF-8 text
+    encoded_symbols: BTreeMap<SymbolID, EncodedSymbol>,
     objects: Vec<RubyObject>,
     root: RubyValue,
+    diagnostics: Diagnostics,
+    /// order `SymbolOrder::Recorded` writes instance variables or struct members in, by object
+    member_orders: HashMap<ObjectID, Vec<SymbolID>>,
+    /// pairs replaced by a later pair with the same key, by hash, see `DuplicateKeys::CollectAll`
+    duplicate_hash_pairs: HashMap<ObjectID, Vec<(RubyValue, RubyValue)>>,
 }
 
 impl Root {
-    pub fn new(root: RubyValue, symbols: Vec<String>, objects: Vec<RubyObject>) -> Self {
-        Self {root, symbols, objects}
+    pub fn new(root: RubyValue, symbols: Vec<SymbolStr>, objects: Vec<RubyObject>) -> Self {
+        Self {root, symbols, encoded_symbols: BTreeMap::new(), objects, diagnostics: Diagnostics::new(), member_orders: HashMap::new(), duplicate_hash_pairs: HashMap::new()}
+    }
+
+    /// Minimal document holding just `nil`, dumps to `\x04\x080`
+    pub fn nil() -> Self {
+        Self::new(RubyValue::Nil, Vec::new(), Vec::new())
+    }
+
+    /// Document holding just `value` and the symbols and objects it needs
+    pub fn of_value(value: impl IntoRubyValue) -> Result<Self, ConvertError> {
+        let mut root = Self::nil();
+        let value = value.into_ruby_value(&mut root)?;
+        root.set_root(value);
+        Ok(root)
     }
 
     pub fn get_root(&self) -> &RubyValue {
         &self.root
     }
 
-    pub fn get_symbols(&self) -> &Vec<String> {
+    pub fn get_symbols(&self) -> &Vec<SymbolStr> {
         &self.symbols
     }
 
@@ -276,13 +761,65 @@ impl Root {
         &self.objects
     }
 
-    pub fn get_symbol(&self, id: SymbolID) -> Option<&String> {
+    /// Text of symbol `id`, clone it to keep it beyond the Root
+    pub fn get_symbol(&self, id: SymbolID) -> Option<&SymbolStr> {
         self.symbols.get(id)
     }
 
+    /// Oddities the loader noticed in the document, empty for documents that weren't loaded. Errors a lenient load
+    /// recovered from aren't among them, see `Loader::recovered_errors()`.
+    pub fn get_diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn set_diagnostics(&mut self, diagnostics: Diagnostics) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Pairs of hash `id` that a later pair with the same key replaced, in stream order, only collected with
+    /// `DuplicateKeys::CollectAll`
+    pub fn get_duplicate_hash_pairs(&self, id: ObjectID) -> Option<&[(RubyValue, RubyValue)]> {
+        self.duplicate_hash_pairs.get(&id).map(Vec::as_slice)
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn set_duplicate_hash_pairs(&mut self, duplicate_hash_pairs: HashMap<ObjectID, Vec<(RubyValue, RubyValue)>>) {
+        self.duplicate_hash_pairs = duplicate_hash_pairs;
+    }
+
+    /// Names of the instance variables or members of object `id` in the order `SymbolOrder::Recorded` dumps them, see
+    /// `LoaderOptions::record_member_order`
+    pub fn get_member_order(&self, id: ObjectID) -> Option<&[SymbolID]> {
+        self.member_orders.get(&id).map(Vec::as_slice)
+    }
+
+    /// Sets the order `SymbolOrder::Recorded` dumps the instance variables or members of object `id` in, like the
+    /// order a specific Ruby version writes them in
+    pub fn set_member_order(&mut self, id: ObjectID, member_order: Vec<SymbolID>) {
+        self.member_orders.insert(id, member_order);
+    }
+
+    /// Bytes of symbol `id` as written in the stream
+    pub fn get_symbol_bytes(&self, id: SymbolID) -> Option<&[u8]> {
+        match self.encoded_symbols.get(&id) {
+            Some(encoded_symbol) => Some(encoded_symbol.get_bytes()),
+            None => self.symbols.get(id).map(|symbol| symbol.as_bytes()),
+        }
+    }
+
+    pub fn get_encoded_symbol(&self, id: SymbolID) -> Option<&EncodedSymbol> {
+        self.encoded_symbols.get(&id)
+    }
+
+    /// Sets the bytes and encoding symbol `id` is written with, its text stays unchanged
+    pub fn set_encoded_symbol(&mut self, id: SymbolID, encoded_symbol: EncodedSymbol) {
+        self.encoded_symbols.insert(id, encoded_symbol);
+    }
+
     pub fn get_symbol_id(&self, symbol: &str) -> Option<SymbolID> {
         for (i, s) in self.symbols.iter().enumerate() {
-            if *s == symbol {
+            if **s == *symbol {
                 return Some(i);
             }
         }
@@ -293,41 +830,431 @@ impl Root {
         self.objects.get(id)
     }
 
+    /// Returns the class of object `id`, for object types that carry one (see `RubyObject::class_symbol()`)
+    pub fn get_class_name(&self, id: ObjectID) -> Option<ClassName> {
+        let class_symbol = self.objects.get(id)?.class_symbol()?;
+        self.symbols.get(class_symbol).map(|name| ClassName::parse(name))
+    }
+
+    /// Returns the instance variables of object `id` that are special (see `is_special_instance_variable()`), these
+    /// aren't data of the object but are kept in the graph and written back by default (see `DumperOptions`)
+    pub fn get_special_instance_variables(&self, id: ObjectID) -> Vec<(&str, &RubyValue)> {
+        let Some(instance_variables) = self.objects.get(id).and_then(RubyObject::instance_variables) else {
+            return Vec::new();
+        };
+        self.named_ivars(instance_variables.iter())
+            .filter(|(name, _)| is_special_instance_variable(name))
+            .collect()
+    }
+
+    /// Returns the instance variables of `value` with their names in the order they were written, empty for values
+    /// without any. The instance variables of a `WithIvars` wrapper come before those of the object inside it.
+    pub fn ivars_of<'r>(&'r self, value: &'r RubyValue) -> Vec<(&'r str, &'r RubyValue)> {
+        let wrapper_ivars = value.get_wrapper_ivars().iter().map(|(name, value)| (name, value));
+        let object_ivars = value.without_ivars().object_id()
+            .and_then(|id| self.objects.get(id))
+            .and_then(RubyObject::instance_variables)
+            .into_iter()
+            .flatten();
+        self.named_ivars(wrapper_ivars.chain(object_ivars)).collect()
+    }
+
+    /// Follows `path` from `value` like Ruby's `dig`: instance variables of objects (`"@config"`), members of structs,
+    /// values of hashes with a symbol or string key of that name and elements of arrays by index (`"0"`, or `"-1"` for
+    /// the last one). Returns None if a step reaches nil or the key, member or index doesn't exist. Missing keys of
+    /// hashes with a default value lead to the default value. `""` finds the empty symbol or the empty string key,
+    /// whichever comes first.
+    pub fn dig<'r>(&'r self, value: &'r RubyValue, path: &[&str]) -> Option<&'r RubyValue> {
+        let mut value = value;
+        for key in path {
+            value = self.dig_step(value, key)?;
+        }
+        Some(value)
+    }
+
+    fn dig_step<'r>(&'r self, value: &'r RubyValue, key: &str) -> Option<&'r RubyValue> {
+        let named = |symbol_id: &SymbolID| self.symbols.get(*symbol_id).is_some_and(|name| **name == *key);
+        let find_pair = |pairs: &'r ValuePairsSymbolKeys| pairs.iter().find(|(name, _)| named(name)).map(|(_, value)| value);
+        let find_key = |hash: &'r ValuePairs| hash.iter().find(|(hash_key, _)| self.is_dig_key(hash_key, key)).map(|(_, value)| value);
+        match self.objects.get(value.without_ivars().object_id()?)? {
+            RubyObject::Object(object) => find_pair(object.get_instance_variables()),
+            RubyObject::Struct(ruby_struct) => find_pair(ruby_struct.get_members()),
+            RubyObject::Hash(hash) => find_key(hash),
+            RubyObject::HashWithDefault(hash) => find_key(hash.hash()).or(Some(hash.default())).filter(|value| !value.is_nil()),
+            RubyObject::Array(array) => {
+                let index: isize = key.parse().ok()?;
+                let index = if index < 0 { array.len().checked_sub(index.unsigned_abs())? } else { index as usize };
+                array.get(index)
+            }
+            // subclasses of Array and Hash
+            RubyObject::UserClass(user_class) => self.dig_step(user_class.get_wrapped_object(), key),
+            _ => None,
+        }
+    }
+
+    /// Whether hash key `hash_key` is a symbol or a string named `key`
+    fn is_dig_key(&self, hash_key: &RubyValue, key: &str) -> bool {
+        match hash_key.without_ivars() {
+            RubyValue::Symbol(symbol_id) => self.symbols.get(*symbol_id).is_some_and(|name| **name == *key),
+            RubyValue::String(object_id) => self.objects.get(*object_id)
+                .and_then(|object| object.try_as_string())
+                .is_some_and(|string| string.get_string() == key.as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Resolves the names of instance variables, ones with names missing from the symbol table are left out
+    fn named_ivars<'r>(&'r self, ivars: impl Iterator<Item = (&'r SymbolID, &'r RubyValue)>) -> impl Iterator<Item = (&'r str, &'r RubyValue)> {
+        ivars.filter_map(|(name, value)| self.symbols.get(*name).map(|name| (&**name, value)))
+    }
+
+    /// Returns the ids of all objects whose class matches the glob `pattern`, see `ClassName::matches()`
+    pub fn find_objects(&self, pattern: &str) -> Vec<ObjectID> {
+        (0..self.objects.len())
+            .filter(|id| self.get_class_name(*id).is_some_and(|class_name| class_name.matches(pattern)))
+            .collect()
+    }
+
+    /// Counts how often every class or module name is referenced by the objects of the document: the classes of
+    /// objects, structs, user classes, user defined, user marshal and data objects, the modules they were extended
+    /// with and class and module references. These are the constants `Marshal.load` would look up.
+    pub fn classes_used(&self) -> BTreeMap<String, usize> {
+        let mut classes: BTreeMap<String, usize> = BTreeMap::new();
+        let mut count = |name: &str| *classes.entry(name.to_string()).or_default() += 1;
+        for object in &self.objects {
+            let extended_modules: &[SymbolID] = match object {
+                RubyObject::Class(name) | RubyObject::Module(name) | RubyObject::ClassOrModule(name) => {
+                    count(name);
+                    continue;
+                }
+                RubyObject::Object(object) => &object.extended_modules,
+                RubyObject::UserDefined(user_defined) => &user_defined.extended_modules,
+                _ => &[],
+            };
+            let symbols = object.class_symbol().into_iter().chain(extended_modules.iter().copied());
+            symbols.filter_map(|symbol_id| self.symbols.get(symbol_id)).for_each(|name| count(name));
+        }
+        classes
+    }
+
     pub fn get_mut_object(&mut self, id: ObjectID) -> Option<&mut RubyObject> {
         self.objects.get_mut(id)
     }
 
+    /// Moves object `id` out of the Root without cloning it, its slot holds `RubyObject::Unloaded` of the same kind
+    /// until the object is handed back with `restore()`. Values referencing it stay in place, but dumping or reading
+    /// them fails in the meantime.
+    pub fn take_object(&mut self, id: ObjectID) -> Option<(RubyObject, Placeholder)> {
+        let slot = self.objects.get_mut(id)?;
+        let kind = slot.kind();
+        let object = std::mem::replace(slot, RubyObject::Unloaded(kind));
+        Some((object, Placeholder { object_id: id, kind }))
+    }
+
+    /// Puts an object back into the slot emptied by `take_object()`, it may have been changed but needs the same kind
+    /// so the values referencing it stay valid. Otherwise both are returned and the slot stays empty.
+    pub fn restore(&mut self, placeholder: Placeholder, object: RubyObject) -> Result<(), Box<(Placeholder, RubyObject)>> {
+        if object.kind() != placeholder.kind {
+            return Err(Box::new((placeholder, object)));
+        }
+        match self.objects.get_mut(placeholder.object_id) {
+            Some(slot) if *slot == RubyObject::Unloaded(placeholder.kind) => *slot = object,
+            _ => return Err(Box::new((placeholder, object))),
+        }
+        Ok(())
+    }
+
+    /// Returns the object without checking that `id` is in bounds, for hot paths working on a graph that was validated before
+    ///
+    /// # Safety
+    ///
+    /// `id` must be smaller than the number of objects, calling this with an out of bounds id is undefined behavior
+    pub unsafe fn get_object_unchecked(&self, id: ObjectID) -> &RubyObject {
+        self.objects.get_unchecked(id)
+    }
+
+    /// Looks up object `id` and checks its type with `accessor` (one of the `RubyObject::try_as_*` methods)
+    fn get_typed_object<'a, O>(&'a self, id: ObjectID, accessor: fn(&'a RubyObject) -> Option<&'a O>) -> Option<&'a O> {
+        self.objects.get(id).and_then(accessor)
+    }
+
+    pub fn set_root(&mut self, root: RubyValue) {
+        self.root = root;
+    }
+
+    /// Returns the symbol `name`, it is appended to the symbol table unless it's already there
+    pub fn add_symbol(&mut self, name: &str) -> RubyValue {
+        RubyValue::Symbol(self.intern_symbol(name))
+    }
+
+    fn intern_symbol(&mut self, name: &str) -> SymbolID {
+        match self.get_symbol_id(name) {
+            Some(symbol_id) => symbol_id,
+            None => {
+                self.symbols.push(name.into());
+                self.symbols.len() - 1
+            }
+        }
+    }
+
+    /// Parses a single value without version header (see `Loader::load_fragment()`) and appends its objects to this
+    /// Root, symbols are interned and links inside the fragment are remapped, the Root is unchanged if parsing fails
+    #[cfg(feature = "decode")]
+    pub fn insert_fragment(&mut self, bytes: &[u8]) -> Result<RubyValue, LoadError> {
+        let mut reader = bytes;
+        let fragment = Loader::new(&mut reader).load_fragment()?;
+        if !reader.is_empty() {
+            return Err(LoadError::ParserError(
+                ErrorCode::TrailingBytes,
+                format!("Fragment has {} trailing bytes", reader.len()),
+                ErrorLocation { offset: Some((bytes.len() - reader.len()) as u64), object_offset: None },
+            ));
+        }
+        Ok(self.append(fragment))
+    }
+
+    /// Adds an encoded value (without version header) that is dumped verbatim instead of being parsed into this Root,
+    /// see `RawFragment`. The bytes are checked to hold exactly one well-formed value without symbol or object links.
+    #[cfg(feature = "decode")]
+    pub fn add_raw(&mut self, bytes: &[u8]) -> Result<RubyValue, LoadError> {
+        let mut reader = bytes;
+        let (symbol_count, object_count) = Loader::new(&mut reader).scan_raw()?;
+        self.objects.push(RubyObject::Raw(RawFragment { bytes: bytes.to_vec(), symbol_count, object_count }));
+        Ok(RubyValue::Raw(self.objects.len() - 1))
+    }
+
+    /// Moves the objects of `other` behind the objects of this Root and returns its root value remapped to them,
+    /// symbols are interned
+    pub fn append(&mut self, other: Root) -> RubyValue {
+        fragment::FragmentImporter::import(self, other)
+    }
+
+    /// Encodes `value` and everything it references on its own, links are numbered from the start of the fragment
+    /// so it can be stored separately and read back with `insert_fragment()` (or `Loader::load()` with the header)
+    #[cfg(feature = "encode")]
+    pub fn export_fragment(&self, value: &RubyValue, with_header: bool) -> Result<Vec<u8>, DumpError> {
+        let mut output = Vec::new();
+        let mut dumper = Dumper::new(&mut output);
+        if with_header {
+            dumper.dump(self, value)?;
+        } else {
+            dumper.dump_fragment(self, value)?;
+        }
+        Ok(output)
+    }
+
+    /// Appends `object` to the object table and returns a value referencing it
+    pub fn add_ruby_object(&mut self, object: RubyObject) -> RubyValue {
+        let value = object.kind().to_value(self.objects.len());
+        self.objects.push(object);
+        value
+    }
+
+    /// Appends a UTF-8 string (a string with `E` set to `true`, like Ruby's string literals)
+    pub fn add_string(&mut self, string: &str) -> RubyValue {
+        let encoding = self.add_symbol("E").as_symbol();
+        let mut ruby_string = RubyString::new(string.as_bytes().to_vec());
+        ruby_string.set_instance_variables(ValuePairsSymbolKeys::from([(encoding, RubyValue::Boolean(true))]));
+        self.add_ruby_object(RubyObject::String(ruby_string))
+    }
+
+    /// Appends an array holding `elements`
+    pub fn add_array(&mut self, elements: Vec<RubyValue>) -> RubyValue {
+        self.add_ruby_object(RubyObject::Array(elements))
+    }
+
+    /// Appends an object of class `class_name` with the given instance variables (names include the `@`)
+    pub fn add_object<'a>(&mut self, class_name: &str, instance_variables: impl IntoIterator<Item = (&'a str, RubyValue)>) -> RubyValue {
+        let class_name = self.add_symbol(class_name).as_symbol();
+        let instance_variables = instance_variables.into_iter()
+            .map(|(name, value)| (self.add_symbol(name).as_symbol(), value))
+            .collect();
+        self.add_ruby_object(RubyObject::Object(Object::new(class_name, instance_variables)))
+    }
+
+    /// Converts a hash (or hash with default) into a `HashMap`, errors carry the path of keys leading to the pair that
+    /// couldn't be converted (see `ConvertError::AtKey`)
+    pub fn hash_to_map<K: FromRubyValue + Eq + Hash, V: FromRubyValue>(&self, hash: &RubyValue) -> Result<HashMap<K, V>, ConvertError> {
+        HashMap::from_ruby_value(self, hash)
+    }
+
+    /// Converts the root value into a flat map from paths like `"@party[0].@name"` to leaf values, for diff tools,
+    /// spreadsheets and assertions in tests, see `dynamic::flatten()`. Symbol keys of hashes use their plain name unless
+    /// the hash also has the string key (`KeyCollision::Disambiguate`), cyclic graphs fail like in `dynamic::to_dyn()`.
+    pub fn flatten(&self) -> Result<HashMap<String, DynValue>, ConvertError> {
+        let options = DynOptions { key_collision: KeyCollision::Disambiguate };
+        Ok(crate::dynamic::flatten(crate::dynamic::to_dyn(self, &self.root, &options)?))
+    }
+
+    /// Extracts the instance variable `instance_variable` (e.g. `"@hp"`) from every element of `array` in a single pass,
+    /// elements that aren't objects, don't have the instance variable or have it set to nil yield `None`
+    pub fn column<T: FromRubyValue>(&self, array: &RubyValue, instance_variable: &str) -> Result<Vec<Option<T>>, ConvertError> {
+        let RubyValue::Array(array_id) = array else {
+            return Err(ConvertError::TypeMismatch { expected: "array", found: array.kind() });
+        };
+        let Some(RubyObject::Array(elements)) = self.objects.get(*array_id) else {
+            return Err(ConvertError::InvalidReference(array.kind(), *array_id));
+        };
+        let Some(symbol_id) = self.get_symbol_id(instance_variable) else {
+            return Ok(elements.iter().map(|_| None).collect());
+        };
+
+        elements.iter().map(|element| {
+            let value = match element.object_id().and_then(|object_id| self.objects.get(object_id)) {
+                Some(RubyObject::Object(object)) => object.get_instance_variable(symbol_id),
+                _ => None,
+            };
+            match value {
+                Some(value) => Option::<T>::from_ruby_value(self, value),
+                None => Ok(None),
+            }
+        }).collect()
+    }
+
+    /// Writes a listing of the symbol table and a summary line for every object (id, kind, class, size and
+    /// number of references to it from other objects and the root), meant for debugging link related problems
+    pub fn dump_tables(&self, f: &mut impl Write) -> Result<(), std::fmt::Error> {
+        let mut reference_counts = vec![0_usize; self.objects.len()];
+        let references = self.objects.iter().flat_map(|object| object.referenced_values()).chain([&self.root]);
+        for object_id in references.filter_map(|value| value.object_id()) {
+            if let Some(count) = reference_counts.get_mut(object_id) {
+                *count += 1;
+            }
+        }
+
+        f.write_str(&format!("symbols ({}):\n", self.symbols.len()))?;
+        for (symbol_id, symbol) in self.symbols.iter().enumerate() {
+            f.write_str(&format!("  {:>6}  :{:?}\n", symbol_id, symbol))?;
+        }
+
+        f.write_str(&format!("objects ({}):\n", self.objects.len()))?;
+        f.write_str(&format!("  {:>6}  {:<16} {:<32} {:>8} {:>6}\n", "id", "kind", "class", "size", "refs"))?;
+        for (object_id, object) in self.objects.iter().enumerate() {
+            let class = match object {
+                RubyObject::Class(name) | RubyObject::Module(name) | RubyObject::ClassOrModule(name) => name.as_str(),
+                _ => object.class_symbol().and_then(|symbol_id| self.symbols.get(symbol_id)).map_or("-", |name| &**name),
+            };
+            let size = match object {
+                RubyObject::Array(array) => array.len(),
+                RubyObject::Hash(hash) => hash.len(),
+                RubyObject::HashWithDefault(hash) => hash.len(),
+                RubyObject::String(string) => string.string.len(),
+                RubyObject::RegExp(regexp) => regexp.pattern.len(),
+                RubyObject::Struct(ruby_struct) => ruby_struct.members.len(),
+                RubyObject::Object(object) => object.instance_variables.len(),
+                RubyObject::UserDefined(user_defined) => user_defined.data.len(),
+                _ => 0,
+            };
+            f.write_str(&format!("  {:>6}  {:<16} {:<32} {:>8} {:>6}\n", object_id, object.kind().to_string(), class, size, reference_counts[object_id]))?;
+        }
+        f.write_str(&format!("root: {:?}\n", self.root))
+    }
+
+    /// Returns a hash of the symbol table, the number of objects and the kind of the root value. Documents with the
+    /// same symbols and as many objects share it whatever the fixnums, floats and string contents in them, so caches
+    /// can cheaply tell documents of the same shape apart from others without normalizing them. Anything that changes
+    /// the number of objects changes it too, like an array with another number of strings in it or a `nil` in place of
+    /// a string. Equal fingerprints don't guarantee an equal structure, and the value is only stable within one build
+    /// of the crate.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.symbols.hash(&mut hasher);
+        self.objects.len().hash(&mut hasher);
+        self.root.kind().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Guesses which Ruby version wrote the document from the constructs in it, for triaging files of unknown origin:
+    /// encodings on strings and symbols mean Ruby 1.9 or later, strings without any and floats with extra digits mean
+    /// Ruby 1.8. Binary strings of newer versions look like Ruby 1.8 strings, so a document with nothing but those is
+    /// taken for Ruby 1.8. Floats are only checked when loading, a Root that wasn't loaded has no legacy floats.
+    pub fn guess_emitter(&self) -> crate::analysis::EmitterGuess {
+        crate::analysis::guess_emitter(self)
+    }
+
+    /// Returns a canonical copy of the document, so semantically identical documents compare, hash and diff equal:
+    /// objects and symbols are renumbered in traversal order from the root (unreachable ones are dropped), hash pairs
+    /// are sorted by the Marshal encoding of their keys, instance variables by name and equal strings are merged,
+    /// cycles are kept as references to the object being built
+    #[cfg(feature = "encode")]
+    pub fn normalize(&self) -> Root {
+        normalize::Normalizer::new(self).normalize()
+    }
+
+    /// Returns the exact number of bytes `Dumper::dump` would produce for `value` (including the version header),
+    /// without keeping the output in memory, can be used to pre-allocate buffers or reject over-quota payloads
+    #[cfg(feature = "encode")]
+    pub fn serialized_size_estimate(&self, value: &RubyValue) -> Result<usize, DumpError> {
+        let mut counter = ByteCounter::default();
+        Dumper::new(&mut counter).dump(self, value)?;
+        Ok(counter.0)
+    }
+
+    /// Decodes a string in the encoding its instance variables name with `DefaultTextDecoder`
     pub fn decode_string(&self, string: &RubyString) -> Result<String, RubyError> {
+        self.decode_string_with(string, &DefaultTextDecoder)
+    }
+
+    /// Decodes a string in the encoding its instance variables name with `decoder`, see `TextDecoders` for decoding
+    /// encodings the default decoder doesn't know
+    pub fn decode_string_with(&self, string: &RubyString, decoder: &dyn TextDecoder) -> Result<String, RubyError> {
         if let Some(string_instance_variables) = string.get_instance_variables() {
-            return self.decode_string_with_instance_variables(string, string_instance_variables);
+            return self.decode_string_with_instance_variables(string, string_instance_variables, decoder);
         }
         Err(RubyError::EncodingError("Tried to decode a string in a binary encoding".to_string()))
     }
 
-    fn decode_string_with_instance_variables(&self, string: &RubyString, instance_variables: &ValuePairsSymbolKeys) -> Result<String, RubyError> {
+    /// Decodes a string value, for a string in a `WithIvars` wrapper the wrapper's instance variables give the encoding
+    pub fn decode_string_value(&self, value: &RubyValue) -> Result<String, RubyError> {
+        self.decode_string_value_with(value, &DefaultTextDecoder)
+    }
+
+    /// Like `decode_string_value()` with a custom decoder, see `decode_string_with()`
+    pub fn decode_string_value_with(&self, value: &RubyValue, decoder: &dyn TextDecoder) -> Result<String, RubyError> {
+        let invalid = || RubyError::EncodingError(format!("{:?} is not a string", value));
+        match value {
+            RubyValue::String(object_id) => {
+                self.decode_string_with(self.get_typed_object(*object_id, RubyObject::try_as_string).ok_or_else(invalid)?, decoder)
+            }
+            RubyValue::WithIvars(wrapper) => {
+                let (RubyValue::String(object_id), ivars) = &**wrapper else {
+                    return Err(invalid());
+                };
+                let string = self.get_typed_object(*object_id, RubyObject::try_as_string).ok_or_else(invalid)?;
+                self.decode_string_with_instance_variables(string, &ivars.iter().cloned().collect(), decoder)
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn decode_string_with_instance_variables(
+        &self,
+        string: &RubyString,
+        instance_variables: &ValuePairsSymbolKeys,
+        decoder: &dyn TextDecoder,
+    ) -> Result<String, RubyError> {
         if string.get_string().is_empty() {
             return Ok(String::new());
         }
         if let Some(encoding_symbol_id) = self.get_symbol_id("E") {
             if let Some(encoding) = instance_variables.get(&encoding_symbol_id) {
-                let RubyValue::Boolean(boolean) = encoding else { panic!("Symbol E for string was not boolean")} ;
-                if *boolean {
-                    return Ok(encoding::all::UTF_8.decode(string.get_string(), DecoderTrap::Strict).unwrap());
-                } else {
-                    return Ok(encoding::all::ASCII.decode(string.get_string(), DecoderTrap::Strict).unwrap());
-                }
+                let RubyValue::Boolean(boolean) = encoding else {
+                    return Err(RubyError::EncodingError(format!("Instance variable E of string is {}, expected a boolean", encoding.kind())));
+                };
+                let encoding = if *boolean { "UTF-8" } else { "US-ASCII" };
+                return decoder.decode(encoding, string.get_string()).map_err(|error| RubyError::EncodingError(error.to_string()));
             }
         }
         if let Some(encoding_symbol_id) = self.get_symbol_id("encoding") {
             if let Some(encoding) = instance_variables.get(&encoding_symbol_id) {
-                let RubyValue::String(encoding) = encoding else { panic!("Symbol encoding for string was not a string") };
-                let encoding = self.objects[*encoding].as_string();
-                let encoding_string = self.decode_string(encoding).unwrap(); // should be raw encoded
-                if let Some(encoding) = encoding_from_whatwg_label(&encoding_string) {
-                    return Ok(encoding.decode(string.get_string(), DecoderTrap::Strict).unwrap())
-                } else {
-                    return Err(RubyError::EncodingError(format!("Could not find encoding {}", encoding_string)))
-                }
+                let RubyValue::String(encoding) = encoding else {
+                    return Err(RubyError::EncodingError(format!("Instance variable encoding of string is {}, expected a string", encoding.kind())));
+                };
+                let Some(encoding) = self.get_typed_object(*encoding, RubyObject::try_as_string) else {
+                    return Err(RubyError::EncodingError(format!("Instance variable encoding of string references invalid object {}", encoding)));
+                };
+                let encoding_string = String::from_utf8_lossy(encoding.get_string()); // names are written raw, without an encoding
+                return decoder.decode(&encoding_string, string.get_string()).map_err(|error| RubyError::EncodingError(error.to_string()));
             }
         }
         Err(RubyError::EncodingError("Tried to decode a string in a binary encoding".to_string()))
@@ -335,176 +1262,319 @@ impl Root {
     }
 
 
+    /// Writes a one line description of `value`, nested values deeper than `max_depth` are replaced by `...`. Strings
+    /// are quoted, regexps are written as `/pattern/` and the empty symbol as `:""`, so empty ones stay visible.
     pub fn print(&self, value: &RubyValue, f: &mut impl Write, depth: usize, max_depth: usize) -> Result<(), std::fmt::Error> {
-        if depth >= max_depth {
-            f.write_str("...")?;
-            return Ok(());
+        self.print_styled(value, f, depth, &PrintStyle::new(max_depth, None))
+    }
+
+    /// Like `print()`, but every element of a container goes on its own line, indented by `indent` spaces per level
+    pub fn print_pretty(&self, value: &RubyValue, f: &mut impl Write, max_depth: usize, indent: usize) -> Result<(), std::fmt::Error> {
+        self.print_styled(value, f, 0, &PrintStyle::new(max_depth, Some(indent)))
+    }
+
+    /// Like `print()` and `print_pretty()`, but also stops after `limits.max_nodes` objects and `limits.max_bytes`
+    /// bytes of output, so documents from untrusted sources (huge or with heavily shared objects) can be rendered into
+    /// logs safely. Elided parts are replaced by `...`, a cut off output ends with it. Returns whether anything was
+    /// elided or cut off.
+    pub fn print_limited(&self, value: &RubyValue, f: &mut impl Write, limits: &PrintLimits) -> Result<bool, std::fmt::Error> {
+        let style = PrintStyle { max_nodes: limits.max_nodes, ..PrintStyle::new(limits.max_depth, limits.indent) };
+        let mut writer = LimitedWriter { inner: f, remaining: limits.max_bytes, cut: false };
+        match self.print_styled(value, &mut writer, 0, &style) {
+            Ok(()) => Ok(style.nodes.get() > style.max_nodes),
+            Err(_) if writer.cut => Ok(true),
+            Err(err) => Err(err),
         }
-        match value {
-            RubyValue::Nil | RubyValue::FixNum(_) | RubyValue::Boolean(_) => f.write_str(&format!("{}", value)),
-            RubyValue::Symbol(symbol_id) => f.write_str(&self.symbols[*symbol_id]),
-            RubyValue::Array(object_id) => {
-                let array = self.objects[*object_id].as_array();
-                if !array.is_empty() {
-                    f.write_str("Array [ ")?;
-                    for (i, obj) in array.iter().enumerate() {
-                        self.print(obj, f, depth + 1, max_depth)?;
-                        if i != array.len() - 1 {
-                            f.write_str(", ")?;
-                        }
-                    }
-                    f.write_str(" ]")?;
-                } else {
-                    f.write_str("Array []")?;
+    }
+
+    fn print_styled(&self, value: &RubyValue, f: &mut impl Write, depth: usize, style: &PrintStyle) -> Result<(), std::fmt::Error> {
+        if let RubyValue::WithIvars(wrapper) = value {
+            let (inner, ivars) = &**wrapper;
+            if depth >= style.max_depth {
+                return f.write_str("...");
+            }
+            let mut entries = vec![PrintEntry::Field("value", inner)];
+            entries.extend(ivars.iter().map(|(key, value)| PrintEntry::SymbolPair(*key, value)));
+            return self.print_entries(f, depth, style, "WithIvars {", "}", &entries);
+        }
+        let Some(object_id) = value.object_id() else {
+            return match value {
+                RubyValue::Symbol(symbol_id) => f.write_str(self.printed_symbol(*symbol_id)?),
+                _ => f.write_str(&format!("{}", value)),
+            };
+        };
+        let object = self.get_object(object_id).ok_or(std::fmt::Error)?;
+        if object.kind().to_value(object_id) != *value {
+            return Err(std::fmt::Error);
+        }
+        style.nodes.set(style.nodes.get() + 1);
+        if style.nodes.get() > style.max_nodes {
+            return f.write_str("...");
+        }
+
+        // scalar objects
+        match object {
+            RubyObject::BigNum(bignum) => return f.write_str(&bignum.to_string()),
+            RubyObject::Float(float) => return f.write_str(&float.to_string()),
+            RubyObject::Class(name) => return f.write_str(&format!("Class {}", name)),
+            RubyObject::Module(name) => return f.write_str(&format!("Module {}", name)),
+            RubyObject::ClassOrModule(name) => return f.write_str(&format!("ClassOrModule {}", name)),
+            RubyObject::String(string) => {
+                return match self.decode_string(string) {
+                    Ok(decoded) => f.write_str(&format!("\"{}\"", decoded)),
+                    Err(_) => f.write_str(&format!("{:?}", String::from_utf8_lossy(string.get_string()))),
+                };
+            }
+            _ => (),
+        }
+
+        if depth >= style.max_depth {
+            return f.write_str("...");
+        }
+
+        let mut entries = Vec::new();
+        let instance_variables = match object {
+            RubyObject::Array(array) => {
+                entries.extend(array.iter().map(PrintEntry::Value));
+                return self.print_entries(f, depth, style, "Array [", "]", &entries);
+            }
+            RubyObject::Hash(hash) => {
+                entries.extend(hash.iter().map(|(key, value)| PrintEntry::Pair(key, value)));
+                return self.print_entries(f, depth, style, "Hash {", "}", &entries);
+            }
+            RubyObject::HashWithDefault(hash) => {
+                entries.extend(hash.hash.iter().map(|(key, value)| PrintEntry::Pair(key, value)));
+                entries.push(PrintEntry::Field("default", &hash.default));
+                return self.print_entries(f, depth, style, "HashWithDefault {", "}", &entries);
+            }
+            RubyObject::Object(object) => {
+                entries.push(PrintEntry::Name("class_name", object.class_name));
+                self.push_extended_modules(&mut entries, &object.extended_modules);
+                Some(&object.instance_variables)
+            }
+            RubyObject::RegExp(regexp) => {
+                entries.push(PrintEntry::Text("pattern", format!("/{}/", regexp.pattern)));
+                entries.push(PrintEntry::Text("options", regexp.options.to_string()));
+                regexp.instance_variables.as_ref()
+            }
+            RubyObject::Struct(ruby_struct) => {
+                entries.push(PrintEntry::Name("name", ruby_struct.name));
+                entries.extend(ruby_struct.members.iter().map(|(key, value)| PrintEntry::SymbolPair(*key, value)));
+                ruby_struct.instance_variables.as_ref()
+            }
+            RubyObject::UserClass(user_class) => {
+                entries.push(PrintEntry::Name("name", user_class.name));
+                entries.push(PrintEntry::Field("wrapped_object", &user_class.wrapped_object));
+                user_class.instance_variables.as_ref()
+            }
+            RubyObject::UserDefined(user_defined) => {
+                entries.push(PrintEntry::Name("class_name", user_defined.class_name));
+                self.push_extended_modules(&mut entries, &user_defined.extended_modules);
+                if let Some(original_len) = user_defined.original_len {
+                    entries.push(PrintEntry::Text("original_len", original_len.to_string()));
                 }
-                Ok(())
-            },
-            RubyValue::BigNum(object_id) => f.write_str(&self.objects[*object_id].as_bignum().to_string()),
-            RubyValue::Class(object_id) => f.write_str(&format!("Class {}", self.objects[*object_id].as_class())),
-            RubyValue::Module(object_id) => f.write_str(&format!("Module {}", self.objects[*object_id].as_module())),
-            RubyValue::ClassOrModule(object_id) => f.write_str(&format!("ClassOrModule {}", self.objects[*object_id].as_class_or_module())),
-            RubyValue::Float(object_id) => f.write_str(&self.objects[*object_id].as_float().to_string()),
-            RubyValue::Hash(object_id) => {
-                let hash = self.objects[*object_id].as_hash();
-                f.write_str("Hash { ")?;
-                for (i, (key, value)) in hash.iter().enumerate() {
-                    self.print(key, f, depth + 1, max_depth)?;
-                    f.write_str(": ")?;
-                    self.print(value, f, depth + 1, max_depth)?;
-                    if i != hash.len() - 1 {
-                        f.write_str(", ")?;
-                    }
+                #[cfg(feature = "decode")]
+                match user_defined.parse_nested() {
+                    Ok(nested) => entries.push(PrintEntry::Nested(nested)),
+                    Err(_) => entries.push(PrintEntry::Text("data", format!("{:?}", user_defined.data))),
                 }
-                f.write_str(" }")?;
-                Ok(())
-            },
-            RubyValue::HashWithDefault(object_id) => {
-                let hash = self.objects[*object_id].as_hash_with_default();
-                f.write_str("HashWithDefault { ")?;
-                for (key, value) in hash.hash.iter() {
-                    self.print(key, f, depth+1, max_depth)?;
+                #[cfg(not(feature = "decode"))]
+                entries.push(PrintEntry::Text("data", format!("{:?}", user_defined.data)));
+                user_defined.instance_variables.as_ref()
+            }
+            RubyObject::UserMarshal(user_marshal) => {
+                entries.push(PrintEntry::Name("class_name", user_marshal.class_name));
+                entries.push(PrintEntry::Field("wrapped_object", &user_marshal.wrapped_object));
+                None
+            }
+            RubyObject::Data(data) => {
+                entries.push(PrintEntry::Name("class_name", data.class_name));
+                entries.push(PrintEntry::Field("state", &data.state));
+                None
+            }
+            RubyObject::Raw(raw) => {
+                entries.push(PrintEntry::Text("bytes", format!("{:?}", raw.bytes)));
+                None
+            }
+            _ => return Err(std::fmt::Error),
+        };
+        entries.extend(instance_variables.into_iter().flatten().map(|(key, value)| PrintEntry::SymbolPair(*key, value)));
+        let open = format!("{} {{", object.kind());
+        self.print_entries(f, depth, style, &open, "}", &entries)
+    }
+
+    /// Symbols are printed by name, the empty symbol as `:""` so it doesn't disappear from the output
+    fn printed_symbol(&self, symbol_id: SymbolID) -> Result<&str, std::fmt::Error> {
+        match self.get_symbol(symbol_id).map(|name| &**name) {
+            Some("") => Ok(":\"\""),
+            Some(name) => Ok(name),
+            None => Err(std::fmt::Error),
+        }
+    }
+
+    fn push_extended_modules(&self, entries: &mut Vec<PrintEntry>, extended_modules: &[SymbolID]) {
+        if !extended_modules.is_empty() {
+            let names: Vec<&str> = extended_modules.iter().map(|module| self.get_symbol(*module).map_or("?", |name| &**name)).collect();
+            entries.push(PrintEntry::Text("extended_modules", format!("[{}]", names.join(", "))));
+        }
+    }
+
+    fn print_entries(
+        &self, f: &mut impl Write, depth: usize, style: &PrintStyle, open: &str, close: &str, entries: &[PrintEntry]
+    ) -> Result<(), std::fmt::Error> {
+        f.write_str(open)?;
+        if entries.is_empty() {
+            return f.write_str(close);
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            f.write_str(if i == 0 { "" } else { "," })?;
+            style.new_line(f, depth + 1)?;
+            match entry {
+                PrintEntry::Value(value) => self.print_styled(value, f, depth + 1, style)?,
+                PrintEntry::Pair(key, value) => {
+                    self.print_styled(key, f, depth + 1, style)?;
                     f.write_str(": ")?;
-                    self.print(value, f, depth+1, max_depth)?;
-                    f.write_str(", ")?;
+                    self.print_styled(value, f, depth + 1, style)?;
                 }
-                f.write_str("default: ")?;
-                self.print(&hash.default, f, depth+1, max_depth)?;
-                f.write_str(" }")?;
-                Ok(())
-            },
-            RubyValue::Object(object_id) => {
-                let object = self.objects[*object_id].as_object();
-                f.write_str("Object { ")?;
-                f.write_str("class_name: ")?;
-                self.print(&RubyValue::Symbol(object.class_name), f, depth+1, max_depth)?;
-                f.write_str(", instance_variables: [ ")?;
-                for (key, value) in object.instance_variables.iter() {
-                    self.print(&RubyValue::Symbol(*key), f, depth+1, max_depth)?;
+                PrintEntry::SymbolPair(key, value) => {
+                    f.write_str(self.printed_symbol(*key)?)?;
                     f.write_str(": ")?;
-                    self.print(value, f, depth+1, max_depth)?;
-                    f.write_str(", ")?;
-                }
-                f.write_str(" ] }")?;
-                Ok(())
-            },
-            RubyValue::RegExp(object_id) => {
-                let regexp = self.objects[*object_id].as_regexp();
-                f.write_str("RegExp { ")?;
-                f.write_str("pattern: ")?;
-                f.write_str(&regexp.pattern)?;
-                f.write_str(", options: ")?;
-                f.write_str(&regexp.options.to_string())?;
-                if let Some(instance_variables) = &regexp.instance_variables {
-                    f.write_str(", instance_variables: [ ")?;
-                    for (key, value) in instance_variables.iter() {
-                        self.print(&RubyValue::Symbol(*key), f, depth+1, max_depth)?;
-                        f.write_str(": ")?;
-                        self.print(value, f, depth+1, max_depth)?;
-                        f.write_str(", ")?;
-                    }
-                    f.write_str(" ] }")?;
-                } else {
-                    f.write_str(" }")?;
+                    self.print_styled(value, f, depth + 1, style)?;
                 }
-                Ok(())
-            },
-            RubyValue::String(object_id) => {
-                let string = self.objects[*object_id].as_string();
-                f.write_str(&format!("\"{}\"", self.decode_string(string).unwrap()))?;
-                Ok(())
-            },
-            RubyValue::Struct(object_id) => {
-                let ruby_struct = self.objects[*object_id].as_struct();
-                f.write_str("Stuct { ")?;
-                f.write_str(&format!("name: {}", ruby_struct.name))?;
-                f.write_str(", members: [ ")?;
-                for (key, value) in ruby_struct.members.iter() {
-                    self.print(&RubyValue::Symbol(*key), f, depth+1, max_depth)?;
-                    f.write_str(": ")?;
-                    self.print(value, f, depth+1, max_depth)?;
-                    f.write_str(", ")?;
+                PrintEntry::Field(name, value) => {
+                    f.write_str(&format!("{}: ", name))?;
+                    self.print_styled(value, f, depth + 1, style)?;
                 }
-                f.write_str(" ] }")?;
-                Ok(())
-            },
-            RubyValue::UserClass(object_id) => {
-                let user_class = self.objects[*object_id].as_user_class();
-                f.write_str("UserClass { ")?;
-                f.write_str("name: ")?;
-                self.print(&RubyValue::Symbol(user_class.name), f, depth+1, max_depth)?;
-                f.write_str(", wrapped_object: ")?;
-                self.print(&user_class.wrapped_object, f, depth+1, max_depth)?;
-                if let Some(instance_variables) = &user_class.instance_variables {
-                    f.write_str(", instance_variables: [ ")?;
-                    for (key, value) in instance_variables.iter() {
-                        self.print(&RubyValue::Symbol(*key), f, depth+1, max_depth)?;
-                        f.write_str(": ")?;
-                        self.print(value, f, depth+1, max_depth)?;
-                        f.write_str(", ")?;
-                    }
-                    f.write_str(" ] }")?;
-                } else {
-                    f.write_str(" }")?;
+                PrintEntry::Name(name, symbol_id) => {
+                    f.write_str(&format!("{}: {}", name, self.printed_symbol(*symbol_id)?))?;
                 }
-                Ok(())
-            },
-            RubyValue::UserDefined(object_id) => {
-                let user_defined = self.objects[*object_id].as_user_defined();
-                f.write_str("UserDefined { ")?;
-                f.write_str("class_name: ")?;
-                self.print(&RubyValue::Symbol(user_defined.class_name), f, depth+1, max_depth)?;
-                f.write_str(&format!(", data: {:?}", user_defined.data))?;
-                if let Some(instance_variables) = &user_defined.instance_variables {
-                    f.write_str(", instance_variables: [ ")?;
-                    for (key, value) in instance_variables.iter() {
-                        self.print(&RubyValue::Symbol(*key), f, depth+1, max_depth)?;
-                        f.write_str(": ")?;
-                        self.print(value, f, depth+1, max_depth)?;
-                        f.write_str(", ")?;
-                    }
-                    f.write_str(" ] }")?;
-                } else {
-                    f.write_str(" }")?;
+                PrintEntry::Text(name, text) => f.write_str(&format!("{}: {}", name, text))?,
+                #[cfg(feature = "decode")]
+                PrintEntry::Nested(nested) => {
+                    f.write_str("nested: ")?;
+                    nested.print_styled(nested.get_root(), f, depth + 1, style)?;
                 }
-                Ok(())
-            },
-            RubyValue::UserMarshal(object_id) => {
-                let user_marshal = self.objects[*object_id].as_user_marshal();
-                f.write_str("UserMarshal { ")?;
-                f.write_str("class_name: ")?;
-                self.print(&RubyValue::Symbol(user_marshal.class_name), f, depth+1, max_depth)?;
-                f.write_str(", wrapped_object: ")?;
-                self.print(&user_marshal.wrapped_object, f, depth+1, max_depth)?;
-                f.write_str(" }")?;
-                Ok(())
-            },
+            }
+        }
+        style.new_line(f, depth)?;
+        f.write_str(close)
+    }
+}
+
+/// Caps for `Root::print_limited()`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PrintLimits {
+    /// containers nested deeper are printed as `...`
+    pub max_depth: usize,
+    /// objects printed before the remaining ones are printed as `...`, shared objects count every time they are printed
+    pub max_nodes: usize,
+    /// bytes written before the output is cut off, the final `...` comes on top
+    pub max_bytes: usize,
+    /// `None` prints everything on one line, otherwise see `Root::print_pretty()`
+    pub indent: Option<usize>,
+}
+
+impl Default for PrintLimits {
+    fn default() -> Self {
+        Self { max_depth: 8, max_nodes: 1000, max_bytes: 16 * 1024, indent: None }
+    }
+}
+
+/// Layout used by `Root::print()` and `Root::print_pretty()`
+struct PrintStyle {
+    max_depth: usize,
+    /// `None` prints everything on one line
+    indent: Option<usize>,
+    max_nodes: usize,
+    /// objects printed so far
+    nodes: Cell<usize>,
+}
+
+impl PrintStyle {
+    fn new(max_depth: usize, indent: Option<usize>) -> Self {
+        Self { max_depth, indent, max_nodes: usize::MAX, nodes: Cell::new(0) }
+    }
+
+    /// Starts a new line indented for `depth` in pretty mode, separates entries with a space otherwise
+    fn new_line(&self, f: &mut impl Write, depth: usize) -> Result<(), std::fmt::Error> {
+        match self.indent {
+            Some(indent) => f.write_str(&format!("\n{:1$}", "", indent * depth)),
+            None => f.write_str(" "),
         }
     }
 }
 
+/// Writer of `Root::print_limited()`, fails once `remaining` is used up so printing stops early
+struct LimitedWriter<'a, W: Write> {
+    inner: &'a mut W,
+    remaining: usize,
+    cut: bool,
+}
+
+impl<W: Write> Write for LimitedWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if self.cut {
+            return Err(std::fmt::Error);
+        }
+        if s.len() <= self.remaining {
+            self.remaining -= s.len();
+            return self.inner.write_str(s);
+        }
+        let mut end = self.remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.inner.write_str(s.get(..end).unwrap_or_default())?;
+        self.inner.write_str("...")?;
+        self.cut = true;
+        Err(std::fmt::Error)
+    }
+}
+
+/// One element printed inside a container by `Root::print_entries()`
+enum PrintEntry<'a> {
+    Value(&'a RubyValue),
+    Pair(&'a RubyValue, &'a RubyValue),
+    /// instance variable or struct member
+    SymbolPair(SymbolID, &'a RubyValue),
+    Field(&'static str, &'a RubyValue),
+    /// field holding a symbol, printed as its name
+    Name(&'static str, SymbolID),
+    Text(&'static str, String),
+    /// document embedded in a UserDefined payload
+    #[cfg(feature = "decode")]
+    Nested(Root),
+}
+
+/// Writer that discards the data and only counts the number of bytes written
+#[cfg(feature = "encode")]
+#[derive(Default)]
+struct ByteCounter(usize);
+
+#[cfg(feature = "encode")]
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Prints the root value, the precision sets the maximum depth (4 by default) and the alternate flag (`{:#}`) switches
+/// to pretty printing with the width as indentation (2 by default), e.g. `{:#4.10}`
 impl Display for Root {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.print(&self.root, f, 0, 4)
+        let max_depth = f.precision().unwrap_or(4);
+        if f.alternate() {
+            let indent = f.width().unwrap_or(2);
+            self.print_pretty(&self.root, f, max_depth, indent)
+        } else {
+            self.print(&self.root, f, 0, max_depth)
+        }
     }
 }
 
@@ -568,6 +1638,11 @@ impl RubyString {
         &self.string
     }
 
+    #[cfg(feature = "decode")]
+    pub(crate) fn set_string(&mut self, string: Vec<u8>) {
+        self.string = string;
+    }
+
     pub fn set_instance_variables(&mut self, instance_variables: ValuePairsSymbolKeys) {
         self.instance_variables = Some(instance_variables);
     }
@@ -626,11 +1701,12 @@ impl RegExp {
 pub struct Struct {
     name: SymbolID,
     members: ValuePairsSymbolKeys,
+    instance_variables: Option<ValuePairsSymbolKeys>,
 }
 
 impl Struct {
     pub fn new(name: SymbolID, members: ValuePairsSymbolKeys) -> Self {
-       Self {name, members} 
+       Self {name, members, instance_variables: None}
     }
 
     pub fn get_name(&self) -> SymbolID {
@@ -644,17 +1720,40 @@ impl Struct {
     pub fn get_member(&self, symbol_id: SymbolID) -> Option<&RubyValue> {
         self.members.get(&symbol_id)
     }
+
+    /// Instance variables set on the struct besides its members, written with an `I` around it
+    pub fn set_instance_variables(&mut self, instance_variables: ValuePairsSymbolKeys) {
+        self.instance_variables = Some(instance_variables);
+    }
+
+    pub fn get_instance_variables(&self) -> &Option<ValuePairsSymbolKeys> {
+        &self.instance_variables
+    }
+
+    pub fn get_instance_variable(&self, symbol_id: SymbolID) -> Option<&RubyValue> {
+        self.instance_variables.as_ref().and_then(|instance_variables| instance_variables.get(&symbol_id))
+    }
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Object {
     class_name: SymbolID,
     instance_variables: ValuePairsSymbolKeys,
+    /// modules the object was extended with (`obj.extend(M)`), in stream order
+    extended_modules: Vec<SymbolID>,
 }
 
 impl Object {
     pub fn new(class_name: SymbolID, instance_variables: ValuePairsSymbolKeys) -> Self {
-       Self {class_name, instance_variables} 
+       Self {class_name, instance_variables, extended_modules: Vec::new()} 
+    }
+
+    pub fn get_extended_modules(&self) -> &Vec<SymbolID> {
+        &self.extended_modules
+    }
+
+    pub fn set_extended_modules(&mut self, extended_modules: Vec<SymbolID>) {
+        self.extended_modules = extended_modules;
     }
 
     pub fn get_class_name(&self) -> SymbolID {
@@ -665,6 +1764,10 @@ impl Object {
         &self.instance_variables
     }
 
+    pub fn get_mut_instance_variables(&mut self) -> &mut ValuePairsSymbolKeys {
+        &mut self.instance_variables
+    }
+
     pub fn get_instance_variable(&self, symbol_id: SymbolID) -> Option<&RubyValue> {
         self.instance_variables.get(&symbol_id)
     }
@@ -690,10 +1793,34 @@ impl UserClass {
         &self.wrapped_object
     }
 
+    /// Returns the pairs of the wrapped hash for subclasses of Hash (like `HashWithIndifferentAccess`), with or
+    /// without a default value
+    pub fn get_wrapped_hash<'r>(&self, root: &'r Root) -> Option<&'r ValuePairs> {
+        match self.wrapped_object {
+            RubyValue::Hash(object_id) => root.get_typed_object(object_id, RubyObject::try_as_hash),
+            RubyValue::HashWithDefault(object_id) => root.get_typed_object(object_id, RubyObject::try_as_hash_with_default).map(HashWithDefault::hash),
+            _ => None,
+        }
+    }
+
+    /// Returns the default value of the wrapped hash, `None` if it's a plain hash (the default is `nil`) or not a hash
+    pub fn get_wrapped_hash_default<'r>(&self, root: &'r Root) -> Option<&'r RubyValue> {
+        match self.wrapped_object {
+            RubyValue::HashWithDefault(object_id) => root.get_typed_object(object_id, RubyObject::try_as_hash_with_default).map(HashWithDefault::default),
+            _ => None,
+        }
+    }
+
     pub fn decode_wrapped_string(&self, root: &Root) -> Result<String, RubyError> {
         if let Some(instance_variables) = &self.instance_variables {
-            let inner_string = root.get_object(self.wrapped_object.as_string()).unwrap().as_string();
-            root.decode_string_with_instance_variables(inner_string, instance_variables)
+            let inner_string = match self.wrapped_object {
+                RubyValue::String(object_id) => root.get_typed_object(object_id, RubyObject::try_as_string),
+                _ => None,
+            };
+            let Some(inner_string) = inner_string else {
+                return Err(RubyError::EncodingError(format!("Wrapped object {:?} is not a valid string", self.wrapped_object)));
+            };
+            root.decode_string_with_instance_variables(inner_string, instance_variables, &DefaultTextDecoder)
         } else {
             Err(RubyError::EncodingError("Tried to decode a string in a binary encoding".to_string()))
         }
@@ -721,12 +1848,39 @@ impl UserClass {
 pub struct UserDefined {
     class_name: SymbolID,
     data: Vec<u8>,
+    /// length of the data in the stream if only a part of it was loaded
+    original_len: Option<usize>,
     instance_variables: Option<ValuePairsSymbolKeys>,
+    /// modules the object was extended with (`obj.extend(M)`), in stream order
+    extended_modules: Vec<SymbolID>,
 }
 
 impl UserDefined {
     pub fn new(class_name: SymbolID, data: Vec<u8>) -> Self {
-       Self {class_name, data, instance_variables: None} 
+       Self {class_name, data, original_len: None, instance_variables: None, extended_modules: Vec::new()} 
+    }
+
+    /// Placeholder holding only the first bytes of a payload that was `original_len` bytes long, can't be dumped
+    pub fn truncated(class_name: SymbolID, data: Vec<u8>, original_len: usize) -> Self {
+        Self {class_name, data, original_len: Some(original_len), instance_variables: None, extended_modules: Vec::new()}
+    }
+
+    pub fn get_extended_modules(&self) -> &Vec<SymbolID> {
+        &self.extended_modules
+    }
+
+    pub fn set_extended_modules(&mut self, extended_modules: Vec<SymbolID>) {
+        self.extended_modules = extended_modules;
+    }
+
+    /// Whether the data is only a part of the payload, see `LoaderOptions::user_defined_limits`
+    pub fn is_truncated(&self) -> bool {
+        self.original_len.is_some()
+    }
+
+    /// Length of the full payload, which differs from the data's length for truncated payloads
+    pub fn get_original_len(&self) -> usize {
+        self.original_len.unwrap_or(self.data.len())
     }
 
     pub fn get_class_name(&self) -> SymbolID {
@@ -737,6 +1891,26 @@ impl UserDefined {
         &self.data
     }
 
+    #[cfg(feature = "decode")]
+    pub(crate) fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
+    /// Whether the data starts with a Marshal version header, i.e. `_dump` returned another Marshal stream
+    pub fn has_nested_marshal(&self) -> bool {
+        self.data.starts_with(&MARSHAL_VERSION)
+    }
+
+    /// Loads the data as a Marshal stream of its own, see `has_nested_marshal()`
+    #[cfg(feature = "decode")]
+    pub fn parse_nested(&self) -> Result<Root, LoadError> {
+        if !self.has_nested_marshal() {
+            return Err(LoadError::ParserError(ErrorCode::NotMarshalData, "UserDefined data is not a Marshal stream".to_string(), ErrorLocation::UNKNOWN));
+        }
+        let mut data = &self.data[..];
+        Loader::new(&mut data).load()
+    }
+
     pub fn set_instance_variables(&mut self, instance_variables: ValuePairsSymbolKeys) {
         self.instance_variables = Some(instance_variables);
     }
@@ -774,3 +1948,442 @@ impl UserMarshal {
         &self.wrapped_object
     }
 }
+
+/// Object of a class implemented in a C extension (`T_DATA`), the state is whatever its `_dump_data` returned and is
+/// passed to `_load_data` when loading
+#[derive(PartialEq, Clone, Debug)]
+pub struct Data {
+    class_name: SymbolID,
+    state: RubyValue,
+}
+
+impl Data {
+    pub fn new(class_name: SymbolID, state: RubyValue) -> Self {
+        Self { class_name, state }
+    }
+
+    pub fn get_class_name(&self) -> SymbolID {
+        self.class_name
+    }
+
+    pub fn get_state(&self) -> &RubyValue {
+        &self.state
+    }
+}
+
+/// A single encoded value (without version header) that the `Dumper` writes as it is, see `Root::add_raw()`. It can't
+/// contain symbol or object links since their targets depend on where it is written, the symbols and objects it
+/// defines are counted so the links written after it stay right. Writing it twice writes two copies of its objects.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RawFragment {
+    bytes: Vec<u8>,
+    symbol_count: usize,
+    object_count: usize,
+}
+
+impl RawFragment {
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Number of symbols the fragment defines
+    pub fn get_symbol_count(&self) -> usize {
+        self.symbol_count
+    }
+
+    /// Number of objects the fragment defines
+    pub fn get_object_count(&self) -> usize {
+        self.object_count
+    }
+}
+
+/// Slot of an object moved out of a Root by `Root::take_object()`, hand it back to `Root::restore()`
+#[must_use = "the object is missing from the Root until it's restored"]
+#[derive(PartialEq, Eq, Debug)]
+pub struct Placeholder {
+    object_id: ObjectID,
+    kind: ObjectKind,
+}
+
+impl Placeholder {
+    pub fn get_object_id(&self) -> ObjectID {
+        self.object_id
+    }
+
+    /// Kind of the object taken, the one handed back must have the same kind
+    pub fn get_kind(&self) -> ObjectKind {
+        self.kind
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::test_util::load;
+
+    use super::*;
+
+    #[test]
+    fn test_value_size() {
+        // values are stored inline in every array, hash and instance variable list, wrappers are boxed to keep them small
+        assert_eq!(std::mem::size_of::<RubyValue>(), 16);
+        let wrapped = RubyValue::FixNum(1).with_ivars(vec![(0, RubyValue::Nil)]);
+        assert_eq!(wrapped.without_ivars(), &RubyValue::FixNum(1));
+        assert_eq!(wrapped.get_wrapper_ivars(), [(0, RubyValue::Nil)]);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // Actor(@hp=10, @name="a") and Actor(@hp=3, @name="bc")
+        let first = load(b"\x04\x08o:\x0aActor\x07:\x08@hpi\x0f:\x0a@name\"\x06a");
+        let second = load(b"\x04\x08o:\x0aActor\x07:\x08@hpi\x08:\x0a@name\"\x07bc");
+        assert_eq!(first.fingerprint(), second.fingerprint());
+
+        // Enemy(@hp=10, @name="a")
+        let other_class = load(b"\x04\x08o:\x0aEnemy\x07:\x08@hpi\x0f:\x0a@name\"\x06a");
+        assert_ne!(first.fingerprint(), other_class.fingerprint());
+        // Actor(@hp=10, @name=nil)
+        let fewer_objects = load(b"\x04\x08o:\x0aActor\x07:\x08@hpi\x0f:\x0a@name0");
+        assert_ne!(first.fingerprint(), fewer_objects.fingerprint());
+        // arrays holding another number of strings have another number of objects
+        assert_ne!(load(b"\x04\x08[\x06\"\x06a").fingerprint(), load(b"\x04\x08[\x07\"\x06a\"\x06b").fingerprint());
+        // fixnums aren't objects, arrays of any number of them share the fingerprint
+        assert_eq!(load(b"\x04\x08[\x06i\x06").fingerprint(), load(b"\x04\x08[\x07i\x06i\x07").fingerprint());
+    }
+
+    #[test]
+    fn test_classes_used() {
+        // [Test(@a=Test(@a=String)), Foo._load(""), Point.new(1)] with Test extended with M
+        let root = load(b"\x04\x08[\x08e:\x06Mo:\x09Test\x06:\x07@ao;\x06\x06;\x07c\x0bStringu:\x08Foo\x00S:\x0aPoint\x06:\x06xi\x06");
+        assert_eq!(
+            root.classes_used().into_iter().collect::<Vec<_>>(),
+            [("Foo".to_string(), 1), ("M".to_string(), 1), ("Point".to_string(), 1), ("String".to_string(), 1), ("Test".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_find_objects() {
+        // [RPG::Map::Event.new, RPG::Actor.new, Game_Actor.new, "RPG::Actor"]
+        let root = load(b"\x04\x08[\x09o:\x14RPG::Map::Event\x00o:\x0fRPG::Actor\x00o:\x0fGame_Actor\x00\"\x0fRPG::Actor");
+        assert_eq!(root.get_class_name(1), Some(ClassName::parse("RPG::Map::Event")));
+        assert_eq!(root.get_class_name(4), None);
+        assert_eq!(root.find_objects("RPG::*"), [1, 2]);
+        assert_eq!(root.find_objects("*::Actor"), [2]);
+        assert_eq!(root.find_objects("*_Actor"), [3]);
+    }
+
+    #[test]
+    fn test_fragments() {
+        // [:a, "x"]
+        let mut root = load(b"\x04\x08[\x07:\x06a\"\x06x");
+        // [:b, :a, [1], @2] where @2 links to the inner array
+        let fragment = root.insert_fragment(b"[\x09:\x06b:\x06a[\x06i\x06@\x06").unwrap();
+        assert_eq!(fragment, RubyValue::Array(2));
+        assert_eq!(root.get_symbols(), &[SymbolStr::from("a"), SymbolStr::from("b")]);
+        assert_eq!(
+            root.get_object(2).unwrap().try_as_array().unwrap(),
+            &[RubyValue::Symbol(1), RubyValue::Symbol(0), RubyValue::Array(3), RubyValue::Array(3)]
+        );
+
+        // links inside the exported fragment are renumbered
+        assert_eq!(root.export_fragment(&fragment, false).unwrap(), b"[\x09:\x06b:\x06a[\x06i\x06@\x06");
+        let array = root.get_object(2).unwrap().try_as_array().unwrap()[2].clone();
+        assert_eq!(root.export_fragment(&array, true).unwrap(), b"\x04\x08[\x06i\x06");
+
+        assert!(root.insert_fragment(b"[\x06@\x09").is_err());
+        assert!(root.insert_fragment(b"i\x06i\x06").is_err());
+        assert_eq!(root.get_objects().len(), 4);
+    }
+
+    #[test]
+    fn test_take_object() {
+        use crate::encode::Dumper;
+
+        // ["abc", "abc" as a link to the first]
+        let input = b"\x04\x08[\x07\"\x08abc@\x06";
+        let mut root = load(input);
+        let (object, placeholder) = root.take_object(1).unwrap();
+        assert_eq!(placeholder.get_kind(), ObjectKind::String);
+        assert_eq!(root.get_object(1), Some(&RubyObject::Unloaded(ObjectKind::String)));
+        assert!(Dumper::new(&mut Vec::new()).dump(&root, root.get_root()).is_err());
+
+        let string = RubyString::new(object.as_string().get_string().to_ascii_uppercase());
+        let (placeholder, object) = *root.restore(placeholder, RubyObject::Float(1.0)).unwrap_err();
+        assert_eq!(object, RubyObject::Float(1.0));
+        root.restore(placeholder, RubyObject::String(string)).unwrap();
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08[\x07\"\x08ABC@\x06");
+
+        assert!(root.take_object(2).is_none());
+    }
+
+    #[test]
+    fn test_raw_fragments() {
+        use crate::{builder::RootBuilder, encode::Dumper};
+
+        // [[:a, 1], :a, :a, "x", "x"] with the first element written verbatim
+        let mut builder = RootBuilder::new();
+        let raw = builder.push_raw(b"[\x07:\x06ai\x06").unwrap();
+        let symbol = builder.symbol("a");
+        let string = builder.string("x");
+        let array = builder.array(vec![raw, symbol.clone(), symbol, string.clone(), string]);
+        let root = builder.build(array);
+        let raw_fragment = root.get_object(0).unwrap().try_as_raw().unwrap();
+        assert_eq!((raw_fragment.get_symbol_count(), raw_fragment.get_object_count()), (1, 1));
+
+        // the links after the fragment count its symbol and array
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08[\x0a[\x07:\x06ai\x06:\x06a;\x06I\"\x06x\x06:\x06ET@\x07");
+        let loaded = load(&output);
+        let elements = loaded.get_object(0).unwrap().try_as_array().unwrap();
+        assert_eq!(elements[1], elements[2]);
+        assert_eq!(elements[3], elements[4]);
+        assert_eq!(loaded.get_object(1).unwrap().try_as_array().unwrap()[1], RubyValue::FixNum(1));
+
+        let mut builder = RootBuilder::new();
+        assert!(matches!(builder.push_raw(b"[\x07:\x06a;\x00"), Err(LoadError::ParserError(ErrorCode::BadSymbolLink, ..))));
+        assert!(matches!(builder.push_raw(b"[\x06@\x00"), Err(LoadError::ParserError(ErrorCode::BadObjectLink, ..))));
+        assert!(matches!(builder.push_raw(b"i\x06i\x06"), Err(LoadError::ParserError(ErrorCode::TrailingBytes, ..))));
+        assert!(builder.push_raw(b"[\x07").is_err());
+        assert_eq!(builder.root().get_objects().len(), 0);
+    }
+
+    #[test]
+    fn test_special_instance_variables() {
+        // [Test(@a=1, __id__=2), "x" with E=true, 1..2]
+        let root = load(b"\x04\x08[\x08o:\x09Test\x07:\x07@ai\x06:\x0b__id__i\x07I\"\x06x\x06:\x06ETo:\x0aRange\x08:\x09exclF:\x0abegini\x06:\x08endi\x07");
+        assert_eq!(root.get_special_instance_variables(1), [("__id__", &RubyValue::FixNum(2))]);
+        assert!(root.get_special_instance_variables(2).is_empty());
+        assert!(root.get_special_instance_variables(3).is_empty());
+    }
+
+    #[test]
+    fn test_ivars_of() {
+        // [Test(@b=1, @a=2), "x" with E=true, [] with @a=nil, 1]
+        let root = load(b"\x04\x08[\x09o:\x09Test\x07:\x07@bi\x06:\x07@ai\x07I\"\x06x\x06:\x06ETI[\x00\x06;\x070i\x06");
+        let array = root.get_object(0).unwrap().as_array();
+        assert_eq!(root.ivars_of(&array[0]), [("@b", &RubyValue::FixNum(1)), ("@a", &RubyValue::FixNum(2))]);
+        assert_eq!(root.ivars_of(&array[1]), [("E", &RubyValue::Boolean(true))]);
+        assert_eq!(root.ivars_of(&array[2]), [("@a", &RubyValue::Nil)]);
+        assert!(root.ivars_of(&array[3]).is_empty());
+    }
+
+    #[test]
+    fn test_dig() {
+        // Test(@config={:volume=>[1, nil], "name"=>S(a: 2)}, @none=nil)
+        let root = load(b"\x04\x08o:\x09Test\x07:\x0c@config{\x07:\x0bvolume[\x07i\x060I\"\x09name\x06:\x06ETS:\x06S\x06:\x06ai\x07:\x0a@none0");
+        let value = root.get_root();
+        assert_eq!(root.dig(value, &["@config", "volume", "0"]), Some(&RubyValue::FixNum(1)));
+        assert_eq!(root.dig(value, &["@config", "volume", "-2"]), Some(&RubyValue::FixNum(1)));
+        assert_eq!(root.dig(value, &["@config", "volume", "1"]), Some(&RubyValue::Nil));
+        assert_eq!(root.dig(value, &["@config", "name", "a"]), Some(&RubyValue::FixNum(2)));
+        assert_eq!(root.dig(value, &[]), Some(value));
+        assert_eq!(root.dig(value, &["@config", "volume", "2"]), None);
+        assert_eq!(root.dig(value, &["@config", "volume", "-3"]), None);
+        assert_eq!(root.dig(value, &["@config", "missing"]), None);
+        assert_eq!(root.dig(value, &["@none", "volume"]), None);
+        assert_eq!(root.dig(value, &["@config", "volume", "0", "x"]), None);
+    }
+
+    #[test]
+    fn test_hash_to_map() {
+        // {"a"=>1, "b"=>2}
+        let root = load(b"\x04\x08{\x07I\"\x06a\x06:\x06ETi\x06I\"\x06b\x06;\x00Ti\x07");
+        let map: HashMap<String, i64> = root.hash_to_map(root.get_root()).unwrap();
+        assert_eq!(map, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+
+        // {:a=>{1=>2, 3=>"x"}}
+        let root = load(b"\x04\x08{\x06:\x06a{\x07i\x06i\x07i\x08\"\x06x");
+        let error = root.hash_to_map::<RubyValue, std::collections::BTreeMap<i32, i32>>(root.get_root()).unwrap_err();
+        assert_eq!(error.to_string(), "At key a -> 3: Expected fixnum, found String");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut root = Root::nil();
+        let value = (1_u8, "a".to_string(), vec![Some(2.5_f32), None]).into_ruby_value(&mut root).unwrap();
+        assert_eq!(<(i32, String, Vec<Option<f64>>)>::from_ruby_value(&root, &value), Ok((1, "a".to_string(), vec![Some(2.5), None])));
+        assert_eq!(
+            <(i32, String)>::from_ruby_value(&root, &value),
+            Err(ConvertError::LengthMismatch { expected: 2, found: 3 })
+        );
+
+        let value = 300_i64.into_ruby_value(&mut root).unwrap();
+        assert_eq!(u8::from_ruby_value(&root, &value), Err(ConvertError::OutOfRange { expected: "u8", value: 300 }));
+
+        let value = std::collections::BTreeMap::from([(1, "x")]).into_ruby_value(&mut root).unwrap();
+        assert_eq!(root.hash_to_map::<u32, String>(&value), Ok(HashMap::from([(1, "x".to_string())])));
+
+        assert_eq!(u64::MAX.into_ruby_value(&mut root), Err(ConvertError::OutOfRange { expected: "bignum", value: u64::MAX.into() }));
+
+        // Ruby writes integers outside of -2^30..2^30 - 1 as bignums
+        assert_eq!((1_i32 << 30).into_ruby_value(&mut root), Ok(RubyValue::BigNum(root.get_objects().len() - 1)));
+        assert_eq!(((1_i64 << 30) - 1).into_ruby_value(&mut root), Ok(RubyValue::FixNum((1 << 30) - 1)));
+        assert_eq!((-(1_i64 << 30)).into_ruby_value(&mut root), Ok(RubyValue::FixNum(-(1 << 30))));
+        let value = (-(1_i64 << 30) - 1).into_ruby_value(&mut root).unwrap();
+        assert_eq!(root.get_object(value.as_bignum()), Some(&RubyObject::BigNum(-(1 << 30) - 1)));
+    }
+
+    #[test]
+    fn test_column() {
+        // [Actor(@hp=10), nil, Actor(@hp=nil), Actor(@mp=3), Actor(@hp=20)]
+        let root = load(b"\x04\x08[\x0ao:\x0aActor\x06:\x08@hpi\x0f0o;\x00\x06;\x060o;\x00\x06:\x08@mpi\x08o;\x00\x06;\x06i\x19");
+        let hp = root.column::<i32>(root.get_root(), "@hp").unwrap();
+        assert_eq!(hp, vec![Some(10), None, None, None, Some(20)]);
+
+        let missing = root.column::<i32>(root.get_root(), "@sp").unwrap();
+        assert_eq!(missing, vec![None; 5]);
+
+        assert!(matches!(root.column::<String>(root.get_root(), "@hp"), Err(ConvertError::TypeMismatch { .. })));
+        assert!(matches!(root.column::<i32>(&RubyValue::Nil, "@hp"), Err(ConvertError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_add_values() {
+        let mut root = load(b"\x04\x08[\x06i\x06");
+        let old_root = root.get_root().clone();
+        let string = root.add_string("h\u{e9}llo");
+        let point = root.add_symbol("Point");
+        let object = root.add_object("Point", [("@x", RubyValue::FixNum(1)), ("@y", point.clone())]);
+        assert_eq!(root.add_symbol("Point"), point);
+        let array = root.add_array(vec![string.clone(), object, string, old_root]);
+        root.set_root(array);
+
+        // the new root is written first, so links have to use positions in the output instead of ids
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+        assert_eq!(
+            output,
+            b"\x04\x08[\x09I\"\x0bh\xc3\xa9llo\x06:\x06ETo:\x0aPoint\x07:\x07@xi\x06:\x07@y;\x06@\x06[\x06i\x06"
+        );
+    }
+
+    #[test]
+    fn test_of_value() {
+        let dump = |root: Root| {
+            let mut output = Vec::new();
+            Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+            output
+        };
+
+        assert_eq!(dump(Root::nil()), b"\x04\x080");
+        assert_eq!(dump(Root::of_value(1.5).unwrap()), b"\x04\x08f\x081.5");
+        assert_eq!(dump(Root::of_value(1_i64 << 40).unwrap()), b"\x04\x08l+\x08\x00\x00\x00\x00\x00\x01");
+        assert_eq!(dump(Root::of_value(vec![Some("a"), None, Some("b")]).unwrap()), b"\x04\x08[\x08I\"\x06a\x06:\x06ET0I\"\x06b\x06;\x00T");
+    }
+
+    #[test]
+    fn test_print_limited() {
+        // 30 nested arrays each holding the next one twice, 2^30 elements when printed in full
+        let mut objects: Vec<RubyObject> = (1..30).map(|next| RubyObject::Array(vec![RubyValue::Array(next), RubyValue::Array(next)])).collect();
+        objects.push(RubyObject::Array(Vec::new()));
+        let root = Root::new(RubyValue::Array(0), Vec::new(), objects);
+        let limits = PrintLimits { max_depth: 100, ..PrintLimits::default() };
+
+        let mut output = String::new();
+        assert_eq!(root.print_limited(root.get_root(), &mut output, &PrintLimits { max_bytes: usize::MAX, ..limits }), Ok(true));
+        assert_eq!(output.matches("Array").count(), limits.max_nodes);
+
+        let mut output = String::new();
+        assert_eq!(root.print_limited(root.get_root(), &mut output, &PrintLimits { max_bytes: 20, ..limits }), Ok(true));
+        assert_eq!(output, "Array [ Array [ Arra...");
+
+        let root = load(b"\x04\x08[\x07i\x06i\x07");
+        let mut output = String::new();
+        assert_eq!(root.print_limited(root.get_root(), &mut output, &limits), Ok(false));
+        assert_eq!(output, "Array [ 1, 2 ]");
+    }
+
+    #[test]
+    fn test_malformed_graph() {
+        // the array references itself as a string and a non-existent object
+        let root = Root::new(RubyValue::Array(0), Vec::new(), vec![RubyObject::Array(vec![RubyValue::String(0), RubyValue::Float(5)])]);
+        let mut output = String::new();
+        assert!(root.print(&RubyValue::String(0), &mut output, 0, 10).is_err());
+        assert!(root.print(&RubyValue::Float(5), &mut output, 0, 10).is_err());
+        assert!(root.print(&RubyValue::Symbol(0), &mut output, 0, 10).is_err());
+        assert!(matches!(root.serialized_size_estimate(root.get_root()), Err(DumpError::ForeignReference { id: RubyValue::Float(5), .. })));
+        assert!(matches!(unsafe { root.get_object_unchecked(0) }, RubyObject::Array(_)));
+
+        // invalid UTF-8 and an E instance variable that isn't a boolean
+        let root = load(b"\x04\x08[\x07I\"\x06\xff\x06:\x06ETI\"\x06a\x06;\x00i\x06");
+        let elements = root.get_object(0).unwrap().as_array();
+        for element in elements {
+            let string = root.get_object(element.as_string()).unwrap().as_string();
+            assert!(matches!(root.decode_string(string), Err(RubyError::EncodingError(_))));
+        }
+    }
+
+    #[test]
+    fn test_nested_marshal() {
+        // Wrapper._dump returning Marshal.dump([1, "a"]) and Other._dump returning "abc"
+        let root = load(b"\x04\x08[\x07u:\x0cWrapper\x14\x04\x08[\x07i\x06I\"\x06a\x06:\x06ETu:\x0aOther\x08abc");
+        let elements = root.get_object(0).unwrap().as_array();
+        let wrapper = root.get_object(elements[0].as_user_defined()).unwrap().as_user_defined();
+        let other = root.get_object(elements[1].as_user_defined()).unwrap().as_user_defined();
+
+        assert!(wrapper.has_nested_marshal());
+        let nested = wrapper.parse_nested().unwrap();
+        assert_eq!(nested.get_object(0).unwrap().as_array(), &vec![RubyValue::FixNum(1), RubyValue::String(1)]);
+        assert!(!other.has_nested_marshal());
+        assert!(other.parse_nested().is_err());
+
+        let mut output = String::new();
+        root.print(root.get_root(), &mut output, 0, 10).unwrap();
+        assert!(output.contains("nested: Array [ 1, \"a\" ]"));
+        assert!(output.contains("data: [97, 98, 99]"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        // {:b=>"x", :a=>"x"} and {:a=>"x", :b=>"x"}
+        let a = load(b"\x04\x08{\x07:\x06bI\"\x06x\x06:\x06ET:\x06aI\"\x06x\x06;\x06T");
+        let mut b = load(b"\x04\x08{\x07:\x06aI\"\x06x\x06:\x06ET:\x06bI\"\x06x\x06;\x06T");
+        b.add_string("unreachable");
+        assert_ne!(a, b);
+
+        let normalized = a.normalize();
+        assert_eq!(normalized, b.normalize());
+        assert_eq!(normalized.get_objects().len(), 2);
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&normalized, normalized.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08{\x07:\x06aI\"\x06x\x06:\x06ET:\x06b@\x06");
+
+        // a = []; a << a
+        let cyclic = load(b"\x04\x08[\x06@\x00");
+        assert_eq!(cyclic.normalize(), cyclic);
+
+        // s = "x"; s.instance_variable_set(:@s, s)
+        let cyclic = load(b"\x04\x08I\"\x06x\x06:\x07@s@\x00");
+        assert_eq!(cyclic.normalize(), cyclic);
+    }
+
+    #[test]
+    fn test_display() {
+        // [1, {:a=>[2]}, Test(@b=nil)]
+        let root = load(b"\x04\x08[\x08i\x06{\x06:\x06a[\x06i\x07o:\x09Test\x06:\x07@b0");
+        assert_eq!(format!("{}", root), "Array [ 1, Hash { a: Array [ 2 ] }, Object { class_name: Test, @b: nil } ]");
+        assert_eq!(format!("{:.2}", root), "Array [ 1, Hash { a: ... }, Object { class_name: Test, @b: nil } ]");
+        assert_eq!(format!("{:#.2}", root), "Array [\n  1,\n  Hash {\n    a: ...\n  },\n  Object {\n    class_name: Test,\n    @b: nil\n  }\n]");
+        assert_eq!(format!("{:#1}", Root::of_value(vec![vec![1]]).unwrap()), "Array [\n Array [\n  1\n ]\n]");
+    }
+
+    #[test]
+    fn test_dump_tables() {
+        let root = load(b"\x04\x08[\x08o:\x09Test\x06:\x07@a\"\x06a@\x06c\x09Test");
+        let mut output = String::new();
+        root.dump_tables(&mut output).unwrap();
+
+        let lines: Vec<&str> = output.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(lines[0], "symbols (2):");
+        assert_eq!(lines[1], "       0  :\"Test\"");
+        assert_eq!(lines[2], "       1  :\"@a\"");
+        assert_eq!(lines[3], "objects (4):");
+        assert!(lines[5].split_whitespace().eq(["0", "Array", "-", "3", "1"]));
+        assert!(lines[6].split_whitespace().eq(["1", "Object", "Test", "1", "2"]));
+        assert!(lines[7].split_whitespace().eq(["2", "String", "-", "1", "1"]));
+        assert!(lines[8].split_whitespace().eq(["3", "Class", "Test", "0", "1"]));
+        assert_eq!(lines[9], "root: Array(0)");
+    }
+}
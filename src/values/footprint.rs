@@ -0,0 +1,101 @@
+//! Estimated memory usage of a document, see `Root::memory_footprint()`
+
+use std::{collections::HashMap, mem::size_of};
+
+use crate::diagnostics::{Diagnostic, DiagnosticKind};
+
+use super::*;
+
+impl Root {
+    /// Estimated bytes of memory the document takes up: the `Root` itself and the symbols, objects, strings and
+    /// tables of instance variables and hash pairs it owns. Allocations are counted by their capacity, the overhead of
+    /// the allocator isn't. Symbols shared with a `SymbolTable` or with other Roots are counted by every Root holding
+    /// them. Meant for keeping caches of loaded documents within a memory budget.
+    pub fn memory_footprint(&self) -> usize {
+        let symbols = vec_size(&self.symbols) + self.symbols.iter().map(symbol_size).sum::<usize>();
+        let encoded_symbols = self.encoded_symbols.values()
+            .map(|encoded_symbol| {
+                size_of::<(SymbolID, EncodedSymbol)>() + encoded_symbol.bytes.capacity() + pairs_size(&encoded_symbol.instance_variables)
+            })
+            .sum::<usize>();
+        let objects = vec_size(&self.objects) + self.objects.iter().map(object_size).sum::<usize>();
+        let diagnostics = self.diagnostics.iter()
+            .map(|diagnostic| match &diagnostic.kind {
+                DiagnosticKind::UnknownEncoding(name) => size_of::<Diagnostic>() + name.capacity(),
+                _ => size_of::<Diagnostic>(),
+            })
+            .sum::<usize>();
+        let member_orders = map_size(&self.member_orders) + self.member_orders.values().map(vec_size).sum::<usize>();
+        let duplicate_hash_pairs = map_size(&self.duplicate_hash_pairs) + self.duplicate_hash_pairs.values().map(vec_size).sum::<usize>();
+        size_of::<Root>() + symbols + encoded_symbols + objects + diagnostics + member_orders + duplicate_hash_pairs
+    }
+}
+
+/// Heap usage of an object, not counting its slot in the object table
+fn object_size(object: &RubyObject) -> usize {
+    match object {
+        RubyObject::Incomplete(_) | RubyObject::Unloaded(_) | RubyObject::Float(_) | RubyObject::BigNum(_) => 0,
+        RubyObject::UserMarshal(_) | RubyObject::Data(_) => 0,
+        RubyObject::Array(elements) => vec_size(elements),
+        RubyObject::Hash(hash) => pairs_size(hash),
+        RubyObject::HashWithDefault(hash) => pairs_size(&hash.hash),
+        RubyObject::Class(name) | RubyObject::Module(name) | RubyObject::ClassOrModule(name) => name.capacity(),
+        RubyObject::String(string) => string.string.capacity() + optional_pairs_size(&string.instance_variables),
+        RubyObject::RegExp(regexp) => regexp.pattern.capacity() + optional_pairs_size(&regexp.instance_variables),
+        RubyObject::Struct(ruby_struct) => pairs_size(&ruby_struct.members) + optional_pairs_size(&ruby_struct.instance_variables),
+        RubyObject::Object(object) => pairs_size(&object.instance_variables) + vec_size(&object.extended_modules),
+        RubyObject::UserClass(user_class) => optional_pairs_size(&user_class.instance_variables),
+        RubyObject::UserDefined(user_defined) => {
+            user_defined.data.capacity() + optional_pairs_size(&user_defined.instance_variables) + vec_size(&user_defined.extended_modules)
+        }
+        RubyObject::Raw(raw) => raw.bytes.capacity(),
+    }
+}
+
+/// The text of an `Rc<str>` or `Arc<str>` is stored after its two reference counts
+fn symbol_size(symbol: &SymbolStr) -> usize {
+    2 * size_of::<usize>() + symbol.len()
+}
+
+fn vec_size<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+/// An `IndexMap` keeps its entries with their hashes in a vector and their positions in a hash table
+fn pairs_size<K, V>(pairs: &IndexMap<K, V>) -> usize {
+    pairs.capacity() * (size_of::<(usize, K, V)>() + size_of::<usize>() + 1)
+}
+
+fn optional_pairs_size<K, V>(pairs: &Option<IndexMap<K, V>>) -> usize {
+    pairs.as_ref().map_or(0, pairs_size)
+}
+
+/// A `HashMap` stores its entries in place with a control byte each
+fn map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_footprint() {
+        assert_eq!(Root::nil().memory_footprint(), size_of::<Root>());
+
+        let document = |len| {
+            let objects = vec![RubyObject::Array(vec![RubyValue::String(1)]), RubyObject::String(RubyString::new(vec![b'a'; len]))];
+            Root::new(RubyValue::Array(0), vec![SymbolStr::from("E")], objects)
+        };
+        let (small, large) = (document(10), document(10_000));
+        assert!(small.memory_footprint() > size_of::<Root>() + 10 + 2 * size_of::<RubyObject>());
+        assert_eq!(large.memory_footprint() - small.memory_footprint(), 10_000 - 10);
+
+        // instance variables and the recorded member order are counted
+        let mut with_ivars = document(10);
+        let RubyObject::String(string) = with_ivars.get_mut_object(1).unwrap() else { unreachable!() };
+        string.set_instance_variables(ValuePairsSymbolKeys::from([(0, RubyValue::Boolean(true))]));
+        with_ivars.set_member_order(1, vec![0]);
+        assert!(with_ivars.memory_footprint() >= small.memory_footprint() + size_of::<(usize, SymbolID, RubyValue)>() + size_of::<SymbolID>());
+    }
+}
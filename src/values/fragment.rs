@@ -0,0 +1,113 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+use super::*;
+
+/// Moves the tables of a separately loaded `Root` into another one, see `Root::append()`
+pub(super) struct FragmentImporter {
+    /// fragment symbol id to symbol id in the target, symbols are interned
+    symbol_ids: Vec<SymbolID>,
+    /// fragment objects are appended, their ids are offset by the target's object count
+    object_base: ObjectID,
+}
+
+impl FragmentImporter {
+    pub(super) fn import(target: &mut Root, fragment: Root) -> RubyValue {
+        // looking up every symbol in the target's table one by one gets slow for large merges
+        let mut known: HashMap<SymbolStr, SymbolID> = HashMap::with_capacity(target.symbols.len());
+        for (symbol_id, name) in target.symbols.iter().enumerate() {
+            known.entry(name.clone()).or_insert(symbol_id);
+        }
+        let symbol_ids = fragment.symbols.iter()
+            .map(|name| *known.entry(name.clone()).or_insert_with(|| {
+                target.symbols.push(name.clone());
+                target.symbols.len() - 1
+            }))
+            .collect();
+        let importer = Self { symbol_ids, object_base: target.objects.len() };
+
+        target.objects.reserve(fragment.objects.len());
+        for object in fragment.objects {
+            target.objects.push(importer.object(object));
+        }
+        importer.value(&fragment.root)
+    }
+
+    fn symbol(&self, symbol_id: SymbolID) -> SymbolID {
+        // references to missing symbols stay dangling
+        self.symbol_ids.get(symbol_id).copied().unwrap_or(SymbolID::MAX)
+    }
+
+    fn value(&self, value: &RubyValue) -> RubyValue {
+        match value {
+            RubyValue::Symbol(symbol_id) => RubyValue::Symbol(self.symbol(*symbol_id)),
+            RubyValue::WithIvars(wrapper) => {
+                let (inner, ivars) = &**wrapper;
+                self.value(inner).with_ivars(ivars.iter().map(|(name, value)| (self.symbol(*name), self.value(value))).collect())
+            }
+            _ => match value.object_id() {
+                Some(object_id) => value.with_object_id(object_id + self.object_base),
+                None => value.clone(),
+            },
+        }
+    }
+
+    fn pairs(&self, pairs: ValuePairs) -> ValuePairs {
+        pairs.iter().map(|(key, value)| (self.value(key), self.value(value))).collect()
+    }
+
+    fn symbol_pairs(&self, pairs: ValuePairsSymbolKeys) -> ValuePairsSymbolKeys {
+        pairs.iter().map(|(name, value)| (self.symbol(*name), self.value(value))).collect()
+    }
+
+    fn optional_symbol_pairs(&self, pairs: Option<ValuePairsSymbolKeys>) -> Option<ValuePairsSymbolKeys> {
+        pairs.map(|pairs| self.symbol_pairs(pairs))
+    }
+
+    fn object(&self, object: RubyObject) -> RubyObject {
+        match object {
+            RubyObject::Array(elements) => RubyObject::Array(elements.iter().map(|element| self.value(element)).collect()),
+            RubyObject::Hash(hash) => RubyObject::Hash(self.pairs(hash)),
+            RubyObject::HashWithDefault(hash) => {
+                let default = self.value(&hash.default);
+                RubyObject::HashWithDefault(HashWithDefault::new(self.pairs(hash.hash), default))
+            }
+            RubyObject::String(mut string) => {
+                string.instance_variables = self.optional_symbol_pairs(string.instance_variables);
+                RubyObject::String(string)
+            }
+            RubyObject::RegExp(mut regexp) => {
+                regexp.instance_variables = self.optional_symbol_pairs(regexp.instance_variables);
+                RubyObject::RegExp(regexp)
+            }
+            RubyObject::Struct(mut ruby_struct) => {
+                ruby_struct.name = self.symbol(ruby_struct.name);
+                ruby_struct.members = self.symbol_pairs(ruby_struct.members);
+                ruby_struct.instance_variables = self.optional_symbol_pairs(ruby_struct.instance_variables);
+                RubyObject::Struct(ruby_struct)
+            }
+            RubyObject::Object(object) => {
+                let mut imported = Object::new(self.symbol(object.class_name), self.symbol_pairs(object.instance_variables));
+                imported.extended_modules = object.extended_modules.iter().map(|module| self.symbol(*module)).collect();
+                RubyObject::Object(imported)
+            }
+            RubyObject::UserClass(mut user_class) => {
+                user_class.name = self.symbol(user_class.name);
+                user_class.wrapped_object = self.value(&user_class.wrapped_object);
+                user_class.instance_variables = self.optional_symbol_pairs(user_class.instance_variables);
+                RubyObject::UserClass(user_class)
+            }
+            RubyObject::UserDefined(mut user_defined) => {
+                user_defined.class_name = self.symbol(user_defined.class_name);
+                user_defined.instance_variables = self.optional_symbol_pairs(user_defined.instance_variables);
+                user_defined.extended_modules = user_defined.extended_modules.iter().map(|module| self.symbol(*module)).collect();
+                RubyObject::UserDefined(user_defined)
+            }
+            RubyObject::UserMarshal(user_marshal) => {
+                let class_name = self.symbol(user_marshal.class_name);
+                RubyObject::UserMarshal(UserMarshal::new(class_name, self.value(&user_marshal.wrapped_object)))
+            }
+            RubyObject::Data(data) => RubyObject::Data(Data::new(self.symbol(data.class_name), self.value(&data.state))),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Numeric grids packed into the payload of a UserDefined object, like the RGSS `Table` holding map and tileset data
+
+use super::*;
+
+/// Bytes of the header: the number of dimensions, the sizes along x, y and z and the element count, each a
+/// little-endian i32
+const HEADER_LEN: usize = 20;
+
+/// Elements of a grid, stored little-endian
+trait GridElement: Copy {
+    const SIZE: usize;
+
+    fn from_le_slice(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_grid_element {
+    ($($element_type:ty),*) => {
+        $(
+            impl GridElement for $element_type {
+                const SIZE: usize = std::mem::size_of::<$element_type>();
+
+                fn from_le_slice(bytes: &[u8]) -> Option<Self> {
+                    bytes.try_into().ok().map(<$element_type>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_grid_element!(u8, i16, u16, u32, i32);
+
+/// Grid of up to three dimensions unpacked from a payload, see `UserDefined::as_i16_grid()`. Elements are stored in
+/// row-major order, x varies fastest, then y, then z.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Grid<T> {
+    dimensions: usize,
+    sizes: [usize; 3],
+    values: Vec<T>,
+}
+
+impl<T: Copy> Grid<T> {
+    /// Number of dimensions given by the header, between 1 and 3
+    pub fn get_dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Sizes along x, y and z, unused dimensions have size 1
+    pub fn get_sizes(&self) -> [usize; 3] {
+        self.sizes
+    }
+
+    pub fn get_values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<T> {
+        self.values
+    }
+
+    /// Element at `x`, `y` and `z`, pass 0 for unused dimensions
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<T> {
+        let [xsize, ysize, zsize] = self.sizes;
+        if x >= xsize || y >= ysize || z >= zsize {
+            return None;
+        }
+        self.values.get(x + xsize * (y + ysize * z)).copied()
+    }
+
+    /// The `xsize` elements of row `y` in layer `z`
+    pub fn row(&self, y: usize, z: usize) -> Option<&[T]> {
+        let [xsize, ysize, zsize] = self.sizes;
+        if y >= ysize || z >= zsize {
+            return None;
+        }
+        let start = xsize * (y + ysize * z);
+        self.values.get(start..start + xsize)
+    }
+}
+
+impl UserDefined {
+    /// Unpacks a grid of `dimensions` dimensions with i16 elements, the layout RGSS writes `Table` objects in: a
+    /// header of five little-endian i32 (the number of dimensions, the sizes along x, y and z and the element count)
+    /// followed by the elements
+    pub fn as_i16_grid(&self, dimensions: usize) -> Result<Grid<i16>, ConvertError> {
+        self.grid(dimensions)
+    }
+
+    /// Like `as_i16_grid()` for u8 elements
+    pub fn as_u8_grid(&self, dimensions: usize) -> Result<Grid<u8>, ConvertError> {
+        self.grid(dimensions)
+    }
+
+    /// Like `as_i16_grid()` for u16 elements
+    pub fn as_u16_grid(&self, dimensions: usize) -> Result<Grid<u16>, ConvertError> {
+        self.grid(dimensions)
+    }
+
+    /// Like `as_i16_grid()` for u32 elements
+    pub fn as_u32_grid(&self, dimensions: usize) -> Result<Grid<u32>, ConvertError> {
+        self.grid(dimensions)
+    }
+
+    fn grid<T: GridElement>(&self, dimensions: usize) -> Result<Grid<T>, ConvertError> {
+        let invalid = |reason: String| Err(ConvertError::InvalidPayload(reason));
+        if self.is_truncated() {
+            return invalid("payload is truncated".to_string());
+        }
+        let header_field = |index: usize| self.data.get(index * 4..index * 4 + 4).and_then(i32::from_le_slice);
+        let header = (0..HEADER_LEN / 4).map(header_field).collect::<Option<Vec<i32>>>();
+        let Some(header) = header else {
+            return invalid(format!("{} bytes are too short for a grid header", self.data.len()));
+        };
+        let fields: Vec<_> = header.iter().map(|&field| usize::try_from(field)).collect();
+        let [Ok(found_dimensions), Ok(xsize), Ok(ysize), Ok(zsize), Ok(count)] = fields[..] else {
+            return invalid(format!("grid header {:?} holds a negative number", header));
+        };
+        if found_dimensions != dimensions {
+            return invalid(format!("expected a grid of {} dimensions, found {}", dimensions, found_dimensions));
+        }
+        if xsize.checked_mul(ysize).and_then(|size| size.checked_mul(zsize)) != Some(count) {
+            return invalid(format!("grid of {}x{}x{} doesn't hold {} elements", xsize, ysize, zsize, count));
+        }
+        let elements = &self.data[HEADER_LEN..];
+        if count.checked_mul(T::SIZE) != Some(elements.len()) {
+            return invalid(format!("{} bytes don't hold {} elements of {} bytes", elements.len(), count, T::SIZE));
+        }
+
+        let values = elements.chunks_exact(T::SIZE).filter_map(T::from_le_slice).collect();
+        Ok(Grid { dimensions, sizes: [xsize, ysize, zsize], values })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(header: [i32; 5], elements: &[u8]) -> UserDefined {
+        let mut data: Vec<u8> = header.iter().flat_map(|field| field.to_le_bytes()).collect();
+        data.extend_from_slice(elements);
+        UserDefined::new(0, data)
+    }
+
+    #[test]
+    fn test_grid() {
+        // a 3x2 map of tile ids
+        let elements: Vec<u8> = [1i16, 2, 3, -4, 5, 6].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let grid = table([2, 3, 2, 1, 6], &elements).as_i16_grid(2).unwrap();
+        assert_eq!(grid.get_sizes(), [3, 2, 1]);
+        assert_eq!(grid.get(0, 1, 0), Some(-4));
+        assert_eq!(grid.get(3, 0, 0), None);
+        assert_eq!(grid.row(1, 0), Some(&[-4, 5, 6][..]));
+        assert_eq!(grid.row(2, 0), None);
+
+        assert_eq!(table([1, 4, 1, 1, 4], &[7, 8, 9, 10]).as_u8_grid(1).unwrap().into_values(), [7, 8, 9, 10]);
+        assert_eq!(table([1, 1, 1, 1, 1], &[1, 0, 0, 1]).as_u32_grid(1).unwrap().get_values(), [0x0100_0001]);
+        assert_eq!(table([3, 1, 1, 2, 2], &[1, 0, 2, 0]).as_u16_grid(3).unwrap().get(0, 0, 1), Some(2));
+
+        let error = |user_defined: UserDefined| user_defined.as_i16_grid(2).unwrap_err().to_string();
+        assert_eq!(error(table([1, 6, 1, 1, 6], &elements)), "Invalid payload: expected a grid of 2 dimensions, found 1");
+        assert_eq!(error(table([2, 3, 3, 1, 6], &elements)), "Invalid payload: grid of 3x3x1 doesn't hold 6 elements");
+        assert_eq!(error(table([2, 3, 2, 1, 6], &elements[..11])), "Invalid payload: 11 bytes don't hold 6 elements of 2 bytes");
+        assert_eq!(error(table([2, -3, 2, 1, 6], &elements)), "Invalid payload: grid header [2, -3, 2, 1, 6] holds a negative number");
+        assert_eq!(error(UserDefined::new(0, vec![2, 0, 0, 0])), "Invalid payload: 4 bytes are too short for a grid header");
+        assert_eq!(error(UserDefined::truncated(0, elements.clone(), 100)), "Invalid payload: payload is truncated");
+    }
+}
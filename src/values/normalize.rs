@@ -0,0 +1,206 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::encode::dump::Dumper;
+
+use super::*;
+
+/// Contents and instance variables of a string, strings with equal keys are merged
+type StringKey = (Vec<u8>, Vec<(SymbolID, RubyValue)>);
+
+/// Builds the canonical form of a `Root`, see `Root::normalize()`
+pub(super) struct Normalizer<'a> {
+    source: &'a Root,
+    symbols: Vec<SymbolStr>,
+    /// source symbol id to normalized symbol id
+    symbol_ids: HashMap<SymbolID, SymbolID>,
+    /// `None` while the object is still being built
+    objects: Vec<Option<RubyObject>>,
+    /// source object id to normalized object id
+    object_ids: HashMap<ObjectID, ObjectID>,
+    /// strings already added
+    strings: HashMap<StringKey, ObjectID>,
+    /// source strings whose instance variables are being visited
+    strings_in_progress: HashSet<ObjectID>,
+}
+
+impl<'a> Normalizer<'a> {
+    pub(super) fn new(source: &'a Root) -> Self {
+        Self {
+            source,
+            symbols: Vec::new(),
+            symbol_ids: HashMap::new(),
+            objects: Vec::new(),
+            object_ids: HashMap::new(),
+            strings: HashMap::new(),
+            strings_in_progress: HashSet::new(),
+        }
+    }
+
+    // every slot is filled before the visit() call that created it returns
+    #[allow(clippy::expect_used)]
+    pub(super) fn normalize(mut self) -> Root {
+        let root = self.visit(&self.source.root);
+        let objects = self.objects.into_iter().map(|object| object.expect("object left incomplete")).collect();
+        Root::new(root, self.symbols, objects)
+    }
+
+    fn symbol(&mut self, symbol_id: SymbolID) -> SymbolID {
+        if let Some(normalized) = self.symbol_ids.get(&symbol_id) {
+            return *normalized;
+        }
+        let name = self.source.get_symbol(symbol_id).cloned().unwrap_or_else(|| format!("<missing symbol {}>", symbol_id).into());
+        self.symbols.push(name);
+        self.symbol_ids.insert(symbol_id, self.symbols.len() - 1);
+        self.symbols.len() - 1
+    }
+
+    fn visit(&mut self, value: &RubyValue) -> RubyValue {
+        let object_id = match value {
+            RubyValue::Symbol(symbol_id) => return RubyValue::Symbol(self.symbol(*symbol_id)),
+            RubyValue::WithIvars(wrapper) => {
+                let (inner, ivars) = &**wrapper;
+                let inner = self.visit(inner);
+                let ivars = ivars.iter().map(|(name, value)| (self.symbol(*name), self.visit(value))).collect();
+                return inner.with_ivars(ivars);
+            }
+            _ => match value.object_id() {
+                Some(object_id) => object_id,
+                None => return value.clone(),
+            },
+        };
+        // references to missing objects can't be represented
+        let Some(object) = self.source.get_object(object_id) else {
+            return RubyValue::Nil;
+        };
+        if let Some(normalized) = self.object_ids.get(&object_id) {
+            return object.kind().to_value(*normalized);
+        }
+
+        if let RubyObject::String(string) = object {
+            if self.strings_in_progress.contains(&object_id) {
+                // the string references itself through its instance variables, visit_string() fills the slot
+                self.objects.push(None);
+                self.object_ids.insert(object_id, self.objects.len() - 1);
+                return RubyValue::String(self.objects.len() - 1);
+            }
+            return self.visit_string(object_id, string);
+        }
+
+        let normalized_id = self.objects.len();
+        self.objects.push(None);
+        self.object_ids.insert(object_id, normalized_id);
+
+        let normalized = match object {
+            RubyObject::Array(elements) => RubyObject::Array(elements.iter().map(|element| self.visit(element)).collect()),
+            RubyObject::Hash(hash) => RubyObject::Hash(self.visit_hash(hash)),
+            RubyObject::HashWithDefault(hash) => {
+                let pairs = self.visit_hash(&hash.hash);
+                RubyObject::HashWithDefault(HashWithDefault::new(pairs, self.visit(&hash.default)))
+            }
+            RubyObject::RegExp(regexp) => {
+                let mut normalized = RegExp::new(regexp.pattern.clone(), regexp.options);
+                normalized.instance_variables = self.visit_optional_instance_variables(&regexp.instance_variables);
+                RubyObject::RegExp(normalized)
+            }
+            RubyObject::Struct(ruby_struct) => {
+                let name = self.symbol(ruby_struct.name);
+                // struct members keep their order, Ruby checks it when loading
+                let members = ruby_struct.members.iter().map(|(member, value)| (self.symbol(*member), self.visit(value))).collect();
+                let mut normalized = Struct::new(name, members);
+                normalized.instance_variables = self.visit_optional_instance_variables(&ruby_struct.instance_variables);
+                RubyObject::Struct(normalized)
+            }
+            RubyObject::Object(object) => {
+                // extended modules are written before the class name
+                let extended_modules = object.extended_modules.iter().map(|module| self.symbol(*module)).collect();
+                let class_name = self.symbol(object.class_name);
+                let mut normalized = Object::new(class_name, self.visit_instance_variables(&object.instance_variables));
+                normalized.extended_modules = extended_modules;
+                RubyObject::Object(normalized)
+            }
+            RubyObject::UserClass(user_class) => {
+                let mut normalized = UserClass::new(self.symbol(user_class.name), self.visit(&user_class.wrapped_object));
+                normalized.instance_variables = self.visit_optional_instance_variables(&user_class.instance_variables);
+                RubyObject::UserClass(normalized)
+            }
+            RubyObject::UserDefined(user_defined) => {
+                let extended_modules = user_defined.extended_modules.iter().map(|module| self.symbol(*module)).collect();
+                let mut normalized = UserDefined::new(self.symbol(user_defined.class_name), user_defined.data.clone());
+                normalized.extended_modules = extended_modules;
+                normalized.original_len = user_defined.original_len;
+                normalized.instance_variables = self.visit_optional_instance_variables(&user_defined.instance_variables);
+                RubyObject::UserDefined(normalized)
+            }
+            RubyObject::UserMarshal(user_marshal) => {
+                let class_name = self.symbol(user_marshal.class_name);
+                RubyObject::UserMarshal(UserMarshal::new(class_name, self.visit(&user_marshal.wrapped_object)))
+            }
+            RubyObject::Data(data) => {
+                let class_name = self.symbol(data.class_name);
+                RubyObject::Data(Data::new(class_name, self.visit(&data.state)))
+            }
+            other => other.clone(),
+        };
+        let kind = normalized.kind();
+        self.objects[normalized_id] = Some(normalized);
+        kind.to_value(normalized_id)
+    }
+
+    /// Equal strings (same bytes and instance variables) are merged into one object, except for strings referencing
+    /// themselves
+    fn visit_string(&mut self, object_id: ObjectID, string: &RubyString) -> RubyValue {
+        self.strings_in_progress.insert(object_id);
+        let instance_variables = self.visit_optional_instance_variables(&string.instance_variables);
+        self.strings_in_progress.remove(&object_id);
+        let key: StringKey = (
+            string.string.clone(),
+            instance_variables.iter().flatten().map(|(name, value)| (*name, value.clone())).collect(),
+        );
+        if let Some(normalized_id) = self.object_ids.get(&object_id).copied() {
+            let mut normalized = RubyString::new(string.string.clone());
+            normalized.instance_variables = instance_variables;
+            self.objects[normalized_id] = Some(RubyObject::String(normalized));
+            self.strings.entry(key).or_insert(normalized_id);
+            return RubyValue::String(normalized_id);
+        }
+        let normalized_id = match self.strings.get(&key) {
+            Some(normalized_id) => *normalized_id,
+            None => {
+                let mut normalized = RubyString::new(string.string.clone());
+                normalized.instance_variables = instance_variables;
+                self.objects.push(Some(RubyObject::String(normalized)));
+                self.strings.insert(key, self.objects.len() - 1);
+                self.objects.len() - 1
+            }
+        };
+        self.object_ids.insert(object_id, normalized_id);
+        RubyValue::String(normalized_id)
+    }
+
+    /// Pairs are sorted by the Marshal encoding of their key, which only depends on the key's contents
+    fn visit_hash(&mut self, hash: &ValuePairs) -> ValuePairs {
+        let mut pairs: Vec<(Vec<u8>, &RubyValue, &RubyValue)> = hash.iter()
+            .map(|(key, value)| {
+                let mut encoded_key = Vec::new();
+                // keys that can't be encoded (invalid references) sort first
+                let _ = Dumper::new(&mut encoded_key).dump(self.source, key);
+                (encoded_key, key, value)
+            })
+            .collect();
+        pairs.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        pairs.into_iter().map(|(_, key, value)| (self.visit(key), self.visit(value))).collect()
+    }
+
+    /// Instance variables are sorted by name
+    fn visit_instance_variables(&mut self, instance_variables: &ValuePairsSymbolKeys) -> ValuePairsSymbolKeys {
+        let mut sorted: Vec<(&SymbolID, &RubyValue)> = instance_variables.iter().collect();
+        sorted.sort_by_key(|(name, _)| self.source.get_symbol(**name));
+        sorted.into_iter().map(|(name, value)| (self.symbol(*name), self.visit(value))).collect()
+    }
+
+    fn visit_optional_instance_variables(&mut self, instance_variables: &Option<ValuePairsSymbolKeys>) -> Option<ValuePairsSymbolKeys> {
+        instance_variables.as_ref().map(|instance_variables| self.visit_instance_variables(instance_variables))
+    }
+}
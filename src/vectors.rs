@@ -0,0 +1,230 @@
+//! Categorized test inputs covering the whole format (every tag, fixnum boundaries, links, cycles, encodings and
+//! malformed data), written to a directory by `marshr gen-vectors` so other Marshal implementations can use them as a
+//! conformance suite. Every input is a complete document as Ruby 3.x writes it, malformed ones name the `ErrorCode`
+//! loading them fails with.
+
+use std::{io, path::Path};
+
+use crate::{error_code::ErrorCode, export::json_string, fixnum, spec::*};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TestVector {
+    /// directory the vector is written to, like `tags` or `fixnums`
+    pub category: &'static str,
+    /// file name without extension, unique within the category
+    pub name: String,
+    /// the Ruby value (or the defect of malformed input) in words
+    pub description: String,
+    /// the document including the version header
+    pub bytes: Vec<u8>,
+    /// `None` for valid input, which dumps back to the same bytes
+    pub error: Option<ErrorCode>,
+}
+
+impl TestVector {
+    fn valid(category: &'static str, name: &str, description: &str, body: &[u8]) -> Self {
+        let mut bytes = MARSHAL_VERSION.to_vec();
+        bytes.extend_from_slice(body);
+        Self { category, name: name.to_string(), description: description.to_string(), bytes, error: None }
+    }
+
+    fn malformed(name: &str, description: &str, bytes: &[u8], error: ErrorCode) -> Self {
+        Self { category: "errors", name: name.to_string(), description: description.to_string(), bytes: bytes.to_vec(), error: Some(error) }
+    }
+
+    /// Description of the vector as a JSON object, `file` is the path of the input relative to the output directory
+    pub fn to_json(&self) -> String {
+        let hex: String = self.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let error = self.error.map_or("null".to_string(), |error| json_string(error.as_str()));
+        format!(
+            "{{\"category\": {}, \"name\": {}, \"description\": {}, \"file\": {}, \"hex\": \"{}\", \"valid\": {}, \"error\": {}}}",
+            json_string(self.category),
+            json_string(&self.name),
+            json_string(&self.description),
+            json_string(&format!("{}/{}.bin", self.category, self.name)),
+            hex,
+            self.error.is_none(),
+            error,
+        )
+    }
+}
+
+/// All vectors, grouped by category
+pub fn test_vectors() -> Vec<TestVector> {
+    let mut vectors = tag_vectors();
+    vectors.extend(fixnum_vectors());
+    vectors.extend(link_vectors());
+    vectors.extend(cycle_vectors());
+    vectors.extend(encoding_vectors());
+    vectors.extend(error_vectors());
+    vectors
+}
+
+/// One document per tag, named after `tag_name()`
+fn tag_vectors() -> Vec<TestVector> {
+    let vector = |tag: u8, description: &str, body: &[u8]| {
+        let name = tag_name(tag).unwrap_or("unknown").replace(' ', "_");
+        TestVector::valid("tags", &name, description, body)
+    };
+    vec![
+        vector(TAG_NIL, "nil", b"0"),
+        vector(TAG_TRUE, "true", b"T"),
+        vector(TAG_FALSE, "false", b"F"),
+        vector(TAG_FIXNUM, "1", b"i\x06"),
+        vector(TAG_SYMBOL, ":a", b":\x06a"),
+        vector(TAG_SYMBOL_LINK, "[:a, :a]", b"[\x07:\x06a;\x00"),
+        vector(TAG_OBJECT_LINK, "a = [], [a, a]", b"[\x07[\x00@\x06"),
+        vector(TAG_INSTANCE_VARIABLES, "\"a\" (UTF-8)", b"I\"\x06a\x06:\x06ET"),
+        vector(TAG_EXTENDED, "Object.new.extend(Comparable)", b"e:\x0fComparableo:\x0bObject\x00"),
+        vector(TAG_ARRAY, "[1, 2]", b"[\x07i\x06i\x07"),
+        vector(TAG_BIGNUM, "2**40", b"l+\x08\x00\x00\x00\x00\x00\x01"),
+        vector(TAG_CLASS, "String", b"c\x0bString"),
+        vector(TAG_MODULE, "Kernel", b"m\x0bKernel"),
+        vector(TAG_CLASS_OR_MODULE, "Kernel as written by Ruby 1.8 and older", b"M\x0bKernel"),
+        vector(TAG_DATA, "a T_DATA object of class Point with the state [1, 2] (from _dump_data)", b"d:\x0aPoint[\x07i\x06i\x07"),
+        vector(TAG_FLOAT, "1.5", b"f\x081.5"),
+        vector(TAG_HASH, "{1=>2}", b"{\x06i\x06i\x07"),
+        vector(TAG_HASH_WITH_DEFAULT, "Hash.new(0) with 1=>2", b"}\x06i\x06i\x07i\x00"),
+        vector(TAG_OBJECT, "Point with @x=1, @y=2", b"o:\x0aPoint\x07:\x07@xi\x06:\x07@yi\x07"),
+        vector(TAG_REGEXP, "/a/i (US-ASCII)", b"I/\x06a\x01\x06:\x06EF"),
+        vector(TAG_STRING, "\"a\" (ASCII-8BIT)", b"\"\x06a"),
+        vector(TAG_STRUCT, "Struct.new(:a).new(1) named Point", b"S:\x0aPoint\x06:\x06ai\x06"),
+        vector(TAG_USER_CLASS, "a String subclass Name holding \"a\" (ASCII-8BIT)", b"C:\x09Name\"\x06a"),
+        vector(TAG_USER_DEFINED, "a Point whose _dump returns \"1,2\"", b"u:\x0aPoint\x081,2"),
+        vector(TAG_USER_MARSHAL, "a Point whose marshal_dump returns [1, 2]", b"U:\x0aPoint[\x07i\x06i\x07"),
+    ]
+}
+
+/// Fixnums at the edges of every encoded length and the bignums right outside the fixnum range
+fn fixnum_vectors() -> Vec<TestVector> {
+    let boundaries = [
+        0, 1, SMALL_FIXNUM_MAX, SMALL_FIXNUM_MAX + 1, 0xff, 0x100, 0xffff, 0x1_0000, 0xff_ffff, 0x100_0000, FIXNUM_MAX,
+        -1, SMALL_FIXNUM_MIN, SMALL_FIXNUM_MIN - 1, -0x100, -0x101, -0x1_0000, -0x1_0001, -0x100_0000, -0x100_0001,
+        FIXNUM_MIN,
+    ];
+    let mut vectors: Vec<TestVector> = boundaries.iter().map(|number| {
+        let mut body = vec![TAG_FIXNUM];
+        body.extend_from_slice(fixnum::encode(*number).as_bytes());
+        let name = if *number < 0 { format!("minus_{}", number.unsigned_abs()) } else { number.to_string() };
+        TestVector::valid("fixnums", &name, &number.to_string(), &body)
+    }).collect();
+    vectors.push(TestVector::valid("fixnums", "bignum_max_plus_1", "2**30, the smallest positive bignum", b"l+\x07\x00\x00\x00\x40"));
+    vectors.push(TestVector::valid("fixnums", "bignum_min_minus_1", "-(2**30) - 1, the largest negative bignum", b"l-\x07\x01\x00\x00\x40"));
+    vectors
+}
+
+fn link_vectors() -> Vec<TestVector> {
+    vec![
+        TestVector::valid("links", "shared_string", "s = \"a\", [s, s]", b"[\x07I\"\x06a\x06:\x06ET@\x06"),
+        TestVector::valid("links", "equal_strings", "[\"a\", \"a\"], equal but separate objects", b"[\x07I\"\x06a\x06:\x06ETI\"\x06a\x06;\x00T"),
+        TestVector::valid(
+            "links", "symbol_links_in_objects", "[Point(@x=1), Point(@x=2)], class and instance variable names are linked",
+            b"[\x07o:\x0aPoint\x06:\x07@xi\x06o;\x00\x06;\x06i\x07",
+        ),
+        // floats are objects, the second occurrence of the same float object is a link
+        TestVector::valid("links", "shared_float", "f = 1.5, [f, f]", b"[\x07f\x081.5@\x06"),
+        TestVector::valid(
+            "links", "link_after_user_defined", "d = Point._load(\"1\"), [d, \"a\", d], the user defined object counts after its data",
+            b"[\x08u:\x0aPoint\x061\"\x06a@\x06",
+        ),
+    ]
+}
+
+fn cycle_vectors() -> Vec<TestVector> {
+    vec![
+        TestVector::valid("cycles", "array_in_itself", "a = []; a << a", b"[\x06@\x00"),
+        TestVector::valid("cycles", "hash_value_itself", "h = {}; h[1] = h", b"{\x06i\x06@\x00"),
+        TestVector::valid("cycles", "object_ivar_itself", "o = Node.new; o.next = o", b"o:\x09Node\x06:\x0a@next@\x00"),
+        TestVector::valid(
+            "cycles", "two_objects", "a = Node.new; b = Node.new; a.next = b; b.next = a",
+            b"o:\x09Node\x06:\x0a@nexto;\x00\x06;\x06@\x00",
+        ),
+    ]
+}
+
+fn encoding_vectors() -> Vec<TestVector> {
+    vec![
+        TestVector::valid("encodings", "utf_8", "\"é\" (UTF-8, E=true)", b"I\"\x07\xc3\xa9\x06:\x06ET"),
+        TestVector::valid("encodings", "us_ascii", "\"a\" (US-ASCII, E=false)", b"I\"\x06a\x06:\x06EF"),
+        TestVector::valid("encodings", "binary", "\"\\xFF\" (ASCII-8BIT, no instance variables)", b"\"\x06\xff"),
+        TestVector::valid(
+            "encodings", "shift_jis", "\"あ\" (Shift_JIS, named by the encoding instance variable)",
+            b"I\"\x07\x82\xa0\x06:\x0dencoding\"\x0eShift_JIS",
+        ),
+        TestVector::valid("encodings", "utf_8_symbol", ":é, symbols that aren't ASCII carry their encoding", b"I:\x07\xc3\xa9\x06:\x06ET"),
+        TestVector::valid(
+            "encodings", "string_with_encoding_and_ivar", "\"a\" (UTF-8) with @b=1 on the string",
+            b"I\"\x06a\x07:\x06ET:\x07@bi\x06",
+        ),
+    ]
+}
+
+fn error_vectors() -> Vec<TestVector> {
+    vec![
+        TestVector::malformed("empty", "no bytes at all", b"", ErrorCode::UnexpectedEnd),
+        TestVector::malformed("version_5_0", "version 5.0", b"\x05\x000", ErrorCode::UnsupportedVersion),
+        TestVector::malformed("truncated_string", "a string announcing 5 bytes with only 1", b"\x04\x08\"\x0aa", ErrorCode::UnexpectedEnd),
+        TestVector::malformed("unknown_tag", "the byte 'x' where a value starts", b"\x04\x08x", ErrorCode::UnknownTag),
+        TestVector::malformed("bad_symbol_link", "a symbol link without symbols", b"\x04\x08;\x00", ErrorCode::BadSymbolLink),
+        TestVector::malformed("bad_object_link", "an object link without objects", b"\x04\x08@\x00", ErrorCode::BadObjectLink),
+        TestVector::malformed("bignum_sign", "a bignum with the sign byte '*'", b"\x04\x08l*\x06\x01\x00", ErrorCode::BadBignum),
+        TestVector::malformed("negative_length", "an array with -1 elements", b"\x04\x08[\xfa", ErrorCode::BadLength),
+    ]
+}
+
+/// Writes every vector to `<category>/<name>.bin` below `directory` with its description in `<category>/<name>.json`,
+/// and all descriptions to `index.json`. Returns the number of vectors.
+pub fn write_test_vectors(directory: &Path) -> io::Result<usize> {
+    let vectors = test_vectors();
+    let mut index = Vec::with_capacity(vectors.len());
+    for vector in &vectors {
+        let category = directory.join(vector.category);
+        std::fs::create_dir_all(&category)?;
+        std::fs::write(category.join(format!("{}.bin", vector.name)), &vector.bytes)?;
+        std::fs::write(category.join(format!("{}.json", vector.name)), vector.to_json() + "\n")?;
+        index.push(vector.to_json());
+    }
+    std::fs::write(directory.join("index.json"), format!("[\n  {}\n]\n", index.join(",\n  ")))?;
+    Ok(vectors.len())
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{decode::load::Loader, encode::dump::Dumper};
+
+    use super::*;
+
+    #[test]
+    fn test_vectors_load() {
+        let vectors = test_vectors();
+        let names: HashSet<(&str, &str)> = vectors.iter().map(|vector| (vector.category, vector.name.as_str())).collect();
+        assert_eq!(names.len(), vectors.len());
+        for tag in TAGS {
+            let name = tag_name(tag).unwrap().replace(' ', "_");
+            assert!(names.contains(&("tags", name.as_str())), "no vector for {}", name);
+        }
+
+        for vector in vectors {
+            let result = Loader::new(&mut &vector.bytes[..]).load();
+            match vector.error {
+                None => {
+                    let root = result.unwrap_or_else(|error| panic!("{}: {}", vector.name, error));
+                    assert_eq!(Dumper::verify_roundtrip(&root, &vector.bytes).unwrap(), None, "{}", vector.name);
+                }
+                Some(code) => assert_eq!(result.map(|_| ()).unwrap_err().code(), code, "{}", vector.name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let vector = TestVector::malformed("x", "a \"quote\"\n", b"\x04", ErrorCode::UnexpectedEnd);
+        assert_eq!(
+            vector.to_json(),
+            "{\"category\": \"errors\", \"name\": \"x\", \"description\": \"a \\\"quote\\\"\\n\", \"file\": \"errors/x.bin\", \
+             \"hex\": \"04\", \"valid\": false, \"error\": \"E002\"}",
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Typed views of Ruby core classes that Marshal stores as plain objects (`o`), along with constructors building them
+
+pub mod exception;
+pub mod range;
+
+pub use exception::ExceptionView;
+pub use range::RangeView;
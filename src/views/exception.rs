@@ -0,0 +1,131 @@
+use crate::{convert::{ConvertError, FromRubyValue}, values::*};
+
+/// Ruby stores the state of exceptions in instance variables without the `@` prefix
+const MESSAGE: &str = "mesg";
+const BACKTRACE: &str = "bt";
+const CAUSE: &str = "cause";
+
+/// View of an exception object (e.g. `o:\x11RuntimeError` with the instance variables `mesg`, `bt` and `cause`),
+/// any object with a `mesg` instance variable is treated as an exception since the class hierarchy isn't part of the data
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExceptionView<'a> {
+    root: &'a Root,
+    class_name: &'a str,
+    object: &'a Object,
+}
+
+impl<'a> ExceptionView<'a> {
+    pub fn new(root: &'a Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let RubyValue::Object(object_id) = value else {
+            return Err(ConvertError::TypeMismatch { expected: "exception", found: value.kind() });
+        };
+        let Some(RubyObject::Object(object)) = root.get_object(*object_id) else {
+            return Err(ConvertError::InvalidReference(value.kind(), *object_id));
+        };
+        let class_name = root.get_symbol(object.get_class_name())
+            .ok_or(ConvertError::InvalidReference(ValueKind::Symbol, object.get_class_name()))?;
+
+        let view = Self { root, class_name, object };
+        if view.instance_variable(MESSAGE).is_none() {
+            return Err(ConvertError::MissingInstanceVariable { class_name: "Exception", name: MESSAGE });
+        }
+        Ok(view)
+    }
+
+    fn instance_variable(&self, name: &str) -> Option<&'a RubyValue> {
+        let symbol_id = self.root.get_symbol_id(name)?;
+        self.object.get_instance_variable(symbol_id)
+    }
+
+    pub fn class_name(&self) -> &'a str {
+        self.class_name
+    }
+
+    /// `None` if the message is nil (Ruby then uses the class name as message)
+    pub fn message(&self) -> Result<Option<String>, ConvertError> {
+        Option::<String>::from_ruby_value(self.root, self.instance_variable(MESSAGE).unwrap_or(&RubyValue::Nil))
+    }
+
+    /// Backtrace lines, empty for exceptions that were never raised
+    pub fn backtrace(&self) -> Result<Vec<String>, ConvertError> {
+        let value = self.instance_variable(BACKTRACE).unwrap_or(&RubyValue::Nil);
+        match value {
+            RubyValue::Nil => Ok(Vec::new()),
+            RubyValue::Array(object_id) => match self.root.get_object(*object_id) {
+                Some(RubyObject::Array(lines)) => lines.iter().map(|line| String::from_ruby_value(self.root, line)).collect(),
+                _ => Err(ConvertError::InvalidReference(value.kind(), *object_id)),
+            },
+            _ => Err(ConvertError::TypeMismatch { expected: "array", found: value.kind() }),
+        }
+    }
+
+    /// The exception that was being handled when this one was raised
+    pub fn cause(&self) -> Result<Option<ExceptionView<'a>>, ConvertError> {
+        match self.instance_variable(CAUSE) {
+            None | Some(RubyValue::Nil) => Ok(None),
+            Some(cause) => ExceptionView::new(self.root, cause).map(Some),
+        }
+    }
+
+    /// This exception followed by its causes, stops when a cause repeats
+    pub fn cause_chain(&self) -> Result<Vec<ExceptionView<'a>>, ConvertError> {
+        let mut chain = vec![*self];
+        while let Some(cause) = chain.last().unwrap().cause()? {
+            if chain.iter().any(|exception| std::ptr::eq(exception.object, cause.object)) {
+                break;
+            }
+            chain.push(cause);
+        }
+        Ok(chain)
+    }
+}
+
+/// Appends an exception of class `class_name`, the backtrace is left out if it's empty like for exceptions that were never raised
+pub fn add_exception(root: &mut Root, class_name: &str, message: &str, backtrace: &[&str]) -> RubyValue {
+    let message = root.add_string(message);
+    let mut instance_variables = vec![(MESSAGE, message)];
+    if !backtrace.is_empty() {
+        let lines = backtrace.iter().map(|line| root.add_string(line)).collect();
+        instance_variables.push((BACKTRACE, root.add_array(lines)));
+    }
+    root.add_object(class_name, instance_variables)
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::{encode::dump::Dumper, test_util::load};
+
+    use super::*;
+
+    #[test]
+    fn test_exception_view() {
+        // ArgumentError(mesg="bad", bt=["a.rb:1"], cause=RuntimeError(mesg=nil, bt=nil))
+        let root = load(b"\x04\x08o:\x12ArgumentError\x08:\x09mesgI\"\x08bad\x06:\x06ET:\x07bt[\x06I\"\x0ba.rb:1\x06;\x07T:\x0acauseo:\x11RuntimeError\x07;\x060;\x080");
+        let exception = ExceptionView::new(&root, root.get_root()).unwrap();
+        assert_eq!(exception.class_name(), "ArgumentError");
+        assert_eq!(exception.message(), Ok(Some("bad".to_string())));
+        assert_eq!(exception.backtrace(), Ok(vec!["a.rb:1".to_string()]));
+
+        let chain = exception.cause_chain().unwrap();
+        let classes: Vec<&str> = chain.iter().map(|exception| exception.class_name()).collect();
+        assert_eq!(classes, ["ArgumentError", "RuntimeError"]);
+        assert_eq!(chain[1].message(), Ok(None));
+        assert_eq!(chain[1].backtrace(), Ok(Vec::new()));
+        assert_eq!(chain[1].cause(), Ok(None));
+
+        // Object without mesg
+        let root = load(b"\x04\x08o:\x09Test\x06:\x07@ai\x06");
+        assert!(matches!(ExceptionView::new(&root, root.get_root()), Err(ConvertError::MissingInstanceVariable { .. })));
+    }
+
+    #[test]
+    fn test_build_exception() {
+        let mut root = Root::nil();
+        let exception = add_exception(&mut root, "RuntimeError", "boom", &["a.rb:1"]);
+        root.set_root(exception);
+
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(&root, root.get_root()).unwrap();
+        assert_eq!(output, b"\x04\x08o:\x11RuntimeError\x07:\x09mesgI\"\x09boom\x06:\x06ET:\x07bt[\x06I\"\x0ba.rb:1\x06;\x07T");
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::{Range, RangeInclusive};
+
+use crate::{convert::{ConvertError, FromRubyValue, IntoRubyValue}, values::*};
+
+/// Ruby stores the instance variables of ranges without the `@` prefix
+const EXCLUDE_END: &str = "excl";
+const BEGIN: &str = "begin";
+const END: &str = "end";
+
+/// View of a `Range` object (`o:\x0aRange` with the instance variables `excl`, `begin` and `end`)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RangeView<'a> {
+    pub begin: &'a RubyValue,
+    pub end: &'a RubyValue,
+    /// `true` for `a...b`, `false` for `a..b`
+    pub exclude_end: bool,
+}
+
+impl<'a> RangeView<'a> {
+    pub fn new(root: &'a Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let RubyValue::Object(object_id) = value else {
+            return Err(ConvertError::TypeMismatch { expected: "range", found: value.kind() });
+        };
+        let Some(RubyObject::Object(object)) = root.get_object(*object_id) else {
+            return Err(ConvertError::InvalidReference(value.kind(), *object_id));
+        };
+        match root.get_symbol(object.get_class_name()) {
+            Some(class_name) if &**class_name == "Range" => (),
+            Some(class_name) => return Err(ConvertError::ClassMismatch { expected: "Range", found: class_name.to_string() }),
+            None => return Err(ConvertError::InvalidReference(ValueKind::Symbol, object.get_class_name())),
+        }
+
+        let instance_variable = |name: &'static str| {
+            root.get_symbol_id(name)
+                .and_then(|symbol_id| object.get_instance_variable(symbol_id))
+                .ok_or(ConvertError::MissingInstanceVariable { class_name: "Range", name })
+        };
+        let exclude_end = bool::from_ruby_value(root, instance_variable(EXCLUDE_END)?)?;
+        Ok(Self { begin: instance_variable(BEGIN)?, end: instance_variable(END)?, exclude_end })
+    }
+
+    /// Converts both endpoints, `nil` (beginless or endless ranges) only converts to `Option`s
+    pub fn endpoints<T: FromRubyValue>(&self, root: &Root) -> Result<(T, T), ConvertError> {
+        Ok((T::from_ruby_value(root, self.begin)?, T::from_ruby_value(root, self.end)?))
+    }
+}
+
+/// Appends a `Range` object, pass `RubyValue::Nil` as `begin` or `end` for beginless or endless ranges
+pub fn add_range(root: &mut Root, begin: RubyValue, end: RubyValue, exclude_end: bool) -> RubyValue {
+    root.add_object("Range", [(EXCLUDE_END, RubyValue::Boolean(exclude_end)), (BEGIN, begin), (END, end)])
+}
+
+/// Only exclusive ranges (`a...b`) convert to `Range`
+impl<T: FromRubyValue> FromRubyValue for Range<T> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let range = RangeView::new(root, value)?;
+        if !range.exclude_end {
+            return Err(ConvertError::TypeMismatch { expected: "exclusive range", found: value.kind() });
+        }
+        let (start, end) = range.endpoints(root)?;
+        Ok(start..end)
+    }
+}
+
+/// Only inclusive ranges (`a..b`) convert to `RangeInclusive`
+impl<T: FromRubyValue> FromRubyValue for RangeInclusive<T> {
+    fn from_ruby_value(root: &Root, value: &RubyValue) -> Result<Self, ConvertError> {
+        let range = RangeView::new(root, value)?;
+        if range.exclude_end {
+            return Err(ConvertError::TypeMismatch { expected: "inclusive range", found: value.kind() });
+        }
+        let (start, end) = range.endpoints(root)?;
+        Ok(start..=end)
+    }
+}
+
+impl<T: IntoRubyValue> IntoRubyValue for Range<T> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        let begin = self.start.into_ruby_value(root)?;
+        let end = self.end.into_ruby_value(root)?;
+        Ok(add_range(root, begin, end, true))
+    }
+}
+
+impl<T: IntoRubyValue> IntoRubyValue for RangeInclusive<T> {
+    fn into_ruby_value(self, root: &mut Root) -> Result<RubyValue, ConvertError> {
+        let (start, end) = self.into_inner();
+        let begin = start.into_ruby_value(root)?;
+        let end = end.into_ruby_value(root)?;
+        Ok(add_range(root, begin, end, false))
+    }
+}
+
+#[cfg(all(test, feature = "decode", feature = "encode"))]
+mod tests {
+    use crate::{encode::dump::Dumper, test_util::load};
+
+    use super::*;
+
+    fn dump(root: &Root) -> Vec<u8> {
+        let mut output = Vec::new();
+        Dumper::new(&mut output).dump(root, root.get_root()).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_range_view() {
+        // [1..5, 1...5, 1.., "a"]
+        let root = load(b"\x04\x08[\x09o:\x0aRange\x08:\x09exclF:\x0abegini\x06:\x08endi\x0ao;\x00\x08;\x06T;\x07i\x06;\x08i\x0ao;\x00\x08;\x06F;\x07i\x06;\x080I\"\x06a\x06:\x06ET");
+        let elements = root.get_object(0).unwrap().as_array();
+
+        let range = RangeView::new(&root, &elements[0]).unwrap();
+        assert_eq!((range.begin, range.end, range.exclude_end), (&RubyValue::FixNum(1), &RubyValue::FixNum(5), false));
+        assert_eq!(RangeInclusive::<i32>::from_ruby_value(&root, &elements[0]), Ok(1..=5));
+        assert_eq!(Range::<i64>::from_ruby_value(&root, &elements[1]), Ok(1..5));
+        assert!(Range::<i32>::from_ruby_value(&root, &elements[0]).is_err());
+        assert_eq!(RangeView::new(&root, &elements[2]).unwrap().endpoints::<Option<i32>>(&root), Ok((Some(1), None)));
+        assert!(RangeInclusive::<i32>::from_ruby_value(&root, &elements[2]).is_err());
+        assert!(matches!(RangeView::new(&root, &elements[3]), Err(ConvertError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_build_range() {
+        assert_eq!(dump(&Root::of_value(1..=5).unwrap()), b"\x04\x08o:\x0aRange\x08:\x09exclF:\x0abegini\x06:\x08endi\x0a");
+        assert_eq!(dump(&Root::of_value(-1_i64..1_i64 << 40).unwrap()), b"\x04\x08o:\x0aRange\x08:\x09exclT:\x0abegini\xfa:\x08endl+\x08\x00\x00\x00\x00\x00\x01");
+    }
+}
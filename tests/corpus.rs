@@ -0,0 +1,234 @@
+use std::io::BufReader;
+
+use marshr::prelude::*;
+
+fn load(name: &str) -> (Vec<u8>, Root) {
+    let input = std::fs::read(format!("{}/tests/corpus/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+    let mut reader = BufReader::new(&input[..]);
+    let root = Loader::new(&mut reader).load().unwrap();
+    (input, root)
+}
+
+fn assert_round_trip(input: &[u8], root: &Root) {
+    if let Some(mismatch) = Dumper::verify_roundtrip(root, input).unwrap() {
+        panic!("{}", mismatch);
+    }
+}
+
+fn ivar<'a>(root: &'a Root, value: &RubyValue, name: &str) -> &'a RubyValue {
+    let object = root.get_object(value.as_object()).unwrap().as_object();
+    object.get_instance_variable(root.get_symbol_id(name).unwrap()).unwrap()
+}
+
+fn string(root: &Root, value: &RubyValue) -> String {
+    root.decode_string(root.get_object(value.as_string()).unwrap().as_string()).unwrap()
+}
+
+fn hash_get<'a>(root: &'a Root, hash: &'a ValuePairs, key: &str) -> &'a RubyValue {
+    hash.iter()
+        .find(|(hash_key, _)| string(root, hash_key) == key)
+        .map(|(_, value)| value)
+        .unwrap()
+}
+
+fn class_name<'a>(root: &'a Root, value: &RubyValue) -> &'a str {
+    root.get_symbol(root.get_object(value.as_object()).unwrap().as_object().get_class_name()).unwrap()
+}
+
+#[test]
+fn test_rpg_maker_actors() {
+    let (input, root) = load("rpg_maker_actors.rvdata2");
+    assert_round_trip(&input, &root);
+
+    let actors = root.get_object(root.get_root().as_array()).unwrap().as_array();
+    assert_eq!(actors.len(), 4);
+    assert_eq!(actors[0], RubyValue::Nil);
+
+    let names: Vec<String> = actors[1..].iter().map(|actor| string(&root, ivar(&root, actor, "@name"))).collect();
+    assert_eq!(names, ["Eric", "ナタリー", "Terence"]);
+    assert_eq!(string(&root, ivar(&root, &actors[1], "@note")), "");
+
+    let features = root.get_object(ivar(&root, &actors[1], "@features").as_array()).unwrap().as_array();
+    assert_eq!(features.len(), 3);
+    assert_eq!(class_name(&root, &features[0]), "RPG::BaseItem::Feature");
+    let value = ivar(&root, &features[1], "@value");
+    assert_eq!(*root.get_object(value.as_float()).unwrap().as_float(), 0.95);
+}
+
+#[test]
+fn test_rpg_maker_map() {
+    let (input, root) = load("rpg_maker_map.rvdata2");
+    assert_round_trip(&input, &root);
+
+    let map = root.get_root();
+    assert_eq!(class_name(&root, map), "RPG::Map");
+    assert_eq!(ivar(&root, map, "@width").as_fixnum(), 4);
+
+    // Table payload: 5 i32 header values followed by x * y * z i16 values
+    let table = root.get_object(ivar(&root, map, "@data").as_user_defined()).unwrap().as_user_defined();
+    assert_eq!(&**root.get_symbol(table.get_class_name()).unwrap(), "Table");
+    assert_eq!(table.get_data().len(), 20 + 4 * 3 * 2 * 2);
+
+    // the same BGM object is referenced twice, the second time through an object link
+    assert_eq!(ivar(&root, map, "@bgm"), ivar(&root, map, "@battle_bgm"));
+
+    let events = root.get_object(ivar(&root, map, "@events").as_hash()).unwrap().as_hash();
+    let event = &events[&RubyValue::FixNum(1)];
+    assert_eq!(class_name(&root, event), "RPG::Event");
+    assert_eq!(string(&root, ivar(&root, event, "@name")), "EV001");
+}
+
+#[test]
+fn test_rails6_cache_entry() {
+    let (input, root) = load("rails6_cache_entry.bin");
+    assert_round_trip(&input, &root);
+
+    let entry = root.get_root();
+    assert_eq!(class_name(&root, entry), "ActiveSupport::Cache::Entry");
+    assert_eq!(ivar(&root, entry, "@version"), &RubyValue::Nil);
+
+    let value = root.get_object(ivar(&root, entry, "@value").as_hash()).unwrap().as_hash();
+    assert_eq!(hash_get(&root, value, "id").as_fixnum(), 4821);
+    assert_eq!(string(&root, hash_get(&root, value, "name")), "Jane Doe");
+
+    // Time objects are registered after their instance variables, the link must resolve to the Time and not its zone string
+    let created_at = hash_get(&root, value, "created_at");
+    assert_eq!(created_at, hash_get(&root, value, "updated_at"));
+    let time = root.get_object(created_at.as_user_defined()).unwrap().as_user_defined();
+    assert_eq!(&**root.get_symbol(time.get_class_name()).unwrap(), "Time");
+    assert_eq!(time.get_data().len(), 8);
+
+    let balance = hash_get(&root, value, "balance_cents");
+    assert_eq!(*root.get_object(balance.as_bignum()).unwrap().as_bignum(), 12345678901234);
+}
+
+#[test]
+fn test_rails7_cache_entry() {
+    let (input, root) = load("rails7_cache_entry.bin");
+    assert_round_trip(&input, &root);
+
+    let entry = root.get_root();
+    let settings = root.get_object(ivar(&root, entry, "@value").as_user_class()).unwrap().as_user_class();
+    assert_eq!(&**root.get_symbol(settings.get_name()).unwrap(), "ActiveSupport::HashWithIndifferentAccess");
+
+    let hash = root.get_object(settings.get_wrapped_object().as_hash()).unwrap().as_hash();
+    assert_eq!(hash.len(), 4);
+    assert_eq!(string(&root, hash_get(&root, hash, "theme")), "dark");
+
+    let expires_in = ivar(&root, entry, "@expires_in");
+    assert_eq!(*root.get_object(expires_in.as_float()).unwrap().as_float(), 300.0);
+}
+
+#[test]
+fn test_settings_with_default() {
+    let (input, root) = load("settings_with_default.bin");
+    assert_round_trip(&input, &root);
+
+    // a HashWithIndifferentAccess subclass with a default value, the second element links to the first
+    let elements = root.get_object(root.get_root().as_array()).unwrap().as_array();
+    assert_eq!(elements[0], elements[1]);
+    let settings = root.get_object(elements[0].as_user_class()).unwrap().as_user_class();
+    assert_eq!(&**root.get_symbol(settings.get_name()).unwrap(), "Settings");
+    assert!(matches!(settings.get_wrapped_object(), RubyValue::HashWithDefault(_)));
+    let hash = settings.get_wrapped_hash(&root).unwrap();
+    assert_eq!(string(&root, hash_get(&root, hash, "theme")), "dark");
+    assert_eq!(hash_get(&root, hash, "retries").as_fixnum(), 3);
+    assert_eq!(string(&root, settings.get_wrapped_hash_default(&root).unwrap()), "none");
+
+    // the empty one has a nil default and is written with `{`
+    let empty = root.get_object(elements[2].as_user_class()).unwrap().as_user_class();
+    assert!(empty.get_wrapped_hash(&root).unwrap().is_empty());
+    assert_eq!(empty.get_wrapped_hash_default(&root), None);
+}
+
+#[test]
+fn test_gemspec_index() {
+    let (input, root) = load("gemspec_index.bin");
+    assert_round_trip(&input, &root);
+
+    let specs = root.get_object(root.get_root().as_array()).unwrap().as_array();
+    assert_eq!(specs.len(), 6);
+
+    let first = root.get_object(specs[0].as_array()).unwrap().as_array();
+    let version = root.get_object(first[1].as_user_marshal()).unwrap().as_user_marshal();
+    assert_eq!(&**root.get_symbol(version.get_class_name()).unwrap(), "Gem::Version");
+
+    // the "ruby" platform string is shared between the specs
+    let second = root.get_object(specs[1].as_array()).unwrap().as_array();
+    assert_eq!(first[2], second[2]);
+    let platform = root.get_object(first[2].as_string()).unwrap().as_string();
+    assert_eq!(platform.get_string(), b"ruby");
+}
+
+#[test]
+fn test_empty_values() {
+    let (input, root) = load("empty_values.bin");
+    assert_round_trip(&input, &root);
+
+    // {:"" => "", "" => //, :list => [:"", []]}
+    assert_eq!(&**root.get_symbol(0).unwrap(), "");
+    assert_eq!(root.get_diagnostics().iter().filter(|diagnostic| diagnostic.kind == DiagnosticKind::EmptySymbol).count(), 1);
+    assert_eq!(format!("{}", root), "Hash { :\"\": \"\", \"\": RegExp { pattern: //, options: 0, E: false }, list: Array [ :\"\", Array [] ] }");
+
+    // the symbol key comes first, the string key is reached through the pairs
+    let hash = root.get_object(root.get_root().as_hash()).unwrap().as_hash();
+    assert_eq!(root.dig(root.get_root(), &[""]), Some(&RubyValue::String(1)));
+    let (string_key, regexp) = hash.get_index(1).unwrap();
+    assert_eq!(string(&root, string_key), "");
+    assert_eq!(root.get_object(regexp.as_regexp()).unwrap().as_regexp().get_pattern(), "");
+    assert_eq!(root.dig(root.get_root(), &["list", "0"]), Some(&RubyValue::Symbol(0)));
+
+    // built from scratch they dump to the same bytes
+    let mut builder = RootBuilder::new();
+    let empty_symbol = builder.symbol("");
+    let empty_string = builder.string("");
+    let empty_array = builder.array(Vec::new());
+    let array = builder.array(vec![empty_symbol, empty_string, empty_array]);
+    let built = builder.build(array);
+    let mut output = Vec::new();
+    Dumper::new(&mut output).dump(&built, built.get_root()).unwrap();
+    assert_eq!(output, b"\x04\x08[\x08:\x00I\"\x00\x06:\x06ET[\x00");
+}
+
+#[test]
+#[ignore = "needs the documents written by tests/corpus/capture.rb in tests/corpus/captured"]
+fn test_captured() {
+    // documents written by capture.rb, see README.md
+    let directory = format!("{}/tests/corpus/captured", env!("CARGO_MANIFEST_DIR"));
+    let entries = std::fs::read_dir(&directory).unwrap_or_else(|err| panic!("{}: {}", directory, err));
+    let paths: Vec<_> = entries.map(|entry| entry.unwrap().path()).collect();
+    assert!(!paths.is_empty(), "{} is empty, run capture.rb first", directory);
+    for path in paths {
+        let input = std::fs::read(&path).unwrap();
+        let root = Loader::new(&mut &input[..]).load().unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        if let Some(mismatch) = Dumper::verify_roundtrip(&root, &input).unwrap() {
+            panic!("{}: {}", path.display(), mismatch);
+        }
+    }
+}
+
+#[test]
+fn test_push_loader() {
+    let names = [
+        "empty_values.bin",
+        "gemspec_index.bin",
+        "rails6_cache_entry.bin",
+        "rails7_cache_entry.bin",
+        "rpg_maker_actors.rvdata2",
+        "rpg_maker_map.rvdata2",
+        "settings_with_default.bin",
+    ];
+    let (inputs, roots): (Vec<Vec<u8>>, Vec<Root>) = names.iter().map(|name| load(name)).unzip();
+    let stream = inputs.concat();
+
+    // documents suspended anywhere continue like they were loaded at once
+    for chunk_len in [1, 3, 64] {
+        let mut loader = PushLoader::new();
+        let mut pushed = Vec::new();
+        for chunk in stream.chunks(chunk_len) {
+            pushed.extend(loader.feed(chunk).unwrap());
+        }
+        loader.finish().unwrap();
+        assert!(pushed == roots, "chunks of {} bytes", chunk_len);
+    }
+}
@@ -0,0 +1,51 @@
+#!/usr/bin/env ruby
+# frozen_string_literal: true
+
+# Captures corpus documents with the Marshal.dump of the running Ruby, see README.md. The files are written to
+# captured/ and named after the Ruby (and Rails) version, so captures of several versions sit side by side:
+#
+#   ruby tests/corpus/capture.rb
+#   bundle exec ruby tests/corpus/capture.rb   # with activesupport in the bundle, adds the Rails documents
+
+require "fileutils"
+
+DIR = File.join(__dir__, "captured")
+RUBY = "ruby#{RUBY_VERSION.split(".").first(2).join}"
+
+def write(name, value)
+  path = File.join(DIR, name)
+  File.binwrite(path, Marshal.dump(value))
+  puts "wrote #{path}"
+end
+
+FileUtils.mkdir_p(DIR)
+
+Point = Struct.new(:x, :y)
+module Tagged; end
+
+tagged = +"tagged"
+tagged.extend(Tagged)
+shared = "shared"
+with_default = Hash.new(0).merge!("a" => 1)
+write("#{RUBY}_core_values.bin", [
+  nil, true, false, 0, -1, 122, -123, 2**31, -(2**64), 1.5, -0.0, 1e-5, Float::INFINITY, Float::NAN,
+  :symbol, :"", "utf-8 ナタリー", "binary \xFF".b, "ascii".encode("US-ASCII"), "sjis".encode("Shift_JIS"),
+  /pattern/i, 1..10, Point.new(1, [shared, shared]), with_default, Time.at(0, 123_456, :usec).utc,
+  Rational(1, 3), Complex(1, 2), tagged, String, Kernel,
+])
+
+# the entries of a RubyGems specs.4.8 index, for the gems installed here
+write("#{RUBY}_gemspec_index.bin", Gem::Specification.first(20).map { |spec| [spec.name, spec.version, spec.platform.to_s] })
+
+begin
+  require "active_support"
+  require "active_support/cache"
+  require "active_support/hash_with_indifferent_access"
+rescue LoadError
+  puts "activesupport isn't available, skipping the Rails documents"
+else
+  rails = "#{RUBY}_rails#{ActiveSupport::VERSION::MAJOR}"
+  value = { "count" => 4821, "roles" => %w[admin editor], "updated_at" => Time.at(1_700_000_000).utc, "big" => 2**70 }
+  write("#{rails}_cache_entry.bin", ActiveSupport::Cache::Entry.new(value, expires_in: 300))
+  write("#{rails}_indifferent_access.bin", ActiveSupport::HashWithIndifferentAccess.new(value))
+end
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use marshr::prelude::*;
+
+#[derive(Debug, PartialEq, Clone, Copy, RubyEnum)]
+enum Priority {
+    Low,
+    HighPriority,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, RubyEnum)]
+#[marshr(repr = "string", casing = "as_is")]
+enum Kind {
+    Bug,
+    FeatureRequest,
+}
+
+#[derive(Debug, PartialEq, FromRubyObject, IntoRubyObject)]
+#[marshr(class = "Owner")]
+struct Owner {
+    name: String,
+}
+
+#[derive(Debug, PartialEq, FromRubyObject, IntoRubyObject)]
+#[marshr(class = "Task", none = "omit")]
+struct Task {
+    title: String,
+    #[marshr(rename = "@prio")]
+    #[marshr(enum)]
+    priority: Priority,
+    #[marshr(enum)]
+    kind: Kind,
+    due: Option<i64>,
+    #[marshr(object)]
+    owner: Owner,
+}
+
+#[derive(Debug, FromRubyObject)]
+#[marshr(class = "Task")]
+struct PartialTask {
+    #[marshr(rest)]
+    rest: HashMap<String, RubyValue>,
+    title: String,
+}
+
+#[derive(IntoRubyObject)]
+struct Counter {
+    count: u64,
+}
+
+fn round_trip(value: impl IntoRubyValue) -> Root {
+    let root = Root::of_value(value).unwrap();
+    let options = Options::default();
+    let dumped = marshr::dump(&root, &options).unwrap();
+    marshr::load(&dumped, &options).unwrap()
+}
+
+fn task() -> Task {
+    Task {
+        title: "Fix the loader".to_string(),
+        priority: Priority::HighPriority,
+        kind: Kind::FeatureRequest,
+        due: None,
+        owner: Owner { name: "Ralph".to_string() },
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let root = round_trip(task());
+    let object = root.get_object(root.get_root().as_object()).unwrap().as_object();
+    assert_eq!(root.get_class_name(root.get_root().as_object()).unwrap().get_name(), "Task");
+    // `due` is left out
+    assert!(root.get_symbol_id("@due").is_none());
+    let kind = object.get_instance_variable(root.get_symbol_id("@kind").unwrap()).unwrap();
+    assert_eq!(String::from_ruby_value(&root, kind).unwrap(), "FeatureRequest");
+    assert!(root.get_symbol_id("high_priority").is_some());
+
+    let task_value: Task = TypedDecoder::new(&root).decode(root.get_root()).unwrap();
+    assert_eq!(task_value, task());
+}
+
+#[test]
+fn test_rest() {
+    let root = round_trip(task());
+    let partial: PartialTask = TypedDecoder::new(&root).decode(root.get_root()).unwrap();
+    assert_eq!(partial.title, "Fix the loader");
+    let mut rest = partial.rest.keys().cloned().collect::<Vec<_>>();
+    rest.sort();
+    assert_eq!(rest, ["@kind", "@owner", "@prio"]);
+}
+
+#[test]
+fn test_default_class_name_and_errors() {
+    let root = round_trip(Counter { count: 3 });
+    assert_eq!(root.get_class_name(root.get_root().as_object()).unwrap().get_name(), "Counter");
+
+    let error = Root::of_value(Counter { count: u64::MAX }).unwrap_err();
+    assert_eq!(error, ConvertError::OutOfRange { expected: "bignum", value: u64::MAX.into() });
+}